@@ -0,0 +1,154 @@
+//! The accounts the auth service knows, kept in a cfg file.
+//!
+//! One line per account: `account <player id> <account key> <created>`. Only the key made
+//! from the secret is kept, but that is enough to log in with, so keep the file private.
+use std::collections::HashMap;
+
+use net::auth::{from_hex, to_hex, LoginProof};
+use shared::cfg::{self, CfgError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Account {
+    /// `net::auth::account_key` of the secret
+    pub key: [u8; 32],
+    /// Unix time the account was made
+    pub created: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Accounts {
+    accounts: HashMap<u64, Account>,
+}
+
+/// What checking a login came to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Login {
+    Known,
+    /// First time we saw the id, it's theirs now
+    Created,
+    WrongSecret,
+    /// No account and the login didn't bring a key to make one
+    NoAccount,
+}
+
+impl Accounts {
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn contains(&self, player_id: u64) -> bool {
+        self.accounts.contains_key(&player_id)
+    }
+
+    /// Checks `login` against the account, making the account from its key if there is none.
+    pub fn login(&mut self, login: &LoginProof, now: u64) -> Login {
+        match (self.accounts.get(&login.player_id), login.new_account) {
+            (Some(account), _) if login.verify(&account.key) => Login::Known,
+            (Some(_), _) => Login::WrongSecret,
+            (None, Some(key)) if login.verify(&key) => {
+                self.accounts.insert(login.player_id, Account { key, created: now });
+                Login::Created
+            }
+            (None, Some(_)) => Login::WrongSecret,
+            (None, None) => Login::NoAccount,
+        }
+    }
+
+    /// Reads the accounts file. Bad lines are skipped and returned as errors.
+    pub fn parse(text: &str) -> (Self, Vec<CfgError>) {
+        let (statements, mut errors) = cfg::parse(text);
+        let mut accounts = Accounts::default();
+        for statement in statements {
+            let parsed = match statement.args.as_slice() {
+                [kind, id, key, created] if kind == "account" => id
+                    .parse()
+                    .ok()
+                    .zip(from_hex::<32>(key))
+                    .zip(created.parse().ok())
+                    .map(|((id, key), created)| (id, Account { key, created })),
+                _ => None,
+            };
+            match parsed {
+                Some((id, account)) => {
+                    accounts.accounts.insert(id, account);
+                }
+                None => errors.push(CfgError { line: statement.line, column: 1, message: "bad account" }),
+            }
+        }
+        (accounts, errors)
+    }
+
+    pub fn to_cfg(&self) -> String {
+        let mut ids: Vec<&u64> = self.accounts.keys().collect();
+        ids.sort();
+        let mut text = String::new();
+        for id in ids {
+            let account = &self.accounts[id];
+            let args = [
+                "account".to_string(),
+                id.to_string(),
+                to_hex(&account.key),
+                account.created.to_string(),
+            ];
+            text.push_str(&cfg::join(&args));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use net::auth::{account_key, SECRET_BYTES};
+
+    use super::*;
+
+    const SECRET: [u8; SECRET_BYTES] = [5; SECRET_BYTES];
+
+    #[test]
+    fn the_first_login_makes_the_account_and_later_ones_need_its_secret() {
+        let mut accounts = Accounts::default();
+        // utan nyckel går det inte att skapa kontot
+        assert_eq!(accounts.login(&LoginProof::new(&SECRET, 7, [1; 32], false), 100), Login::NoAccount);
+        assert_eq!(accounts.login(&LoginProof::new(&SECRET, 7, [1; 32], true), 100), Login::Created);
+        assert!(accounts.contains(7));
+        assert_eq!(accounts.login(&LoginProof::new(&SECRET, 7, [2; 32], false), 200), Login::Known);
+        assert_eq!(accounts.login(&LoginProof::new(&[6; SECRET_BYTES], 7, [2; 32], false), 200), Login::WrongSecret);
+        // en ny nyckel tar inte över ett konto som finns
+        assert_eq!(accounts.login(&LoginProof::new(&[6; SECRET_BYTES], 7, [2; 32], true), 200), Login::WrongSecret);
+        assert_eq!(accounts.accounts[&7], Account { key: account_key(&SECRET), created: 100 });
+    }
+
+    #[test]
+    fn a_new_account_has_to_prove_the_key_it_brings() {
+        let mut accounts = Accounts::default();
+        let other_key = Some(account_key(&[6; SECRET_BYTES]));
+        let login = LoginProof { new_account: other_key, ..LoginProof::new(&SECRET, 7, [1; 32], true) };
+        assert_eq!(accounts.login(&login, 100), Login::WrongSecret);
+        assert!(!accounts.contains(7));
+    }
+
+    #[test]
+    fn accounts_survive_the_file() {
+        let mut accounts = Accounts::default();
+        accounts.login(&LoginProof::new(&SECRET, 7, [1; 32], true), 100);
+        accounts.login(&LoginProof::new(&[6; SECRET_BYTES], 3, [1; 32], true), 200);
+        let text = accounts.to_cfg();
+        let (read, errors) = Accounts::parse(&text);
+        assert!(errors.is_empty());
+        assert_eq!(read.accounts, accounts.accounts);
+        assert_eq!(read.to_cfg(), text);
+        // sorterat på id
+        assert!(text.starts_with("account 3 "));
+    }
+
+    #[test]
+    fn bad_account_lines_are_skipped() {
+        let key = to_hex(&account_key(&SECRET));
+        let text = format!("account 7 {key} 100\naccount x {key} 100\naccount 8 abc 100\nplayer 9\n");
+        let (accounts, errors) = Accounts::parse(&text);
+        assert_eq!(accounts.len(), 1);
+        assert!(accounts.contains(7));
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), [2, 3, 4]);
+    }
+}
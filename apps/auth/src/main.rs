@@ -0,0 +1,411 @@
+//! Auth service: checks who players are and hands out the connect tokens game servers want.
+//!
+//! Clients talk to it through `cl_auth`, see `net::auth` for the protocol. Tokens are signed
+//! with the key in `--key`, which every game server needs a copy of as its `sv_auth_key`; a
+//! new key is made the first time the service runs. Accounts are kept in `--accounts` (see
+//! `accounts`). The same login also gets a player a session for the profile service and
+//! matchmaker, signed with a key made from `--key`; those need a copy of the key too.
+//!
+//! Run with `cargo run -p auth -- [--bind <addr>] [--key <file>] [--accounts <file>]`.
+mod accounts;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{AddrParseError, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use accounts::{Accounts, Login};
+use net::auth::{
+    create_key, read_key, session_key, AuthPacket, JoinData, LoginProof, Session, AUTH_PORT, CONNECTION_TIMEOUT_SECS,
+    COOKIE_WINDOW, PROTOCOL_ID, SESSION_EXPIRY, TOKEN_EXPIRY,
+};
+use net::query::MAX_PACKET_SIZE;
+use renet_netcode::{generate_random_bytes, ConnectToken, NETCODE_KEY_BYTES};
+
+/// Longest we wait for a packet before dropping old tokens.
+const TICK: Duration = Duration::from_secs(1);
+/// A resent request within this long gets the same token back instead of a new one.
+const TOKEN_REUSE: Duration = Duration::from_secs(5);
+
+fn main() -> ExitCode {
+    let mut bind = SocketAddr::from(([0, 0, 0, 0], AUTH_PORT));
+    let mut key = PathBuf::from("auth.key");
+    let mut accounts = PathBuf::from("accounts.cfg");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(value) = args.next() else {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        };
+        let parsed = match arg.as_str() {
+            "--bind" => value.parse().map(|addr| bind = addr).map_err(|e: AddrParseError| e.to_string()),
+            "--key" => {
+                key = value.clone().into();
+                Ok(())
+            }
+            "--accounts" => {
+                accounts = value.clone().into();
+                Ok(())
+            }
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        };
+        if let Err(err) = parsed {
+            eprintln!("error: bad value {value} for {arg}: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    match run(bind, key, accounts) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USAGE: &str = "usage: auth [--bind <addr>] [--key <file>] [--accounts <file>]
+
+options:
+    --bind <addr>        address to listen on (default 0.0.0.0:27018)
+    --key <file>         key tokens are signed with, made if missing (default auth.key)
+    --accounts <file>    where accounts are kept (default accounts.cfg)";
+
+struct Auth {
+    key: [u8; NETCODE_KEY_BYTES],
+    /// Bara för cookies, byts varje start
+    cookie_key: [u8; 32],
+    accounts: Accounts,
+    accounts_path: PathBuf,
+    /// Senast utfärdade token per spelare, server och det som följer med
+    issued: HashMap<(u64, SocketAddr, JoinData), (Instant, Vec<u8>)>,
+}
+
+fn run(bind: SocketAddr, key_path: PathBuf, accounts_path: PathBuf) -> Result<(), String> {
+    let key = if key_path.exists() {
+        read_key(&key_path)?
+    } else {
+        let key = create_key(&key_path)?;
+        println!(
+            "made a new key in {}, copy it to sv_auth_key on the game servers and --auth-key on the services",
+            key_path.display()
+        );
+        key
+    };
+    let text = fs::read_to_string(&accounts_path).unwrap_or_default();
+    let (accounts, errors) = Accounts::parse(&text);
+    for err in errors {
+        eprintln!("{}: {err}", accounts_path.display());
+    }
+
+    let socket = UdpSocket::bind(bind).map_err(|e| format!("can't listen on {bind}: {e}"))?;
+    socket.set_read_timeout(Some(TICK)).map_err(|e| e.to_string())?;
+    println!("auth service listening on {bind}, {} accounts", accounts.len());
+
+    let mut auth = Auth {
+        key,
+        cookie_key: generate_random_bytes(),
+        accounts,
+        accounts_path,
+        issued: HashMap::new(),
+    };
+    let mut buf = [0u8; MAX_PACKET_SIZE];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if let Some(packet) = AuthPacket::decode(&buf[..len]) {
+                    for reply in auth.handle(packet, from) {
+                        if let Err(err) = socket.send_to(&reply.encode(), from) {
+                            eprintln!("could not send to {from}: {err}");
+                        }
+                    }
+                }
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => {}
+            Err(err) => return Err(err.to_string()),
+        }
+        auth.forget_old_tokens(Instant::now());
+    }
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+impl Auth {
+    /// The cookie `addr` gets in time window `window`.
+    fn cookie(&self, addr: SocketAddr, window: u64) -> [u8; 32] {
+        let input = format!("{addr} {window}");
+        *blake3::keyed_hash(&self.cookie_key, input.as_bytes()).as_bytes()
+    }
+
+    fn current_window() -> u64 {
+        unix_time().as_secs() / COOKIE_WINDOW.as_secs()
+    }
+
+    /// Takes cookies from this window and the one before, so one made just before a new
+    /// window starts still works.
+    fn cookie_valid(&self, addr: SocketAddr, cookie: &[u8; 32]) -> bool {
+        let window = Self::current_window();
+        // jämförs som blake3::Hash för att få konstant tid
+        let cookie = blake3::Hash::from(*cookie);
+        [window, window.saturating_sub(1)].iter().any(|&w| cookie == blake3::Hash::from(self.cookie(addr, w)))
+    }
+
+    /// What a client at `from` logging in as `player_id` gets asked to prove.
+    fn challenge(&self, player_id: u64, from: SocketAddr) -> AuthPacket {
+        AuthPacket::Challenge {
+            cookie: self.cookie(from, Self::current_window()),
+            new_account: !self.accounts.contains(player_id),
+        }
+    }
+
+    /// Checks the cookie and the proof and logs the player in, or gives what to answer instead.
+    fn login(&mut self, login: &LoginProof, from: SocketAddr) -> Result<(), Vec<AuthPacket>> {
+        let denied = |reason: &str| Err(vec![AuthPacket::Denied { reason: reason.into() }]);
+        let player_id = login.player_id;
+        // utan giltig cookie kan avsändaradressen vara förfalskad, så svaret blir litet
+        if !self.cookie_valid(from, &login.cookie) {
+            return Err(vec![self.challenge(player_id, from)]);
+        }
+        if player_id == 0 {
+            return denied("bad player id");
+        }
+        match self.accounts.login(login, unix_time().as_secs()) {
+            Login::Known => Ok(()),
+            Login::Created => {
+                println!("new account {player_id}");
+                if let Err(err) = fs::write(&self.accounts_path, self.accounts.to_cfg()) {
+                    eprintln!("could not save {}: {err}", self.accounts_path.display());
+                }
+                Ok(())
+            }
+            Login::WrongSecret => {
+                println!("wrong secret for {player_id} from {from}");
+                denied("wrong secret for this player id")
+            }
+            // kontot kan ha försvunnit sedan utmaningen, en ny utmaning säger till
+            Login::NoAccount => Err(vec![self.challenge(player_id, from)]),
+        }
+    }
+
+    fn handle(&mut self, packet: AuthPacket, from: SocketAddr) -> Vec<AuthPacket> {
+        let denied = |reason: &str| vec![AuthPacket::Denied { reason: reason.into() }];
+        match packet {
+            AuthPacket::Hello { player_id } => vec![self.challenge(player_id, from)],
+            AuthPacket::TokenRequest { login, server, join } => {
+                if let Err(replies) = self.login(&login, from) {
+                    return replies;
+                }
+                let player_id = login.player_id;
+                let key = (player_id, server, join);
+                if let Some((_, token)) = self.issued.get(&key) {
+                    return AuthPacket::token_replies(server, token);
+                }
+                match self.token(player_id, server, &key.2) {
+                    Ok(token) => {
+                        let replies = AuthPacket::token_replies(server, &token);
+                        self.issued.insert(key, (Instant::now(), token));
+                        replies
+                    }
+                    Err(err) => {
+                        eprintln!("could not make a token for {player_id}: {err}");
+                        denied("could not make a token")
+                    }
+                }
+            }
+            AuthPacket::SessionRequest { login } => {
+                if let Err(replies) = self.login(&login, from) {
+                    return replies;
+                }
+                let expires = unix_time().as_secs() + SESSION_EXPIRY.as_secs();
+                let session = Session::issue(&session_key(&self.key), login.player_id, expires);
+                vec![AuthPacket::Session { session }]
+            }
+            // svar tar vi inte emot
+            AuthPacket::Challenge { .. }
+            | AuthPacket::Token { .. }
+            | AuthPacket::Session { .. }
+            | AuthPacket::Denied { .. } => Vec::new(),
+        }
+    }
+
+    /// Drops tokens too old to be given out again.
+    fn forget_old_tokens(&mut self, now: Instant) {
+        self.issued.retain(|_, (issued, _)| now.duration_since(*issued) < TOKEN_REUSE);
+    }
+
+    /// A connect token for `player_id` that only `server` takes, carrying `join` to it.
+    fn token(&self, player_id: u64, server: SocketAddr, join: &JoinData) -> Result<Vec<u8>, String> {
+        let token = ConnectToken::generate(
+            unix_time(),
+            PROTOCOL_ID,
+            TOKEN_EXPIRY.as_secs(),
+            player_id,
+            CONNECTION_TIMEOUT_SECS,
+            vec![server],
+            Some(&join.to_user_data()),
+            &self.key,
+        )
+        .map_err(|e| e.to_string())?;
+        let mut bytes = Vec::new();
+        token.write(&mut bytes).map_err(|e| e.to_string())?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use renet::{ConnectionConfig, RenetClient, RenetServer};
+    use renet_netcode::{
+        ClientAuthentication, NetcodeClientTransport, NetcodeServerTransport, ServerAuthentication, ServerConfig,
+    };
+
+    use super::*;
+
+    const SECRET: [u8; 32] = [5; 32];
+
+    fn auth() -> Auth {
+        Auth {
+            key: [1; NETCODE_KEY_BYTES],
+            cookie_key: [2; 32],
+            accounts: Accounts::default(),
+            // går inte att skriva, så testerna lämnar inga filer efter sig
+            accounts_path: PathBuf::from("/nonexistent/accounts.cfg"),
+            issued: HashMap::new(),
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    /// Says hello from `from` and logs in with the cookie that comes back.
+    fn proof(auth: &mut Auth, player_id: u64, from: SocketAddr) -> LoginProof {
+        match auth.handle(AuthPacket::Hello { player_id }, from).as_slice() {
+            [AuthPacket::Challenge { cookie, new_account }] => {
+                LoginProof::new(&SECRET, player_id, *cookie, *new_account)
+            }
+            replies => panic!("expected a challenge, got {replies:?}"),
+        }
+    }
+
+    fn token_request(login: LoginProof) -> AuthPacket {
+        AuthPacket::TokenRequest { login, server: addr(27015), join: JoinData::default() }
+    }
+
+    fn token_bytes(replies: &[AuthPacket]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for reply in replies {
+            match reply {
+                AuthPacket::Token { bytes: part, .. } => bytes.extend(part),
+                _ => panic!("expected token parts, got {replies:?}"),
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn cookies_are_good_for_their_address_this_window_and_the_one_before() {
+        let auth = auth();
+        let window = Auth::current_window();
+        assert!(auth.cookie_valid(addr(1), &auth.cookie(addr(1), window)));
+        assert!(auth.cookie_valid(addr(1), &auth.cookie(addr(1), window - 1)));
+        assert!(!auth.cookie_valid(addr(1), &auth.cookie(addr(1), window - 2)));
+        assert!(!auth.cookie_valid(addr(2), &auth.cookie(addr(1), window)));
+    }
+
+    #[test]
+    fn hello_says_whether_the_account_is_new() {
+        let mut auth = auth();
+        assert!(proof(&mut auth, 7, addr(1)).new_account.is_some());
+        let login = proof(&mut auth, 7, addr(1));
+        let replies = auth.handle(AuthPacket::SessionRequest { login }, addr(1));
+        assert!(matches!(replies.as_slice(), [AuthPacket::Session { session }] if session.player_id == 7));
+        // kontot finns nu, så nyckeln skickas inte igen
+        assert_eq!(proof(&mut auth, 7, addr(1)).new_account, None);
+    }
+
+    #[test]
+    fn a_wrong_secret_is_denied() {
+        let mut auth = auth();
+        let request = token_request(proof(&mut auth, 7, addr(1)));
+        auth.handle(request, addr(1));
+        let cookie = proof(&mut auth, 7, addr(1)).cookie;
+        let login = LoginProof::new(&[6; 32], 7, cookie, false);
+        assert!(matches!(auth.handle(token_request(login), addr(1)).as_slice(), [AuthPacket::Denied { .. }]));
+    }
+
+    #[test]
+    fn a_request_from_another_address_only_gets_a_challenge() {
+        let mut auth = auth();
+        let request = token_request(proof(&mut auth, 7, addr(1)));
+        // den som ser paketet kan skicka det igen, men inte ta emot svaret på sin egen adress
+        let replies = auth.handle(request.clone(), addr(2));
+        assert!(matches!(replies.as_slice(), [AuthPacket::Challenge { .. }]));
+        assert!(matches!(auth.handle(request, addr(1)).as_slice(), [AuthPacket::Token { .. }, ..]));
+    }
+
+    #[test]
+    fn a_resent_request_gets_the_same_token_until_it_is_too_old() {
+        let mut auth = auth();
+        let request = token_request(proof(&mut auth, 7, addr(1)));
+        let first = token_bytes(&auth.handle(request.clone(), addr(1)));
+        assert_eq!(token_bytes(&auth.handle(request.clone(), addr(1))), first);
+        auth.forget_old_tokens(Instant::now() + TOKEN_REUSE);
+        assert_ne!(token_bytes(&auth.handle(request, addr(1))), first);
+    }
+
+    #[test]
+    fn a_token_only_connects_from_the_first_address_it_is_used_from() {
+        let auth = auth();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let config = ServerConfig {
+            current_time: unix_time(),
+            max_clients: 4,
+            protocol_id: PROTOCOL_ID,
+            public_addresses: vec![server_addr],
+            authentication: ServerAuthentication::Secure { private_key: auth.key },
+        };
+        let mut server = RenetServer::new(ConnectionConfig::default());
+        let mut server_transport = NetcodeServerTransport::new(config, socket).unwrap();
+        let token = auth.token(7, server_addr, &JoinData::default()).unwrap();
+        let client = || {
+            let connect_token = ConnectToken::read(&mut token.as_slice()).unwrap();
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let authentication = ClientAuthentication::Secure { connect_token };
+            let transport = NetcodeClientTransport::new(unix_time(), authentication, socket).unwrap();
+            (RenetClient::new(ConnectionConfig::default()), transport)
+        };
+        let mut clients = vec![client()];
+        let step = Duration::from_millis(10);
+        for tick in 0..200 {
+            // den andra klienten har samma token från en annan port
+            if tick == 50 {
+                clients.push(client());
+            }
+            for (client, transport) in &mut clients {
+                client.update(step);
+                let _ = transport.update(step, client);
+                let _ = transport.send_packets(client);
+            }
+            server.update(step);
+            let _ = server_transport.update(step, &mut server);
+            server_transport.send_packets(&mut server);
+            sleep(step);
+        }
+        assert!(clients[0].0.is_connected());
+        assert!(!clients[1].0.is_connected());
+        assert_eq!(server.connected_clients(), 1);
+    }
+}
@@ -1,6 +1,8 @@
+use bevy::log::LogPlugin;
 use bevy::prelude::*;
 use shared::AppState;
 use core::CorePlugin;
+use core::player::player::PlayerPlugin;
 use map::MapPlugin;
 use physics::PhysicsPlugin;
 use ui::UiPlugin;
@@ -11,10 +13,15 @@ use shared::startup::StartupConfigPlugin;
 fn main() {
     App::new()
         .insert_resource(GameConfig::load())
-        .add_plugins(DefaultPlugins)
+        .add_plugins(DefaultPlugins.set(LogPlugin {
+            // loggen visas även i konsolen
+            custom_layer: ui::console::console_log_layer,
+            ..default()
+        }))
         .add_plugins((
             StartupConfigPlugin,
             CorePlugin,
+            PlayerPlugin,
             PhysicsPlugin,
             UiPlugin,
             AudioPlugin,
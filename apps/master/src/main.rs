@@ -0,0 +1,263 @@
+//! Master server: keeps the list of internet servers for the server browser.
+//!
+//! Game servers heartbeat to it (`sv_master`) and clients ask it for the list (`cl_master`),
+//! see `net::master` for the protocol. Servers that stop sending heartbeats are dropped, and
+//! each IP is limited in how many servers it can list and how many packets it can send.
+//!
+//! Run with `cargo run -p master -- [--bind <addr>]`. It listens on every interface on the
+//! master port by default; `--bind 127.0.0.1:27010` keeps it to this machine.
+mod registry;
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use net::master::{MasterPacket, MASTER_PORT};
+use net::query::MAX_PACKET_SIZE;
+use registry::{Registry, TooManyServers};
+
+/// How often stale servers are swept out.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(5);
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let bind = match args.as_slice() {
+        [] => SocketAddr::from(([0, 0, 0, 0], MASTER_PORT)),
+        [flag, addr] if flag == "--bind" => match addr.parse() {
+            Ok(addr) => addr,
+            Err(err) => {
+                eprintln!("error: bad address {addr}: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(bind) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USAGE: &str = "usage: master [--bind <addr>]
+
+options:
+    --bind <addr>   address to listen on (default 0.0.0.0:27010)";
+
+fn run(bind: SocketAddr) -> Result<(), String> {
+    let socket = UdpSocket::bind(bind).map_err(|e| format!("can't listen on {bind}: {e}"))?;
+    println!("master server listening on {bind}");
+    serve(socket)
+}
+
+/// Answers on `socket` until it fails.
+fn serve(socket: UdpSocket) -> Result<(), String> {
+    // vaknar då och då även utan trafik för att rensa gamla servrar
+    socket
+        .set_read_timeout(Some(EXPIRE_INTERVAL))
+        .map_err(|e| e.to_string())?;
+
+    let mut registry = Registry::default();
+    let mut last_expire = Instant::now();
+    let mut buf = [0u8; MAX_PACKET_SIZE];
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => handle_packet(&socket, &mut registry, &buf[..len], from),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => {}
+            Err(err) => return Err(err.to_string()),
+        }
+
+        let now = Instant::now();
+        if now.duration_since(last_expire) >= EXPIRE_INTERVAL {
+            last_expire = now;
+            for addr in registry.expire(now) {
+                println!("{addr} timed out");
+            }
+        }
+    }
+}
+
+fn handle_packet(socket: &UdpSocket, registry: &mut Registry, bytes: &[u8], from: SocketAddr) {
+    let now = Instant::now();
+    let Some(packet) = MasterPacket::decode(bytes) else {
+        return;
+    };
+    if !registry.allow(from.ip(), now) {
+        return;
+    }
+
+    match packet {
+        MasterPacket::Heartbeat => {
+            let challenge = MasterPacket::Challenge { challenge: registry.challenge(from) };
+            if let Err(err) = socket.send_to(&challenge.encode(), from) {
+                eprintln!("could not answer {from}: {err}");
+            }
+        }
+        MasterPacket::ChallengeReply { challenge } if challenge == registry.challenge(from) => {
+            match registry.heartbeat(from, now) {
+                Ok(true) => println!("{from} registered"),
+                Ok(false) => {}
+                Err(TooManyServers) => println!("{from} rejected, too many servers from that IP"),
+            }
+        }
+        MasterPacket::ListRequest { token } => {
+            for reply in MasterPacket::list_replies(token, &registry.servers()) {
+                if let Err(err) = socket.send_to(&reply.encode(), from) {
+                    eprintln!("could not answer {from}: {err}");
+                    break;
+                }
+            }
+        }
+        MasterPacket::ChallengeReply { .. } | MasterPacket::Challenge { .. } | MasterPacket::ListReply { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use net::browser::ServerBrowser;
+    use net::query::QueryServerPlugin;
+    use shared::config::GameConfig;
+
+    use super::*;
+
+    fn start_master() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        std::thread::spawn(move || serve(socket));
+        addr
+    }
+
+    fn client() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        socket
+    }
+
+    fn list(master: SocketAddr) -> Vec<SocketAddr> {
+        let socket = client();
+        socket.send_to(&MasterPacket::ListRequest { token: 7 }.encode(), master).unwrap();
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let mut servers = Vec::new();
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).expect("the master should answer");
+            match MasterPacket::decode(&buf[..len]) {
+                Some(MasterPacket::ListReply { token: 7, servers: chunk, last }) => {
+                    servers.extend(chunk);
+                    if last {
+                        return servers;
+                    }
+                }
+                other => panic!("unexpected reply {other:?}"),
+            }
+        }
+    }
+
+    /// Heartbeat och challenge, som en spelserver gör det
+    fn register(server: &UdpSocket, master: SocketAddr) {
+        server.send_to(&MasterPacket::Heartbeat.encode(), master).unwrap();
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let (len, _) = server.recv_from(&mut buf).expect("the master should send a challenge");
+        let Some(MasterPacket::Challenge { challenge }) = MasterPacket::decode(&buf[..len]) else {
+            panic!("expected a challenge");
+        };
+        server.send_to(&MasterPacket::ChallengeReply { challenge }.encode(), master).unwrap();
+    }
+
+    /// UDP har ingen kvittens, så vi frågar tills listan har `count` servrar
+    fn list_until(master: SocketAddr, count: usize) -> Vec<SocketAddr> {
+        let mut listed = Vec::new();
+        for _ in 0..20 {
+            listed = list(master);
+            if listed.len() == count {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        listed
+    }
+
+    #[test]
+    fn servers_that_heartbeat_show_up_in_the_list() {
+        let master = start_master();
+        assert!(list(master).is_empty());
+
+        let servers = [client(), client()];
+        for server in &servers {
+            register(server, master);
+        }
+        let mut expected: Vec<SocketAddr> = servers.iter().map(|s| s.local_addr().unwrap()).collect();
+        expected.sort();
+        assert_eq!(list_until(master, expected.len()), expected);
+    }
+
+    #[test]
+    fn a_heartbeat_without_the_challenge_lists_nothing() {
+        let master = start_master();
+        let (server, other) = (client(), client());
+        server.send_to(&MasterPacket::Heartbeat.encode(), master).unwrap();
+        // en gissad challenge, eller en som skickades till någon annan, räknas inte
+        server.send_to(&MasterPacket::ChallengeReply { challenge: 42 }.encode(), master).unwrap();
+        other.send_to(&MasterPacket::Heartbeat.encode(), master).unwrap();
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let (len, _) = other.recv_from(&mut buf).unwrap();
+        let Some(MasterPacket::Challenge { challenge }) = MasterPacket::decode(&buf[..len]) else {
+            panic!("expected a challenge");
+        };
+        server.send_to(&MasterPacket::ChallengeReply { challenge }.encode(), master).unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(list(master).is_empty());
+    }
+
+    #[test]
+    fn a_game_server_registers_and_the_browser_finds_it() {
+        let master = start_master();
+        // en ledig port för frågorna, sv_query_port kan inte vara 0
+        let query_port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let mut server = App::new();
+        server.insert_resource(GameConfig::default()).add_plugins((MinimalPlugins, QueryServerPlugin));
+        let mut config = server.world_mut().resource_mut::<GameConfig>();
+        config.set("sv_master", &master.to_string()).unwrap();
+        config.set("sv_matchmaker", "").unwrap();
+        config.set("sv_query_port", &query_port.to_string()).unwrap();
+
+        let mut browser = ServerBrowser::default();
+        let server_addr = SocketAddr::from(([127, 0, 0, 1], query_port));
+        for _ in 0..200 {
+            server.update();
+            browser.poll();
+            if browser.entry(server_addr).is_some_and(|e| e.internet && e.info.is_some()) {
+                return;
+            }
+            // listan kan ha hämtats innan servern hann bli listad
+            if !browser.is_refreshing() {
+                browser.request_master_list(master);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the browser never found the server: {:?}", browser.entries());
+    }
+
+    #[test]
+    fn garbage_gets_no_answer() {
+        let master = start_master();
+        let socket = client();
+        socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        socket.send_to(b"FPSM\xff\xff", master).unwrap();
+        // en för kort listfråga ska inte heller besvaras
+        socket.send_to(&MasterPacket::ListRequest { token: 1 }.encode()[..8], master).unwrap();
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        assert!(socket.recv_from(&mut buf).is_err());
+    }
+}
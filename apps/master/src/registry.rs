@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use net::master::{HEARTBEAT_INTERVAL, MISSED_HEARTBEATS};
+
+/// Servers one IP may have listed at once.
+pub const MAX_SERVERS_PER_IP: usize = 8;
+/// Packets one IP may send per `RATE_WINDOW` before the rest are dropped.
+pub const MAX_PACKETS_PER_WINDOW: u32 = 30;
+pub const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// The IP already has `MAX_SERVERS_PER_IP` servers listed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TooManyServers;
+
+/// The registered servers and how much each IP has been talking to us.
+#[derive(Default)]
+pub struct Registry {
+    /// Senaste heartbeat per server
+    servers: HashMap<SocketAddr, Instant>,
+    /// Början på nuvarande fönster och antal paket i det
+    traffic: HashMap<IpAddr, (Instant, u32)>,
+    /// Slumpad nyckel för challenges, ny varje gång mastern startar
+    secret: RandomState,
+}
+
+impl Registry {
+    /// Counts a packet from `ip`; `false` once it has sent too many this window.
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let (start, count) = self.traffic.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= RATE_WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= MAX_PACKETS_PER_WINDOW
+    }
+
+    /// What a server at `addr` has to send back to be listed. It only reaches whoever gets
+    /// packets at `addr`, so a heartbeat can't be sent for someone else's address.
+    pub fn challenge(&self, addr: SocketAddr) -> u64 {
+        self.secret.hash_one(addr)
+    }
+
+    /// Registers or refreshes a server. Returns whether it's new.
+    pub fn heartbeat(&mut self, addr: SocketAddr, now: Instant) -> Result<bool, TooManyServers> {
+        if let Some(seen) = self.servers.get_mut(&addr) {
+            *seen = now;
+            return Ok(false);
+        }
+        if self.servers.keys().filter(|a| a.ip() == addr.ip()).count() >= MAX_SERVERS_PER_IP {
+            return Err(TooManyServers);
+        }
+        self.servers.insert(addr, now);
+        Ok(true)
+    }
+
+    /// Drops servers that stopped sending heartbeats, returning them.
+    pub fn expire(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let timeout = HEARTBEAT_INTERVAL * MISSED_HEARTBEATS;
+        let stale: Vec<SocketAddr> = self
+            .servers
+            .iter()
+            .filter(|(_, seen)| now.duration_since(**seen) > timeout)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in &stale {
+            self.servers.remove(addr);
+        }
+        self.traffic.retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+        stale
+    }
+
+    pub fn servers(&self) -> Vec<SocketAddr> {
+        let mut servers: Vec<SocketAddr> = self.servers.keys().copied().collect();
+        servers.sort();
+        servers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(ip: [u8; 4], port: u16) -> SocketAddr {
+        SocketAddr::from((ip, port))
+    }
+
+    #[test]
+    fn each_ip_gets_a_packet_budget_per_window() {
+        let mut registry = Registry::default();
+        let start = Instant::now();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        for _ in 0..MAX_PACKETS_PER_WINDOW {
+            assert!(registry.allow(ip, start));
+        }
+        assert!(!registry.allow(ip, start + Duration::from_secs(1)));
+        // andra IP:n har sin egen kvot
+        assert!(registry.allow(IpAddr::from([10, 0, 0, 2]), start));
+        assert!(registry.allow(ip, start + RATE_WINDOW));
+    }
+
+    #[test]
+    fn one_ip_can_only_list_so_many_servers() {
+        let mut registry = Registry::default();
+        let now = Instant::now();
+        for port in 0..MAX_SERVERS_PER_IP as u16 {
+            assert_eq!(registry.heartbeat(addr([10, 0, 0, 1], 27015 + port), now), Ok(true));
+        }
+        assert_eq!(registry.heartbeat(addr([10, 0, 0, 1], 28000), now), Err(TooManyServers));
+        // servrar som redan finns får fortsätta skicka heartbeats
+        assert_eq!(registry.heartbeat(addr([10, 0, 0, 1], 27015), now), Ok(false));
+        assert_eq!(registry.heartbeat(addr([10, 0, 0, 2], 28000), now), Ok(true));
+        assert_eq!(registry.servers().len(), MAX_SERVERS_PER_IP + 1);
+    }
+
+    #[test]
+    fn servers_that_stop_sending_heartbeats_are_dropped() {
+        let mut registry = Registry::default();
+        let start = Instant::now();
+        let timeout = HEARTBEAT_INTERVAL * MISSED_HEARTBEATS;
+        let (quiet, alive) = (addr([10, 0, 0, 1], 27015), addr([10, 0, 0, 2], 27015));
+        registry.heartbeat(quiet, start).unwrap();
+        registry.heartbeat(alive, start).unwrap();
+        registry.heartbeat(alive, start + timeout).unwrap();
+
+        assert!(registry.expire(start + timeout).is_empty());
+        assert_eq!(registry.expire(start + timeout + Duration::from_secs(1)), [quiet]);
+        assert_eq!(registry.servers(), [alive]);
+    }
+}
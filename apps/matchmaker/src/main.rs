@@ -0,0 +1,672 @@
+//! Matchmaking service for competitive and wingman games.
+//!
+//! Party leaders queue here (`cl_matchmaker`), see `net::matchmaking` for the protocol. Once a
+//! second the matcher forms balanced matches out of the queue, every party gets a ready check,
+//! and when all have accepted the match goes to a free game server from the pool and every
+//! party gets its connect tickets. Parties that accepted a match somebody else declined go
+//! back to the queue with their old place. When a server reports the final score the players
+//! are rated (see `shared::rating`) and the ratings saved, and the match is reported to the
+//! profile service for everybody's stats and history.
+//!
+//! A party is only queued with a valid session for one of its players, and a party of more
+//! than one with the profile service's pass for exactly those players; both are checked with
+//! the auth service's key in `--auth-key`. Nobody can be in two queued parties at once.
+//!
+//! Run with `cargo run -p matchmaker -- --server <addr> [--server <addr>...] [--bind <addr>]
+//! [--ratings <file>] [--profiles <addr>] [--auth-key <file>]`, where each server is a game
+//! server's query address.
+mod matcher;
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::BuildHasher;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use matcher::{find_matches, QueuedParty};
+use net::auth::{read_key, session_key, PartyPass, Session};
+use net::matchmaking::{
+    ConnectTicket, MatchmakingPacket, PartyMember, MATCHMAKER_PORT, READY_CHECK_SECONDS, SESSION_TIMEOUT,
+};
+use net::profile::ProfilePacket;
+use net::query::MAX_PACKET_SIZE;
+use shared::game_state::Team;
+use shared::lobby::GameMode;
+use shared::maps::MapId;
+use shared::rating::{rate_match, PlayerRating, RatingStore};
+
+const TICK: Duration = Duration::from_millis(250);
+const MATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// A server that never says its match is over is taken back after this long.
+const MATCH_LEASE: Duration = Duration::from_secs(2 * 60 * 60);
+
+fn main() -> ExitCode {
+    let mut bind = SocketAddr::from(([0, 0, 0, 0], MATCHMAKER_PORT));
+    let mut servers = Vec::new();
+    let mut ratings = PathBuf::from("ratings.cfg");
+    let mut auth_key = PathBuf::from("auth.key");
+    let mut profiles = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        if arg == "--ratings" || arg == "--auth-key" {
+            match value {
+                Some(path) if arg == "--ratings" => ratings = path.into(),
+                Some(path) => auth_key = path.into(),
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            }
+            continue;
+        }
+        let parsed = value.as_deref().map(str::parse::<SocketAddr>);
+        match (arg.as_str(), parsed) {
+            ("--bind", Some(Ok(addr))) => bind = addr,
+            ("--server", Some(Ok(addr))) => servers.push(addr),
+            ("--profiles", Some(Ok(addr))) => profiles = Some(addr),
+            (_, Some(Err(err))) => {
+                eprintln!("error: bad address for {arg}: {err}");
+                return ExitCode::FAILURE;
+            }
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if servers.is_empty() {
+        eprintln!("error: no game servers given\n\n{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    let key = match read_key(&auth_key) {
+        Ok(key) => session_key(&key),
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(bind, servers, ratings, profiles, key) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USAGE: &str = "usage: matchmaker --server <addr> [--server <addr>...] [--bind <addr>] [--ratings <file>]
+
+options:
+    --server <addr>    query address of a game server in the pool
+    --bind <addr>      address to listen on (default 0.0.0.0:27012)
+    --ratings <file>   where player ratings are kept (default ratings.cfg)
+    --profiles <addr>  profile service finished matches are reported to
+    --auth-key <file>  the auth service's key, to check sessions and party passes (default auth.key)";
+
+fn run(
+    bind: SocketAddr,
+    servers: Vec<SocketAddr>,
+    ratings_path: PathBuf,
+    profiles: Option<SocketAddr>,
+    key: [u8; 32],
+) -> Result<(), String> {
+    let ratings = match fs::read_to_string(&ratings_path) {
+        Ok(text) => {
+            let (store, errors) = RatingStore::parse(&text);
+            for err in errors {
+                eprintln!("{}: {err}", ratings_path.display());
+            }
+            store
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => RatingStore::default(),
+        Err(err) => return Err(format!("can't read {}: {err}", ratings_path.display())),
+    };
+
+    let socket = UdpSocket::bind(bind).map_err(|e| format!("can't listen on {bind}: {e}"))?;
+    socket.set_read_timeout(Some(TICK)).map_err(|e| e.to_string())?;
+    println!("matchmaker listening on {bind} with {} game servers", servers.len());
+
+    let mut matchmaker = Matchmaker::new(servers, ratings, ratings_path, key);
+    matchmaker.profiles = profiles;
+    let mut last_match = Instant::now();
+    let mut buf = [0u8; MAX_PACKET_SIZE];
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if let Some(packet) = MatchmakingPacket::decode(&buf[..len]) {
+                    matchmaker.handle(packet, from, Instant::now());
+                }
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => {}
+            Err(err) => return Err(err.to_string()),
+        }
+
+        let now = Instant::now();
+        matchmaker.expire(now);
+        if now.duration_since(last_match) >= MATCH_INTERVAL {
+            last_match = now;
+            matchmaker.form_matches(now);
+        }
+        matchmaker.allocate(now);
+
+        for (packet, to) in matchmaker.outbox.drain(..) {
+            if let Err(err) = socket.send_to(&packet.encode(), to) {
+                eprintln!("could not send to {to}: {err}");
+            }
+        }
+        for (packet, to) in matchmaker.profile_outbox.drain(..) {
+            if let Err(err) = socket.send_to(&packet.encode(), to) {
+                eprintln!("could not send to {to}: {err}");
+            }
+        }
+    }
+}
+
+struct Party {
+    seq: u64,
+    members: Vec<PartyMember>,
+    mode: GameMode,
+    maps: Vec<MapId>,
+    queued_at: Instant,
+    last_seen: Instant,
+    /// Matchen partyt har fått ready check för
+    pending: Option<u64>,
+}
+
+struct PendingMatch {
+    mode: GameMode,
+    map: MapId,
+    /// Party leaders' addresses on each team
+    teams: [Vec<SocketAddr>; 2],
+    accepted: HashSet<SocketAddr>,
+    deadline: Instant,
+}
+
+impl PendingMatch {
+    fn parties(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.teams.iter().flatten().copied()
+    }
+}
+
+struct PoolServer {
+    addr: SocketAddr,
+    running: Option<RunningMatch>,
+}
+
+struct RunningMatch {
+    match_id: u64,
+    mode: GameMode,
+    map: MapId,
+    since: Instant,
+    /// Spelarna i varje lag, CT först, för att kunna räkna ut rating efteråt
+    teams: [Vec<u64>; 2],
+}
+
+/// Unix time in seconds, what ratings are stamped with.
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+struct Matchmaker {
+    parties: HashMap<SocketAddr, Party>,
+    pending: HashMap<u64, PendingMatch>,
+    pool: Vec<PoolServer>,
+    next_seq: u64,
+    next_match: u64,
+    tokens: RandomState,
+    ratings: RatingStore,
+    ratings_path: PathBuf,
+    profiles: Option<SocketAddr>,
+    /// Nyckeln sessioner och partypass är signerade med
+    session_key: [u8; 32],
+    outbox: Vec<(MatchmakingPacket, SocketAddr)>,
+    profile_outbox: Vec<(ProfilePacket, SocketAddr)>,
+}
+
+impl Matchmaker {
+    fn new(servers: Vec<SocketAddr>, ratings: RatingStore, ratings_path: PathBuf, session_key: [u8; 32]) -> Self {
+        Matchmaker {
+            parties: HashMap::new(),
+            pending: HashMap::new(),
+            pool: servers.into_iter().map(|addr| PoolServer { addr, running: None }).collect(),
+            next_seq: 0,
+            next_match: 1,
+            tokens: RandomState::new(),
+            ratings,
+            ratings_path,
+            profiles: None,
+            session_key,
+            outbox: Vec::new(),
+            profile_outbox: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, packet: MatchmakingPacket, to: SocketAddr) {
+        self.outbox.push((packet, to));
+    }
+
+    fn players_in_queue(&self) -> u32 {
+        self.parties.values().filter(|p| p.pending.is_none()).map(|p| p.members.len() as u32).sum()
+    }
+
+    fn handle(&mut self, packet: MatchmakingPacket, from: SocketAddr, now: Instant) {
+        match packet {
+            MatchmakingPacket::Enqueue { party, mode, maps, session, pass } => {
+                // keepalive från ett party i kön är redan kontrollerat
+                if !self.parties.contains_key(&from) {
+                    if let Err(reason) = self.check_party(&party, &session, pass.as_ref()) {
+                        println!("{from} can't queue: {reason}");
+                        self.send(MatchmakingPacket::Rejected { reason }, from);
+                        return;
+                    }
+                }
+                self.enqueue(from, party, mode, maps, now);
+            }
+            MatchmakingPacket::Cancel => {
+                if let Some(match_id) = self.parties.get(&from).and_then(|p| p.pending) {
+                    self.cancel_match(match_id, Some(from));
+                }
+                self.parties.remove(&from);
+            }
+            MatchmakingPacket::Accept { match_id } => {
+                let Some(pending) = self.pending.get_mut(&match_id) else {
+                    return;
+                };
+                if !pending.parties().any(|p| p == from) {
+                    return;
+                }
+                pending.accepted.insert(from);
+                self.send_ready_check(match_id);
+            }
+            MatchmakingPacket::Decline { match_id }
+                if self.pending.get(&match_id).is_some_and(|m| m.parties().any(|p| p == from)) =>
+            {
+                self.cancel_match(match_id, Some(from));
+                self.parties.remove(&from);
+            }
+            MatchmakingPacket::Release { match_id, rounds } => {
+                let Some(server) = self
+                    .pool
+                    .iter_mut()
+                    .find(|s| s.addr.ip() == from.ip() && s.running.as_ref().is_some_and(|m| m.match_id == match_id))
+                else {
+                    return;
+                };
+                let Some(running) = server.running.take() else {
+                    return;
+                };
+                println!("match {match_id} on {} is over", server.addr);
+                if let Some(rounds) = rounds {
+                    self.rate(&running.teams, rounds);
+                    if let Some(profiles) = self.profiles {
+                        let RunningMatch { mode, map, teams, .. } = running;
+                        let report = ProfilePacket::RecordMatch { match_id, mode, map, rounds, teams };
+                        self.profile_outbox.push((report, profiles));
+                    }
+                }
+            }
+            MatchmakingPacket::RankRequest { player_id } => {
+                let summary = self.ratings.get(player_id).summary(unix_now());
+                self.send(MatchmakingPacket::RankReply { player_id, summary }, from);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the session's player may queue `members`: the session is valid, they're one of
+    /// them, a party of more than one has a pass for exactly these players and none of them
+    /// are queued already.
+    fn check_party(&self, members: &[PartyMember], session: &Session, pass: Option<&PartyPass>) -> Result<(), String> {
+        let now = unix_now();
+        if !session.verify(&self.session_key, now) {
+            return Err("your session isn't valid, log in again".into());
+        }
+        let ids: Vec<u64> = members.iter().map(|m| m.id).collect();
+        if !ids.contains(&session.player_id) {
+            return Err("you can only queue a party you're in".into());
+        }
+        let unique: HashSet<u64> = ids.iter().copied().collect();
+        if unique.len() != ids.len() {
+            return Err("somebody is in the party twice".into());
+        }
+        if ids.len() > 1 && !pass.is_some_and(|p| p.verify(&self.session_key, now, &ids)) {
+            return Err("the profile service hasn't confirmed this party".into());
+        }
+        if self.parties.values().flat_map(|p| &p.members).any(|m| unique.contains(&m.id)) {
+            return Err("somebody in the party is already queued".into());
+        }
+        Ok(())
+    }
+
+    fn enqueue(&mut self, from: SocketAddr, members: Vec<PartyMember>, mode: GameMode, maps: Vec<MapId>, now: Instant) {
+        // samma paket är också keepalive
+        if let Some(party) = self.parties.get_mut(&from) {
+            party.last_seen = now;
+            match party.pending {
+                Some(match_id) => self.send_ready_check(match_id),
+                None => self.send(MatchmakingPacket::Searching { players_in_queue: self.players_in_queue() }, from),
+            }
+            return;
+        }
+
+        let rejected = match mode.matchmaking_team_size() {
+            None => Some(format!("{} isn't matchmade", mode.label())),
+            Some(_) if members.is_empty() => Some("the party is empty".to_string()),
+            Some(size) if members.len() > size => Some(format!("{} takes parties of at most {size}", mode.label())),
+            Some(_) => None,
+        };
+        if let Some(reason) = rejected {
+            self.send(MatchmakingPacket::Rejected { reason }, from);
+            return;
+        }
+
+        self.next_seq += 1;
+        println!("{from} queued {} for {}", members.len(), mode.label());
+        self.parties.insert(
+            from,
+            Party {
+                seq: self.next_seq,
+                members,
+                mode,
+                maps,
+                queued_at: now,
+                last_seen: now,
+                pending: None,
+            },
+        );
+        self.send(MatchmakingPacket::Searching { players_in_queue: self.players_in_queue() }, from);
+    }
+
+    fn form_matches(&mut self, now: Instant) {
+        for mode in GameMode::ALL {
+            let Some(team_size) = mode.matchmaking_team_size() else {
+                continue;
+            };
+            let queue: Vec<QueuedParty> = self
+                .parties
+                .values()
+                .filter(|p| p.mode == mode && p.pending.is_none())
+                .map(|p| QueuedParty {
+                    seq: p.seq,
+                    ratings: p.members.iter().map(|m| self.ratings.get(m.id).matchmaking_rating()).collect(),
+                    maps: p.maps.clone(),
+                    waited: now.duration_since(p.queued_at),
+                })
+                .collect();
+            let by_seq: HashMap<u64, SocketAddr> = self
+                .parties
+                .iter()
+                .filter(|(_, p)| p.mode == mode)
+                .map(|(addr, p)| (p.seq, *addr))
+                .collect();
+
+            for formed in find_matches(&queue, team_size) {
+                let match_id = self.next_match;
+                self.next_match += 1;
+                let teams = formed.teams.map(|team| team.iter().map(|seq| by_seq[seq]).collect::<Vec<_>>());
+                let pending = PendingMatch {
+                    mode,
+                    map: formed.map,
+                    teams,
+                    accepted: HashSet::new(),
+                    deadline: now + Duration::from_secs(READY_CHECK_SECONDS as u64),
+                };
+                println!("match {match_id}: {} on {}", mode.label(), formed.map.label());
+                for addr in pending.parties().collect::<Vec<_>>() {
+                    if let Some(party) = self.parties.get_mut(&addr) {
+                        party.pending = Some(match_id);
+                    }
+                    self.send(
+                        MatchmakingPacket::MatchFound {
+                            match_id,
+                            mode,
+                            map: formed.map,
+                            seconds_left: READY_CHECK_SECONDS,
+                        },
+                        addr,
+                    );
+                }
+                self.pending.insert(match_id, pending);
+            }
+        }
+    }
+
+    fn send_ready_check(&mut self, match_id: u64) {
+        let Some(pending) = self.pending.get(&match_id) else {
+            return;
+        };
+        let count = |addrs: &mut dyn Iterator<Item = SocketAddr>| -> u32 {
+            addrs.filter_map(|a| self.parties.get(&a)).map(|p| p.members.len() as u32).sum()
+        };
+        let accepted = count(&mut pending.accepted.iter().copied());
+        let needed = count(&mut pending.parties());
+        let packets: Vec<_> = pending
+            .parties()
+            .map(|addr| (MatchmakingPacket::ReadyCheck { match_id, accepted, needed }, addr))
+            .collect();
+        self.outbox.extend(packets);
+    }
+
+    /// Ends a ready check that failed. Parties that accepted go back in the queue, the
+    /// rest (and `culprit`) are dropped.
+    fn cancel_match(&mut self, match_id: u64, culprit: Option<SocketAddr>) {
+        let Some(pending) = self.pending.remove(&match_id) else {
+            return;
+        };
+        println!("match {match_id} cancelled");
+        for addr in pending.parties().collect::<Vec<_>>() {
+            let requeued = pending.accepted.contains(&addr) && Some(addr) != culprit;
+            if requeued {
+                if let Some(party) = self.parties.get_mut(&addr) {
+                    party.pending = None;
+                }
+            } else {
+                self.parties.remove(&addr);
+            }
+            self.send(MatchmakingPacket::MatchCancelled { match_id, requeued }, addr);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let gone: Vec<SocketAddr> = self
+            .parties
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.last_seen) > SESSION_TIMEOUT)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in gone {
+            println!("{addr} timed out");
+            if let Some(match_id) = self.parties.get(&addr).and_then(|p| p.pending) {
+                self.cancel_match(match_id, Some(addr));
+            }
+            self.parties.remove(&addr);
+        }
+
+        let late: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, m)| now > m.deadline && m.accepted.len() < m.parties().count())
+            .map(|(id, _)| *id)
+            .collect();
+        for match_id in late {
+            self.cancel_match(match_id, None);
+        }
+
+        for server in &mut self.pool {
+            if server.running.as_ref().is_some_and(|m| now.duration_since(m.since) > MATCH_LEASE) {
+                server.running = None;
+            }
+        }
+    }
+
+    /// Starts every fully accepted match there is a free server for.
+    fn allocate(&mut self, now: Instant) {
+        let ready: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, m)| m.accepted.len() == m.parties().count())
+            .map(|(id, _)| *id)
+            .collect();
+        for match_id in ready {
+            // vänta kvar tills en server blir ledig
+            let Some(server) = self.pool.iter_mut().find(|s| s.running.is_none()) else {
+                return;
+            };
+            let server_addr = server.addr;
+            let Some(pending) = self.pending.remove(&match_id) else {
+                continue;
+            };
+            let mut teams = [Vec::new(), Vec::new()];
+
+            let mut all_tickets = Vec::new();
+            let mut per_party = Vec::new();
+            let sides = [Team::CounterTerrorist, Team::Terrorist];
+            for (index, (side, team)) in sides.into_iter().zip(&pending.teams).enumerate() {
+                for addr in team {
+                    let Some(party) = self.parties.remove(addr) else {
+                        continue;
+                    };
+                    let tickets: Vec<ConnectTicket> = party
+                        .members
+                        .iter()
+                        .map(|member| ConnectTicket {
+                            match_id,
+                            player_id: member.id,
+                            team: side,
+                            token: self.tokens.hash_one((match_id, member.id)),
+                        })
+                        .collect();
+                    teams[index].extend(party.members.iter().map(|m| m.id));
+                    all_tickets.extend(tickets.iter().cloned());
+                    per_party.push((*addr, tickets));
+                }
+            }
+
+            if let Some(server) = self.pool.iter_mut().find(|s| s.addr == server_addr) {
+                let running = RunningMatch { match_id, mode: pending.mode, map: pending.map, since: now, teams };
+                server.running = Some(running);
+            }
+            println!("match {match_id} goes to {server_addr}");
+            self.send(
+                MatchmakingPacket::Allocate {
+                    match_id,
+                    mode: pending.mode,
+                    map: pending.map,
+                    tickets: all_tickets,
+                },
+                server_addr,
+            );
+            for (addr, tickets) in per_party {
+                self.send(MatchmakingPacket::Tickets { server: server_addr, map: pending.map, tickets }, addr);
+            }
+        }
+    }
+
+    /// Rates a finished match and saves the ratings.
+    fn rate(&mut self, teams: &[Vec<u64>; 2], rounds: [u32; 2]) {
+        let now = unix_now();
+        let before = teams
+            .clone()
+            .map(|team| team.iter().map(|&id| self.ratings.get(id)).collect::<Vec<PlayerRating>>());
+        let after = rate_match([&before[0], &before[1]], rounds, now);
+        for (team, rated) in teams.iter().zip(after) {
+            for (&id, rating) in team.iter().zip(rated) {
+                self.ratings.set(id, rating);
+            }
+        }
+        if let Err(err) = fs::write(&self.ratings_path, self.ratings.to_cfg()) {
+            eprintln!("could not save {}: {err}", self.ratings_path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn matchmaker() -> Matchmaker {
+        Matchmaker::new(Vec::new(), RatingStore::default(), PathBuf::from("unused.cfg"), KEY)
+    }
+
+    fn party(ids: &[u64]) -> Vec<PartyMember> {
+        ids.iter().map(|&id| PartyMember { id, name: format!("Player {id}") }).collect()
+    }
+
+    fn enqueue(ids: &[u64], sender: u64, pass: Option<PartyPass>) -> MatchmakingPacket {
+        let session = Session::issue(&KEY, sender, unix_now() + 60);
+        MatchmakingPacket::Enqueue { party: party(ids), mode: GameMode::Wingman, maps: Vec::new(), session, pass }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn rejected(matchmaker: &mut Matchmaker) -> bool {
+        matchmaker.outbox.drain(..).any(|(p, _)| matches!(p, MatchmakingPacket::Rejected { .. }))
+    }
+
+    #[test]
+    fn only_players_in_the_party_can_queue_it() {
+        let mut mm = matchmaker();
+        let now = Instant::now();
+        // någon annans id utan att själv vara med
+        mm.handle(enqueue(&[2], 1, None), addr(1), now);
+        assert!(rejected(&mut mm));
+        // en förfalskad session
+        let mut packet = enqueue(&[1], 1, None);
+        if let MatchmakingPacket::Enqueue { session, .. } = &mut packet {
+            *session = Session::issue(&[8; 32], 1, unix_now() + 60);
+        }
+        mm.handle(packet, addr(1), now);
+        assert!(rejected(&mut mm));
+        assert!(mm.parties.is_empty());
+
+        mm.handle(enqueue(&[1], 1, None), addr(1), now);
+        assert!(!rejected(&mut mm));
+        assert_eq!(mm.parties.len(), 1);
+    }
+
+    #[test]
+    fn parties_need_a_pass_for_exactly_their_players() {
+        let mut mm = matchmaker();
+        let now = Instant::now();
+        let expires = unix_now() + 60;
+        mm.handle(enqueue(&[1, 2], 1, None), addr(1), now);
+        assert!(rejected(&mut mm));
+        mm.handle(enqueue(&[1, 3], 1, Some(PartyPass::issue(&KEY, vec![1, 2], expires))), addr(1), now);
+        assert!(rejected(&mut mm));
+        mm.handle(enqueue(&[1, 1], 1, Some(PartyPass::issue(&KEY, vec![1, 1], expires))), addr(1), now);
+        assert!(rejected(&mut mm));
+        assert!(mm.parties.is_empty());
+
+        mm.handle(enqueue(&[1, 2], 1, Some(PartyPass::issue(&KEY, vec![2, 1], expires))), addr(1), now);
+        assert!(!rejected(&mut mm));
+        assert_eq!(mm.players_in_queue(), 2);
+    }
+
+    #[test]
+    fn nobody_is_queued_twice() {
+        let mut mm = matchmaker();
+        let now = Instant::now();
+        let pass = PartyPass::issue(&KEY, vec![1, 2], unix_now() + 60);
+        mm.handle(enqueue(&[1, 2], 1, Some(pass)), addr(1), now);
+        assert!(!rejected(&mut mm));
+        // spelare 2 försöker köa själv från en annan adress
+        mm.handle(enqueue(&[2], 2, None), addr(2), now);
+        assert!(rejected(&mut mm));
+        // keepalive från partyt i kön går fortfarande igenom
+        mm.handle(enqueue(&[1, 2], 1, None), addr(1), now);
+        assert!(!rejected(&mut mm));
+        assert_eq!(mm.players_in_queue(), 2);
+    }
+}
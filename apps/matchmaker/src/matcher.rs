@@ -0,0 +1,251 @@
+//! Forms matches out of the queue.
+//!
+//! Everything here is a pure function of its input, so the same queue always gives the same
+//! matches no matter what order the parties are passed in.
+use std::time::Duration;
+
+use shared::maps::MapId;
+
+/// Rating difference accepted right away.
+const BASE_WINDOW: u32 = 100;
+/// How much the window grows per second in the queue.
+const WINDOW_PER_SECOND: u32 = 10;
+const MAX_WINDOW: u32 = 1000;
+/// Groups tried per anchor before it has to wait for the next pass.
+const MAX_SEARCH_STEPS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct QueuedParty {
+    /// Order the parties joined the queue in; lower goes first
+    pub seq: u64,
+    /// Matchmaking rating of each member
+    pub ratings: Vec<u32>,
+    /// Maps the party wants, empty for any
+    pub maps: Vec<MapId>,
+    pub waited: Duration,
+}
+
+impl QueuedParty {
+    fn size(&self) -> usize {
+        self.ratings.len()
+    }
+
+    fn rating(&self) -> u32 {
+        let total: u64 = self.ratings.iter().map(|&r| r as u64).sum();
+        (total / self.ratings.len().max(1) as u64) as u32
+    }
+
+    /// Max rating difference to other parties; grows the longer the party waits.
+    fn window(&self) -> u32 {
+        (BASE_WINDOW + WINDOW_PER_SECOND * self.waited.as_secs() as u32).min(MAX_WINDOW)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormedMatch {
+    pub map: MapId,
+    /// `seq` of the parties on each team
+    pub teams: [Vec<u64>; 2],
+}
+
+/// Forms as many matches of `team_size` a side as the queue allows. Parties are never split.
+pub fn find_matches(queue: &[QueuedParty], team_size: usize) -> Vec<FormedMatch> {
+    let mut queue: Vec<&QueuedParty> = queue.iter().filter(|p| p.size() <= team_size).collect();
+    queue.sort_by_key(|p| p.seq);
+
+    let mut used = vec![false; queue.len()];
+    let mut matches = Vec::new();
+
+    // den som väntat längst får välja först
+    for anchor in 0..queue.len() {
+        if used[anchor] {
+            continue;
+        }
+        let a = queue[anchor];
+        let mut candidates: Vec<usize> = (0..queue.len())
+            .filter(|&i| i != anchor && !used[i])
+            .filter(|&i| {
+                let diff = a.rating().abs_diff(queue[i].rating());
+                diff <= a.window().max(queue[i].window())
+            })
+            .collect();
+        candidates.sort_by_key(|&i| (a.rating().abs_diff(queue[i].rating()), queue[i].seq));
+
+        let mut search = Search { queue: &queue, candidates, team_size, steps: 0 };
+        let mut group = vec![anchor];
+        let Some((maps, team_a)) = search.fill(0, &mut group, &a.maps, a.size()) else {
+            continue;
+        };
+        let mut teams = [Vec::new(), Vec::new()];
+        for (index, &i) in group.iter().enumerate() {
+            used[i] = true;
+            let side = if team_a & (1 << index) != 0 { 0 } else { 1 };
+            teams[side].push(queue[i].seq);
+        }
+        matches.push(FormedMatch { map: pick_map(&maps, a.seq), teams });
+    }
+    matches
+}
+
+/// Looks for parties to fill a match around an anchor, closest rating first.
+struct Search<'a> {
+    queue: &'a [&'a QueuedParty],
+    /// Index i `queue`, bäst först
+    candidates: Vec<usize>,
+    team_size: usize,
+    steps: usize,
+}
+
+impl Search<'_> {
+    /// Adds candidates from `from` on to `group` until it holds two full teams that can be
+    /// split evenly, backing out of choices that can't. Returns the shared maps and the split.
+    fn fill(
+        &mut self,
+        from: usize,
+        group: &mut Vec<usize>,
+        maps: &[MapId],
+        size: usize,
+    ) -> Option<(Vec<MapId>, u32)> {
+        if size == self.team_size * 2 {
+            let parties: Vec<&QueuedParty> = group.iter().map(|&i| self.queue[i]).collect();
+            return split_teams(&parties, self.team_size).map(|team_a| (maps.to_vec(), team_a));
+        }
+        for next in from..self.candidates.len() {
+            let party = self.queue[self.candidates[next]];
+            if size + party.size() > self.team_size * 2 {
+                continue;
+            }
+            let Some(shared) = common_maps(maps, &party.maps) else {
+                continue;
+            };
+            self.steps += 1;
+            if self.steps > MAX_SEARCH_STEPS {
+                return None;
+            }
+            group.push(self.candidates[next]);
+            if let Some(found) = self.fill(next + 1, group, &shared, size + party.size()) {
+                return Some(found);
+            }
+            group.pop();
+        }
+        None
+    }
+}
+
+/// The maps both lists accept, where an empty list accepts any. `None` if they share none.
+fn common_maps(a: &[MapId], b: &[MapId]) -> Option<Vec<MapId>> {
+    let shared: Vec<MapId> = match (a.is_empty(), b.is_empty()) {
+        (true, _) => b.to_vec(),
+        (_, true) => a.to_vec(),
+        _ => a.iter().copied().filter(|m| b.contains(m)).collect(),
+    };
+    if shared.is_empty() && !(a.is_empty() && b.is_empty()) {
+        None
+    } else {
+        Some(shared)
+    }
+}
+
+/// Picks from the maps everybody accepts, turning through them by the anchor's queue number.
+fn pick_map(maps: &[MapId], seq: u64) -> MapId {
+    let choices: Vec<MapId> = MapId::ALL.into_iter().filter(|m| maps.is_empty() || maps.contains(m)).collect();
+    choices[(seq % choices.len() as u64) as usize]
+}
+
+/// Splits the parties into two teams of `team_size` with ratings as even as possible.
+/// Returns a bit mask of the parties on the first team.
+fn split_teams(parties: &[&QueuedParty], team_size: usize) -> Option<u32> {
+    let total: i64 = parties.iter().map(|p| p.rating() as i64 * p.size() as i64).sum();
+    let mut best: Option<(i64, u32)> = None;
+
+    // första partyt ligger alltid i lag ett, annars räknas varje delning två gånger
+    for mask in (1..(1u32 << parties.len())).step_by(2) {
+        let (size, rating) = parties
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .fold((0, 0i64), |(size, rating), (_, p)| (size + p.size(), rating + p.rating() as i64 * p.size() as i64));
+        if size != team_size {
+            continue;
+        }
+        let diff = (total - 2 * rating).abs();
+        if best.is_none_or(|(best_diff, _)| diff < best_diff) {
+            best = Some((diff, mask));
+        }
+    }
+    best.map(|(_, mask)| mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn party(seq: u64, ratings: &[u32]) -> QueuedParty {
+        QueuedParty { seq, ratings: ratings.to_vec(), maps: Vec::new(), waited: Duration::ZERO }
+    }
+
+    fn sorted(mut teams: [Vec<u64>; 2]) -> [Vec<u64>; 2] {
+        teams[0].sort();
+        teams[1].sort();
+        teams
+    }
+
+    #[test]
+    fn two_four_stacks_are_filled_up_with_solo_players() {
+        // 4+4+2 går inte att dela i två lag om fem, så tvåan får vänta trots att den köat först
+        let queue = [
+            party(1, &[1000; 2]),
+            party(2, &[1000; 4]),
+            party(3, &[1000; 4]),
+            party(4, &[1000]),
+            party(5, &[1000]),
+        ];
+        let matches = find_matches(&queue, 5);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(sorted(matches[0].teams.clone()), [vec![2, 4], vec![3, 5]]);
+    }
+
+    #[test]
+    fn parties_that_cannot_make_even_teams_are_not_matched() {
+        let queue = [party(1, &[1000; 4]), party(2, &[1000; 4]), party(3, &[1000; 2])];
+        assert!(find_matches(&queue, 5).is_empty());
+        // för stora partyn tas aldrig med
+        assert!(find_matches(&[party(1, &[1000; 3]), party(2, &[1000])], 1).is_empty());
+    }
+
+    #[test]
+    fn the_same_queue_gives_the_same_matches_in_any_order() {
+        let queue: Vec<QueuedParty> = (0..12).map(|seq| party(seq, &[900 + seq as u32 * 10])).collect();
+        let matches = find_matches(&queue, 2);
+        assert_eq!(matches.len(), 3);
+        let mut reversed = queue.clone();
+        reversed.reverse();
+        assert_eq!(find_matches(&reversed, 2), matches);
+    }
+
+    #[test]
+    fn teams_are_split_by_rating() {
+        let queue = [party(1, &[2000]), party(2, &[1990]), party(3, &[1960]), party(4, &[1950])];
+        let matches = find_matches(&queue, 2);
+        assert_eq!(sorted(matches[0].teams.clone()), [vec![1, 4], vec![2, 3]]);
+    }
+
+    #[test]
+    fn the_rating_window_grows_while_waiting() {
+        let mut queue = [party(1, &[1000]), party(2, &[1500])];
+        assert!(find_matches(&queue, 1).is_empty());
+        queue[1].waited = Duration::from_secs(40);
+        assert_eq!(find_matches(&queue, 1).len(), 1);
+    }
+
+    #[test]
+    fn parties_only_meet_on_maps_they_all_accept() {
+        let mut queue = [party(1, &[1000]), party(2, &[1000]), party(3, &[1000])];
+        queue[0].maps = vec![MapId::ALL[0]];
+        queue[1].maps = vec![MapId::ALL[1]];
+        let matches = find_matches(&queue, 1);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(sorted(matches[0].teams.clone()), [vec![1], vec![3]]);
+        assert_eq!(matches[0].map, MapId::ALL[0]);
+    }
+}
@@ -0,0 +1,627 @@
+//! Profile service: keeps every player's name, stats, match history, friends and inventory,
+//! and hosts the parties friends put together.
+//!
+//! Clients talk to it through `cl_profiles`, see `net::profile` for the protocol. The data
+//! lives in a directory of file-backed tables (see `store`) that is migrated to the newest
+//! schema on start; who is online and the parties are only kept in memory (see `social`).
+//! Players' requests are only taken with a session from the auth service for the same
+//! player, checked with the auth service's key in `--auth-key`; the key also signs the
+//! party passes the matchmaker wants.
+//! Finished matches and new items are only taken from the addresses given with `--trusted`,
+//! which should be the matchmaker's. Cases and the drops handed out after reported matches
+//! are rolled with each player's seeds, which the players can check (see `shared::loot`).
+//! Players trade items with each other and on the market (see `market`); credits only come
+//! into the game through `--trusted` addresses as well. `--check` audits the data directory
+//! for duplicated items and credits out of nowhere instead of serving.
+//!
+//! Run with `cargo run -p profiles -- [--bind <addr>] [--data <dir>] [--trusted <ip>...]
+//! [--auth-key <file>] [--check]`.
+mod market;
+mod profiles;
+mod social;
+mod store;
+
+use std::io::ErrorKind;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use net::auth::{read_key, session_key, PartyPass, PARTY_PASS_EXPIRY};
+use net::profile::{FriendStatus, ProfilePacket, ITEMS_PER_REPLY, MAX_NAME_LENGTH, PROFILE_PORT};
+use net::query::MAX_PACKET_SIZE;
+use shared::chat::ChatMessage;
+use shared::items::{item_def, ItemKind, PATTERNS};
+use social::{Outbox, Social};
+use store::Store;
+
+/// Longest we wait for a packet before looking for players that went offline.
+const TICK: Duration = Duration::from_secs(1);
+
+fn main() -> ExitCode {
+    let mut bind = SocketAddr::from(([0, 0, 0, 0], PROFILE_PORT));
+    let mut data = PathBuf::from("profiles");
+    let mut auth_key = PathBuf::from("auth.key");
+    let mut trusted = Vec::new();
+    let mut check = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--check" {
+            check = true;
+            continue;
+        }
+        let Some(value) = args.next() else {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        };
+        let parsed = match arg.as_str() {
+            "--bind" => value.parse().map(|addr| bind = addr).map_err(|e: AddrParseError| e.to_string()),
+            "--trusted" => value.parse().map(|ip| trusted.push(ip)).map_err(|e: AddrParseError| e.to_string()),
+            "--data" => {
+                data = value.clone().into();
+                Ok(())
+            }
+            "--auth-key" => {
+                auth_key = value.clone().into();
+                Ok(())
+            }
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        };
+        if let Err(err) = parsed {
+            eprintln!("error: bad value {value} for {arg}: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if trusted.is_empty() {
+        trusted.push(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    let result = if check { audit(data) } else { run(bind, data, trusted, auth_key) };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USAGE: &str = "usage: profiles [--bind <addr>] [--data <dir>] [--trusted <ip>...] [--auth-key <file>] [--check]
+
+options:
+    --bind <addr>        address to listen on (default 0.0.0.0:27014)
+    --data <dir>         directory the tables are kept in (default profiles)
+    --trusted <ip>       address allowed to report matches and grant items and credits (default 127.0.0.1)
+    --auth-key <file>    the auth service's key, to check sessions and sign party passes (default auth.key)
+    --check              audit the data for duplicated items and missing credits, then exit";
+
+fn audit(data: PathBuf) -> Result<(), String> {
+    let store = Store::open(&data, profiles::MIGRATIONS)?;
+    let problems = market::audit(&store);
+    for problem in &problems {
+        println!("{problem}");
+    }
+    match problems.len() {
+        0 => {
+            println!("{} is consistent", data.display());
+            Ok(())
+        }
+        _ => Err(format!("{} isn't consistent", data.display())),
+    }
+}
+
+fn run(bind: SocketAddr, data: PathBuf, trusted: Vec<IpAddr>, auth_key: PathBuf) -> Result<(), String> {
+    let key = session_key(&read_key(&auth_key)?);
+    let mut store = Store::open(&data, profiles::MIGRATIONS)?;
+    let socket = UdpSocket::bind(bind).map_err(|e| format!("can't listen on {bind}: {e}"))?;
+    socket.set_read_timeout(Some(TICK)).map_err(|e| e.to_string())?;
+    println!("profile service listening on {bind}, data in {} at schema {}", data.display(), store.version());
+
+    let mut social = Social::default();
+    let mut buf = [0u8; MAX_PACKET_SIZE];
+    // svar på det som inte gått att spara än
+    let mut held: Outbox = Vec::new();
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => match ProfilePacket::decode(&buf[..len]) {
+                // inget nytt tas emot förrän det gamla är sparat
+                Some(_) if store.unsaved() => {
+                    let reason = "the profile service can't save right now, try again soon".to_string();
+                    send_all(&socket, vec![(from, ProfilePacket::Error { reason })]);
+                }
+                Some(packet) => held.extend(match authenticate(packet, &key, unix_now()) {
+                    Ok(packet) => handle(&mut store, &mut social, packet, from, &trusted, &key),
+                    Err(reason) => vec![(from, ProfilePacket::Error { reason })],
+                }),
+                None => {}
+            },
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => {}
+            Err(err) => return Err(err.to_string()),
+        }
+        held.extend(social.expire(Instant::now()));
+
+        // ingen får höra att något gått igenom innan det är sparat
+        match store.flush() {
+            Ok(()) => send_all(&socket, std::mem::take(&mut held)),
+            Err(err) => eprintln!("could not save, not answering until it works: {err}"),
+        }
+    }
+}
+
+fn send_all(socket: &UdpSocket, outbox: Outbox) {
+    for (to, packet) in outbox {
+        if let Err(err) = socket.send_to(&packet.encode(), to) {
+            eprintln!("could not send to {to}: {err}");
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Wear and pattern seed for a granted skin; only cases and drops use the player's seeds.
+fn roll_skin() -> (f32, u16) {
+    (rand::random(), rand::random_range(0..PATTERNS))
+}
+
+/// Unwraps a signed request, checking its session is valid and for the player the request is
+/// made as. Requests made as a player aren't taken any other way, not even from `--trusted`
+/// addresses.
+fn authenticate(packet: ProfilePacket, key: &[u8; 32], now: u64) -> Result<ProfilePacket, String> {
+    match packet {
+        ProfilePacket::Signed { session, packet } => {
+            if !session.verify(key, now) {
+                return Err("your session isn't valid, log in again".into());
+            }
+            match packet.sender() {
+                Some(player_id) if player_id == session.player_id => Ok(*packet),
+                _ => Err("that request isn't yours to make".into()),
+            }
+        }
+        packet if packet.sender().is_some() => Err("log in with the auth service first".into()),
+        packet => Ok(packet),
+    }
+}
+
+fn handle(
+    store: &mut Store,
+    social: &mut Social,
+    packet: ProfilePacket,
+    from: SocketAddr,
+    trusted: &[IpAddr],
+    key: &[u8; 32],
+) -> Outbox {
+    let reply = |packets: Vec<ProfilePacket>| packets.into_iter().map(|p| (from, p)).collect();
+    let error = |reason: String| vec![(from, ProfilePacket::Error { reason })];
+    match packet {
+        ProfilePacket::GetProfile { player_id } => {
+            reply(vec![ProfilePacket::Profile { info: profiles::profile(store, player_id) }])
+        }
+        ProfilePacket::GetMatchHistory { player_id } => {
+            reply(ProfilePacket::list_replies(&profiles::match_history(store, player_id), |matches, last| {
+                ProfilePacket::MatchHistory { player_id, matches, last }
+            }))
+        }
+        ProfilePacket::GetFriends { player_id } => {
+            let mut friends = profiles::friends(store, player_id);
+            // bara riktiga vänner får se vad man gör
+            for friend in friends.iter_mut().filter(|f| f.status == FriendStatus::Accepted) {
+                friend.presence = social.presence(friend.player_id);
+            }
+            reply(ProfilePacket::list_replies(&friends, |friends, last| ProfilePacket::Friends {
+                player_id,
+                friends,
+                last,
+            }))
+        }
+        ProfilePacket::GetInventory { player_id } => {
+            let items = profiles::inventory(store, player_id);
+            reply(ProfilePacket::chunked_replies(&items, ITEMS_PER_REPLY, |items, last| ProfilePacket::Inventory {
+                player_id,
+                items,
+                last,
+            }))
+        }
+        ProfilePacket::GetLoadout { player_id } => {
+            let skins = profiles::loadout(store, player_id);
+            reply(ProfilePacket::chunked_replies(&skins, ITEMS_PER_REPLY, |skins, last| ProfilePacket::Loadout {
+                player_id,
+                skins,
+                last,
+            }))
+        }
+        ProfilePacket::SetName { player_id, name } => {
+            let name = name.trim();
+            if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+                return error(format!("names are 1 to {MAX_NAME_LENGTH} characters"));
+            }
+            profiles::set_name(store, player_id, name, unix_now());
+            Vec::new()
+        }
+        ProfilePacket::Presence { player_id, presence } => {
+            social.seen(player_id, from, presence, Instant::now());
+            Vec::new()
+        }
+        ProfilePacket::AddFriend { player_id, name } => match profiles::find_player(store, name.trim()) {
+            Some(friend_id) if friend_id == player_id => error("that's you".into()),
+            Some(friend_id) => {
+                profiles::add_friend(store, player_id, friend_id, unix_now());
+                Vec::new()
+            }
+            None => error(format!("nobody is called {}", name.trim())),
+        },
+        ProfilePacket::AcceptFriend { player_id, friend_id } => {
+            if profiles::friend_status(store, player_id, friend_id) != Some(FriendStatus::Incoming) {
+                return error("there's no friend request to accept".into());
+            }
+            profiles::add_friend(store, player_id, friend_id, unix_now());
+            Vec::new()
+        }
+        ProfilePacket::RemoveFriend { player_id, friend_id } => {
+            profiles::remove_friend(store, player_id, friend_id);
+            Vec::new()
+        }
+        ProfilePacket::InviteToParty { player_id, friend_id, lobby } => {
+            if profiles::friend_status(store, player_id, friend_id) != Some(FriendStatus::Accepted) {
+                return error("you can only invite friends".into());
+            }
+            let name = profiles::profile(store, player_id).name;
+            social.invite(player_id, &name, friend_id, &lobby, Instant::now()).unwrap_or_else(error)
+        }
+        ProfilePacket::JoinParty { player_id, friend_id } => {
+            let friends = profiles::friend_status(store, player_id, friend_id) == Some(FriendStatus::Accepted);
+            let name = profiles::profile(store, player_id).name;
+            social.join(player_id, &name, friend_id, friends, Instant::now()).unwrap_or_else(error)
+        }
+        ProfilePacket::DeclineInvite { player_id, friend_id } => {
+            social.decline(player_id, friend_id);
+            Vec::new()
+        }
+        ProfilePacket::PartyRequest { player_id, request } => {
+            social.request(player_id, &request).unwrap_or_else(error)
+        }
+        ProfilePacket::PartyChat { player_id, text } => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64());
+            // bara avsändaren får veta varför
+            social.chat(player_id, &text, now).unwrap_or_else(|reason| {
+                reply(vec![ProfilePacket::Chat { message: ChatMessage::notice(reason) }])
+            })
+        }
+        ProfilePacket::Equip { player_id, item_id, team } => {
+            profiles::equip(store, player_id, item_id, team).map_or_else(error, |_| Vec::new())
+        }
+        ProfilePacket::Unequip { player_id, team, weapon } => {
+            profiles::unequip(store, player_id, team, weapon);
+            Vec::new()
+        }
+        ProfilePacket::ApplySticker { player_id, item_id, sticker_id } => {
+            profiles::apply_sticker(store, player_id, item_id, sticker_id).map_or_else(error, |_| Vec::new())
+        }
+        ProfilePacket::ApplyNameTag { player_id, item_id, tag_id, name } => {
+            profiles::apply_name_tag(store, player_id, item_id, tag_id, &name).map_or_else(error, |_| Vec::new())
+        }
+        ProfilePacket::RecordMatch { match_id, mode, map, rounds, teams } => {
+            if !trusted.contains(&from.ip()) {
+                println!("{from} isn't trusted to report matches");
+                return Vec::new();
+            }
+            println!("match {match_id} recorded, {}-{}", rounds[0], rounds[1]);
+            profiles::record_match(store, match_id, mode, map, rounds, &teams, unix_now());
+            let mut out = Vec::new();
+            for (player_id, item) in profiles::roll_drops(store, &teams, unix_now()) {
+                println!("{player_id} got a drop: item {} ({})", item.item_id, item.definition);
+                social.send(&mut out, player_id, ProfilePacket::ItemDropped { item });
+            }
+            out
+        }
+        ProfilePacket::GrantItem { player_id, definition } => {
+            if !trusted.contains(&from.ip()) {
+                println!("{from} isn't trusted to grant items");
+                return Vec::new();
+            }
+            let Some(def) = item_def(&definition) else {
+                return error(format!("there's no item {definition}"));
+            };
+            let (wear, pattern) = if matches!(def.kind, ItemKind::Skin { .. }) { roll_skin() } else { (0.0, 0) };
+            let item = profiles::grant_item(store, player_id, def.id, wear, pattern, unix_now());
+            println!("gave {player_id} item {} ({})", item.item_id, def.name);
+            Vec::new()
+        }
+        ProfilePacket::OpenCase { player_id, item_id } => {
+            match profiles::open_case(store, player_id, item_id, unix_now()) {
+                Ok((item, roll)) => {
+                    println!("{player_id} opened a case: item {} ({})", item.item_id, item.definition);
+                    reply(vec![ProfilePacket::CaseOpened { item, roll }])
+                }
+                Err(reason) => error(reason),
+            }
+        }
+        ProfilePacket::GetSeeds { player_id } => {
+            reply(vec![ProfilePacket::Seeds { player_id, seeds: profiles::seeds(store, player_id) }])
+        }
+        ProfilePacket::SetClientSeed { player_id, client_seed } => {
+            match profiles::set_client_seed(store, player_id, &client_seed) {
+                Ok(()) => reply(vec![ProfilePacket::Seeds { player_id, seeds: profiles::seeds(store, player_id) }]),
+                Err(reason) => error(reason),
+            }
+        }
+        ProfilePacket::GetRolls { player_id } => {
+            let rolls = profiles::rolls(store, player_id);
+            reply(ProfilePacket::chunked_replies(&rolls, ITEMS_PER_REPLY, |rolls, last| ProfilePacket::Rolls {
+                player_id,
+                rolls,
+                last,
+            }))
+        }
+        ProfilePacket::GetWallet { player_id } => {
+            reply(vec![ProfilePacket::Wallet { player_id, balance: market::balance(store, player_id) }])
+        }
+        ProfilePacket::GetListings { player_id, definition } => {
+            let listings = market::listings(store, player_id, definition.as_deref());
+            reply(ProfilePacket::chunked_replies(&listings, ITEMS_PER_REPLY, |listings, last| {
+                ProfilePacket::Listings { listings, last }
+            }))
+        }
+        ProfilePacket::GetOffers { player_id } => {
+            // ett erbjudande kan ha åtta föremål, ett per svar
+            let offers = market::offers(store, player_id);
+            reply(ProfilePacket::chunked_replies(&offers, 1, |offers, last| ProfilePacket::Offers {
+                player_id,
+                offers,
+                last,
+            }))
+        }
+        ProfilePacket::GetTransactions { player_id } => {
+            let transactions = market::transactions(store, player_id);
+            reply(ProfilePacket::chunked_replies(&transactions, ITEMS_PER_REPLY, |transactions, last| {
+                ProfilePacket::Transactions { player_id, transactions, last }
+            }))
+        }
+        ProfilePacket::GetPriceHistory { definition, .. } => {
+            if item_def(&definition).is_none() {
+                return error(format!("there's no item {definition}"));
+            }
+            let points = market::price_history(store, &definition, unix_now());
+            reply(vec![ProfilePacket::PriceHistory { definition, points }])
+        }
+        ProfilePacket::CreateListing { player_id, item_id, price } => {
+            market::create_listing(store, player_id, item_id, price, unix_now()).map_or_else(error, |_| Vec::new())
+        }
+        ProfilePacket::CancelListing { player_id, listing_id } => {
+            market::cancel_listing(store, player_id, listing_id).map_or_else(error, |_| Vec::new())
+        }
+        ProfilePacket::BuyListing { player_id, listing_id, price } => {
+            match market::buy_listing(store, player_id, listing_id, price, unix_now()) {
+                Ok(sale) => {
+                    println!("{} sold item {:?} to {player_id} for {price}", sale.from, sale.from_items);
+                    let mut out = Vec::new();
+                    social.send(&mut out, sale.from, ProfilePacket::TradesChanged);
+                    out
+                }
+                Err(reason) => error(reason),
+            }
+        }
+        ProfilePacket::SendOffer { player_id, to, give, give_credits, take, take_credits } => {
+            let offer =
+                market::Offer { from: player_id, to, give, give_credits, take, take_credits, created: unix_now() };
+            match market::send_offer(store, &offer) {
+                Ok(_) => {
+                    let mut out = Vec::new();
+                    social.send(&mut out, to, ProfilePacket::TradesChanged);
+                    out
+                }
+                Err(reason) => error(reason),
+            }
+        }
+        ProfilePacket::AcceptOffer { player_id, offer_id } => {
+            match market::accept_offer(store, player_id, offer_id, unix_now()) {
+                Ok(trade) => {
+                    println!("{} and {player_id} traded, transaction {}", trade.from, trade.tx_id);
+                    let mut out = Vec::new();
+                    social.send(&mut out, trade.from, ProfilePacket::TradesChanged);
+                    out
+                }
+                Err(reason) => error(reason),
+            }
+        }
+        ProfilePacket::DeclineOffer { player_id, offer_id } => match market::decline_offer(store, player_id, offer_id) {
+            Ok(other) => {
+                let mut out = Vec::new();
+                social.send(&mut out, other, ProfilePacket::TradesChanged);
+                out
+            }
+            Err(reason) => error(reason),
+        },
+        ProfilePacket::GetPartyPass { player_id } => {
+            let expires = unix_now() + PARTY_PASS_EXPIRY.as_secs();
+            let pass = PartyPass::issue(key, social.party_members(player_id), expires);
+            reply(vec![ProfilePacket::PartyPass { pass }])
+        }
+        ProfilePacket::GrantCredits { player_id, amount } => {
+            if !trusted.contains(&from.ip()) {
+                println!("{from} isn't trusted to grant credits");
+                return Vec::new();
+            }
+            let balance = market::grant_credits(store, player_id, amount, unix_now());
+            println!("gave {player_id} {amount} credits, {balance} now");
+            Vec::new()
+        }
+        ProfilePacket::Profile { .. }
+        | ProfilePacket::MatchHistory { .. }
+        | ProfilePacket::Friends { .. }
+        | ProfilePacket::Inventory { .. }
+        | ProfilePacket::Loadout { .. }
+        | ProfilePacket::Error { .. }
+        | ProfilePacket::PartyInvite { .. }
+        | ProfilePacket::Party { .. }
+        | ProfilePacket::PartyEnded
+        | ProfilePacket::Chat { .. }
+        | ProfilePacket::Seeds { .. }
+        | ProfilePacket::Rolls { .. }
+        | ProfilePacket::CaseOpened { .. }
+        | ProfilePacket::ItemDropped { .. }
+        | ProfilePacket::Wallet { .. }
+        | ProfilePacket::Listings { .. }
+        | ProfilePacket::Offers { .. }
+        | ProfilePacket::Transactions { .. }
+        | ProfilePacket::PriceHistory { .. }
+        | ProfilePacket::TradesChanged
+        | ProfilePacket::PartyPass { .. }
+        // authenticate packar upp dem
+        | ProfilePacket::Signed { .. } => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use net::auth::Session;
+    use net::profile::{Friend, Presence};
+    use shared::lobby::Lobby;
+    use shared::maps::MapId;
+
+    use super::*;
+
+    const KEY: [u8; 32] = [5; 32];
+
+    fn signed(player_id: u64, packet: ProfilePacket) -> ProfilePacket {
+        ProfilePacket::Signed { session: Session::issue(&KEY, player_id, 1000), packet: Box::new(packet) }
+    }
+
+    /// Tjänsten som `run` kör den, en spelare med giltig session per adress
+    struct Service {
+        store: Store,
+        social: Social,
+    }
+
+    impl Service {
+        fn new(name: &str) -> Self {
+            Service { store: store::temporary_store(name), social: Social::default() }
+        }
+
+        fn send(&mut self, player_id: u64, packet: ProfilePacket) -> Outbox {
+            let session = Session::issue(&KEY, player_id, unix_now() + 60);
+            let packet = ProfilePacket::Signed { session, packet: Box::new(packet) };
+            match authenticate(packet, &KEY, unix_now()) {
+                Ok(packet) => handle(&mut self.store, &mut self.social, packet, addr(player_id), &[], &KEY),
+                Err(reason) => vec![(addr(player_id), ProfilePacket::Error { reason })],
+            }
+        }
+
+        fn friends(&mut self, player_id: u64) -> Vec<Friend> {
+            let replies = self.send(player_id, ProfilePacket::GetFriends { player_id });
+            replies
+                .into_iter()
+                .flat_map(|(_, p)| match p {
+                    ProfilePacket::Friends { friends, .. } => friends,
+                    _ => Vec::new(),
+                })
+                .collect()
+        }
+    }
+
+    fn addr(player_id: u64) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 40000 + player_id as u16))
+    }
+
+    fn errors(out: &Outbox) -> Vec<&str> {
+        out.iter()
+            .filter_map(|(_, p)| match p {
+                ProfilePacket::Error { reason } => Some(reason.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Partyt som skickades till `player_id`, om något
+    fn party_sent_to(out: &Outbox, player_id: u64) -> Option<Lobby> {
+        out.iter().rev().find_map(|(to, p)| match p {
+            ProfilePacket::Party { lobby } if *to == addr(player_id) => Some(lobby.clone()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn friends_party_up_and_join_a_game_in_progress() {
+        let mut service = Service::new("social");
+        for (id, name) in [(1, "Alice"), (2, "Bob"), (3, "Eve")] {
+            service.send(id, ProfilePacket::SetName { player_id: id, name: name.into() });
+            service.send(id, ProfilePacket::Presence { player_id: id, presence: Presence::InMenus });
+        }
+
+        // vänförfrågan, och innan den accepteras syns inte vad Alice gör
+        assert!(errors(&service.send(1, ProfilePacket::AddFriend { player_id: 1, name: "bob".into() })).is_empty());
+        let bob_sees = service.friends(2);
+        assert_eq!(bob_sees.len(), 1);
+        assert_eq!((bob_sees[0].status, bob_sees[0].presence), (FriendStatus::Incoming, Presence::Offline));
+        // inbjudningar går bara till vänner
+        let invite = ProfilePacket::InviteToParty { player_id: 1, friend_id: 2, lobby: Lobby::solo(1, "Alice") };
+        let out = service.send(1, invite);
+        assert_eq!(errors(&out), ["you can only invite friends"]);
+
+        assert!(errors(&service.send(2, ProfilePacket::AcceptFriend { player_id: 2, friend_id: 1 })).is_empty());
+        let bob_sees = service.friends(2);
+        assert_eq!((bob_sees[0].status, bob_sees[0].presence), (FriendStatus::Accepted, Presence::InMenus));
+
+        // Alice bjuder in Bob, som hamnar i samma lobby
+        let mut lobby = Lobby::solo(1, "Alice");
+        lobby.map = MapId::BoxArena;
+        let out = service.send(1, ProfilePacket::InviteToParty { player_id: 1, friend_id: 2, lobby });
+        assert!(out.contains(&(addr(2), ProfilePacket::PartyInvite { from: 1, name: "Alice".into() })));
+        let out = service.send(2, ProfilePacket::JoinParty { player_id: 2, friend_id: 1 });
+        for player_id in [1, 2] {
+            let party = party_sent_to(&out, player_id).expect("both members get the party");
+            assert_eq!(party.members.iter().map(|m| m.id).collect::<Vec<_>>(), [1, 2]);
+            assert_eq!((party.leader, party.map), (1, MapId::BoxArena));
+        }
+        // Eve är inte inbjuden och kan inte ta sig in
+        let out = service.send(3, ProfilePacket::JoinParty { player_id: 3, friend_id: 1 });
+        assert_eq!(errors(&out), ["you need an invite to join that party"]);
+
+        // Alice går in i en match, Bob ser servern och kan gå med i den pågående matchen
+        let server = SocketAddr::from(([10, 0, 0, 1], 27015));
+        let in_game = Presence::InGame { map: MapId::BoxArena, server: Some(server) };
+        service.send(1, ProfilePacket::Presence { player_id: 1, presence: in_game });
+        assert_eq!(service.friends(2)[0].presence, in_game);
+        assert!(service.friends(3).is_empty());
+    }
+
+    #[test]
+    fn requests_as_someone_else_change_nothing() {
+        let mut service = Service::new("forged");
+        for (id, name) in [(1, "Alice"), (2, "Bob")] {
+            service.send(id, ProfilePacket::SetName { player_id: id, name: name.into() });
+        }
+        service.send(1, ProfilePacket::AddFriend { player_id: 1, name: "Bob".into() });
+        // spelare 1 försöker acceptera åt spelare 2
+        let out = service.send(1, ProfilePacket::AcceptFriend { player_id: 2, friend_id: 1 });
+        assert_eq!(errors(&out), ["that request isn't yours to make"]);
+        assert_eq!(profiles::friend_status(&service.store, 2, 1), Some(FriendStatus::Incoming));
+    }
+
+    #[test]
+    fn requests_are_only_taken_as_the_sessions_player() {
+        let buy = ProfilePacket::BuyListing { player_id: 1, listing_id: 9, price: 10 };
+        assert_eq!(authenticate(signed(1, buy.clone()), &KEY, 999), Ok(buy.clone()));
+        // någon annans session, en gammal session och en från fel nyckel
+        assert!(authenticate(signed(2, buy.clone()), &KEY, 999).is_err());
+        assert!(authenticate(signed(1, buy.clone()), &KEY, 1000).is_err());
+        assert!(authenticate(signed(1, buy.clone()), &[6; 32], 999).is_err());
+        // osignerat går inte heller, inte ens inuti en annan signerad
+        assert!(authenticate(buy.clone(), &KEY, 999).is_err());
+        assert!(authenticate(signed(1, signed(1, buy)), &KEY, 999).is_err());
+    }
+
+    #[test]
+    fn public_and_trusted_packets_need_no_session() {
+        let loadout = ProfilePacket::GetLoadout { player_id: 3 };
+        assert_eq!(authenticate(loadout.clone(), &KEY, 0), Ok(loadout));
+        // betrodda adresser kollas i handle
+        let grant = ProfilePacket::GrantCredits { player_id: 3, amount: 100 };
+        assert_eq!(authenticate(grant.clone(), &KEY, 0), Ok(grant));
+    }
+}
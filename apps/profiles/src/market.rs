@@ -0,0 +1,593 @@
+//! Trade offers and the market: moving items and credits between players.
+//!
+//! Every operation checks all it needs before it changes anything, and the service handles
+//! one packet at a time and saves everything it changed together (see `store`), so a sale or
+//! trade happens in full or not at all. An item has a single owner column, so moving it to
+//! one player takes it from the other. Listed items and items in offers stay with their owner
+//! until they're sold or traded, and whatever moves or is used up takes its listings and
+//! offers with it.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use net::profile::{
+    market_fee, FriendStatus, InventoryItem, Listing, PricePoint, TradeOffer, Transaction, TransactionKind,
+    MAX_LISTINGS_SENT, MAX_OPEN_OFFERS, MAX_PRICE, MAX_TRADE_ITEMS, PRICE_HISTORY_DAYS, TRADE_HOLD,
+};
+
+use crate::profiles::{by_name, friend_status, is_listed, next_id, owned_item, profile, release, row, save_item};
+use crate::store::{column, Row, Store};
+
+const DAY: u64 = 24 * 60 * 60;
+
+pub fn balance(store: &Store, player_id: u64) -> u64 {
+    store.table("wallets").get(&player_id.to_string()).map_or(0, |r| column(r, "balance"))
+}
+
+fn set_balance(store: &mut Store, player_id: u64, balance: u64) {
+    store.table_mut("wallets").insert(player_id.to_string(), row([("balance", balance.to_string())]));
+}
+
+/// Gives the player credits out of nowhere; only trusted addresses get to.
+pub fn grant_credits(store: &mut Store, player_id: u64, amount: u64, now: u64) -> u64 {
+    let balance = balance(store, player_id).saturating_add(amount);
+    set_balance(store, player_id, balance);
+    record(
+        store,
+        Transaction {
+            tx_id: 0,
+            kind: TransactionKind::Grant,
+            time: now,
+            from: 0,
+            to: player_id,
+            from_items: Vec::new(),
+            to_items: Vec::new(),
+            from_credits: amount,
+            to_credits: 0,
+            fee: 0,
+        },
+    );
+    balance
+}
+
+/// "3 days" or "5 hours", rounded up.
+fn time_left(seconds: u64) -> String {
+    match seconds {
+        s if s > DAY => format!("{} days", s.div_ceil(DAY)),
+        s => format!("{} hours", s.div_ceil(60 * 60).max(1)),
+    }
+}
+
+fn check_tradable(item: &InventoryItem, now: u64) -> Result<(), String> {
+    if item.tradable_after > now {
+        return Err(format!("{} can't be traded for {}", item.display_name(), time_left(item.tradable_after - now)));
+    }
+    Ok(())
+}
+
+/// Moves an item, already checked to be tradable, to `to`. It goes on trade hold there.
+fn transfer(store: &mut Store, item: &InventoryItem, to: u64, now: u64) {
+    release(store, item.item_id);
+    let item = InventoryItem { tradable_after: now + TRADE_HOLD.as_secs(), ..item.clone() };
+    save_item(store, to, &item);
+}
+
+fn listing(store: &Store, listing_id: u64, r: &Row) -> Option<Listing> {
+    let seller = column(r, "seller");
+    Some(Listing {
+        listing_id,
+        seller,
+        seller_name: profile(store, seller).name,
+        // en annons för något säljaren inte har längre är inget att köpa
+        item: owned_item(store, seller, column(r, "item"))?,
+        price: column(r, "price"),
+        created: column(r, "created"),
+    })
+}
+
+/// The cheapest `MAX_LISTINGS_SENT` listings of `definition`, or of everything, and all of
+/// the player's own.
+pub fn listings(store: &Store, player_id: u64, definition: Option<&str>) -> Vec<Listing> {
+    let all = store.table("listings").rows().filter_map(|(key, r)| listing(store, key.parse().ok()?, r));
+    let (mut ours, mut others): (Vec<Listing>, Vec<Listing>) = all.partition(|l| l.seller == player_id);
+    others.retain(|l| definition.is_none_or(|d| l.item.definition == d));
+    others.sort_by_key(|l| (l.price, l.listing_id));
+    others.truncate(MAX_LISTINGS_SENT);
+    ours.append(&mut others);
+    ours
+}
+
+pub fn create_listing(store: &mut Store, player_id: u64, item_id: u64, price: u64, now: u64) -> Result<u64, String> {
+    if !(1..=MAX_PRICE).contains(&price) {
+        return Err(format!("prices are 1 to {MAX_PRICE} credits"));
+    }
+    let item = owned_item(store, player_id, item_id).ok_or("you don't have that item")?;
+    check_tradable(&item, now)?;
+    if is_listed(store, item_id) {
+        return Err("it's already on the market".into());
+    }
+    let listing_id = next_id(store, "listings");
+    store.table_mut("listings").insert(
+        listing_id.to_string(),
+        row([
+            ("seller", player_id.to_string()),
+            ("item", item_id.to_string()),
+            ("price", price.to_string()),
+            ("created", now.to_string()),
+        ]),
+    );
+    Ok(listing_id)
+}
+
+pub fn cancel_listing(store: &mut Store, player_id: u64, listing_id: u64) -> Result<(), String> {
+    let key = listing_id.to_string();
+    let seller = store.table("listings").get(&key).map(|r| column::<u64>(r, "seller"));
+    if seller != Some(player_id) {
+        return Err("that isn't your listing".into());
+    }
+    store.table_mut("listings").remove(&key);
+    Ok(())
+}
+
+/// Buys a listing at `price`, which has to be what it costs. Returns the sale.
+pub fn buy_listing(
+    store: &mut Store,
+    buyer: u64,
+    listing_id: u64,
+    price: u64,
+    now: u64,
+) -> Result<Transaction, String> {
+    let key = listing_id.to_string();
+    let listing = store.table("listings").get(&key).and_then(|r| listing(store, listing_id, r));
+    let listing = listing.ok_or("that listing is gone")?;
+    if listing.seller == buyer {
+        return Err("that's your own listing".into());
+    }
+    if listing.price != price {
+        return Err("the price has changed".into());
+    }
+    let paid = balance(store, buyer);
+    if paid < price {
+        return Err("you don't have enough credits".into());
+    }
+    check_tradable(&listing.item, now)?;
+
+    // allt är kollat, nu flyttas det
+    let fee = market_fee(price);
+    set_balance(store, buyer, paid - price);
+    set_balance(store, listing.seller, balance(store, listing.seller) + price - fee);
+    transfer(store, &listing.item, buyer, now);
+    let sale = Transaction {
+        tx_id: 0,
+        kind: TransactionKind::Sale,
+        time: now,
+        from: listing.seller,
+        to: buyer,
+        from_items: traded(&[listing.item]),
+        to_items: Vec::new(),
+        from_credits: 0,
+        to_credits: price,
+        fee,
+    };
+    Ok(record(store, sale))
+}
+
+/// Checks that the player has the items and credits, and that the items can be traded.
+fn check_side(
+    store: &Store,
+    player_id: u64,
+    item_ids: &[u64],
+    credits: u64,
+    whose: &str,
+    now: u64,
+) -> Result<Vec<InventoryItem>, String> {
+    let mut items = Vec::new();
+    for &item_id in item_ids {
+        let item = owned_item(store, player_id, item_id).ok_or_else(|| format!("item {item_id} isn't {whose}"))?;
+        check_tradable(&item, now)?;
+        items.push(item);
+    }
+    if balance(store, player_id) < credits {
+        return Err("there aren't enough credits for the trade".into());
+    }
+    Ok(items)
+}
+
+fn ids(text: Option<&String>) -> Vec<u64> {
+    text.map_or(Vec::new(), |ids| ids.split(',').filter_map(|id| id.parse().ok()).collect())
+}
+
+fn join_ids(ids: &[u64]) -> String {
+    ids.iter().map(u64::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// A trade offer as it's stored, with item ids for the items.
+pub struct Offer {
+    pub from: u64,
+    pub to: u64,
+    pub give: Vec<u64>,
+    pub give_credits: u64,
+    pub take: Vec<u64>,
+    pub take_credits: u64,
+    pub created: u64,
+}
+
+fn stored_offer(r: &Row) -> Offer {
+    Offer {
+        from: column(r, "from"),
+        to: column(r, "to"),
+        give: ids(r.get("give")),
+        give_credits: column(r, "give_credits"),
+        take: ids(r.get("take")),
+        take_credits: column(r, "take_credits"),
+        created: column(r, "created"),
+    }
+}
+
+pub fn send_offer(store: &mut Store, offer: &Offer) -> Result<u64, String> {
+    let Offer { from, to, ref give, give_credits, ref take, take_credits, created: now } = *offer;
+    if from == to {
+        return Err("you can't trade with yourself".into());
+    }
+    if friend_status(store, from, to) != Some(FriendStatus::Accepted) {
+        return Err("you can only send trade offers to friends".into());
+    }
+    if give.is_empty() && take.is_empty() {
+        return Err("a trade needs at least one item".into());
+    }
+    if give.len() > MAX_TRADE_ITEMS || take.len() > MAX_TRADE_ITEMS {
+        return Err(format!("at most {MAX_TRADE_ITEMS} items on each side"));
+    }
+    let unique: HashSet<u64> = give.iter().chain(take.iter()).copied().collect();
+    if unique.len() != give.len() + take.len() {
+        return Err("the same item is in the offer twice".into());
+    }
+    let open = store.table("offers").rows().filter(|(_, r)| column::<u64>(r, "from") == from).count();
+    if open >= MAX_OPEN_OFFERS {
+        return Err(format!("you can have at most {MAX_OPEN_OFFERS} offers waiting"));
+    }
+    check_side(store, from, give, give_credits, "yours", now)?;
+    check_side(store, to, take, take_credits, "theirs", now)?;
+
+    let offer_id = next_id(store, "offers");
+    store.table_mut("offers").insert(
+        offer_id.to_string(),
+        row([
+            ("from", from.to_string()),
+            ("to", to.to_string()),
+            ("give", join_ids(give)),
+            ("give_credits", give_credits.to_string()),
+            ("take", join_ids(take)),
+            ("take_credits", take_credits.to_string()),
+            ("created", now.to_string()),
+        ]),
+    );
+    Ok(offer_id)
+}
+
+/// Offers the player has sent or been sent, oldest first.
+pub fn offers(store: &Store, player_id: u64) -> Vec<TradeOffer> {
+    store
+        .table("offers")
+        .rows()
+        .filter_map(|(key, r)| {
+            let offer = stored_offer(r);
+            if offer.from != player_id && offer.to != player_id {
+                return None;
+            }
+            let items = |owner: u64, ids: &[u64]| -> Option<Vec<InventoryItem>> {
+                ids.iter().map(|&id| owned_item(store, owner, id)).collect()
+            };
+            Some(TradeOffer {
+                offer_id: key.parse().ok()?,
+                from: offer.from,
+                from_name: profile(store, offer.from).name,
+                to: offer.to,
+                to_name: profile(store, offer.to).name,
+                give: items(offer.from, &offer.give)?,
+                give_credits: offer.give_credits,
+                take: items(offer.to, &offer.take)?,
+                take_credits: offer.take_credits,
+                created: offer.created,
+            })
+        })
+        .collect()
+}
+
+/// Accepts an offer to the player, checking both sides again first. Returns the trade.
+pub fn accept_offer(store: &mut Store, player_id: u64, offer_id: u64, now: u64) -> Result<Transaction, String> {
+    let key = offer_id.to_string();
+    let offer = store.table("offers").get(&key).map(stored_offer).ok_or("that offer is gone")?;
+    if offer.to != player_id {
+        return Err("that offer isn't to you".into());
+    }
+    let give = check_side(store, offer.from, &offer.give, offer.give_credits, "theirs", now)?;
+    let take = check_side(store, offer.to, &offer.take, offer.take_credits, "yours", now)?;
+
+    // allt är kollat, nu flyttas det
+    let from_balance = balance(store, offer.from) - offer.give_credits + offer.take_credits;
+    let to_balance = balance(store, offer.to) - offer.take_credits + offer.give_credits;
+    set_balance(store, offer.from, from_balance);
+    set_balance(store, offer.to, to_balance);
+    for item in &give {
+        transfer(store, item, offer.to, now);
+    }
+    for item in &take {
+        transfer(store, item, offer.from, now);
+    }
+    store.table_mut("offers").remove(&key);
+    let trade = Transaction {
+        tx_id: 0,
+        kind: TransactionKind::Trade,
+        time: now,
+        from: offer.from,
+        to: offer.to,
+        from_items: traded(&give),
+        to_items: traded(&take),
+        from_credits: offer.give_credits,
+        to_credits: offer.take_credits,
+        fee: 0,
+    };
+    Ok(record(store, trade))
+}
+
+/// Declines an offer to the player or takes back one they sent. Returns the other player.
+pub fn decline_offer(store: &mut Store, player_id: u64, offer_id: u64) -> Result<u64, String> {
+    let key = offer_id.to_string();
+    let offer = store.table("offers").get(&key).map(stored_offer).ok_or("that offer is gone")?;
+    let other = match player_id {
+        id if id == offer.from => offer.to,
+        id if id == offer.to => offer.from,
+        _ => return Err("that offer isn't yours".into()),
+    };
+    store.table_mut("offers").remove(&key);
+    Ok(other)
+}
+
+fn item_list(items: &[(u64, String)]) -> String {
+    items.iter().map(|(id, definition)| format!("{id}:{definition}")).collect::<Vec<_>>().join(",")
+}
+
+fn parse_items(text: Option<&String>) -> Vec<(u64, String)> {
+    let Some(text) = text.filter(|t| !t.is_empty()) else {
+        return Vec::new();
+    };
+    text.split(',')
+        .filter_map(|entry| entry.split_once(':'))
+        .filter_map(|(id, definition)| Some((id.parse().ok()?, definition.to_string())))
+        .collect()
+}
+
+fn traded(items: &[InventoryItem]) -> Vec<(u64, String)> {
+    items.iter().map(|i| (i.item_id, i.definition.clone())).collect()
+}
+
+/// Saves a transaction under the next id, whatever `tx_id` it came with.
+fn record(store: &mut Store, transaction: Transaction) -> Transaction {
+    let t = Transaction { tx_id: next_id(store, "transactions"), ..transaction };
+    store.table_mut("transactions").insert(
+        format!("{:012}", t.tx_id),
+        row([
+            ("kind", format!("{:?}", t.kind)),
+            ("time", t.time.to_string()),
+            ("from", t.from.to_string()),
+            ("to", t.to.to_string()),
+            ("from_items", item_list(&t.from_items)),
+            ("to_items", item_list(&t.to_items)),
+            ("from_credits", t.from_credits.to_string()),
+            ("to_credits", t.to_credits.to_string()),
+            ("fee", t.fee.to_string()),
+        ]),
+    );
+    t
+}
+
+fn all_transactions(store: &Store) -> impl DoubleEndedIterator<Item = Transaction> + '_ {
+    store.table("transactions").rows().filter_map(|(key, r)| {
+        Some(Transaction {
+            tx_id: key.parse().ok()?,
+            kind: by_name(&TransactionKind::ALL, r.get("kind"))?,
+            time: column(r, "time"),
+            from: column(r, "from"),
+            to: column(r, "to"),
+            from_items: parse_items(r.get("from_items")),
+            to_items: parse_items(r.get("to_items")),
+            from_credits: column(r, "from_credits"),
+            to_credits: column(r, "to_credits"),
+            fee: column(r, "fee"),
+        })
+    })
+}
+
+/// Every transaction the player was part of, newest first.
+pub fn transactions(store: &Store, player_id: u64) -> Vec<Transaction> {
+    all_transactions(store).rev().filter(|t| t.from == player_id || t.to == player_id).collect()
+}
+
+/// Daily sales of `definition` over the last `PRICE_HISTORY_DAYS`, oldest first.
+pub fn price_history(store: &Store, definition: &str, now: u64) -> Vec<PricePoint> {
+    let first_day = (now / DAY).saturating_sub(PRICE_HISTORY_DAYS - 1);
+    let mut days: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    let sales = all_transactions(store).filter(|t| t.kind == TransactionKind::Sale);
+    for sale in sales.filter(|t| t.time / DAY >= first_day && t.from_items.iter().any(|(_, d)| d == definition)) {
+        days.entry(sale.time / DAY).or_default().push(sale.to_credits);
+    }
+    days.into_iter()
+        .map(|(day, prices)| PricePoint {
+            day,
+            low: prices.iter().copied().min().unwrap_or(0),
+            high: prices.iter().copied().max().unwrap_or(0),
+            average: prices.iter().sum::<u64>() / prices.len() as u64,
+            volume: prices.len() as u32,
+        })
+        .collect()
+}
+
+/// Looks for anything a transfer gone wrong would leave behind: items that aren't where
+/// their last transaction put them, listings and offers of items their owner doesn't have,
+/// and credits that didn't come from a grant. Returns what's wrong.
+pub fn audit(store: &Store) -> Vec<String> {
+    let mut problems = Vec::new();
+    let owners: HashMap<u64, u64> = store
+        .table("inventory")
+        .rows()
+        .filter_map(|(key, r)| Some((key.parse().ok()?, column(r, "owner"))))
+        .collect();
+
+    let mut last_owner = HashMap::new();
+    let (mut granted, mut fees) = (0u64, 0u64);
+    for t in all_transactions(store) {
+        for (item_id, _) in &t.from_items {
+            last_owner.insert(*item_id, (t.to, t.tx_id));
+        }
+        for (item_id, _) in &t.to_items {
+            last_owner.insert(*item_id, (t.from, t.tx_id));
+        }
+        if t.kind == TransactionKind::Grant {
+            granted += t.from_credits;
+        }
+        fees += t.fee;
+    }
+    for (item_id, (owner, tx_id)) in last_owner {
+        // använda föremål finns inte kvar, det är inget fel
+        if let Some(&actual) = owners.get(&item_id) {
+            if actual != owner {
+                problems.push(format!("item {item_id} belongs to {actual}, transaction {tx_id} gave it to {owner}"));
+            }
+        }
+    }
+
+    let mut listed = HashSet::new();
+    for (key, r) in store.table("listings").rows() {
+        let item_id = column::<u64>(r, "item");
+        if owners.get(&item_id) != Some(&column(r, "seller")) {
+            problems.push(format!("listing {key} is of item {item_id}, which the seller doesn't have"));
+        }
+        if !listed.insert(item_id) {
+            problems.push(format!("item {item_id} is listed more than once"));
+        }
+    }
+    for (key, r) in store.table("offers").rows() {
+        let offer = stored_offer(r);
+        let sides = offer.give.iter().map(|id| (id, offer.from)).chain(offer.take.iter().map(|id| (id, offer.to)));
+        for (item_id, owner) in sides {
+            if owners.get(item_id) != Some(&owner) {
+                problems.push(format!("offer {key} has item {item_id}, which {owner} doesn't have"));
+            }
+        }
+    }
+
+    let balances: u64 = store.table("wallets").rows().map(|(_, r)| column::<u64>(r, "balance")).sum();
+    if balances + fees != granted {
+        problems.push(format!("wallets hold {balances} credits and fees took {fees}, but {granted} were granted"));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::{add_friend, grant_item, owned_item, set_name};
+    use crate::store::temporary_store;
+
+    const NOW: u64 = 1_700_000_000;
+    const SELLER: u64 = 1;
+
+    /// Tre vänner med 1000 krediter var, och ett skin som säljaren äger
+    fn market(name: &str) -> (Store, u64) {
+        let mut store = temporary_store(name);
+        for (id, name) in [(1, "Alice"), (2, "Bob"), (3, "Carol")] {
+            set_name(&mut store, id, name, NOW);
+            grant_credits(&mut store, id, 1000, NOW);
+        }
+        for (a, b) in [(1, 2), (1, 3), (2, 3)] {
+            add_friend(&mut store, a, b, NOW);
+            add_friend(&mut store, b, a, NOW);
+        }
+        let skin = grant_item(&mut store, SELLER, "skin_ak47_jungle", 0.1, 1, NOW).item_id;
+        (store, skin)
+    }
+
+    fn offer(from: u64, to: u64, give: Vec<u64>, take_credits: u64) -> Offer {
+        Offer { from, to, give, give_credits: 0, take: Vec::new(), take_credits, created: NOW }
+    }
+
+    fn owners(store: &Store, item_id: u64) -> Vec<u64> {
+        [1, 2, 3].into_iter().filter(|&id| owned_item(store, id, item_id).is_some()).collect()
+    }
+
+    /// Alla ordningar av 0..n
+    fn orders(n: usize) -> Vec<Vec<usize>> {
+        if n == 0 {
+            return vec![Vec::new()];
+        }
+        let mut all = Vec::new();
+        for rest in orders(n - 1) {
+            for at in 0..=rest.len() {
+                let mut order = rest.clone();
+                order.insert(at, n - 1);
+                all.push(order);
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn an_item_in_a_listing_and_two_offers_goes_to_one_player_whatever_the_order() {
+        for (run, order) in orders(5).into_iter().enumerate() {
+            let (mut store, skin) = market(&format!("race-{run}"));
+            let listing = create_listing(&mut store, SELLER, skin, 100, NOW).unwrap();
+            let to_bob = send_offer(&mut store, &offer(SELLER, 2, vec![skin], 50)).unwrap();
+            let to_carol = send_offer(&mut store, &offer(SELLER, 3, vec![skin], 70)).unwrap();
+
+            let mut moved = 0;
+            for step in order {
+                let result = match step {
+                    0 => buy_listing(&mut store, 3, listing, 100, NOW).map(|_| true),
+                    1 => accept_offer(&mut store, 2, to_bob, NOW).map(|_| true),
+                    2 => accept_offer(&mut store, 3, to_carol, NOW).map(|_| true),
+                    3 => cancel_listing(&mut store, SELLER, listing).map(|_| false),
+                    // samma erbjudande en gång till
+                    _ => accept_offer(&mut store, 2, to_bob, NOW).map(|_| true),
+                };
+                moved += usize::from(result.unwrap_or(false));
+                assert_eq!(audit(&store), Vec::<String>::new(), "after step {step} of run {run}");
+            }
+            assert!(moved <= 1, "the skin moved {moved} times in run {run}");
+            assert_eq!(owners(&store, skin).len(), 1);
+            let credits: u64 = [1, 2, 3].into_iter().map(|id| balance(&store, id)).sum();
+            assert!(credits <= 3000);
+        }
+    }
+
+    #[test]
+    fn an_offer_is_only_accepted_once() {
+        let (mut store, skin) = market("double-accept");
+        let offer_id = send_offer(&mut store, &offer(SELLER, 2, vec![skin], 50)).unwrap();
+        accept_offer(&mut store, 2, offer_id, NOW).unwrap();
+        assert_eq!(accept_offer(&mut store, 2, offer_id, NOW).unwrap_err(), "that offer is gone");
+        assert_eq!((balance(&store, SELLER), balance(&store, 2)), (1050, 950));
+        assert_eq!(owners(&store, skin), [2]);
+        assert!(audit(&store).is_empty());
+    }
+
+    #[test]
+    fn a_traded_item_cannot_be_bought_or_traded_until_the_hold_is_over() {
+        let (mut store, skin) = market("just-traded");
+        let listing = create_listing(&mut store, SELLER, skin, 100, NOW).unwrap();
+        let offer_id = send_offer(&mut store, &offer(SELLER, 2, vec![skin], 0)).unwrap();
+        accept_offer(&mut store, 2, offer_id, NOW).unwrap();
+
+        // säljarens annons försvann med föremålet
+        assert_eq!(buy_listing(&mut store, 3, listing, 100, NOW).unwrap_err(), "that listing is gone");
+        assert!(create_listing(&mut store, 2, skin, 100, NOW).is_err());
+        assert!(send_offer(&mut store, &offer(2, 3, vec![skin], 0)).is_err());
+        // säljaren har det inte längre att ge bort
+        assert!(send_offer(&mut store, &offer(SELLER, 3, vec![skin], 0)).is_err());
+        assert!(audit(&store).is_empty());
+
+        let later = NOW + TRADE_HOLD.as_secs();
+        let listing = create_listing(&mut store, 2, skin, 100, later).unwrap();
+        buy_listing(&mut store, 3, listing, 100, later).unwrap();
+        assert_eq!(owners(&store, skin), [3]);
+        assert!(audit(&store).is_empty());
+    }
+}
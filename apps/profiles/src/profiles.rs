@@ -0,0 +1,681 @@
+//! The profile service's tables and what it does with them.
+use std::fmt::Debug;
+
+use net::auth::{from_hex, to_hex};
+use net::profile::{
+    CareerStats, EquippedSkin, Friend, FriendStatus, InventoryItem, MatchRecord, Presence, ProfileInfo, RollSeeds,
+    MATCH_HISTORY_LENGTH, ROLL_HISTORY_LENGTH,
+};
+use shared::game_state::Team;
+use shared::items::{ItemKind, Weapon, MAX_NAME_TAG_LENGTH, MAX_STICKERS};
+use shared::lobby::GameMode;
+use shared::loot::{
+    self, commitment, Outcome, RollKind, RollRecord, MAX_CLIENT_SEED_LENGTH, MAX_DROPS_PER_WEEK, SEED_BYTES,
+};
+use shared::maps::MapId;
+
+use crate::store::{column, Migration, Row, Store};
+
+const SIDES: [Team; 2] = [Team::CounterTerrorist, Team::Terrorist];
+
+/// Every schema change, oldest first. Never edit one that has shipped, add a new one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "profiles, stats, match history, friends and inventory",
+        apply: |store| {
+            // profiles: <player> name created
+            store.create_table("profiles");
+            // stats: <player> matches wins losses draws rounds_won rounds_lost
+            store.create_table("stats");
+            // matches: <player>/<finished>/<match> mode map team rounds_won rounds_lost
+            store.create_table("matches");
+            // friends: <player>/<friend> since, en rad åt varje håll
+            store.create_table("friends");
+            // inventory: <item> owner definition acquired
+            store.create_table("inventory");
+        },
+    },
+    Migration {
+        version: 2,
+        description: "friend requests",
+        apply: |store| {
+            // friends: + status, sett från <player>
+            store.add_column("friends", "status", "Accepted");
+        },
+    },
+    Migration {
+        version: 3,
+        description: "weapon skins and loadouts",
+        apply: |store| {
+            // inventory: + wear pattern stickers name_tag, stickers som id:n med komma emellan
+            store.add_column("inventory", "wear", "0");
+            store.add_column("inventory", "pattern", "0");
+            store.add_column("inventory", "stickers", "");
+            store.add_column("inventory", "name_tag", "");
+            // loadout: <player>/<team>/<weapon> item
+            store.create_table("loadout");
+        },
+    },
+    Migration {
+        version: 4,
+        description: "case and drop rolls",
+        apply: |store| {
+            // seeds: <player> seed client_seed nonce, seed i hex och hemligt tills det byts
+            store.create_table("seeds");
+            // rolls: <player>/<nonce> case commitment client_seed definition wear pattern, case tom för drops
+            store.create_table("rolls");
+            // revealed: <commitment> seed
+            store.create_table("revealed");
+            // drops: <player>/<week> count
+            store.create_table("drops");
+        },
+    },
+    Migration {
+        version: 5,
+        description: "marketplace, trade offers and wallets",
+        apply: |store| {
+            // inventory: + tradable_after, unix-tid då föremålet får byta ägare igen
+            store.add_column("inventory", "tradable_after", "0");
+            // counters: <table> next, id:n återanvänds aldrig
+            store.create_table("counters");
+            let next = store.table("inventory").rows().filter_map(|(key, _)| key.parse::<u64>().ok()).max();
+            let next = row([("next", (next.unwrap_or(0) + 1).to_string())]);
+            store.table_mut("counters").insert("inventory".into(), next);
+            // wallets: <player> balance
+            store.create_table("wallets");
+            // listings: <listing> seller item price created
+            store.create_table("listings");
+            // offers: <offer> from to give give_credits take take_credits created, föremål som id:n med komma emellan
+            store.create_table("offers");
+            // transactions: <tx> kind time from to from_items to_items from_credits to_credits fee,
+            // föremål som id:definition med komma emellan
+            store.create_table("transactions");
+        },
+    },
+];
+
+/// Enums are saved by their variant name, which stays put when labels change.
+pub fn by_name<T: Debug + Copy>(all: &[T], name: Option<&String>) -> Option<T> {
+    all.iter().copied().find(|v| Some(&format!("{v:?}")) == name)
+}
+
+pub fn row<const N: usize>(columns: [(&str, String); N]) -> Row {
+    columns.into_iter().map(|(name, value)| (name.to_string(), value)).collect()
+}
+
+/// The next id for a new row of `table`; ids of removed rows aren't handed out again.
+pub fn next_id(store: &mut Store, table: &str) -> u64 {
+    let counters = store.table_mut("counters");
+    let id = counters.get(table).map_or(1, |r| column(r, "next")).max(1);
+    counters.insert(table.to_string(), row([("next", (id + 1).to_string())]));
+    id
+}
+
+pub fn profile(store: &Store, player_id: u64) -> ProfileInfo {
+    let key = player_id.to_string();
+    let name = store.table("profiles").get(&key).and_then(|r| r.get("name")).cloned().unwrap_or_default();
+    let stats = store.table("stats").get(&key).map_or(CareerStats::default(), |r| CareerStats {
+        matches: column(r, "matches"),
+        wins: column(r, "wins"),
+        losses: column(r, "losses"),
+        draws: column(r, "draws"),
+        rounds_won: column(r, "rounds_won"),
+        rounds_lost: column(r, "rounds_lost"),
+    });
+    ProfileInfo { player_id, name, stats }
+}
+
+pub fn set_name(store: &mut Store, player_id: u64, name: &str, now: u64) {
+    let profiles = store.table_mut("profiles");
+    let key = player_id.to_string();
+    let mut profile = profiles.get(&key).cloned().unwrap_or_else(|| row([("created", now.to_string())]));
+    profile.insert("name".into(), name.to_string());
+    profiles.insert(key, profile);
+}
+
+pub fn match_history(store: &Store, player_id: u64) -> Vec<MatchRecord> {
+    let prefix = format!("{player_id}/");
+    store
+        .table("matches")
+        .with_prefix(&prefix)
+        .rev()
+        .filter_map(|(key, r)| {
+            let mut parts = key.split('/').skip(1);
+            let finished = parts.next()?.parse().ok()?;
+            let match_id = parts.next()?.parse().ok()?;
+            Some(MatchRecord {
+                match_id,
+                mode: by_name(&GameMode::ALL, r.get("mode"))?,
+                map: by_name(&MapId::ALL, r.get("map"))?,
+                team: by_name(&SIDES, r.get("team"))?,
+                rounds_won: column(r, "rounds_won"),
+                rounds_lost: column(r, "rounds_lost"),
+                finished,
+            })
+        })
+        .collect()
+}
+
+/// Adds a finished match to every player's stats and history.
+pub fn record_match(
+    store: &mut Store,
+    match_id: u64,
+    mode: GameMode,
+    map: MapId,
+    rounds: [u32; 2],
+    teams: &[Vec<u64>; 2],
+    now: u64,
+) {
+    for (side, team) in teams.iter().enumerate() {
+        let (won, lost) = (rounds[side], rounds[1 - side]);
+        for &player_id in team {
+            let key = player_id.to_string();
+            let stats = store.table_mut("stats");
+            let mut r = stats.get(&key).cloned().unwrap_or_default();
+            let add = |r: &mut Row, name: &str, amount: u32| {
+                let value = column::<u32>(r, name) + amount;
+                r.insert(name.to_string(), value.to_string());
+            };
+            add(&mut r, "matches", 1);
+            add(&mut r, "wins", u32::from(won > lost));
+            add(&mut r, "losses", u32::from(won < lost));
+            add(&mut r, "draws", u32::from(won == lost));
+            add(&mut r, "rounds_won", won);
+            add(&mut r, "rounds_lost", lost);
+            stats.insert(key, r);
+
+            let matches = store.table_mut("matches");
+            matches.insert(
+                format!("{player_id}/{now:012}/{match_id}"),
+                row([
+                    ("mode", format!("{mode:?}")),
+                    ("map", format!("{map:?}")),
+                    ("team", format!("{:?}", SIDES[side])),
+                    ("rounds_won", won.to_string()),
+                    ("rounds_lost", lost.to_string()),
+                ]),
+            );
+            // bara de senaste matcherna sparas
+            let prefix = format!("{player_id}/");
+            let old: Vec<String> =
+                matches.with_prefix(&prefix).rev().skip(MATCH_HISTORY_LENGTH).map(|(k, _)| k.clone()).collect();
+            for key in old {
+                matches.remove(&key);
+            }
+        }
+    }
+}
+
+/// Everyone on the player's list, requests included. Presence is filled in by the caller.
+pub fn friends(store: &Store, player_id: u64) -> Vec<Friend> {
+    let prefix = format!("{player_id}/");
+    store
+        .table("friends")
+        .with_prefix(&prefix)
+        .filter_map(|(key, r)| {
+            let friend_id = key[prefix.len()..].parse().ok()?;
+            Some(Friend {
+                player_id: friend_id,
+                name: profile(store, friend_id).name,
+                status: by_name(&FriendStatus::ALL, r.get("status"))?,
+                presence: Presence::Offline,
+            })
+        })
+        .collect()
+}
+
+pub fn friend_status(store: &Store, player_id: u64, friend_id: u64) -> Option<FriendStatus> {
+    let row = store.table("friends").get(&format!("{player_id}/{friend_id}"))?;
+    by_name(&FriendStatus::ALL, row.get("status"))
+}
+
+/// The player with this name, ignoring case. Names aren't unique, the oldest profile wins.
+pub fn find_player(store: &Store, name: &str) -> Option<u64> {
+    store
+        .table("profiles")
+        .rows()
+        .filter(|(_, r)| r.get("name").is_some_and(|n| n.eq_ignore_ascii_case(name)))
+        .min_by_key(|(_, r)| column::<u64>(r, "created"))
+        .and_then(|(key, _)| key.parse().ok())
+}
+
+fn set_friend_status(store: &mut Store, player_id: u64, friend_id: u64, status: FriendStatus, now: u64) {
+    let friends = store.table_mut("friends");
+    let key = format!("{player_id}/{friend_id}");
+    let mut r = friends.get(&key).cloned().unwrap_or_else(|| row([("since", now.to_string())]));
+    r.insert("status".into(), format!("{status:?}"));
+    friends.insert(key, r);
+}
+
+/// Sends a friend request, or accepts the other player's if they already sent one.
+/// Returns the status as the player now sees it.
+pub fn add_friend(store: &mut Store, player_id: u64, friend_id: u64, now: u64) -> FriendStatus {
+    match friend_status(store, player_id, friend_id) {
+        Some(FriendStatus::Incoming) => {
+            set_friend_status(store, player_id, friend_id, FriendStatus::Accepted, now);
+            set_friend_status(store, friend_id, player_id, FriendStatus::Accepted, now);
+            FriendStatus::Accepted
+        }
+        Some(status) => status,
+        None => {
+            set_friend_status(store, player_id, friend_id, FriendStatus::Outgoing, now);
+            set_friend_status(store, friend_id, player_id, FriendStatus::Incoming, now);
+            FriendStatus::Outgoing
+        }
+    }
+}
+
+/// Removes the friendship or request from both lists.
+pub fn remove_friend(store: &mut Store, player_id: u64, friend_id: u64) -> bool {
+    let friends = store.table_mut("friends");
+    let ours = friends.remove(&format!("{player_id}/{friend_id}")).is_some();
+    let theirs = friends.remove(&format!("{friend_id}/{player_id}")).is_some();
+    ours || theirs
+}
+
+fn inventory_item(key: &str, r: &Row) -> Option<InventoryItem> {
+    let text = |name: &str| r.get(name).filter(|v| !v.is_empty()).cloned();
+    Some(InventoryItem {
+        item_id: key.parse().ok()?,
+        definition: r.get("definition")?.clone(),
+        acquired: column(r, "acquired"),
+        wear: column(r, "wear"),
+        pattern: column(r, "pattern"),
+        stickers: text("stickers").map_or(Vec::new(), |s| s.split(',').map(str::to_string).collect()),
+        name_tag: text("name_tag"),
+        tradable_after: column(r, "tradable_after"),
+    })
+}
+
+pub fn inventory(store: &Store, player_id: u64) -> Vec<InventoryItem> {
+    let owner = player_id.to_string();
+    store
+        .table("inventory")
+        .rows()
+        .filter(|(_, r)| r.get("owner") == Some(&owner))
+        .filter_map(|(key, r)| inventory_item(key, r))
+        .collect()
+}
+
+/// The item if `player_id` owns it.
+pub fn owned_item(store: &Store, player_id: u64, item_id: u64) -> Option<InventoryItem> {
+    let key = item_id.to_string();
+    let r = store.table("inventory").get(&key)?;
+    (r.get("owner") == Some(&player_id.to_string())).then(|| inventory_item(&key, r)).flatten()
+}
+
+pub fn save_item(store: &mut Store, owner: u64, item: &InventoryItem) {
+    store.table_mut("inventory").insert(
+        item.item_id.to_string(),
+        row([
+            ("owner", owner.to_string()),
+            ("definition", item.definition.clone()),
+            ("acquired", item.acquired.to_string()),
+            ("wear", item.wear.to_string()),
+            ("pattern", item.pattern.to_string()),
+            ("stickers", item.stickers.join(",")),
+            ("name_tag", item.name_tag.clone().unwrap_or_default()),
+            ("tradable_after", item.tradable_after.to_string()),
+        ]),
+    );
+}
+
+/// Adds a new copy of `definition` to the player's inventory.
+pub fn grant_item(
+    store: &mut Store,
+    player_id: u64,
+    definition: &str,
+    wear: f32,
+    pattern: u16,
+    now: u64,
+) -> InventoryItem {
+    let item = InventoryItem {
+        item_id: next_id(store, "inventory"),
+        definition: definition.to_string(),
+        acquired: now,
+        wear,
+        pattern,
+        stickers: Vec::new(),
+        name_tag: None,
+        tradable_after: 0,
+    };
+    save_item(store, player_id, &item);
+    item
+}
+
+/// Removes an item, see `release`.
+pub fn remove_item(store: &mut Store, item_id: u64) {
+    store.table_mut("inventory").remove(&item_id.to_string());
+    release(store, item_id);
+}
+
+/// Takes an item that is used up or changes hands out of loadout slots, market listings and
+/// trade offers.
+pub fn release(store: &mut Store, item_id: u64) {
+    let key = item_id.to_string();
+    let loadout = store.table_mut("loadout");
+    let slots: Vec<String> =
+        loadout.rows().filter(|(_, r)| r.get("item") == Some(&key)).map(|(slot, _)| slot.clone()).collect();
+    for slot in slots {
+        loadout.remove(&slot);
+    }
+    let listings = store.table_mut("listings");
+    let listed: Vec<String> =
+        listings.rows().filter(|(_, r)| r.get("item") == Some(&key)).map(|(id, _)| id.clone()).collect();
+    for listing in listed {
+        listings.remove(&listing);
+    }
+    let offers = store.table_mut("offers");
+    let in_offer = |r: &Row| {
+        ["give", "take"].iter().any(|side| r.get(*side).is_some_and(|ids| ids.split(',').any(|id| id == key)))
+    };
+    let stale: Vec<String> = offers.rows().filter(|(_, r)| in_offer(r)).map(|(id, _)| id.clone()).collect();
+    for offer in stale {
+        offers.remove(&offer);
+    }
+}
+
+/// Whether the item is up for sale; it can't be changed then.
+pub fn is_listed(store: &Store, item_id: u64) -> bool {
+    let key = item_id.to_string();
+    store.table("listings").rows().any(|(_, r)| r.get("item") == Some(&key))
+}
+
+fn slot_key(player_id: u64, team: Team, weapon: Weapon) -> String {
+    format!("{player_id}/{team:?}/{weapon:?}")
+}
+
+/// The skins in the player's loadout slots.
+pub fn loadout(store: &Store, player_id: u64) -> Vec<EquippedSkin> {
+    let prefix = format!("{player_id}/");
+    store
+        .table("loadout")
+        .with_prefix(&prefix)
+        .filter_map(|(key, r)| {
+            let team = by_name(&SIDES, key.split('/').nth(1).map(str::to_string).as_ref())?;
+            let item_id = column(r, "item");
+            let skin = owned_item(store, player_id, item_id)?.skin()?;
+            Some(EquippedSkin { team, item_id, skin })
+        })
+        .collect()
+}
+
+pub fn equip(store: &mut Store, player_id: u64, item_id: u64, team: Team) -> Result<(), String> {
+    let item = owned_item(store, player_id, item_id).ok_or("you don't have that item")?;
+    let weapon = item.skin().and_then(|s| s.weapon()).ok_or("only weapon skins can be equipped")?;
+    if !weapon.teams().contains(&team) {
+        return Err(format!("{} can't use the {}", team.label(), weapon.label()));
+    }
+    store.table_mut("loadout").insert(slot_key(player_id, team, weapon), row([("item", item_id.to_string())]));
+    Ok(())
+}
+
+pub fn unequip(store: &mut Store, player_id: u64, team: Team, weapon: Weapon) {
+    store.table_mut("loadout").remove(&slot_key(player_id, team, weapon));
+}
+
+pub fn apply_sticker(store: &mut Store, player_id: u64, item_id: u64, sticker_id: u64) -> Result<(), String> {
+    let mut item = owned_item(store, player_id, item_id).ok_or("you don't have that item")?;
+    let sticker = owned_item(store, player_id, sticker_id).ok_or("you don't have that sticker")?;
+    if item.skin().is_none() {
+        return Err("stickers only go on weapon skins".into());
+    }
+    if is_listed(store, item_id) || is_listed(store, sticker_id) {
+        return Err("take it off the market first".into());
+    }
+    if !matches!(sticker.def().map(|d| d.kind), Some(ItemKind::Sticker { .. })) {
+        return Err("that isn't a sticker".into());
+    }
+    if item.stickers.len() >= MAX_STICKERS {
+        return Err(format!("there's only room for {MAX_STICKERS} stickers"));
+    }
+    item.stickers.push(sticker.definition);
+    save_item(store, player_id, &item);
+    remove_item(store, sticker_id);
+    Ok(())
+}
+
+pub fn apply_name_tag(store: &mut Store, player_id: u64, item_id: u64, tag_id: u64, name: &str) -> Result<(), String> {
+    let mut item = owned_item(store, player_id, item_id).ok_or("you don't have that item")?;
+    let tag = owned_item(store, player_id, tag_id).ok_or("you don't have that name tag")?;
+    if item.skin().is_none() {
+        return Err("name tags only go on weapon skins".into());
+    }
+    if is_listed(store, item_id) || is_listed(store, tag_id) {
+        return Err("take it off the market first".into());
+    }
+    if tag.def().map(|d| d.kind) != Some(ItemKind::NameTag) {
+        return Err("that isn't a name tag".into());
+    }
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_TAG_LENGTH {
+        return Err(format!("name tags are 1 to {MAX_NAME_TAG_LENGTH} characters"));
+    }
+    item.name_tag = Some(name.to_string());
+    save_item(store, player_id, &item);
+    remove_item(store, tag_id);
+    Ok(())
+}
+
+/// A player's server seed, client seed and the nonce of their next roll.
+struct SeedState {
+    seed: [u8; SEED_BYTES],
+    client_seed: String,
+    nonce: u64,
+}
+
+/// The player's seeds, made up the first time they're needed.
+fn seed_state(store: &mut Store, player_id: u64) -> SeedState {
+    let key = player_id.to_string();
+    let saved = store.table("seeds").get(&key).and_then(|r| {
+        Some(SeedState {
+            seed: from_hex(r.get("seed")?)?,
+            client_seed: r.get("client_seed")?.clone(),
+            nonce: column(r, "nonce"),
+        })
+    });
+    saved.unwrap_or_else(|| {
+        let state = SeedState { seed: rand::random(), client_seed: to_hex(&rand::random::<[u8; 8]>()), nonce: 0 };
+        save_seeds(store, player_id, &state);
+        state
+    })
+}
+
+fn save_seeds(store: &mut Store, player_id: u64, state: &SeedState) {
+    store.table_mut("seeds").insert(
+        player_id.to_string(),
+        row([
+            ("seed", to_hex(&state.seed)),
+            ("client_seed", state.client_seed.clone()),
+            ("nonce", state.nonce.to_string()),
+        ]),
+    );
+}
+
+pub fn seeds(store: &mut Store, player_id: u64) -> RollSeeds {
+    let state = seed_state(store, player_id);
+    RollSeeds { commitment: commitment(&state.seed), client_seed: state.client_seed, nonce: state.nonce }
+}
+
+/// Reveals the player's server seed and starts a new one with `client_seed`. The nonce keeps
+/// counting so every roll has its own.
+pub fn set_client_seed(store: &mut Store, player_id: u64, client_seed: &str) -> Result<(), String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if client_seed.is_empty() || client_seed.len() > MAX_CLIENT_SEED_LENGTH || !client_seed.chars().all(valid) {
+        return Err(format!("client seeds are 1 to {MAX_CLIENT_SEED_LENGTH} letters, digits, - or _"));
+    }
+    let old = seed_state(store, player_id);
+    store.table_mut("revealed").insert(to_hex(&commitment(&old.seed)), row([("seed", to_hex(&old.seed))]));
+    let state = SeedState { seed: rand::random(), client_seed: client_seed.to_string(), nonce: old.nonce };
+    save_seeds(store, player_id, &state);
+    Ok(())
+}
+
+/// Makes the player's next roll and keeps it so it can be checked later.
+fn roll(store: &mut Store, player_id: u64, kind: RollKind) -> RollRecord {
+    let mut state = seed_state(store, player_id);
+    let record = RollRecord {
+        nonce: state.nonce,
+        commitment: commitment(&state.seed),
+        client_seed: state.client_seed.clone(),
+        outcome: loot::roll(&kind, &state.seed, &state.client_seed, state.nonce),
+        kind,
+        seed: None,
+    };
+    state.nonce += 1;
+    save_seeds(store, player_id, &state);
+
+    let case = match &record.kind {
+        RollKind::Case(case) => case.clone(),
+        RollKind::Drop => String::new(),
+    };
+    let outcome = record.outcome.as_ref();
+    let rolls = store.table_mut("rolls");
+    rolls.insert(
+        format!("{player_id}/{:012}", record.nonce),
+        row([
+            ("case", case),
+            ("commitment", to_hex(&record.commitment)),
+            ("client_seed", record.client_seed.clone()),
+            ("definition", outcome.map_or(String::new(), |o| o.definition.clone())),
+            ("wear", outcome.map_or(0.0, |o| o.wear).to_string()),
+            ("pattern", outcome.map_or(0, |o| o.pattern).to_string()),
+        ]),
+    );
+    let prefix = format!("{player_id}/");
+    let old: Vec<String> = rolls.with_prefix(&prefix).rev().skip(ROLL_HISTORY_LENGTH).map(|(k, _)| k.clone()).collect();
+    for key in old {
+        rolls.remove(&key);
+    }
+    record
+}
+
+/// The player's kept rolls, newest first, with the seeds that have been revealed.
+pub fn rolls(store: &Store, player_id: u64) -> Vec<RollRecord> {
+    let prefix = format!("{player_id}/");
+    let revealed = store.table("revealed");
+    store
+        .table("rolls")
+        .with_prefix(&prefix)
+        .rev()
+        .filter_map(|(key, r)| {
+            let commitment = r.get("commitment")?;
+            let definition = r.get("definition").filter(|d| !d.is_empty());
+            Some(RollRecord {
+                nonce: key[prefix.len()..].parse().ok()?,
+                kind: r.get("case").filter(|c| !c.is_empty()).map_or(RollKind::Drop, |c| RollKind::Case(c.clone())),
+                commitment: from_hex(commitment)?,
+                client_seed: r.get("client_seed")?.clone(),
+                outcome: definition.map(|definition| Outcome {
+                    definition: definition.clone(),
+                    wear: column(r, "wear"),
+                    pattern: column(r, "pattern"),
+                }),
+                seed: revealed.get(commitment).and_then(|r| from_hex(r.get("seed")?)),
+            })
+        })
+        .collect()
+}
+
+/// Uses up a case the player owns and gives them what it rolled.
+pub fn open_case(
+    store: &mut Store,
+    player_id: u64,
+    item_id: u64,
+    now: u64,
+) -> Result<(InventoryItem, RollRecord), String> {
+    let case = owned_item(store, player_id, item_id).ok_or("you don't have that case")?;
+    if !matches!(case.def().map(|d| d.kind), Some(ItemKind::Case { .. })) {
+        return Err("that isn't a case".into());
+    }
+    if is_listed(store, item_id) {
+        return Err("take it off the market first".into());
+    }
+    let record = roll(store, player_id, RollKind::Case(case.definition));
+    let outcome = record.outcome.clone().ok_or("the case has nothing in it")?;
+    remove_item(store, item_id);
+    Ok((grant_item(store, player_id, &outcome.definition, outcome.wear, outcome.pattern, now), record))
+}
+
+/// The week drops are counted in; weeks start on Monday at midnight UTC.
+fn drop_week(now: u64) -> u64 {
+    // 1 januari 1970 var en torsdag
+    (now + 3 * 86400) / (7 * 86400)
+}
+
+/// Rolls a drop for everyone who played a match, unless they've had this week's share.
+pub fn roll_drops(store: &mut Store, teams: &[Vec<u64>; 2], now: u64) -> Vec<(u64, InventoryItem)> {
+    let week = drop_week(now);
+    let mut dropped = Vec::new();
+    for &player_id in teams.iter().flatten() {
+        let prefix = format!("{player_id}/");
+        let key = format!("{player_id}/{week}");
+        let drops = store.table_mut("drops");
+        let old: Vec<String> = drops.with_prefix(&prefix).filter(|(k, _)| **k != key).map(|(k, _)| k.clone()).collect();
+        for old in old {
+            drops.remove(&old);
+        }
+        let count: u32 = drops.get(&key).map_or(0, |r| column(r, "count"));
+        if count >= MAX_DROPS_PER_WEEK {
+            continue;
+        }
+        let Some(outcome) = roll(store, player_id, RollKind::Drop).outcome else {
+            continue;
+        };
+        store.table_mut("drops").insert(key, row([("count", (count + 1).to_string())]));
+        let item = grant_item(store, player_id, &outcome.definition, outcome.wear, outcome.pattern, now);
+        dropped.push((player_id, item));
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temporary_store;
+
+    const DAY: u64 = 86400;
+    /// Måndag 1 januari 2024, 00:00 UTC
+    const MONDAY: u64 = 1_704_067_200;
+
+    #[test]
+    fn drop_weeks_start_on_monday_at_midnight() {
+        assert_eq!(drop_week(MONDAY - 1) + 1, drop_week(MONDAY));
+        assert_eq!(drop_week(MONDAY), drop_week(MONDAY + 7 * DAY - 1));
+        assert_eq!(drop_week(MONDAY + 7 * DAY), drop_week(MONDAY) + 1);
+        // 5 januari 1970 var den första måndagen
+        assert_eq!(drop_week(4 * DAY - 1), 0);
+        assert_eq!(drop_week(4 * DAY), 1);
+    }
+
+    #[test]
+    fn drops_stop_at_the_weekly_cap_until_monday() {
+        let mut store = temporary_store("drop-cap");
+        let teams = [vec![1], vec![2]];
+        let dropped = |store: &mut Store, from: u64| -> usize {
+            (0..100).map(|i| roll_drops(store, &teams, from + i * 30).len()).sum()
+        };
+        // söndag kväll, sedan måndag
+        assert_eq!(dropped(&mut store, MONDAY - 2 * 60 * 60), 2 * MAX_DROPS_PER_WEEK as usize);
+        assert_eq!(dropped(&mut store, MONDAY - 60 * 60), 0);
+        assert_eq!(dropped(&mut store, MONDAY), 2 * MAX_DROPS_PER_WEEK as usize);
+        assert_eq!(inventory(&store, 1).len(), 2 * MAX_DROPS_PER_WEEK as usize);
+    }
+
+    #[test]
+    fn case_rolls_check_out_once_the_seed_is_revealed() {
+        let mut store = temporary_store("case-rolls");
+        let case = grant_item(&mut store, 1, "case_weapon_1", 0.0, 0, 0);
+        let (item, record) = open_case(&mut store, 1, case.item_id, 0).unwrap();
+        assert_eq!(record.outcome.as_ref().map(|o| &o.definition), Some(&item.definition));
+        assert!(owned_item(&store, 1, case.item_id).is_none());
+        assert_eq!(rolls(&store, 1)[0].verify(), None);
+
+        set_client_seed(&mut store, 1, "my-seed").unwrap();
+        let kept = &rolls(&store, 1)[0];
+        assert_eq!(kept.verify(), Some(true));
+        assert_eq!(kept.outcome, record.outcome);
+        assert_eq!(seeds(&mut store, 1).nonce, 1);
+    }
+}
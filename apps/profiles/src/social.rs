@@ -0,0 +1,259 @@
+//! Who is online and the parties they're in.
+//!
+//! None of this is saved: after a restart clients show up again with their next presence
+//! heartbeat, and parties have to be put together again. Every party's lobby lives here and
+//! is changed with `Lobby::apply`, the same checks a player hosting their own lobby runs.
+//! Lobby chat is checked with the same `ChatFilter` a player hosting their own chat uses.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use net::profile::{Presence, ProfilePacket, INVITE_TIMEOUT, PRESENCE_TIMEOUT};
+use shared::chat::{ChatChannel, ChatFilter, ChatLimits, ChatMessage};
+use shared::lobby::{Lobby, LobbyError, LobbyPermissions, LobbyRequest, MAX_LOBBY_MEMBERS};
+
+/// Packets to send and who to send them to.
+pub type Outbox = Vec<(SocketAddr, ProfilePacket)>;
+
+/// Samma som standardvärdena för sv_chat_*
+const CHAT_LIMITS: ChatLimits = ChatLimits { max_length: 127, flood_limit: 4, flood_window: 5.0 };
+
+struct Online {
+    /// Där senaste hjärtslaget kom ifrån, allt vi skickar till spelaren går dit
+    addr: SocketAddr,
+    presence: Presence,
+    seen: Instant,
+}
+
+struct Party {
+    lobby: Lobby,
+    /// Inbjudna och när de bjöds in
+    invited: HashMap<u64, Instant>,
+}
+
+#[derive(Default)]
+pub struct Social {
+    online: HashMap<u64, Online>,
+    parties: HashMap<u64, Party>,
+    /// Vilket party varje spelare är med i
+    party_of: HashMap<u64, u64>,
+    next_party: u64,
+    chat: ChatFilter,
+}
+
+impl Social {
+    /// A presence heartbeat from `addr`.
+    pub fn seen(&mut self, player_id: u64, addr: SocketAddr, presence: Presence, now: Instant) {
+        self.online.insert(player_id, Online { addr, presence, seen: now });
+    }
+
+    pub fn presence(&self, player_id: u64) -> Presence {
+        self.online.get(&player_id).map_or(Presence::Offline, |o| o.presence)
+    }
+
+    pub fn send(&self, out: &mut Outbox, player_id: u64, packet: ProfilePacket) {
+        if let Some(online) = self.online.get(&player_id) {
+            out.push((online.addr, packet));
+        }
+    }
+
+    /// Everyone in the player's party, or just the player if they aren't in one.
+    pub fn party_members(&self, player_id: u64) -> Vec<u64> {
+        match self.party_of.get(&player_id) {
+            Some(party_id) => self.parties[party_id].lobby.members.iter().map(|m| m.id).collect(),
+            None => vec![player_id],
+        }
+    }
+
+    /// Invites a friend to the player's party, starting one with the settings of `lobby` if
+    /// the player isn't in one yet.
+    pub fn invite(
+        &mut self,
+        player_id: u64,
+        name: &str,
+        friend_id: u64,
+        lobby: &Lobby,
+        now: Instant,
+    ) -> Result<Outbox, String> {
+        if !self.online.contains_key(&player_id) {
+            return Err("the profile service hasn't heard from you yet".into());
+        }
+        if !self.online.contains_key(&friend_id) {
+            return Err("they're offline".into());
+        }
+        let mut out = Vec::new();
+        let party_id = match self.party_of.get(&player_id) {
+            Some(&party_id) => party_id,
+            None => {
+                self.next_party += 1;
+                let mut party = Lobby::solo(player_id, name);
+                party.map = lobby.map;
+                party.mode = lobby.mode;
+                party.permissions = lobby.permissions;
+                self.parties.insert(self.next_party, Party { lobby: party, invited: HashMap::new() });
+                self.party_of.insert(player_id, self.next_party);
+                self.settle(self.next_party, &mut out);
+                self.next_party
+            }
+        };
+        if self.party_of.get(&friend_id) == Some(&party_id) {
+            return Err("they're already in your party".into());
+        }
+        let party = self.parties.get_mut(&party_id).expect("party_of points at a party");
+        if party.lobby.members.len() >= MAX_LOBBY_MEMBERS {
+            return Err(LobbyError::Full.to_string());
+        }
+        party.invited.insert(friend_id, now);
+        self.send(&mut out, friend_id, ProfilePacket::PartyInvite { from: player_id, name: name.to_string() });
+        Ok(out)
+    }
+
+    /// Moves the player into the party `friend_id` is in. `friends` is whether the two are
+    /// friends, which is enough for parties that let friends join.
+    pub fn join(
+        &mut self,
+        player_id: u64,
+        name: &str,
+        friend_id: u64,
+        friends: bool,
+        now: Instant,
+    ) -> Result<Outbox, String> {
+        if !self.online.contains_key(&player_id) {
+            return Err("the profile service hasn't heard from you yet".into());
+        }
+        let party_id = *self.party_of.get(&friend_id).ok_or("they aren't in a party")?;
+        if self.party_of.get(&player_id) == Some(&party_id) {
+            return Ok(Vec::new());
+        }
+        let party = &self.parties[&party_id];
+        let invited = party.invited.get(&player_id).is_some_and(|at| now.duration_since(*at) < INVITE_TIMEOUT);
+        let allowed = invited
+            || match party.lobby.permissions {
+                LobbyPermissions::InviteOnly => false,
+                LobbyPermissions::FriendsCanJoin => friends,
+                LobbyPermissions::Public => true,
+            };
+        if !allowed {
+            return Err("you need an invite to join that party".into());
+        }
+        if party.lobby.members.len() >= MAX_LOBBY_MEMBERS {
+            return Err(LobbyError::Full.to_string());
+        }
+
+        let mut out = self.leave(player_id);
+        let party = self.parties.get_mut(&party_id).expect("party_of points at a party");
+        party.invited.remove(&player_id);
+        party.lobby.apply(player_id, &LobbyRequest::Join(name.to_string())).map_err(|e| e.to_string())?;
+        self.party_of.insert(player_id, party_id);
+        self.settle(party_id, &mut out);
+        Ok(out)
+    }
+
+    pub fn decline(&mut self, player_id: u64, friend_id: u64) {
+        if let Some(party) = self.party_of.get(&friend_id).and_then(|id| self.parties.get_mut(id)) {
+            party.invited.remove(&player_id);
+        }
+    }
+
+    /// A lobby request from a party member.
+    pub fn request(&mut self, player_id: u64, request: &LobbyRequest) -> Result<Outbox, String> {
+        let party_id = *self.party_of.get(&player_id).ok_or("you aren't in a party")?;
+        let party = self.parties.get_mut(&party_id).expect("party_of points at a party");
+        party.lobby.apply(player_id, request).map_err(|e| e.to_string())?;
+        let mut out = Vec::new();
+        self.settle(party_id, &mut out);
+        Ok(out)
+    }
+
+    /// Lobby chat from a party member, sent on to the whole party. `now` is in seconds.
+    pub fn chat(&mut self, player_id: u64, text: &str, now: f64) -> Result<Outbox, String> {
+        let party_id = *self.party_of.get(&player_id).ok_or("you aren't in a party")?;
+        let lobby = &self.parties[&party_id].lobby;
+        let text = self.chat.check(player_id, text, now, &CHAT_LIMITS).map_err(|e| e.to_string())?;
+        let message = ChatMessage {
+            channel: ChatChannel::Lobby,
+            sender: lobby.member(player_id).map(|m| m.name.clone()),
+            team: None,
+            text,
+        };
+        let mut out = Vec::new();
+        for member in &lobby.members {
+            self.send(&mut out, member.id, ProfilePacket::Chat { message: message.clone() });
+        }
+        Ok(out)
+    }
+
+    /// Takes the player out of their party, if they're in one.
+    pub fn leave(&mut self, player_id: u64) -> Outbox {
+        let mut out = Vec::new();
+        if let Some(&party_id) = self.party_of.get(&player_id) {
+            let party = self.parties.get_mut(&party_id).expect("party_of points at a party");
+            // kan inte misslyckas, spelaren är med
+            let _ = party.lobby.apply(player_id, &LobbyRequest::Leave);
+            self.settle(party_id, &mut out);
+        }
+        out
+    }
+
+    /// Forgets players that stopped sending heartbeats and invites nobody answered.
+    pub fn expire(&mut self, now: Instant) -> Outbox {
+        let gone: Vec<u64> = self
+            .online
+            .iter()
+            .filter(|(_, o)| now.duration_since(o.seen) >= PRESENCE_TIMEOUT)
+            .map(|(&id, _)| id)
+            .collect();
+        let mut out = Vec::new();
+        for player_id in gone {
+            self.online.remove(&player_id);
+            self.chat.forget(player_id);
+            out.extend(self.leave(player_id));
+        }
+        for party in self.parties.values_mut() {
+            party.invited.retain(|_, at| now.duration_since(*at) < INVITE_TIMEOUT);
+        }
+        // ett party som ingen tackade ja till löses upp
+        let lonely: Vec<u64> = self
+            .parties
+            .values()
+            .filter(|p| p.lobby.members.len() == 1 && p.invited.is_empty())
+            .map(|p| p.lobby.leader)
+            .collect();
+        for player_id in lonely {
+            out.extend(self.leave(player_id));
+        }
+        out
+    }
+
+    /// Sends the party to its members after a change. Members that are no longer in the
+    /// lobby are told it's over for them, and a party someone left with one player in it
+    /// breaks up.
+    fn settle(&mut self, party_id: u64, out: &mut Outbox) {
+        let Some(party) = self.parties.get_mut(&party_id) else {
+            return;
+        };
+        let lobby = party.lobby.clone();
+        // starten skickas en gång, sedan är lobbyn tillbaka i vänteläge
+        party.lobby.starting = false;
+
+        let removed: Vec<u64> = self
+            .party_of
+            .iter()
+            .filter(|(id, party)| **party == party_id && lobby.member(**id).is_none())
+            .map(|(&id, _)| id)
+            .collect();
+        let ended = lobby.members.len() <= 1 && !removed.is_empty();
+        if ended {
+            self.parties.remove(&party_id);
+        }
+        for id in removed.into_iter().chain(lobby.members.iter().filter(|_| ended).map(|m| m.id)) {
+            self.party_of.remove(&id);
+            self.send(out, id, ProfilePacket::PartyEnded);
+        }
+        if !ended {
+            for member in &lobby.members {
+                self.send(out, member.id, ProfilePacket::Party { lobby: lobby.clone() });
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+//! File-backed tables the profile service keeps its data in.
+//!
+//! Every table is a file `<table>.cfg` in the data directory with one row per line: the row
+//! key followed by `column=value` pairs, written in the cfg grammar. `schema.cfg` holds the
+//! schema version and the table names. Opening a directory runs every migration newer than
+//! its version in order and saves the result, and a directory from a newer version than we
+//! know is refused rather than half understood.
+//!
+//! Everything changed between two flushes is saved together: the new files are written next
+//! to the old ones and `journal.cfg` lists them before any is renamed into place. If we die
+//! halfway, the next `open` finishes the renames, so a trade can't be saved by half. A
+//! flush that fails keeps everything it didn't get to save for the next one.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use shared::cfg;
+
+pub type Row = BTreeMap<String, String>;
+
+/// Reads a number column, 0 (or the type's default) when it's missing or bad.
+pub fn column<T: FromStr + Default>(row: &Row, name: &str) -> T {
+    row.get(name).and_then(|v| v.parse().ok()).unwrap_or_default()
+}
+
+#[derive(Debug, Default)]
+pub struct Table {
+    rows: BTreeMap<String, Row>,
+}
+
+impl Table {
+    pub fn get(&self, key: &str) -> Option<&Row> {
+        self.rows.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, row: Row) {
+        self.rows.insert(key, row);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Row> {
+        self.rows.remove(key)
+    }
+
+    pub fn rows(&self) -> impl DoubleEndedIterator<Item = (&String, &Row)> {
+        self.rows.iter()
+    }
+
+    /// Rows whose key starts with `prefix`, in key order. `prefix` must not be empty.
+    pub fn with_prefix(&self, prefix: &str) -> impl DoubleEndedIterator<Item = (&String, &Row)> {
+        // första nyckeln efter alla med prefixet: sista tecknet ett steg upp
+        let mut end = prefix.to_string();
+        if let Some(last) = end.pop() {
+            end.push(char::from_u32(last as u32 + 1).unwrap_or(char::MAX));
+        }
+        self.rows.range(prefix.to_string()..end)
+    }
+
+    fn parse(text: &str) -> Result<Table, String> {
+        let (statements, errors) = cfg::parse(text);
+        if let Some(err) = errors.first() {
+            return Err(err.to_string());
+        }
+        let mut table = Table::default();
+        for statement in statements {
+            let Some((key, columns)) = statement.args.split_first() else {
+                continue;
+            };
+            let mut row = Row::new();
+            for pair in columns {
+                let Some((name, value)) = pair.split_once('=') else {
+                    return Err(format!("line {}: {pair} is not column=value", statement.line));
+                };
+                row.insert(name.to_string(), value.to_string());
+            }
+            table.rows.insert(key.clone(), row);
+        }
+        Ok(table)
+    }
+
+    fn to_cfg(&self) -> String {
+        let mut text = String::new();
+        for (key, row) in &self.rows {
+            let mut args = vec![key.clone()];
+            args.extend(row.iter().map(|(name, value)| format!("{name}={value}")));
+            text.push_str(&cfg::join(&args));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// One step of the schema. Migrations run in `version` order and only once per directory.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut Store),
+}
+
+pub struct Store {
+    dir: PathBuf,
+    version: u32,
+    tables: BTreeMap<String, Table>,
+    /// Tabeller som ändrats sedan senaste `flush`
+    dirty: BTreeSet<String>,
+}
+
+impl Store {
+    /// Opens the data directory, creating it if needed, and migrates it to the newest schema.
+    pub fn open(dir: &Path, migrations: &[Migration]) -> Result<Store, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("can't create {}: {e}", dir.display()))?;
+        let mut store = Store { dir: dir.to_path_buf(), version: 0, tables: BTreeMap::new(), dirty: BTreeSet::new() };
+        store.finish_journal().map_err(|e| format!("can't finish the last save: {e}"))?;
+
+        let schema = match fs::read_to_string(store.schema_path()) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("can't read schema: {err}")),
+        };
+        let (statements, errors) = cfg::parse(&schema);
+        if let Some(err) = errors.first() {
+            return Err(format!("schema.cfg: {err}"));
+        }
+        for statement in statements {
+            match statement.args.as_slice() {
+                [kind, version] if kind == "version" => {
+                    store.version = version.parse().map_err(|_| format!("schema.cfg: bad version {version}"))?;
+                }
+                [kind, name] if kind == "table" => {
+                    let path = store.table_path(name);
+                    let text = fs::read_to_string(&path).or_else(|e| match e.kind() {
+                        ErrorKind::NotFound => Ok(String::new()),
+                        _ => Err(format!("can't read {}: {e}", path.display())),
+                    })?;
+                    let table = Table::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+                    store.tables.insert(name.clone(), table);
+                }
+                _ => return Err(format!("schema.cfg line {}: unknown entry", statement.line)),
+            }
+        }
+
+        let latest = migrations.iter().map(|m| m.version).max().unwrap_or(0);
+        if store.version > latest {
+            return Err(format!(
+                "data is at schema version {}, this build only knows up to {latest}",
+                store.version
+            ));
+        }
+        let mut pending: Vec<&Migration> = migrations.iter().filter(|m| m.version > store.version).collect();
+        pending.sort_by_key(|m| m.version);
+        for migration in pending {
+            println!("migrating to schema {}: {}", migration.version, migration.description);
+            (migration.apply)(&mut store);
+            store.version = migration.version;
+            // hela schemat skrivs efter varje steg så att ett avbrott inte kör om ett steg
+            store.dirty.extend(store.tables.keys().cloned());
+            store.flush().map_err(|e| format!("can't save migration {}: {e}", migration.version))?;
+        }
+        Ok(store)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Adds an empty table. Only for migrations.
+    pub fn create_table(&mut self, name: &str) {
+        self.tables.entry(name.to_string()).or_default();
+        self.dirty.insert(name.to_string());
+    }
+
+    /// Gives every row of a table a new column. Only for migrations.
+    pub fn add_column(&mut self, table: &str, column: &str, default: &str) {
+        for row in self.table_mut(table).rows.values_mut() {
+            row.entry(column.to_string()).or_insert_with(|| default.to_string());
+        }
+    }
+
+    /// Panics on a table no migration has created; that's a bug, not bad data.
+    pub fn table(&self, name: &str) -> &Table {
+        self.tables.get(name).unwrap_or_else(|| panic!("no table {name}"))
+    }
+
+    pub fn table_mut(&mut self, name: &str) -> &mut Table {
+        self.dirty.insert(name.to_string());
+        self.tables.get_mut(name).unwrap_or_else(|| panic!("no table {name}"))
+    }
+
+    /// Whether something changed that hasn't been saved, e.g. because the last flush failed.
+    pub fn unsaved(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Writes the tables changed since the last flush, all or none of them. On an error the
+    /// changes are still unsaved and the next flush tries them all again.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        // en tidigare sparning som inte flyttats klart måste bli klar innan filerna skrivs över
+        self.finish_journal()?;
+        let mut files = Vec::new();
+        for name in &self.dirty {
+            fs::write(tmp_path(&self.table_path(name)), self.tables[name].to_cfg())?;
+            files.push(name.clone());
+        }
+        let mut schema = cfg::join(&["version".to_string(), self.version.to_string()]) + "\n";
+        for name in self.tables.keys() {
+            schema.push_str(&cfg::join(&["table".to_string(), name.clone()]));
+            schema.push('\n');
+        }
+        fs::write(tmp_path(&self.schema_path()), schema)?;
+        files.push("schema".to_string());
+
+        // från och med journalen räknas allt som sparat
+        let journal: String =
+            files.into_iter().map(|name| cfg::join(&["rename".to_string(), name]) + "\n").collect();
+        write_atomic(&self.journal_path(), &journal)?;
+        self.dirty.clear();
+        self.finish_journal()
+    }
+
+    /// Renames what the journal lists into place and removes it.
+    fn finish_journal(&self) -> io::Result<()> {
+        let text = match fs::read_to_string(self.journal_path()) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for statement in cfg::parse(&text).0 {
+            if let [kind, name] = statement.args.as_slice() {
+                let path = self.table_path(name);
+                // redan flyttad om vi dog efter den här men före nästa
+                if kind == "rename" && tmp_path(&path).exists() {
+                    fs::rename(tmp_path(&path), path)?;
+                }
+            }
+        }
+        fs::remove_file(self.journal_path())
+    }
+
+    fn schema_path(&self) -> PathBuf {
+        self.table_path("schema")
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.dir.join("journal.cfg")
+    }
+
+    fn table_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.cfg"))
+    }
+}
+
+/// A store in a fresh temporary directory, migrated to the newest schema.
+#[cfg(test)]
+pub fn temporary_store(name: &str) -> Store {
+    let dir = std::env::temp_dir().join(format!("profiles-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    Store::open(&dir, crate::profiles::MIGRATIONS).unwrap()
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    path.with_extension("cfg.tmp")
+}
+
+fn write_atomic(path: &Path, text: &str) -> io::Result<()> {
+    fs::write(tmp_path(path), text)?;
+    fs::rename(tmp_path(path), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(pairs: &[(&str, &str)]) -> Row {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    /// En tom katalog under temp
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("profiles-store-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    const CREATE_T: Migration = Migration {
+        version: 1,
+        description: "create t",
+        apply: |store| {
+            store.create_table("t");
+            // skulle skriva över b om steget kördes igen
+            store.table_mut("t").insert("r".into(), row(&[("a", "1")]));
+        },
+    };
+    const ADD_B: Migration = Migration {
+        version: 2,
+        description: "add b",
+        apply: |store| store.add_column("t", "b", "2"),
+    };
+    const ADD_C: Migration = Migration {
+        version: 3,
+        description: "add c",
+        apply: |store| store.add_column("t", "c", "3"),
+    };
+    // medvetet i fel ordning; steg 2 kan bara köras efter steg 1
+    const MIGRATIONS: &[Migration] = &[ADD_B, CREATE_T];
+
+    #[test]
+    fn migrations_run_in_version_order_and_only_once() {
+        let dir = test_dir("migrate");
+        let store = Store::open(&dir, MIGRATIONS).unwrap();
+        assert_eq!(store.version(), 2);
+        assert_eq!(store.table("t").get("r"), Some(&row(&[("a", "1"), ("b", "2")])));
+
+        let store = Store::open(&dir, &[ADD_B, CREATE_T, ADD_C]).unwrap();
+        assert_eq!(store.version(), 3);
+        assert_eq!(store.table("t").get("r"), Some(&row(&[("a", "1"), ("b", "2"), ("c", "3")])));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn data_from_a_newer_schema_is_refused() {
+        let dir = test_dir("newer");
+        Store::open(&dir, MIGRATIONS).unwrap();
+        let err = Store::open(&dir, &[CREATE_T]).err().unwrap();
+        assert!(err.contains("schema version 2"), "{err}");
+        // ingenting skrevs om
+        let store = Store::open(&dir, MIGRATIONS).unwrap();
+        assert_eq!(store.table("t").get("r"), Some(&row(&[("a", "1"), ("b", "2")])));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_save_cut_short_is_finished_by_the_next_open_but_only_after_the_journal() {
+        let dir = test_dir("journal");
+        let store = Store::open(&dir, MIGRATIONS).unwrap();
+        let mut table = Table::default();
+        table.insert("r".into(), row(&[("a", "9")]));
+        let path = store.table_path("t");
+
+        // dog innan journalen skrevs: halvskrivna filer räknas inte
+        fs::write(tmp_path(&path), table.to_cfg()).unwrap();
+        let store = Store::open(&dir, MIGRATIONS).unwrap();
+        assert_eq!(store.table("t").get("r"), Some(&row(&[("a", "1"), ("b", "2")])));
+
+        // dog efter journalen men före flytten
+        fs::write(tmp_path(&path), table.to_cfg()).unwrap();
+        fs::write(store.journal_path(), "rename t\n").unwrap();
+        let store = Store::open(&dir, MIGRATIONS).unwrap();
+        assert_eq!(store.table("t").get("r"), Some(&row(&[("a", "9")])));
+        assert!(!store.journal_path().exists());
+        assert!(!tmp_path(&path).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_flush_saves_everything_with_the_next_one() {
+        let mut store = temporary_store("flush");
+        let dir = store.dir.clone();
+        let tables: Vec<String> = store.tables.keys().take(2).cloned().collect();
+        let [first, second] = tables.as_slice() else {
+            panic!("the schema has fewer than two tables");
+        };
+        store.table_mut(first).insert("a".into(), Row::from([("n".to_string(), "1".to_string())]));
+        store.table_mut(second).insert("b".into(), Row::from([("n".to_string(), "2".to_string())]));
+        // en katalog där den andra tabellens fil ska skrivas gör att skrivningen misslyckas
+        let blocker = tmp_path(&store.table_path(second));
+        fs::create_dir(&blocker).unwrap();
+        assert!(store.flush().is_err());
+        assert!(store.unsaved());
+        let reopened = Store::open(&dir, crate::profiles::MIGRATIONS).unwrap();
+        assert!(reopened.table(first).get("a").is_none());
+
+        fs::remove_dir(&blocker).unwrap();
+        store.flush().unwrap();
+        assert!(!store.unsaved());
+        let reopened = Store::open(&dir, crate::profiles::MIGRATIONS).unwrap();
+        assert_eq!(column::<u32>(reopened.table(first).get("a").unwrap(), "n"), 1);
+        assert_eq!(column::<u32>(reopened.table(second).get("b").unwrap(), "n"), 2);
+    }
+}
@@ -1,6 +1,15 @@
 use bevy::prelude::*;
 use core::CorePlugin;
+use net::chat::ServerChatPlugin;
+use net::connection::GameServerPlugin;
+use net::cvars::ServerCvarsPlugin;
+use net::game_state::ServerGameStatePlugin;
+use net::query::QueryServerPlugin;
+use net::skins::ServerSkinsPlugin;
+use net::stats::ServerStatsPlugin;
 use physics::PhysicsPlugin;
+use shared::chat::ChatPlugin;
+use shared::game_state::MatchStatsPlugin;
 
 fn main() {
     App::new()
@@ -8,6 +17,15 @@ fn main() {
         .add_plugins((
             CorePlugin,
             PhysicsPlugin,
+            MatchStatsPlugin,
+            ChatPlugin,
+            QueryServerPlugin,
+            GameServerPlugin,
+            ServerSkinsPlugin,
+            ServerChatPlugin,
+            ServerCvarsPlugin,
+            ServerStatsPlugin,
+            ServerGameStatePlugin,
         ))
         .run();
 }
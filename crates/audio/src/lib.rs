@@ -1,9 +1,70 @@
+use bevy::audio::Volume;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_asset_loader::prelude::*;
+use shared::config::GameConfig;
+use shared::cvars::{CvarChanged, CvarDef, CvarFlags, RegisterCvarExt};
+use shared::AppState;
 
 pub struct AudioPlugin;
 
 impl Plugin for AudioPlugin {
-    fn build(&self, _app: &mut App) {
+    fn build(&self, app: &mut App) {
         // ljudhantering här
+        app.register_cvar(
+            CvarDef::float("volume", 0.8)
+                .range(0.0, 1.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Master volume"),
+        )
+        .register_cvar(
+            CvarDef::float("snd_musicvolume", 0.5)
+                .range(0.0, 1.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Music volume, relative to the master volume"),
+        )
+        .add_systems(Startup, apply_volume_on_startup)
+        .add_systems(Update, apply_volume_changes)
+        .configure_loading_state(
+            LoadingStateConfig::new(AppState::Loading).load_collection::<SoundAssets>(),
+        );
+    }
+}
+
+/// Every sound under `assets/sounds`, keyed by its path (e.g. `sounds/weapons/ak_fire.ogg`).
+#[derive(AssetCollection, Resource)]
+pub struct SoundAssets {
+    #[asset(path = "sounds", collection(typed, mapped))]
+    pub sounds: HashMap<String, Handle<AudioSource>>,
+}
+
+/// Marks music so `snd_musicvolume` applies to it.
+#[derive(Component)]
+pub struct Music;
+
+fn apply_volume_on_startup(config: Res<GameConfig>, mut global: ResMut<GlobalVolume>) {
+    global.volume = Volume::new(config.get_float("volume").unwrap_or(1.0));
+}
+
+/// `GlobalVolume` only affects sounds started after it changes, so music that is
+/// already playing is adjusted through its sink.
+fn apply_volume_changes(
+    mut changes: EventReader<CvarChanged>,
+    config: Res<GameConfig>,
+    mut global: ResMut<GlobalVolume>,
+    music: Query<&AudioSink, With<Music>>,
+) {
+    let mut changed = false;
+    for change in changes.read() {
+        changed |= change.name == "volume" || change.name == "snd_musicvolume";
+    }
+    if !changed {
+        return;
+    }
+    let master = config.get_float("volume").unwrap_or(1.0);
+    global.volume = Volume::new(master);
+    let music_volume = master * config.get_float("snd_musicvolume").unwrap_or(1.0);
+    for sink in &music {
+        sink.set_volume(music_volume);
     }
 }
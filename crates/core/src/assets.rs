@@ -0,0 +1,9 @@
+use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
+
+/// First-person weapon models, loaded during `AppState::Loading`.
+#[derive(AssetCollection, Resource)]
+pub struct WeaponAssets {
+    #[asset(path = "models/ak.glb#Scene0")]
+    pub ak: Handle<Scene>,
+}
@@ -1,12 +1,63 @@
 use bevy::prelude::*;
+use shared::actions::ActionsPlugin;
+use shared::console::RegisterCommandExt;
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
 
+pub mod assets;
 pub mod player;
+pub mod skins;
 
 pub struct CorePlugin;
 
 impl Plugin for CorePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, hello_system);
+        if !app.is_plugin_added::<ActionsPlugin>() {
+            app.add_plugins(ActionsPlugin);
+        }
+        if !app.is_plugin_added::<skins::WeaponSkinPlugin>() {
+            app.add_plugins(skins::WeaponSkinPlugin);
+        }
+        app.register_cvar(
+            CvarDef::float("sensitivity", 2.0)
+                .range(0.1, 10.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Mouse sensitivity"),
+        )
+        .register_cvar(
+            CvarDef::float("m_yaw", 0.022)
+                .range(0.001, 1.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Degrees turned sideways per mouse count at sensitivity 1"),
+        )
+        .register_cvar(
+            CvarDef::float("m_pitch", 0.022)
+                .range(0.001, 1.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Degrees turned up or down per mouse count at sensitivity 1"),
+        )
+        .register_cvar(
+            CvarDef::bool("m_invert", false)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Invert vertical mouse look"),
+        )
+        .register_cvar(
+            CvarDef::float("fov_desired", 103.0)
+                .range(60.0, 130.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Horizontal field of view in degrees"),
+        )
+        .register_cvar(
+            CvarDef::float("zoom_sensitivity_ratio", 1.0)
+                .range(0.1, 4.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Sensitivity multiplier while zoomed"),
+        )
+        .register_console_command(
+            "sensitivity_import",
+            "Set sensitivity from another game's value: sensitivity_import <game> <value>",
+            player::camera_controller::cmd_sensitivity_import,
+        )
+        .add_systems(Update, hello_system);
     }
 }
 
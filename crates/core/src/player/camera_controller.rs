@@ -1,20 +1,105 @@
-use bevy::{input::mouse::MouseMotion, prelude::*};
+//! Mouse look for the player camera.
+//!
+//! Sensitivity works like in Source games: a mouse count turns the view by
+//! `sensitivity * m_yaw` degrees, so a value from CS can be used as is. `MouseMotion` is
+//! raw device motion, unaffected by OS pointer acceleration.
+use bevy::{
+    input::mouse::MouseMotion,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+use shared::actions::{Action, ActionState};
+use shared::config::GameConfig;
+use shared::cvars::CvarChanged;
+use shared::game_state::LocalPlayerState;
+use shared::items::Weapon;
+
+use crate::skins::WeaponModel;
+
+/// Hur långt man kan titta upp och ner, i grader
+pub const PITCH_LIMIT: f32 = 88.0;
 
 #[derive(Component)]
 pub struct CameraController {
     pub rotation: Vec2,
     pub rotation_lock: f32,
-    pub sensitivity: f32,
+    /// Degrees turned per mouse count, sideways and up/down.
+    pub sensitivity: Vec2,
+    /// Horizontal FOV in degrees when not zoomed.
+    pub fov: f32,
+    /// Horizontal FOV while aiming down a scope, see [`update_zoom`].
+    pub zoom_fov: Option<f32>,
+}
+
+impl CameraController {
+    pub fn new(config: &GameConfig) -> Self {
+        let mut controller = CameraController {
+            rotation: Vec2::ZERO,
+            rotation_lock: PITCH_LIMIT,
+            sensitivity: Vec2::ZERO,
+            fov: 90.0,
+            zoom_fov: None,
+        };
+        controller.apply_config(config);
+        controller
+    }
+
+    pub fn apply_config(&mut self, config: &GameConfig) {
+        let sensitivity = config.get_float("sensitivity").unwrap_or(2.0);
+        let invert = if config.get_bool("m_invert").unwrap_or(false) { -1.0 } else { 1.0 };
+        self.sensitivity = Vec2::new(
+            sensitivity * config.get_float("m_yaw").unwrap_or(0.022),
+            sensitivity * config.get_float("m_pitch").unwrap_or(0.022) * invert,
+        );
+        self.fov = config.get_float("fov_desired").unwrap_or(90.0);
+    }
+
+    /// Current horizontal FOV, zoomed or not.
+    pub fn current_fov(&self) -> f32 {
+        self.zoom_fov.unwrap_or(self.fov)
+    }
+
+    /// Sensitivity scale while zoomed, so the view turns as far per inch of mouse
+    /// movement relative to the screen as it does unzoomed, times `zoom_sensitivity_ratio`.
+    fn zoom_scale(&self, ratio: f32) -> f32 {
+        match self.zoom_fov {
+            Some(zoom) => ratio * half_tan(zoom) / half_tan(self.fov),
+            None => 1.0,
+        }
+    }
+}
+
+fn half_tan(fov_degrees: f32) -> f32 {
+    (fov_degrees.to_radians() / 2.0).tan()
+}
+
+/// Vertical FOV in radians for a horizontal FOV in degrees. The horizontal FOV is measured
+/// on a 4:3 area, so widescreen shows more at the sides instead of cutting the top.
+pub fn vertical_fov(horizontal_degrees: f32) -> f32 {
+    2.0 * (half_tan(horizontal_degrees) * 3.0 / 4.0).atan()
 }
 
 pub fn update_camera_controller(
     mut mouse_motion: EventReader<MouseMotion>,
+    config: Res<GameConfig>,
+    windows: Query<&Window, With<PrimaryWindow>>,
     mut camera_query: Query<(&mut CameraController, &mut Transform)>,
 ) {
+    // bara när musen är fångad, annars rör sig kameran när man klickar i konsolen
+    let grabbed = windows
+        .get_single()
+        .map(|w| w.cursor.grab_mode != CursorGrabMode::None)
+        .unwrap_or(false);
+    if !grabbed {
+        mouse_motion.clear();
+        return;
+    }
     if let Ok((mut camera_controller, mut transform)) = camera_query.get_single_mut() {
+        let zoom = camera_controller.zoom_scale(config.get_float("zoom_sensitivity_ratio").unwrap_or(1.0));
+        let sensitivity = camera_controller.sensitivity * zoom;
         for ev in mouse_motion.read() {
-            camera_controller.rotation.y -= ev.delta.x * camera_controller.sensitivity;
-            camera_controller.rotation.x -= ev.delta.y * camera_controller.sensitivity;
+            camera_controller.rotation.y -= ev.delta.x * sensitivity.x;
+            camera_controller.rotation.x -= ev.delta.y * sensitivity.y;
 
             camera_controller.rotation.x = f32::clamp(
                 camera_controller.rotation.x,
@@ -26,4 +111,121 @@ pub fn update_camera_controller(
         let x_quat = Quat::from_axis_angle(Vec3::X, camera_controller.rotation.x.to_radians());
         transform.rotation = y_quat * x_quat;
     }
-}
\ No newline at end of file
+}
+
+/// Zooms while the aim button is held with a scoped weapon. The weapon in our hands is the
+/// `WeaponModel` under the camera.
+pub fn update_zoom(
+    actions: Res<ActionState>,
+    local: Res<LocalPlayerState>,
+    mut camera_query: Query<(&mut CameraController, &Children)>,
+    models: Query<&WeaponModel>,
+) {
+    for (mut controller, children) in &mut camera_query {
+        let weapon = children.iter().find_map(|&child| models.get(child).ok()).map(|model| model.weapon);
+        let zoom = weapon.and_then(Weapon::zoom_fov).filter(|_| local.alive && actions.pressed(Action::Aim));
+        if controller.zoom_fov != zoom {
+            controller.zoom_fov = zoom;
+        }
+    }
+}
+
+/// Picks up changes to the look cvars and keeps the projection in line with the current FOV.
+pub fn apply_camera_settings(
+    mut changes: EventReader<CvarChanged>,
+    config: Res<GameConfig>,
+    mut camera_query: Query<(&mut CameraController, &mut Projection)>,
+) {
+    let changed = changes.read().any(|c| {
+        matches!(c.name.as_str(), "sensitivity" | "m_yaw" | "m_pitch" | "m_invert" | "fov_desired")
+    });
+    for (mut controller, mut projection) in &mut camera_query {
+        if changed {
+            controller.apply_config(&config);
+        }
+        let fov = vertical_fov(controller.current_fov());
+        if let Projection::Perspective(perspective) = projection.as_ref() {
+            if (perspective.fov - fov).abs() < f32::EPSILON {
+                continue;
+            }
+        }
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = fov;
+        }
+    }
+}
+
+/// Locks and hides the cursor while playing, and lets go of it while the console or
+/// another menu holds the input.
+pub fn update_cursor_grab(actions: Res<ActionState>, mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+    let grab = !actions.is_blocked() && window.focused;
+    if (window.cursor.grab_mode != CursorGrabMode::None) != grab {
+        set_cursor_grab(&mut window, grab);
+    }
+}
+
+pub fn release_cursor(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    if let Ok(mut window) = windows.get_single_mut() {
+        set_cursor_grab(&mut window, false);
+    }
+}
+
+fn set_cursor_grab(window: &mut Window, grab: bool) {
+    // Locked finns inte på alla plattformar, Confined räcker eftersom vi läser MouseMotion
+    window.cursor.grab_mode = if !grab {
+        CursorGrabMode::None
+    } else if cfg!(target_os = "macos") {
+        CursorGrabMode::Locked
+    } else {
+        CursorGrabMode::Confined
+    };
+    window.cursor.visible = !grab;
+}
+
+/// Mouse sensitivity units of other games, as degrees turned per count at sensitivity 1.
+pub const SENSITIVITY_UNITS: &[(&str, f32)] = &[
+    ("cs", 0.022),
+    ("source", 0.022),
+    ("quake", 0.022),
+    ("apex", 0.022),
+    ("overwatch", 0.0066),
+    ("valorant", 0.07),
+    ("r6", 0.00572958),
+];
+
+/// Degrees per count at sensitivity 1 for a game in `SENSITIVITY_UNITS`.
+pub fn sensitivity_unit(game: &str) -> Option<f32> {
+    SENSITIVITY_UNITS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(game))
+        .map(|(_, yaw)| *yaw)
+}
+
+/// Converts a sensitivity from a game with `from_yaw` degrees per count to ours, so the
+/// same mouse movement turns the view as far.
+pub fn convert_sensitivity(sensitivity: f32, from_yaw: f32, m_yaw: f32) -> f32 {
+    sensitivity * from_yaw / m_yaw
+}
+
+/// `sensitivity_import <game> <value>`
+pub fn cmd_sensitivity_import(world: &mut World, args: &[String]) -> Result<String, String> {
+    let games = || SENSITIVITY_UNITS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+    let [game, value] = args else {
+        return Err(format!("usage: sensitivity_import <game> <value>, games: {}", games()));
+    };
+    let from_yaw = sensitivity_unit(game).ok_or_else(|| format!("unknown game \"{game}\", games: {}", games()))?;
+    let value: f32 = value.parse().map_err(|_| format!("\"{value}\" isn't a number"))?;
+
+    let mut config = world.resource_mut::<GameConfig>();
+    let m_yaw = config.get_float("m_yaw").unwrap_or(0.022);
+    let sensitivity = convert_sensitivity(value, from_yaw, m_yaw);
+    config
+        .set("sensitivity", &format!("{sensitivity:.4}"))
+        .map_err(|err| err.to_string())?;
+    config.save();
+    let value = config.get("sensitivity").map(|v| v.to_string()).unwrap_or_default();
+    Ok(format!("sensitivity set to {value}"))
+}
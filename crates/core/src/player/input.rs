@@ -1,7 +1,17 @@
 use bevy::prelude::*;
 
+/// What the player wants to do this frame, built from `ActionState` so nothing here
+/// knows about keys.
 #[derive(Resource,Default)]
 pub struct PlayerInput{
     //x component is forward and y direction is right
     pub movement : Vec2,
-}
\ No newline at end of file
+    pub jump : bool,
+    pub crouch : bool,
+    pub walk : bool,
+    /// true bara den frame avtryckaren trycks ner
+    pub fire : bool,
+    pub aim : bool,
+    pub reload : bool,
+    pub use_item : bool,
+}
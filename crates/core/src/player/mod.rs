@@ -2,4 +2,4 @@ pub mod camera_controller;
 pub mod player;
 pub mod input;
 pub mod player_movement;
-pub mod player_shooting;
\ No newline at end of file
+pub mod player_shooting;
@@ -1,7 +1,15 @@
 use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
 use bevy_rapier3d::prelude::*;
+use shared::config::GameConfig;
+use shared::game_state::LocalPlayerState;
+use shared::items::Weapon;
+use shared::lobby::{local_player_id, Lobby};
+use shared::AppState;
 
 use super::{camera_controller, input::*, player_movement::*, player_shooting::{update_player, TracerSpawnSpot}};
+use crate::assets::WeaponAssets;
+use crate::skins::WeaponModel;
 use crate::game::{math::coordinates::blender_to_world, shooting};
 pub struct PlayerPlugin;
 
@@ -13,13 +21,20 @@ impl Plugin for PlayerPlugin {
                 Update,
                 (
                     update_movement_input,
-                    update_player, 
-                    camera_controller::update_camera_controller
+                    update_player.run_if(in_state(AppState::InGame)),
+                    camera_controller::update_camera_controller,
+                    (camera_controller::update_zoom, camera_controller::apply_camera_settings).chain(),
+                    camera_controller::update_cursor_grab.run_if(in_state(AppState::InGame)),
                 ),
             )
             //physics timestep
-            .add_systems(FixedUpdate, update_movement)
-            .add_systems(Startup, init_player);
+            .add_systems(FixedUpdate, update_movement.run_if(in_state(AppState::InGame)))
+            .add_systems(OnEnter(AppState::InGame), init_player)
+            .add_systems(OnExit(AppState::InGame), (camera_controller::release_cursor, despawn_player))
+            // saknas modellen hamnar vi i LoadingFailed i stället för i spelet utan vapen
+            .configure_loading_state(
+                LoadingStateConfig::new(AppState::Loading).load_collection::<WeaponAssets>(),
+            );
     }
 }
 
@@ -29,8 +44,15 @@ pub struct Player {
     pub gravity : f32,
     pub speed : f32,
 }
-fn init_player(mut commands: Commands,asset_server : Res<AssetServer>) {
-    let fov = 103.0_f32.to_radians();
+fn init_player(
+    mut commands: Commands,
+    weapons: Res<WeaponAssets>,
+    config: Res<GameConfig>,
+    lobby: Option<Res<Lobby>>,
+    local: Option<Res<LocalPlayerState>>,
+) {
+    let controller = camera_controller::CameraController::new(&config);
+    let fov = camera_controller::vertical_fov(controller.current_fov());
     let camera_entity = commands.spawn((
         Camera3dBundle {
             transform: Transform::IDENTITY,
@@ -40,20 +62,22 @@ fn init_player(mut commands: Commands,asset_server : Res<AssetServer>) {
             }),
             ..default()
         },
-        camera_controller::CameraController {
-            sensitivity: 0.035,
-            rotation: Vec2::ZERO,
-            rotation_lock: 88.0,
-        },
+        controller,
     )).id();
-    let gun_model = asset_server.load("models/ak.glb#Scene0");
-    let gun_entity = commands.spawn(
+    let gun_model = weapons.ak.clone();
+    let gun_entity = commands.spawn((
         SceneBundle{
             scene : gun_model,
             transform : Transform::IDENTITY,
             ..Default::default()
-        }
-    ).id();
+        },
+        // skinet följer vår loadout
+        WeaponModel {
+            owner: lobby.map_or_else(local_player_id, |l| l.local_id()),
+            team: local.and_then(|l| l.team),
+            weapon: Weapon::Ak47,
+        },
+    )).id();
     let spawn_spot = blender_to_world(Vec3::new(0.530462,2.10557,-0.466568));
     let tracer_spawn_entity = commands.spawn(
         (
@@ -84,4 +108,11 @@ fn init_player(mut commands: Commands,asset_server : Res<AssetServer>) {
     )).id();
     commands.entity(camera_entity).push_children(&[tracer_spawn_entity,gun_entity]);
     commands.entity(player_entity).add_child(camera_entity);
-}
\ No newline at end of file
+}
+
+/// Kameran, vapnet och tracer-punkten är barn till spelaren och följer med
+fn despawn_player(mut commands: Commands, players: Query<Entity, With<Player>>) {
+    for player in &players {
+        commands.entity(player).despawn_recursive();
+    }
+}
@@ -1,31 +1,32 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
+use shared::actions::{Action, ActionState};
+use shared::game_state::{LocalPlayerState, RoundPhase, RoundState};
 
 use super::{camera_controller::CameraController, input::*, player::Player};
 
+//shift-gång, som i CS
+const WALK_SPEED_FACTOR : f32 = 0.52;
+
 pub fn update_movement_input(
-    keys : Res<ButtonInput<KeyCode>>,
+    actions : Res<ActionState>,
     mut input : ResMut<PlayerInput>,
 ){
-    input.movement = Vec2::ZERO;
-
-    if keys.pressed(KeyCode::KeyW){
-        input.movement.x += 1.;
-    }
-    if keys.pressed(KeyCode::KeyA){
-        input.movement.y -= 1.;
-    }
-    if keys.pressed(KeyCode::KeyS){
-        input.movement.x -= 1.;
-    }
-    if keys.pressed(KeyCode::KeyD){
-        input.movement.y += 1.;
-    }
+    input.movement = actions.movement();
+    input.jump = actions.pressed(Action::Jump);
+    input.crouch = actions.pressed(Action::Crouch);
+    input.walk = actions.pressed(Action::Walk);
+    input.fire = actions.just_pressed(Action::Fire);
+    input.aim = actions.pressed(Action::Aim);
+    input.reload = actions.just_pressed(Action::Reload);
+    input.use_item = actions.just_pressed(Action::Use);
 }
 
 pub fn update_movement(
     time : Res<Time<Fixed>>,
     input : Res<PlayerInput>,
+    round : Res<RoundState>,
+    local : Res<LocalPlayerState>,
     camera_query : Query<&CameraController>,
     mut player_query : Query<(
         &mut Player, 
@@ -33,8 +34,12 @@ pub fn update_movement(
         Option<&KinematicCharacterControllerOutput
     >)>,
 ){
-    let camera = camera_query.get_single().unwrap();
+    let Ok(camera) = camera_query.get_single() else {
+        return;
+    };
 
+    //servern säger om vi lever och när freeze time är slut
+    let can_move = local.alive && round.phase != RoundPhase::FreezeTime;
     for(mut player,mut controller,controller_output) in player_query.iter_mut(){
         if let Some(output) = controller_output{
             if output.grounded{
@@ -50,9 +55,11 @@ pub fn update_movement(
 
         let right = Vec2::new(-forward.y,forward.x);
 
-        if let Some(movement_direction) = (forward*input.movement.x + right*input.movement.y).try_normalize(){
-            player.velocity.x = movement_direction.x*player.speed;
-            player.velocity.z = movement_direction.y*player.speed;
+        let movement = if can_move { input.movement } else { Vec2::ZERO };
+        if let Some(movement_direction) = (forward*movement.x + right*movement.y).try_normalize(){
+            let speed = if input.walk { player.speed*WALK_SPEED_FACTOR } else { player.speed };
+            player.velocity.x = movement_direction.x*speed;
+            player.velocity.z = movement_direction.y*speed;
         }
         player.velocity.y -= player.gravity*time.timestep().as_secs_f32();
         //delta
@@ -0,0 +1,84 @@
+//! Puts equipped skins on weapon models.
+//!
+//! Any weapon model tagged with [`WeaponModel`] gets the finish of the skin its owner has
+//! equipped for it. Only the local first-person model is tagged so far; world and
+//! third-person models need a `WeaponModel` with the remote player's id to follow along.
+//! The materials of the model's scene are swapped for tinted copies and put back when the
+//! skin is taken off.
+use bevy::prelude::*;
+use shared::game_state::Team;
+use shared::items::{EquippedSkins, ItemsPlugin, Weapon, WeaponSkin};
+
+/// A weapon model whose look follows its owner's loadout.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct WeaponModel {
+    pub owner: u64,
+    /// `None` uses the skin equipped for either team
+    pub team: Option<Team>,
+    pub weapon: Weapon,
+}
+
+/// Skinet som modellen visas med just nu
+#[derive(Component, Default)]
+struct AppliedSkin(Option<WeaponSkin>);
+
+/// Materialet meshen hade innan något skin lades på
+#[derive(Component)]
+struct OriginalMaterial(Handle<StandardMaterial>);
+
+pub struct WeaponSkinPlugin;
+
+impl Plugin for WeaponSkinPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ItemsPlugin>() {
+            app.add_plugins(ItemsPlugin);
+        }
+        app.add_systems(Update, apply_weapon_skins);
+    }
+}
+
+fn apply_weapon_skins(
+    mut commands: Commands,
+    skins: Res<EquippedSkins>,
+    mut models: Query<(Entity, &WeaponModel, Option<&mut AppliedSkin>)>,
+    children: Query<&Children>,
+    mut meshes: Query<(&mut Handle<StandardMaterial>, Option<&OriginalMaterial>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, model, applied) in &mut models {
+        let wanted = skins.skin(model.owner, model.team, model.weapon).cloned();
+        let changed = applied.as_ref().is_none_or(|a| a.0 != wanted);
+        for mesh in children.iter_descendants(entity) {
+            let Ok((mut material, original)) = meshes.get_mut(mesh) else {
+                continue;
+            };
+            // scenen laddas in efter modellen, så nya meshar kan dyka upp när som helst
+            if !changed && original.is_some() {
+                continue;
+            }
+            let original = match original {
+                Some(original) => original.0.clone(),
+                None => {
+                    commands.entity(mesh).insert(OriginalMaterial(material.clone()));
+                    material.clone()
+                }
+            };
+            *material = match wanted.as_ref().and_then(WeaponSkin::finish) {
+                Some((color, roughness)) => {
+                    let mut skinned = materials.get(&original).cloned().unwrap_or_default();
+                    skinned.base_color = color;
+                    skinned.perceptual_roughness = roughness;
+                    materials.add(skinned)
+                }
+                None => original,
+            };
+        }
+        match applied {
+            Some(mut applied) if changed => applied.0 = wanted,
+            Some(_) => {}
+            None => {
+                commands.entity(entity).insert(AppliedSkin(wanted));
+            }
+        }
+    }
+}
@@ -2,12 +2,26 @@ pub mod dummy_world;
 pub mod targets;
 
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_asset_loader::prelude::*;
 use dummy_world::DummyWorldPlugin;
+use shared::AppState;
 
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(DummyWorldPlugin);
+        app.add_plugins(DummyWorldPlugin)
+            .configure_loading_state(
+                LoadingStateConfig::new(AppState::Loading).load_collection::<MapAssets>(),
+            );
     }
 }
+
+/// Map files under `assets/maps`, keyed by their path.
+/// Kartor kan vara både glTF-scener och kompilerade `.cmap`, därför otypade handles.
+#[derive(AssetCollection, Resource)]
+pub struct MapAssets {
+    #[asset(path = "maps", collection(mapped))]
+    pub maps: HashMap<String, UntypedHandle>,
+}
@@ -0,0 +1,472 @@
+//! Accounts and connect tokens: the protocol to the auth service and the keys it uses.
+//!
+//! An account is a player id and a secret only its owner knows. The client makes both up
+//! the first time it runs (`player.cfg` and `account.cfg`) and the auth service (`apps/auth`)
+//! takes the first secret it sees for an id. Before joining a game server the client asks
+//! for a netcode connect token for that server: it says [`AuthPacket::Hello`], gets a cookie
+//! back to show it can receive at its address, then proves it knows its secret with a
+//! [`LoginProof`] over the cookie in an [`AuthPacket::TokenRequest`]. The secret itself never
+//! leaves the client; only a new account sends its [`account_key`], once. The token is signed
+//! with the key the auth service shares with the game servers and comes back in a few
+//! [`AuthPacket::Token`] parts. What else the server needs to let us in, like a matchmaking
+//! ticket, goes into the token as [`JoinData`].
+//!
+//! The profile service and matchmaker don't take a player's word for who they are either.
+//! The same way the client gets a [`Session`], signed with a key made from the auth key, and
+//! puts it on everything it sends them; they have the auth key too and refuse requests made
+//! as anyone but the session's player. A party is queued with a [`PartyPass`] from the
+//! profile service, which knows who joined the party, so nobody can queue someone else.
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use renet_netcode::{generate_random_bytes, NETCODE_KEY_BYTES, NETCODE_USER_DATA_BYTES};
+use serde::{Deserialize, Serialize};
+use shared::cfg;
+use shared::config::cfg_path;
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::lobby::{local_player_id, Lobby};
+
+use crate::query::{receive_datagrams, resolve_address_cvar, MAX_PACKET_SIZE, REQUEST_SIZE};
+
+pub const AUTH_PORT: u16 = 27018;
+/// Tells netcode the token is for this game; tokens for anything else are refused.
+pub const PROTOCOL_ID: u64 = 0x4650_5300_0000_0001;
+/// How long a connect token can be used after it's made.
+pub const TOKEN_EXPIRY: Duration = Duration::from_secs(60);
+/// Seconds without packets before netcode drops the connection.
+pub const CONNECTION_TIMEOUT_SECS: i32 = 15;
+/// A cookie is good for this long, and for the window before it.
+pub const COOKIE_WINDOW: Duration = Duration::from_secs(30);
+pub const SECRET_BYTES: usize = 32;
+/// Token bytes per reply.
+pub const TOKEN_CHUNK: usize = 1024;
+/// How long a session is good for.
+pub const SESSION_EXPIRY: Duration = Duration::from_secs(60 * 60);
+/// Clients get a new session when theirs has less than this left.
+pub const SESSION_RENEWAL: Duration = Duration::from_secs(10 * 60);
+/// How long a party pass is good for.
+pub const PARTY_PASS_EXPIRY: Duration = Duration::from_secs(10 * 60);
+/// Hur ofta vi frågar igen när auth-tjänsten inte svarar
+const SESSION_RETRY: Duration = Duration::from_secs(2);
+
+const MAGIC: &[u8; 4] = b"FPSA";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuthPacket {
+    // klient -> auth
+    Hello { player_id: u64 },
+    TokenRequest { login: LoginProof, server: SocketAddr, join: JoinData },
+    /// Like `TokenRequest`, for a session instead of a connect token.
+    SessionRequest { login: LoginProof },
+
+    // auth -> klient
+    /// `new_account` when the service has no account for the id yet.
+    Challenge { cookie: [u8; 32], new_account: bool },
+    /// Part `part` of `parts` of the connect token for `server`.
+    Token { server: SocketAddr, part: u8, parts: u8, bytes: Vec<u8> },
+    Session { session: Session },
+    Denied { reason: String },
+}
+
+impl AuthPacket {
+    /// The requests whose answers can be bigger than the request itself.
+    fn is_request(&self) -> bool {
+        matches!(
+            self,
+            AuthPacket::Hello { .. } | AuthPacket::TokenRequest { .. } | AuthPacket::SessionRequest { .. }
+        )
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serde::encode_to_vec(self, bincode::config::standard()).unwrap_or_default());
+        if self.is_request() && bytes.len() < REQUEST_SIZE {
+            bytes.resize(REQUEST_SIZE, 0);
+        }
+        bytes
+    }
+
+    /// `None` for anything that isn't a well-formed auth packet.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC)?;
+        let config = bincode::config::standard().with_limit::<MAX_PACKET_SIZE>();
+        let (packet, _) = bincode::serde::decode_from_slice::<AuthPacket, _>(body, config).ok()?;
+        if packet.is_request() && bytes.len() < REQUEST_SIZE {
+            return None;
+        }
+        Some(packet)
+    }
+
+    /// Splits a serialized token into replies that each fit in a packet.
+    pub fn token_replies(server: SocketAddr, token: &[u8]) -> Vec<AuthPacket> {
+        let chunks = token.chunks(TOKEN_CHUNK);
+        let parts = chunks.len() as u8;
+        chunks
+            .enumerate()
+            .map(|(part, bytes)| AuthPacket::Token { server, part: part as u8, parts, bytes: bytes.to_vec() })
+            .collect()
+    }
+}
+
+/// The key sessions and party passes are signed with. It's made from the auth key, so any
+/// service given the auth key can check them.
+pub fn session_key(key: &[u8; NETCODE_KEY_BYTES]) -> [u8; 32] {
+    blake3::derive_key("fps sessions and party passes", key)
+}
+
+fn sign(key: &[u8; 32], text: &str) -> blake3::Hash {
+    blake3::keyed_hash(key, text.as_bytes())
+}
+
+/// What the auth service keeps for an account. Whoever has it can log in as the player, so
+/// it's only ever sent when the account is made.
+pub fn account_key(secret: &[u8; SECRET_BYTES]) -> [u8; 32] {
+    *blake3::hash(secret).as_bytes()
+}
+
+/// Shows the auth service we know the secret of `player_id` without sending it: a MAC over
+/// the cookie it gave our address, so it can't be used from anywhere else or for long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginProof {
+    pub player_id: u64,
+    pub cookie: [u8; 32],
+    pub mac: [u8; 32],
+    /// Our [`account_key`], when the service said it has no account for us yet
+    pub new_account: Option<[u8; 32]>,
+}
+
+impl LoginProof {
+    fn text(player_id: u64, cookie: &[u8; 32]) -> String {
+        format!("login {player_id} {}", to_hex(cookie))
+    }
+
+    pub fn new(secret: &[u8; SECRET_BYTES], player_id: u64, cookie: [u8; 32], new_account: bool) -> LoginProof {
+        let key = account_key(secret);
+        let mac = *sign(&key, &Self::text(player_id, &cookie)).as_bytes();
+        LoginProof { player_id, cookie, mac, new_account: new_account.then_some(key) }
+    }
+
+    /// Whether it was made with the secret whose [`account_key`] is `key`.
+    pub fn verify(&self, key: &[u8; 32]) -> bool {
+        sign(key, &Self::text(self.player_id, &self.cookie)) == blake3::Hash::from(self.mac)
+    }
+}
+
+/// What the player brings to the game server besides their id. The auth service signs it
+/// into the connect token as netcode user data, so the server reads it when they connect.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct JoinData {
+    /// Our matchmaking ticket, for a server that was given a match
+    pub ticket: Option<u64>,
+    /// [`JoinData::password_hash`] of the server's password, for a server that has one
+    pub password: Option<[u8; 32]>,
+}
+
+impl JoinData {
+    /// So the auth service never sees the password itself.
+    pub fn password_hash(password: &str) -> [u8; 32] {
+        blake3::derive_key("fps server password", password.as_bytes())
+    }
+
+    pub fn to_user_data(&self) -> [u8; NETCODE_USER_DATA_BYTES] {
+        let mut data = [0; NETCODE_USER_DATA_BYTES];
+        // får alltid plats, det är några få fält
+        let _ = bincode::serde::encode_into_slice(self, &mut data, bincode::config::standard());
+        data
+    }
+
+    /// Empty for user data that doesn't hold any.
+    pub fn from_user_data(data: &[u8; NETCODE_USER_DATA_BYTES]) -> JoinData {
+        bincode::serde::decode_from_slice(data, bincode::config::standard()).map_or_else(|_| default(), |(d, _)| d)
+    }
+}
+
+/// Proof from the auth service that whoever holds it is `player_id`, until `expires`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub player_id: u64,
+    /// Unix time
+    pub expires: u64,
+    pub mac: [u8; 32],
+}
+
+impl Session {
+    pub fn issue(key: &[u8; 32], player_id: u64, expires: u64) -> Session {
+        Session { player_id, expires, mac: *sign(key, &format!("session {player_id} {expires}")).as_bytes() }
+    }
+
+    /// Whether `key` signed it and it hasn't run out at `now`.
+    pub fn verify(&self, key: &[u8; 32], now: u64) -> bool {
+        // blake3::Hash jämför i konstant tid
+        let signed = sign(key, &format!("session {} {}", self.player_id, self.expires)) == blake3::Hash::from(self.mac);
+        signed && now < self.expires
+    }
+}
+
+/// Proof from the profile service that `members` are one party, so one of them can queue
+/// them all.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartyPass {
+    /// Sorted
+    pub members: Vec<u64>,
+    /// Unix time
+    pub expires: u64,
+    pub mac: [u8; 32],
+}
+
+impl PartyPass {
+    fn text(members: &[u64], expires: u64) -> String {
+        let ids: Vec<String> = members.iter().map(u64::to_string).collect();
+        format!("party {expires} {}", ids.join(" "))
+    }
+
+    pub fn issue(key: &[u8; 32], mut members: Vec<u64>, expires: u64) -> PartyPass {
+        members.sort_unstable();
+        let mac = *sign(key, &Self::text(&members, expires)).as_bytes();
+        PartyPass { members, expires, mac }
+    }
+
+    /// Whether `key` signed it, it hasn't run out at `now` and it's for exactly `members`.
+    pub fn verify(&self, key: &[u8; 32], now: u64, members: &[u64]) -> bool {
+        let mut wanted = members.to_vec();
+        wanted.sort_unstable();
+        let signed = sign(key, &Self::text(&self.members, self.expires)) == blake3::Hash::from(self.mac);
+        signed && now < self.expires && wanted == self.members
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// `None` unless `text` is exactly `N` bytes of hex.
+pub fn from_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Reads the key tokens are signed with, kept as hex in a file.
+pub fn read_key(path: &Path) -> Result<[u8; NETCODE_KEY_BYTES], String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read key {}: {e}", path.display()))?;
+    from_hex(&text).ok_or_else(|| format!("{} doesn't hold a {NETCODE_KEY_BYTES} byte hex key", path.display()))
+}
+
+/// Makes up a new key and writes it to `path`.
+pub fn create_key(path: &Path) -> Result<[u8; NETCODE_KEY_BYTES], String> {
+    let key = generate_random_bytes::<NETCODE_KEY_BYTES>();
+    fs::write(path, to_hex(&key) + "\n").map_err(|e| format!("can't write key {}: {e}", path.display()))?;
+    Ok(key)
+}
+
+/// The secret proving our player id is ours, made up the first time and kept in `account.cfg`.
+pub fn account_secret() -> [u8; SECRET_BYTES] {
+    let path = cfg_path("account");
+    let text = fs::read_to_string(&path).unwrap_or_default();
+    let (statements, _) = cfg::parse(&text);
+    let saved = statements.iter().find_map(|s| match s.args.as_slice() {
+        [key, secret] if key == "secret" => from_hex(secret),
+        _ => None,
+    });
+    if let Some(secret) = saved {
+        return secret;
+    }
+    let secret = generate_random_bytes::<SECRET_BYTES>();
+    // förlorad fil betyder förlorat konto, så den skrivs direkt
+    if let Err(err) = fs::write(&path, cfg::join(&["secret".to_string(), to_hex(&secret)]) + "\n") {
+        error!("couldn't write {}: {err}", path.display());
+    }
+    secret
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Our session with the auth service, asked for at startup and renewed before it runs out.
+#[derive(Resource)]
+pub struct AuthSession {
+    socket: Option<UdpSocket>,
+    secret: [u8; SECRET_BYTES],
+    /// Där `cl_auth` pekar, slås bara upp när den ändras
+    service: Option<SocketAddr>,
+    session: Option<Session>,
+    /// Cookien och om tjänsten saknar konto för oss
+    challenge: Option<([u8; 32], bool)>,
+    last_sent: Option<Instant>,
+    /// Why the service turned us down, for the UI
+    pub last_error: Option<String>,
+}
+
+impl Default for AuthSession {
+    fn default() -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).and_then(|s| s.set_nonblocking(true).map(|_| s));
+        if let Err(err) = &socket {
+            error!("auth session can't open a socket: {err}");
+        }
+        AuthSession {
+            socket: socket.ok(),
+            secret: account_secret(),
+            service: None,
+            session: None,
+            challenge: None,
+            last_sent: None,
+            last_error: None,
+        }
+    }
+}
+
+impl AuthSession {
+    pub fn service(&self) -> Option<SocketAddr> {
+        self.service
+    }
+
+    /// The session, while it's still good.
+    pub fn session(&self) -> Option<Session> {
+        self.session.filter(|s| s.expires > unix_now())
+    }
+}
+
+/// Keeps an [`AuthSession`] for the services that want one.
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_cvar(
+            CvarDef::string("cl_auth", "localhost")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Auth service that proves who you are to game servers and the other services"),
+        )
+        .init_resource::<AuthSession>()
+        .add_systems(
+            Update,
+            (
+                // ny tjänst, ny cookie
+                resolve_address_cvar("cl_auth", AUTH_PORT, |auth: &mut AuthSession, address| {
+                    auth.service = address;
+                    auth.challenge = None;
+                    auth.last_sent = None;
+                }),
+                renew_session,
+            )
+                .chain(),
+        );
+    }
+}
+
+fn renew_session(lobby: Option<Res<Lobby>>, mut auth: ResMut<AuthSession>) {
+    let Some(service) = auth.service else {
+        return;
+    };
+    let packets = auth.socket.as_ref().map(receive_datagrams).unwrap_or_default();
+    for (bytes, _) in packets.into_iter().filter(|(_, from)| *from == service) {
+        match AuthPacket::decode(&bytes) {
+            Some(AuthPacket::Challenge { cookie, new_account }) => {
+                auth.challenge = Some((cookie, new_account));
+                auth.last_sent = None;
+            }
+            Some(AuthPacket::Session { session }) => {
+                auth.session = Some(session);
+                auth.challenge = None;
+                auth.last_error = None;
+            }
+            Some(AuthPacket::Denied { reason }) => {
+                warn!("auth: {reason}");
+                auth.last_error = Some(reason);
+            }
+            _ => {}
+        }
+    }
+
+    let player_id = lobby.map_or_else(local_player_id, |l| l.local_id());
+    let renew_at = unix_now() + SESSION_RENEWAL.as_secs();
+    if auth.session.is_some_and(|s| s.player_id == player_id && s.expires > renew_at) {
+        return;
+    }
+    if auth.last_sent.is_some_and(|t| t.elapsed() < SESSION_RETRY) {
+        return;
+    }
+    auth.last_sent = Some(Instant::now());
+    let packet = match auth.challenge {
+        None => AuthPacket::Hello { player_id },
+        Some((cookie, new_account)) => {
+            AuthPacket::SessionRequest { login: LoginProof::new(&auth.secret, player_id, cookie, new_account) }
+        }
+    };
+    if let Some(socket) = &auth.socket {
+        if let Err(err) = socket.send_to(&packet.encode(), service) {
+            warn!("auth: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [3; 32];
+
+    #[test]
+    fn sessions_only_verify_with_the_key_and_in_time() {
+        let session = Session::issue(&KEY, 42, 1000);
+        assert!(session.verify(&KEY, 999));
+        assert!(!session.verify(&KEY, 1000));
+        assert!(!session.verify(&[4; 32], 999));
+        // id och tid går inte att ändra i efterhand
+        assert!(!Session { player_id: 43, ..session }.verify(&KEY, 999));
+        assert!(!Session { expires: 5000, ..session }.verify(&KEY, 999));
+    }
+
+    #[test]
+    fn party_passes_are_for_exactly_their_members() {
+        let pass = PartyPass::issue(&KEY, vec![3, 1, 2], 1000);
+        assert!(pass.verify(&KEY, 0, &[1, 2, 3]));
+        assert!(pass.verify(&KEY, 0, &[2, 3, 1]));
+        assert!(!pass.verify(&KEY, 0, &[1, 2]));
+        assert!(!pass.verify(&KEY, 0, &[1, 2, 3, 4]));
+        assert!(!pass.verify(&KEY, 1000, &[1, 2, 3]));
+        let forged = PartyPass { members: vec![1, 2, 4], ..pass.clone() };
+        assert!(!forged.verify(&KEY, 0, &[1, 2, 4]));
+        // en session går inte att använda som pass eller tvärtom
+        assert_ne!(Session::issue(&KEY, 1, 1000).mac, PartyPass::issue(&KEY, vec![1], 1000).mac);
+    }
+
+    #[test]
+    fn join_data_survives_the_connect_token() {
+        let join = JoinData { ticket: Some(u64::MAX), password: Some(JoinData::password_hash("hemligt")) };
+        assert_eq!(JoinData::from_user_data(&join.to_user_data()), join);
+        assert_eq!(JoinData::from_user_data(&[0; NETCODE_USER_DATA_BYTES]), JoinData::default());
+        assert_eq!(JoinData::from_user_data(&[0xff; NETCODE_USER_DATA_BYTES]), JoinData::default());
+    }
+
+    #[test]
+    fn login_proofs_only_verify_with_the_account_key() {
+        let secret = [9; SECRET_BYTES];
+        let proof = LoginProof::new(&secret, 42, [1; 32], false);
+        assert!(proof.verify(&account_key(&secret)));
+        assert!(!proof.verify(&account_key(&[8; SECRET_BYTES])));
+        assert_eq!(proof.new_account, None);
+        // beviset gäller bara för sin spelare och sin cookie
+        assert!(!LoginProof { player_id: 43, ..proof }.verify(&account_key(&secret)));
+        assert!(!LoginProof { cookie: [2; 32], ..proof }.verify(&account_key(&secret)));
+        assert_eq!(LoginProof::new(&secret, 42, [1; 32], true).new_account, Some(account_key(&secret)));
+    }
+
+    #[test]
+    fn session_requests_are_padded_like_the_others() {
+        let request = AuthPacket::SessionRequest { login: LoginProof::new(&[0; SECRET_BYTES], 1, [0; 32], true) };
+        let bytes = request.encode();
+        assert_eq!(bytes.len(), REQUEST_SIZE);
+        assert_eq!(AuthPacket::decode(&bytes), Some(request));
+        assert_eq!(AuthPacket::decode(&bytes[..bytes.len() - 1]), None);
+    }
+}
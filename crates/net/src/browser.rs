@@ -0,0 +1,362 @@
+//! Client side of the server browser: gets the internet list from the master server, asks
+//! servers for their info, finds LAN servers and keeps the favourites and history in
+//! `servers.cfg`. Joining a server, from here or anywhere else, goes through [`JoinServer`].
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use shared::cfg;
+use shared::config::{cfg_path, GameConfig};
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::lobby::Lobby;
+use shared::maps::MapId;
+use shared::types::AppState;
+
+use crate::connection::GameClientPlugin;
+use crate::master::{MasterPacket, MASTER_PORT};
+use crate::query::{receive_datagrams, resolve_address, QueryPacket, ServerInfo, QUERY_PORT};
+
+/// A server that hasn't answered in this long is shown as not responding.
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Servers kept in the history.
+pub const MAX_HISTORY: usize = 20;
+
+/// One row in the browser.
+#[derive(Debug, Clone)]
+pub struct ServerEntry {
+    pub addr: SocketAddr,
+    /// `None` until the server answers
+    pub info: Option<ServerInfo>,
+    /// Round trip in milliseconds
+    pub ping: Option<u32>,
+    pub lan: bool,
+    /// Listed by the master server
+    pub internet: bool,
+    pub timed_out: bool,
+    /// Token och tid för frågan vi väntar svar på
+    pending: Option<(u32, Instant)>,
+}
+
+#[derive(Resource)]
+pub struct ServerBrowser {
+    socket: Option<UdpSocket>,
+    entries: Vec<ServerEntry>,
+    next_token: u32,
+    /// Broadcasten har ingen egen rad, svaren blir nya rader
+    lan_query: Option<(u32, Instant)>,
+    master_query: Option<(u32, Instant)>,
+}
+
+impl Default for ServerBrowser {
+    fn default() -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))
+            .and_then(|s| s.set_nonblocking(true).and_then(|_| s.set_broadcast(true)).map(|_| s));
+        if let Err(err) = &socket {
+            error!("server browser can't open a socket: {err}");
+        }
+        ServerBrowser {
+            socket: socket.ok(),
+            entries: Vec::new(),
+            next_token: 1,
+            lan_query: None,
+            master_query: None,
+        }
+    }
+}
+
+impl ServerBrowser {
+    pub fn entries(&self) -> &[ServerEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, addr: SocketAddr) -> Option<&ServerEntry> {
+        self.entries.iter().find(|e| e.addr == addr)
+    }
+
+    /// Whether any query is still waiting for an answer.
+    pub fn is_refreshing(&self) -> bool {
+        self.lan_query.is_some() || self.master_query.is_some() || self.entries.iter().any(|e| e.pending.is_some())
+    }
+
+    fn token(&mut self) -> u32 {
+        self.next_token = self.next_token.wrapping_add(1).max(1);
+        self.next_token
+    }
+
+    fn send(&self, bytes: &[u8], to: SocketAddr) {
+        if let Some(socket) = &self.socket {
+            if let Err(err) = socket.send_to(bytes, to) {
+                warn!("server query to {to} failed: {err}");
+            }
+        }
+    }
+
+    /// Asks `addr` for its info, adding it to the list if it's new.
+    pub fn query(&mut self, addr: SocketAddr) {
+        self.query_entry(addr);
+    }
+
+    fn query_entry(&mut self, addr: SocketAddr) -> &mut ServerEntry {
+        let token = self.token();
+        let index = match self.entries.iter().position(|e| e.addr == addr) {
+            Some(i) => i,
+            None => {
+                self.entries.push(ServerEntry {
+                    addr,
+                    info: None,
+                    ping: None,
+                    lan: false,
+                    internet: false,
+                    timed_out: false,
+                    pending: None,
+                });
+                self.entries.len() - 1
+            }
+        };
+        self.send(&QueryPacket::InfoRequest { token }.encode(), addr);
+        let entry = &mut self.entries[index];
+        entry.pending = Some((token, Instant::now()));
+        entry.timed_out = false;
+        entry
+    }
+
+    /// Broadcasts a query on the LAN; every server that answers shows up as a new entry.
+    pub fn discover_lan(&mut self) {
+        let token = self.token();
+        self.lan_query = Some((token, Instant::now()));
+        let broadcast = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::BROADCAST, QUERY_PORT));
+        self.send(&QueryPacket::InfoRequest { token }.encode(), broadcast);
+    }
+
+    /// Asks the master server for the internet list; every listed server gets queried.
+    pub fn request_master_list(&mut self, master: SocketAddr) {
+        let token = self.token();
+        self.master_query = Some((token, Instant::now()));
+        self.send(&MasterPacket::ListRequest { token }.encode(), master);
+    }
+
+    /// Starts over with the bookmarked servers, the master list and whatever is on the LAN.
+    pub fn refresh(&mut self, bookmarks: &ServerBookmarks, master: Option<SocketAddr>) {
+        self.entries.clear();
+        for addr in bookmarks.favorites.iter().chain(&bookmarks.history) {
+            self.query(*addr);
+        }
+        if let Some(master) = master {
+            self.request_master_list(master);
+        }
+        self.discover_lan();
+    }
+
+    /// Takes in the answers that have arrived and times out the ones that won't.
+    pub fn poll(&mut self) {
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        let now = Instant::now();
+        let mut listed = Vec::new();
+        for (bytes, from) in receive_datagrams(socket) {
+            if let Some(MasterPacket::ListReply { token, servers, last }) = MasterPacket::decode(&bytes) {
+                if self.master_query.is_some_and(|(t, _)| t == token) {
+                    listed.extend(servers);
+                    if last {
+                        self.master_query = None;
+                    }
+                }
+                continue;
+            }
+            let Some(QueryPacket::InfoReply { token, info }) = QueryPacket::decode(&bytes) else {
+                continue;
+            };
+            let ping = |sent: Instant| now.duration_since(sent).as_millis().min(u32::MAX as u128) as u32;
+
+            if let Some(entry) = self.entries.iter_mut().find(|e| e.addr == from) {
+                if let Some((expected, sent)) = entry.pending {
+                    if expected == token {
+                        entry.ping = Some(ping(sent));
+                        entry.pending = None;
+                    }
+                }
+                if let Some((lan_token, _)) = self.lan_query {
+                    entry.lan |= lan_token == token;
+                }
+                entry.info = Some(info);
+            } else if let Some((_, sent)) = self.lan_query.filter(|(t, _)| *t == token) {
+                self.entries.push(ServerEntry {
+                    addr: from,
+                    info: Some(info),
+                    ping: Some(ping(sent)),
+                    lan: true,
+                    internet: false,
+                    timed_out: false,
+                    pending: None,
+                });
+            }
+        }
+        for addr in listed {
+            self.query_entry(addr).internet = true;
+        }
+
+        for entry in &mut self.entries {
+            if entry.pending.is_some_and(|(_, sent)| now.duration_since(sent) > QUERY_TIMEOUT) {
+                entry.pending = None;
+                entry.timed_out = true;
+            }
+        }
+        if self.lan_query.is_some_and(|(_, sent)| now.duration_since(sent) > QUERY_TIMEOUT) {
+            self.lan_query = None;
+        }
+        if self.master_query.is_some_and(|(_, sent)| now.duration_since(sent) > QUERY_TIMEOUT) {
+            warn!("master server didn't answer");
+            self.master_query = None;
+        }
+    }
+}
+
+/// The master server from `cl_master`, if one is set.
+pub fn master_address(config: &GameConfig) -> Option<SocketAddr> {
+    resolve_address(config.get_str("cl_master").unwrap_or(""), MASTER_PORT)
+}
+
+/// Parses `host` or `host:port`, using the query port when none is given.
+pub fn parse_address(text: &str) -> Option<SocketAddr> {
+    resolve_address(text, QUERY_PORT)
+}
+
+/// Favourite servers and the ones we joined last, stored in `servers.cfg`.
+#[derive(Resource, Default, Debug, Clone)]
+pub struct ServerBookmarks {
+    pub favorites: Vec<SocketAddr>,
+    /// Senast anslutna först
+    pub history: Vec<SocketAddr>,
+}
+
+impl ServerBookmarks {
+    pub fn load() -> Self {
+        let mut bookmarks = ServerBookmarks::default();
+        let Ok(text) = fs::read_to_string(cfg_path("servers")) else {
+            return bookmarks;
+        };
+        let (statements, errors) = cfg::parse(&text);
+        for err in errors {
+            warn!("servers.cfg: {err}");
+        }
+        for statement in statements {
+            let [kind, addr] = statement.args.as_slice() else {
+                continue;
+            };
+            let Ok(addr) = addr.parse() else {
+                warn!("servers.cfg line {}: bad address {addr}", statement.line);
+                continue;
+            };
+            match kind.as_str() {
+                "favorite" => bookmarks.favorites.push(addr),
+                "history" => bookmarks.history.push(addr),
+                _ => warn!("servers.cfg line {}: unknown entry {kind}", statement.line),
+            }
+        }
+        bookmarks
+    }
+
+    pub fn save(&self) {
+        let mut text = String::new();
+        for (kind, list) in [("favorite", &self.favorites), ("history", &self.history)] {
+            for addr in list {
+                text.push_str(&cfg::join(&[kind.to_string(), addr.to_string()]));
+                text.push('\n');
+            }
+        }
+        if let Err(err) = fs::write(cfg_path("servers"), text) {
+            error!("could not save servers.cfg: {err}");
+        }
+    }
+
+    pub fn is_favorite(&self, addr: SocketAddr) -> bool {
+        self.favorites.contains(&addr)
+    }
+
+    pub fn toggle_favorite(&mut self, addr: SocketAddr) {
+        if let Some(i) = self.favorites.iter().position(|a| *a == addr) {
+            self.favorites.remove(i);
+        } else {
+            self.favorites.push(addr);
+        }
+    }
+
+    pub fn add_history(&mut self, addr: SocketAddr) {
+        self.history.retain(|a| *a != addr);
+        self.history.insert(0, addr);
+        self.history.truncate(MAX_HISTORY);
+    }
+}
+
+/// Asks to join a game server, from the browser, a friend's game or matchmaking.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct JoinServer {
+    pub addr: SocketAddr,
+    /// Map the server is running, loaded before we connect
+    pub map: MapId,
+    /// Keeps it out of the history, e.g. for matchmade servers
+    pub remember: bool,
+    /// Our ticket for a matchmade server, which lets nobody in without one
+    pub ticket: Option<u64>,
+}
+
+/// The server we joined last, `None` in the menus.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct CurrentServer(pub Option<SocketAddr>);
+
+pub struct ServerBrowserPlugin;
+
+impl Plugin for ServerBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<GameClientPlugin>() {
+            app.add_plugins(GameClientPlugin);
+        }
+        app.register_cvar(
+            CvarDef::string("cl_master", "localhost")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Master server the browser gets the internet list from"),
+        )
+        .init_resource::<ServerBrowser>()
+        .init_resource::<CurrentServer>()
+        .insert_resource(ServerBookmarks::load())
+        .add_event::<JoinServer>()
+        .add_systems(Update, (poll_server_browser, join_servers))
+        .add_systems(OnEnter(AppState::MainMenu), leave_server);
+    }
+}
+
+fn join_servers(
+    mut joins: EventReader<JoinServer>,
+    mut bookmarks: ResMut<ServerBookmarks>,
+    mut current: ResMut<CurrentServer>,
+    lobby: Option<ResMut<Lobby>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let Some(join) = joins.read().last() else {
+        return;
+    };
+    if join.remember {
+        bookmarks.add_history(join.addr);
+        bookmarks.save();
+    }
+    // själva anslutningen sköts av connection
+    info!("joining {} on {}", join.addr, join.map.label());
+    current.0 = Some(join.addr);
+    if let Some(mut lobby) = lobby {
+        lobby.map = join.map;
+    }
+    next_state.set(AppState::Loading);
+}
+
+fn leave_server(mut current: ResMut<CurrentServer>) {
+    current.0 = None;
+}
+
+fn poll_server_browser(mut browser: ResMut<ServerBrowser>) {
+    // bara när något väntar, annars räknas resursen som ändrad varje frame
+    if browser.is_refreshing() {
+        browser.poll();
+    }
+}
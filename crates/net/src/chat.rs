@@ -0,0 +1,246 @@
+//! Match chat over the game connection.
+//!
+//! Players send their [`SendChat`] to the game server as [`ClientMessage::Chat`]. The server
+//! runs it through its `ChatFilter`, keyed by the connection's player id so nobody can use
+//! up someone else's flood limit, and sends the [`ChatMessage`] to every connected player it
+//! is `visible_to`. Teams come from the match the matchmaker allocated, names from what each
+//! player sent with [`ClientMessage::Name`]. Lobby chat doesn't go through the game server;
+//! the party service hosts it, see `profile`.
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use shared::chat::{
+    ChatChannel, ChatError, ChatFilter, ChatLimits, ChatMessage, ChatPlugin, RemoteChat, SendChat,
+};
+use shared::config::GameConfig;
+use shared::cvars::CvarChanged;
+use shared::game_state::Team;
+
+use crate::connection::{GameConnection, GameServer, PlayerDisconnected, PlayerMessage};
+use crate::matchmaking::AllocatedMatch;
+use crate::profile::MAX_NAME_LENGTH;
+use crate::protocol::{ClientMessage, ServerMessage};
+
+/// Names the players gave, for the messages they send.
+#[derive(Resource, Default)]
+struct ChatNames(HashMap<u64, String>);
+
+/// Checks a chat request from `sender` and works out who gets what: the message for every
+/// player in `players` that may see it, or a notice for the sender alone.
+pub fn route_chat(
+    filter: &mut ChatFilter,
+    limits: &ChatLimits,
+    now: f64,
+    sender: (u64, &str, Option<Team>),
+    request: &SendChat,
+    players: &[(u64, Option<Team>)],
+) -> Vec<(u64, ChatMessage)> {
+    let (sender_id, name, team) = sender;
+    let result = match request.channel {
+        // lobbychatten går via partytjänsten och ingen får skriva som servern
+        ChatChannel::Lobby | ChatChannel::Server => return Vec::new(),
+        ChatChannel::Team if team.is_none() => Err(ChatError::NoTeam),
+        _ => filter.check(sender_id, &request.text, now, limits),
+    };
+    match result {
+        Ok(text) => {
+            let message = ChatMessage { channel: request.channel, sender: Some(name.to_string()), team, text };
+            players
+                .iter()
+                .filter(|(_, team)| message.visible_to(*team))
+                .map(|(id, _)| (*id, message.clone()))
+                .collect()
+        }
+        Err(err) => vec![(sender_id, ChatMessage::notice(err.to_string()))],
+    }
+}
+
+/// Hosts the match chat on the game server.
+pub struct ServerChatPlugin;
+
+impl Plugin for ServerChatPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ChatPlugin>() {
+            app.add_plugins(ChatPlugin);
+        }
+        app.init_resource::<ChatNames>().add_systems(Update, (host_chat, forget_chatters).chain());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn host_chat(
+    time: Res<Time<Real>>,
+    config: Res<GameConfig>,
+    allocated: Option<Res<AllocatedMatch>>,
+    mut names: ResMut<ChatNames>,
+    mut filter: ResMut<ChatFilter>,
+    mut messages: EventReader<PlayerMessage>,
+    mut log: EventWriter<ChatMessage>,
+    game: Option<ResMut<GameServer>>,
+) {
+    let Some(mut game) = game else {
+        messages.clear();
+        return;
+    };
+    let team_of = |player_id: u64| {
+        allocated.as_ref()?.tickets.iter().find(|t| t.player_id == player_id).map(|t| t.team)
+    };
+    let limits = ChatLimits::from_config(&config);
+    for PlayerMessage { player_id, message } in messages.read() {
+        match message {
+            ClientMessage::Name(name) => {
+                let name: String = name.trim().chars().take(MAX_NAME_LENGTH).collect();
+                if !name.is_empty() {
+                    names.0.insert(*player_id, name);
+                }
+            }
+            ClientMessage::Chat(request) => {
+                let name = names.0.get(player_id).cloned().unwrap_or_else(|| format!("Player {player_id}"));
+                let players: Vec<(u64, Option<Team>)> =
+                    game.players().into_iter().map(|id| (id, team_of(id))).collect();
+                let sender = (*player_id, name.as_str(), team_of(*player_id));
+                let now = time.elapsed_seconds_f64();
+                let routed = route_chat(&mut filter, &limits, now, sender, request, &players);
+                // servern loggar allt den släpper igenom
+                if let Some((_, message)) = routed.iter().find(|(_, m)| m.channel != ChatChannel::Server) {
+                    log.send(message.clone());
+                }
+                for (to, message) in routed {
+                    game.send_message(to, &ServerMessage::Chat(message));
+                }
+            }
+            ClientMessage::Command(_) => {}
+        }
+    }
+}
+
+fn forget_chatters(
+    mut disconnected: EventReader<PlayerDisconnected>,
+    mut names: ResMut<ChatNames>,
+    mut filter: ResMut<ChatFilter>,
+) {
+    for &PlayerDisconnected { player_id } in disconnected.read() {
+        names.0.remove(&player_id);
+        filter.forget(player_id);
+    }
+}
+
+/// Sends our match chat to the game server while we're on one, and shows what comes back.
+pub struct ClientChatPlugin;
+
+impl Plugin for ClientChatPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ChatPlugin>() {
+            app.add_plugins(ChatPlugin);
+        }
+        app.add_event::<ServerMessage>()
+            .add_systems(Update, (track_chat_host, send_chat_name, forward_match_chat, receive_match_chat));
+    }
+}
+
+fn track_chat_host(connection: Res<GameConnection>, mut remote: ResMut<RemoteChat>) {
+    let connected = connection.is_connected();
+    if remote.0 != connected {
+        remote.0 = connected;
+    }
+}
+
+/// Tells the server our name when we get there and whenever the `name` cvar changes.
+fn send_chat_name(
+    mut changes: EventReader<CvarChanged>,
+    config: Res<GameConfig>,
+    mut connection: ResMut<GameConnection>,
+    mut sent: Local<bool>,
+) {
+    let renamed = changes.read().any(|c| c.name == "name");
+    if !connection.is_connected() {
+        *sent = false;
+        return;
+    }
+    if !*sent || renamed {
+        *sent = true;
+        let name = config.get_str("name").unwrap_or("Player").to_string();
+        connection.send_message(&ClientMessage::Name(name));
+    }
+}
+
+fn forward_match_chat(mut requests: EventReader<SendChat>, mut connection: ResMut<GameConnection>) {
+    if !connection.is_connected() {
+        requests.clear();
+        return;
+    }
+    for request in requests.read() {
+        if matches!(request.channel, ChatChannel::All | ChatChannel::Team) {
+            connection.send_message(&ClientMessage::Chat(request.clone()));
+        }
+    }
+}
+
+fn receive_match_chat(mut messages: EventReader<ServerMessage>, mut chat: EventWriter<ChatMessage>) {
+    for message in messages.read() {
+        if let ServerMessage::Chat(message) = message {
+            chat.send(message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ChatLimits = ChatLimits { max_length: 127, flood_limit: 2, flood_window: 5.0 };
+
+    fn say(channel: ChatChannel, text: &str) -> SendChat {
+        SendChat { channel, text: text.to_string() }
+    }
+
+    fn players() -> Vec<(u64, Option<Team>)> {
+        vec![(1, Some(Team::Terrorist)), (2, Some(Team::Terrorist)), (3, Some(Team::CounterTerrorist)), (4, None)]
+    }
+
+    fn recipients(routed: &[(u64, ChatMessage)]) -> Vec<u64> {
+        routed.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn all_chat_reaches_everybody_and_team_chat_only_the_team() {
+        let mut filter = ChatFilter::default();
+        let sender = (1, "Alice", Some(Team::Terrorist));
+        let all = route_chat(&mut filter, &LIMITS, 0.0, sender, &say(ChatChannel::All, " hi "), &players());
+        assert_eq!(recipients(&all), [1, 2, 3, 4]);
+        assert_eq!(all[0].1.text, "hi");
+        assert_eq!(all[0].1.sender.as_deref(), Some("Alice"));
+
+        let team = route_chat(&mut filter, &LIMITS, 0.0, sender, &say(ChatChannel::Team, "rush b"), &players());
+        assert_eq!(recipients(&team), [1, 2]);
+    }
+
+    #[test]
+    fn mistakes_are_only_told_to_the_sender() {
+        let mut filter = ChatFilter::default();
+        // åskådare har inget lag att skriva till
+        let spectator = (4, "Spec", None);
+        let routed = route_chat(&mut filter, &LIMITS, 0.0, spectator, &say(ChatChannel::Team, "hi"), &players());
+        assert_eq!(recipients(&routed), [4]);
+        assert_eq!(routed[0].1.channel, ChatChannel::Server);
+
+        for channel in [ChatChannel::Lobby, ChatChannel::Server] {
+            assert!(route_chat(&mut filter, &LIMITS, 0.0, (1, "A", None), &say(channel, "hi"), &players()).is_empty());
+        }
+    }
+
+    #[test]
+    fn the_flood_limit_counts_per_player() {
+        let mut filter = ChatFilter::default();
+        let mut send = |id: u64, now: f64| {
+            let request = say(ChatChannel::All, "x");
+            route_chat(&mut filter, &LIMITS, now, (id, "P", None), &request, &players()).len() > 1
+        };
+        assert!(send(1, 0.0));
+        assert!(send(1, 1.0));
+        assert!(!send(1, 2.0));
+        // någon annans spam stoppar inte oss
+        assert!(send(2, 2.0));
+        assert!(send(1, 5.5));
+    }
+}
@@ -0,0 +1,635 @@
+//! The connection to a game server: renet over netcode, with secure connect tokens.
+//!
+//! Servers only let in players holding a connect token from the auth service (see `auth`),
+//! signed with the key the two share. A token names the player id and the server address
+//! and runs out after `TOKEN_EXPIRY`. Netcode refuses tokens that weren't signed with the
+//! key, are for another server or have run out, and a token already in use from another
+//! address, so a token that's been seen once can't be replayed. The id in the token is the
+//! player's id on the server; a `Command` claiming any other id gets its sender dropped.
+//!
+//! Joining takes three steps: ask the server's query port which port the game is on, get a
+//! token for that address from the auth service, then connect with it.
+//!
+//! A server the matchmaker gave a match (see [`AllocatedMatch`]) only lets in the players it
+//! has tickets for, and any other server with an `sv_password` only the players who give it.
+//! The ticket and the password come along in the token's [`JoinData`].
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use renet::{ConnectionConfig, DefaultChannel, RenetClient, RenetServer, ServerEvent};
+use renet_netcode::{
+    ClientAuthentication, ConnectToken, NetcodeClientTransport, NetcodeServerTransport, ServerAuthentication,
+    ServerConfig,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use shared::config::{cfg_path, GameConfig};
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::lobby::{local_player_id, Lobby};
+use shared::types::AppState;
+
+use crate::auth::{
+    account_secret, read_key, AuthPacket, AuthSession, JoinData, LoginProof, SessionPlugin, PROTOCOL_ID, SECRET_BYTES,
+};
+use crate::browser::JoinServer;
+use crate::chat::ClientChatPlugin;
+use crate::cvars::ClientCvarsPlugin;
+use crate::game_state::ClientGameStatePlugin;
+use crate::matchmaking::AllocatedMatch;
+use crate::protocol::{ClientMessage, Command, ServerMessage, Snapshot};
+use crate::query::{receive_datagrams, QueryPacket};
+use crate::skins::ClientSkinsPlugin;
+use crate::stats::ClientStatsPlugin;
+
+pub const GAME_PORT: u16 = 27015;
+/// How long each step of joining may take.
+pub const JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Unanswered packets are sent again this often while joining.
+const RESEND: Duration = Duration::from_millis(500);
+
+fn unix_time() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode::serde::encode_to_vec(value, bincode::config::standard()).unwrap_or_default()
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    bincode::serde::decode_from_slice(bytes, bincode::config::standard()).ok().map(|(value, _)| value)
+}
+
+/// A command from a connected player. `player_id` is the one their token was issued to.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct PlayerCommand {
+    pub player_id: u64,
+    pub input: String,
+}
+
+/// Anything else a connected player sent, e.g. chat.
+#[derive(Event, Debug, Clone)]
+pub struct PlayerMessage {
+    pub player_id: u64,
+    pub message: ClientMessage,
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct PlayerConnected {
+    pub player_id: u64,
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct PlayerDisconnected {
+    pub player_id: u64,
+}
+
+#[derive(Resource)]
+pub struct GameServer {
+    server: RenetServer,
+    transport: NetcodeServerTransport,
+}
+
+impl GameServer {
+    pub fn broadcast_snapshot(&mut self, snapshot: &Snapshot) {
+        let bytes = encode(snapshot);
+        for client_id in self.server.clients_id() {
+            self.server.send_message(client_id, DefaultChannel::Unreliable, bytes.clone());
+        }
+    }
+
+    pub fn send_message(&mut self, player_id: u64, message: &ServerMessage) {
+        self.server.send_message(player_id, DefaultChannel::ReliableOrdered, encode(message));
+    }
+
+    pub fn is_connected(&self, player_id: u64) -> bool {
+        self.server.is_connected(player_id)
+    }
+
+    pub fn players(&self) -> Vec<u64> {
+        self.server.clients_id()
+    }
+
+    /// Round trip time to `player_id` in milliseconds.
+    pub fn ping(&self, player_id: u64) -> Option<u32> {
+        self.server.network_info(player_id).ok().map(|info| info.rtt.round() as u32)
+    }
+
+    pub fn broadcast_message(&mut self, message: &ServerMessage) {
+        self.server.broadcast_message(DefaultChannel::ReliableOrdered, encode(message));
+    }
+}
+
+pub struct GameServerPlugin;
+
+impl Plugin for GameServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_cvar(
+            CvarDef::int("sv_port", GAME_PORT as i32)
+                .int_range(1024, 65535)
+                .flags(CvarFlags::ARCHIVE)
+                .description("UDP port players connect to"),
+        )
+        .register_cvar(
+            CvarDef::string("sv_public_ip", "127.0.0.1")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Address players reach the server on; their tokens name it, so it has to match"),
+        )
+        .register_cvar(
+            CvarDef::string("sv_auth_key", "auth.key")
+                .flags(CvarFlags::ARCHIVE)
+                .description("File in the config folder with the key shared with the auth service"),
+        )
+        .add_event::<PlayerCommand>()
+        .add_event::<PlayerMessage>()
+        .add_event::<PlayerConnected>()
+        .add_event::<PlayerDisconnected>()
+        .add_systems(Startup, start_game_server)
+        .add_systems(Update, update_game_server);
+    }
+}
+
+fn start_game_server(mut commands: Commands, config: Res<GameConfig>) {
+    let port = config.get_int("sv_port").unwrap_or(GAME_PORT as i32) as u16;
+    // utan nyckeln kan biljetterna inte kollas, och utan biljett släpps ingen in
+    let key = match read_key(&cfg_path(config.get_str("sv_auth_key").unwrap_or("auth.key"))) {
+        Ok(key) => key,
+        Err(err) => {
+            error!("game server not started: {err}");
+            return;
+        }
+    };
+    let ip = config.get_str("sv_public_ip").unwrap_or("127.0.0.1");
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        error!("game server not started: bad sv_public_ip {ip}");
+        return;
+    };
+    let socket = match UdpSocket::bind(("0.0.0.0", port)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("could not open game port {port}: {err}");
+            return;
+        }
+    };
+    let server_config = ServerConfig {
+        current_time: unix_time(),
+        max_clients: config.get_int("sv_maxplayers").unwrap_or(10).clamp(1, 64) as usize,
+        protocol_id: PROTOCOL_ID,
+        public_addresses: vec![SocketAddr::new(ip, port)],
+        authentication: ServerAuthentication::Secure { private_key: key },
+    };
+    match NetcodeServerTransport::new(server_config, socket) {
+        Ok(transport) => {
+            info!("game server listening on port {port}");
+            commands.insert_resource(GameServer { server: RenetServer::new(ConnectionConfig::default()), transport });
+        }
+        Err(err) => error!("could not start the game server: {err}"),
+    }
+}
+
+/// Why a player who just connected isn't let in, `None` if they are.
+fn refusal(player_id: u64, join: &JoinData, allocated: Option<&AllocatedMatch>, password: &str) -> Option<String> {
+    match allocated {
+        // en matchmakad match tar bara in spelarna den har biljetter för
+        Some(allocated) => {
+            (!allocated.admits(player_id, join.ticket)).then(|| format!("no ticket for match {}", allocated.match_id))
+        }
+        None if password.is_empty() => None,
+        None => (join.password != Some(JoinData::password_hash(password))).then(|| "wrong password".to_string()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_game_server(
+    game: Option<ResMut<GameServer>>,
+    allocated: Option<Res<AllocatedMatch>>,
+    config: Res<GameConfig>,
+    time: Res<Time>,
+    mut commands: EventWriter<PlayerCommand>,
+    mut messages: EventWriter<PlayerMessage>,
+    mut connected: EventWriter<PlayerConnected>,
+    mut disconnected: EventWriter<PlayerDisconnected>,
+) {
+    let Some(mut game) = game else {
+        return;
+    };
+    let GameServer { server, transport } = &mut *game;
+    server.update(time.delta());
+    if let Err(err) = transport.update(time.delta(), server) {
+        warn!("game server: {err}");
+    }
+
+    while let Some(event) = server.get_event() {
+        match event {
+            ServerEvent::ClientConnected { client_id } => {
+                let join = transport.user_data(client_id).map(|d| JoinData::from_user_data(&d)).unwrap_or_default();
+                let password = config.get_str("sv_password").unwrap_or("");
+                if let Some(reason) = refusal(client_id, &join, allocated.as_deref(), password) {
+                    warn!("refusing player {client_id}: {reason}");
+                    server.disconnect(client_id);
+                    continue;
+                }
+                info!("player {client_id} connected");
+                connected.send(PlayerConnected { player_id: client_id });
+            }
+            ServerEvent::ClientDisconnected { client_id, reason } => {
+                info!("player {client_id} left: {reason}");
+                disconnected.send(PlayerDisconnected { player_id: client_id });
+            }
+        }
+    }
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, DefaultChannel::ReliableOrdered) {
+            let command = match decode::<ClientMessage>(&message) {
+                Some(ClientMessage::Command(command)) => command,
+                Some(message) => {
+                    messages.send(PlayerMessage { player_id: client_id, message });
+                    continue;
+                }
+                None => continue,
+            };
+            // id:t i biljetten är det enda vi litar på
+            if command.player_id != client_id {
+                warn!("player {client_id} sent a command as {}, dropping them", command.player_id);
+                server.disconnect(client_id);
+                break;
+            }
+            commands.send(PlayerCommand { player_id: client_id, input: command.input });
+        }
+    }
+    transport.send_packets(server);
+}
+
+/// Where joining a server has got to.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ConnectionStatus {
+    #[default]
+    Disconnected,
+    /// Asking the server's query port for its game port
+    Querying { server: SocketAddr },
+    /// Getting a connect token for the game address; the cookie comes from the auth service,
+    /// along with whether it has an account for us yet
+    Authenticating { addr: SocketAddr, challenge: Option<([u8; 32], bool)> },
+    Connecting { addr: SocketAddr },
+    Connected { addr: SocketAddr },
+    Failed(String),
+}
+
+/// Our connection to a game server, from the first query until we leave.
+#[derive(Resource)]
+pub struct GameConnection {
+    /// För frågor och auth-tjänsten, spelet går över netcodes egen socket
+    socket: Option<UdpSocket>,
+    status: ConnectionStatus,
+    secret: [u8; SECRET_BYTES],
+    /// Följer med i token till servern vi ansluter till
+    join: JoinData,
+    /// När steget vi är på började och när vi skickade senast
+    step_started: Instant,
+    last_sent: Option<Instant>,
+    token_parts: Vec<Option<Vec<u8>>>,
+    client: Option<(RenetClient, NetcodeClientTransport)>,
+}
+
+impl Default for GameConnection {
+    fn default() -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).and_then(|s| s.set_nonblocking(true).map(|_| s));
+        if let Err(err) = &socket {
+            error!("game connection can't open a socket: {err}");
+        }
+        GameConnection {
+            socket: socket.ok(),
+            status: ConnectionStatus::Disconnected,
+            secret: account_secret(),
+            join: JoinData::default(),
+            step_started: Instant::now(),
+            last_sent: None,
+            token_parts: Vec::new(),
+            client: None,
+        }
+    }
+}
+
+impl GameConnection {
+    pub fn status(&self) -> &ConnectionStatus {
+        &self.status
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.status, ConnectionStatus::Connected { .. })
+    }
+
+    pub fn send_command(&mut self, command: &Command) {
+        self.send_message(&ClientMessage::Command(command.clone()));
+    }
+
+    pub fn send_message(&mut self, message: &ClientMessage) {
+        if let (ConnectionStatus::Connected { .. }, Some((client, _))) = (&self.status, &mut self.client) {
+            client.send_message(DefaultChannel::ReliableOrdered, encode(message));
+        }
+    }
+
+    pub fn receive_snapshot(&mut self) -> Option<Snapshot> {
+        let (client, _) = self.client.as_mut()?;
+        decode(&client.receive_message(DefaultChannel::Unreliable)?)
+    }
+
+    pub fn disconnect(&mut self) {
+        if let Some((mut client, mut transport)) = self.client.take() {
+            client.disconnect();
+            transport.disconnect();
+        }
+        self.status = ConnectionStatus::Disconnected;
+    }
+
+    fn step(&mut self, status: ConnectionStatus) {
+        self.status = status;
+        self.step_started = Instant::now();
+        self.last_sent = None;
+    }
+
+    fn fail(&mut self, reason: String) {
+        warn!("can't join: {reason}");
+        self.disconnect();
+        self.status = ConnectionStatus::Failed(reason);
+    }
+
+    /// Time to send the current step's request (again).
+    fn resend_due(&mut self) -> bool {
+        if self.last_sent.is_some_and(|t| t.elapsed() < RESEND) {
+            return false;
+        }
+        self.last_sent = Some(Instant::now());
+        true
+    }
+
+    fn send(&self, bytes: &[u8], to: SocketAddr) {
+        if let Some(socket) = &self.socket {
+            if let Err(err) = socket.send_to(bytes, to) {
+                warn!("joining: {err}");
+            }
+        }
+    }
+
+    /// Takes one part of the token; connects once all of them are here.
+    fn receive_token_part(&mut self, addr: SocketAddr, part: u8, parts: u8, bytes: Vec<u8>) {
+        if self.token_parts.len() != parts as usize {
+            self.token_parts = vec![None; parts as usize];
+        }
+        let Some(slot) = self.token_parts.get_mut(part as usize) else {
+            return;
+        };
+        *slot = Some(bytes);
+        if self.token_parts.iter().any(Option::is_none) {
+            return;
+        }
+        let token: Vec<u8> = self.token_parts.drain(..).flatten().flatten().collect();
+        match connect(&token) {
+            Ok(client) => {
+                info!("connecting to {addr}");
+                self.client = Some(client);
+                self.step(ConnectionStatus::Connecting { addr });
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+}
+
+fn connect(token: &[u8]) -> Result<(RenetClient, NetcodeClientTransport), String> {
+    let connect_token = ConnectToken::read(&mut &token[..]).map_err(|e| format!("bad connect token: {e}"))?;
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| e.to_string())?;
+    let transport = NetcodeClientTransport::new(unix_time(), ClientAuthentication::Secure { connect_token }, socket)
+        .map_err(|e| e.to_string())?;
+    Ok((RenetClient::new(ConnectionConfig::default()), transport))
+}
+
+pub struct GameClientPlugin;
+
+impl Plugin for GameClientPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ClientSkinsPlugin>() {
+            app.add_plugins(ClientSkinsPlugin);
+        }
+        if !app.is_plugin_added::<ClientChatPlugin>() {
+            app.add_plugins(ClientChatPlugin);
+        }
+        if !app.is_plugin_added::<ClientCvarsPlugin>() {
+            app.add_plugins(ClientCvarsPlugin);
+        }
+        if !app.is_plugin_added::<ClientStatsPlugin>() {
+            app.add_plugins(ClientStatsPlugin);
+        }
+        if !app.is_plugin_added::<ClientGameStatePlugin>() {
+            app.add_plugins(ClientGameStatePlugin);
+        }
+        if !app.is_plugin_added::<SessionPlugin>() {
+            app.add_plugins(SessionPlugin);
+        }
+        app.register_cvar(
+            CvarDef::string("password", "").description("Password for the servers you join that have one"),
+        )
+        .init_resource::<GameConnection>()
+        .add_event::<ServerMessage>()
+        .add_systems(Update, (start_joining, advance_joining, update_game_client, follow_server).chain())
+        .add_systems(OnEnter(AppState::MainMenu), leave_game);
+    }
+}
+
+fn start_joining(
+    mut joins: EventReader<JoinServer>,
+    config: Res<GameConfig>,
+    mut connection: ResMut<GameConnection>,
+) {
+    let Some(join) = joins.read().last() else {
+        return;
+    };
+    connection.disconnect();
+    let password = config.get_str("password").unwrap_or("");
+    connection.join = JoinData {
+        ticket: join.ticket,
+        password: (!password.is_empty()).then(|| JoinData::password_hash(password)),
+    };
+    connection.step(ConnectionStatus::Querying { server: join.addr });
+}
+
+fn advance_joining(auth: Res<AuthSession>, lobby: Option<Res<Lobby>>, mut connection: ResMut<GameConnection>) {
+    let status = connection.status.clone();
+    if !matches!(status, ConnectionStatus::Querying { .. } | ConnectionStatus::Authenticating { .. }) {
+        return;
+    }
+    if connection.step_started.elapsed() >= JOIN_TIMEOUT {
+        let who = match status {
+            ConnectionStatus::Querying { server } => server.to_string(),
+            _ => "the auth service".to_string(),
+        };
+        connection.fail(format!("{who} didn't answer"));
+        return;
+    }
+    let packets = connection.socket.as_ref().map(receive_datagrams).unwrap_or_default();
+
+    match status {
+        ConnectionStatus::Querying { server } => {
+            for (bytes, _) in packets.into_iter().filter(|(_, from)| *from == server) {
+                if let Some(QueryPacket::InfoReply { info, .. }) = QueryPacket::decode(&bytes) {
+                    let addr = SocketAddr::new(server.ip(), info.port);
+                    connection.step(ConnectionStatus::Authenticating { addr, challenge: None });
+                    return;
+                }
+            }
+            if connection.resend_due() {
+                connection.send(&QueryPacket::InfoRequest { token: 0 }.encode(), server);
+            }
+        }
+        ConnectionStatus::Authenticating { addr, mut challenge } => {
+            let Some(service) = auth.service() else {
+                connection.fail("cl_auth isn't set".into());
+                return;
+            };
+            for (bytes, _) in packets.into_iter().filter(|(_, from)| *from == service) {
+                match AuthPacket::decode(&bytes) {
+                    Some(AuthPacket::Challenge { cookie, new_account }) => {
+                        challenge = Some((cookie, new_account));
+                        connection.status = ConnectionStatus::Authenticating { addr, challenge };
+                        connection.last_sent = None;
+                    }
+                    Some(AuthPacket::Token { server, part, parts, bytes }) if server == addr => {
+                        connection.receive_token_part(addr, part, parts, bytes);
+                    }
+                    Some(AuthPacket::Denied { reason }) => {
+                        connection.fail(reason);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+            if !matches!(connection.status, ConnectionStatus::Authenticating { .. }) || !connection.resend_due() {
+                return;
+            }
+            let player_id = lobby.map_or_else(local_player_id, |l| l.local_id());
+            let packet = match challenge {
+                None => AuthPacket::Hello { player_id },
+                Some((cookie, new_account)) => AuthPacket::TokenRequest {
+                    login: LoginProof::new(&connection.secret, player_id, cookie, new_account),
+                    server: addr,
+                    join: connection.join.clone(),
+                },
+            };
+            connection.send(&packet.encode(), service);
+        }
+        _ => {}
+    }
+}
+
+fn update_game_client(
+    time: Res<Time>,
+    mut connection: ResMut<GameConnection>,
+    mut messages: EventWriter<ServerMessage>,
+) {
+    if connection.client.is_none() {
+        return;
+    }
+    let connection = &mut *connection;
+    let Some((client, transport)) = &mut connection.client else {
+        return;
+    };
+    client.update(time.delta());
+    if let Err(err) = transport.update(time.delta(), client) {
+        let reason = err.to_string();
+        connection.fail(reason);
+        return;
+    }
+    if client.is_disconnected() {
+        let reason = client.disconnect_reason().map_or("disconnected".to_string(), |r| r.to_string());
+        connection.fail(reason);
+        return;
+    }
+    if let (ConnectionStatus::Connecting { addr }, true) = (&connection.status, client.is_connected()) {
+        info!("connected to {addr}");
+        connection.status = ConnectionStatus::Connected { addr: *addr };
+    }
+    while let Some(message) = client.receive_message(DefaultChannel::ReliableOrdered) {
+        if let Some(message) = decode::<ServerMessage>(&message) {
+            messages.send(message);
+        }
+    }
+    if let Err(err) = transport.send_packets(client) {
+        warn!("game connection: {err}");
+    }
+}
+
+/// While we're connected the server decides the REPLICATED cvars, and the game doesn't stop
+/// when we pause.
+fn follow_server(connection: Res<GameConnection>, mut config: ResMut<GameConfig>) {
+    let connected = connection.is_connected();
+    if config.server_authoritative != connected {
+        config.server_authoritative = connected;
+    }
+}
+
+fn leave_game(mut connection: ResMut<GameConnection>) {
+    if connection.status != ConnectionStatus::Disconnected {
+        connection.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::game_state::Team;
+    use shared::lobby::GameMode;
+    use shared::maps::MapId;
+
+    use super::*;
+    use crate::matchmaking::{ConnectTicket, MATCHMAKER_PORT};
+
+    fn join(ticket: Option<u64>, password: Option<&str>) -> JoinData {
+        JoinData { ticket, password: password.map(JoinData::password_hash) }
+    }
+
+    #[test]
+    fn the_server_is_in_charge_only_while_we_are_connected() {
+        let mut app = App::new();
+        app.init_resource::<GameConfig>().init_resource::<GameConnection>().add_systems(Update, follow_server);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 27015));
+        app.world_mut().resource_mut::<GameConnection>().status = ConnectionStatus::Connecting { addr };
+        app.update();
+        assert!(!app.world().resource::<GameConfig>().server_authoritative);
+
+        app.world_mut().resource_mut::<GameConnection>().status = ConnectionStatus::Connected { addr };
+        app.update();
+        assert!(app.world().resource::<GameConfig>().server_authoritative);
+
+        // både att lämna och att tappa anslutningen lämnar tillbaka kontrollen
+        app.world_mut().resource_mut::<GameConnection>().disconnect();
+        app.update();
+        assert!(!app.world().resource::<GameConfig>().server_authoritative);
+        app.world_mut().resource_mut::<GameConnection>().status = ConnectionStatus::Connected { addr };
+        app.update();
+        app.world_mut().resource_mut::<GameConnection>().fail("timed out".into());
+        app.update();
+        assert!(!app.world().resource::<GameConfig>().server_authoritative);
+    }
+
+    #[test]
+    fn a_password_server_only_lets_in_players_who_give_it() {
+        assert_eq!(refusal(1, &join(None, None), None, ""), None);
+        assert_eq!(refusal(1, &join(None, Some("hemligt")), None, "hemligt"), None);
+        assert!(refusal(1, &join(None, Some("fel")), None, "hemligt").is_some());
+        assert!(refusal(1, &join(None, None), None, "hemligt").is_some());
+    }
+
+    #[test]
+    fn a_matchmade_server_only_lets_in_players_with_their_ticket() {
+        let allocated = AllocatedMatch {
+            match_id: 1,
+            mode: GameMode::Competitive,
+            map: MapId::BoxArena,
+            tickets: vec![ConnectTicket { match_id: 1, player_id: 1, team: Team::Terrorist, token: 11 }],
+            matchmaker: ([127, 0, 0, 1], MATCHMAKER_PORT).into(),
+        };
+        assert_eq!(refusal(1, &join(Some(11), None), Some(&allocated), ""), None);
+        assert!(refusal(1, &join(None, None), Some(&allocated), "").is_some());
+        assert!(refusal(2, &join(Some(11), None), Some(&allocated), "").is_some());
+        // lösenordet hjälper inte utan biljett
+        assert!(refusal(2, &join(None, Some("hemligt")), Some(&allocated), "hemligt").is_some());
+    }
+}
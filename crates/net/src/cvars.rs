@@ -0,0 +1,95 @@
+//! The server's REPLICATED cvars on every player's machine.
+//!
+//! The game server sends a player all its replicated cvars when they connect, and the ones
+//! that change after that to everybody, with [`ServerMessage::Cvars`]. Clients set them
+//! with `GameConfig::set_replicated`, which only takes REPLICATED cvars, so a server can't
+//! touch anything else. While connected the player can't change them themselves.
+use bevy::prelude::*;
+use shared::config::GameConfig;
+
+use crate::connection::{GameServer, PlayerConnected};
+use crate::protocol::ServerMessage;
+
+/// Sends the players the server's replicated cvars.
+pub struct ServerCvarsPlugin;
+
+impl Plugin for ServerCvarsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, send_cvars);
+    }
+}
+
+/// `sent` is what everybody has been sent so far.
+fn send_cvars(
+    mut connected: EventReader<PlayerConnected>,
+    config: Res<GameConfig>,
+    game: Option<ResMut<GameServer>>,
+    mut sent: Local<Vec<(String, String)>>,
+) {
+    let Some(mut game) = game else {
+        connected.clear();
+        return;
+    };
+    if config.is_changed() {
+        let current = config.replicated();
+        let changed: Vec<_> = current.iter().filter(|cvar| !sent.contains(cvar)).cloned().collect();
+        if !changed.is_empty() {
+            game.broadcast_message(&ServerMessage::Cvars(changed));
+        }
+        *sent = current;
+    }
+    for &PlayerConnected { player_id } in connected.read() {
+        game.send_message(player_id, &ServerMessage::Cvars(sent.clone()));
+    }
+}
+
+/// Takes the replicated cvars from the server we're on.
+pub struct ClientCvarsPlugin;
+
+impl Plugin for ClientCvarsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ServerMessage>().add_systems(Update, receive_cvars);
+    }
+}
+
+fn receive_cvars(mut messages: EventReader<ServerMessage>, mut config: ResMut<GameConfig>) {
+    for message in messages.read() {
+        let ServerMessage::Cvars(cvars) = message else {
+            continue;
+        };
+        for (name, value) in cvars {
+            if let Err(err) = config.set_replicated(name, value) {
+                warn!("server cvar: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::cvars::{CvarDef, CvarError, CvarFlags};
+
+    use super::*;
+
+    #[test]
+    fn the_server_only_sets_replicated_cvars() {
+        let mut app = App::new();
+        let mut config = GameConfig::default();
+        config.register(CvarDef::int("mp_maxrounds", 24).flags(CvarFlags::REPLICATED));
+        config.register(CvarDef::float("sensitivity", 2.0));
+        config.server_authoritative = true;
+        app.insert_resource(config).add_plugins(ClientCvarsPlugin);
+
+        let cvars = [("mp_maxrounds", "30"), ("sv_cheats", "1"), ("sensitivity", "9")];
+        let cvars = cvars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        app.world_mut().send_event(ServerMessage::Cvars(cvars));
+        app.update();
+
+        let mut config = app.world_mut().resource_mut::<GameConfig>();
+        assert_eq!(config.get_int("mp_maxrounds"), Some(30));
+        assert_eq!(config.get_bool("sv_cheats"), Some(true));
+        assert_eq!(config.get_float("sensitivity"), Some(2.0));
+        // spelaren själv får inte ändra dem medan servern bestämmer
+        assert_eq!(config.set("mp_maxrounds", "5"), Err(CvarError::ServerControlled("mp_maxrounds".into())));
+    }
+}
@@ -0,0 +1,427 @@
+//! Rounds and each player's own state, run by the game server.
+//!
+//! The server gives every player entity from `net::stats` a [`LocalPlayerState`] and sends
+//! it to its owner with [`ServerMessage::PlayerState`] whenever it changes. Whatever hurts
+//! a player on the server raises [`PlayerDamaged`]: the victim hears of it with
+//! [`ServerMessage::Damage`], the attacker with [`ServerMessage::Hit`], and a death goes to
+//! everybody as [`ServerMessage::Kill`]. The rounds follow the `mp_*` cvars and the
+//! [`RoundState`] is sent whenever it moves on. Clients take all of it as their HUD state
+//! and events.
+use std::collections::HashMap;
+use std::mem::discriminant;
+
+use bevy::prelude::*;
+use shared::config::GameConfig;
+use shared::game_state::{
+    Combatant, DamageTaken, GameStatePlugin, HitConfirmed, KillEvent, LocalPlayerState, MatchStatsPlugin,
+    PlayerStats, RoundPhase, RoundState, Team,
+};
+
+use crate::connection::{GameConnection, GameServer, PlayerConnected};
+use crate::protocol::ServerMessage;
+use crate::stats::START_MONEY;
+
+/// Hur länge vinnaren visas innan nästa runda börjar, i sekunder
+const ROUND_END_TIME: f32 = 5.0;
+const MAX_MONEY: u32 = 16000;
+const KILL_REWARD: u32 = 300;
+const WIN_REWARD: u32 = 3250;
+const LOSS_REWARD: u32 = 1400;
+/// Skada som krävs för en assist, som i CS
+const ASSIST_DAMAGE: u32 = 41;
+
+/// Something on the game server hurt a player.
+#[derive(Event, Debug, Clone)]
+pub struct PlayerDamaged {
+    /// `None` for damage from the world, e.g. falling.
+    pub attacker: Option<u64>,
+    pub victim: u64,
+    pub amount: u32,
+    pub weapon: String,
+    pub headshot: bool,
+    /// Where the damage came from.
+    pub source: Option<Vec3>,
+}
+
+/// Damage each attacker has done to a player since they last spawned, for assists.
+#[derive(Component, Default)]
+struct DamageReceived(HashMap<u64, u32>);
+
+/// The server's players, with everything the rounds change on them.
+type Players<'w, 's> =
+    Query<'w, 's, (&'static mut PlayerStats, &'static mut LocalPlayerState, &'static mut DamageReceived)>;
+
+/// Runs the rounds and the players' health and money on the game server.
+pub struct ServerGameStatePlugin;
+
+impl Plugin for ServerGameStatePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<MatchStatsPlugin>() {
+            app.add_plugins(MatchStatsPlugin);
+        }
+        app.add_event::<PlayerDamaged>().add_systems(
+            Update,
+            (equip_players, apply_damage, run_rounds, sync_player_states, send_player_states, send_round).chain(),
+        );
+    }
+}
+
+fn equip_players(mut commands: Commands, players: Query<(Entity, &PlayerStats), Without<LocalPlayerState>>) {
+    for (entity, stats) in &players {
+        let state = LocalPlayerState {
+            player_id: stats.player_id,
+            name: stats.name.clone(),
+            team: stats.team,
+            alive: stats.alive,
+            money: stats.money,
+            ..default()
+        };
+        // spelaren kan ha gått samma frame
+        commands.entity(entity).try_insert((state, DamageReceived::default()));
+    }
+}
+
+fn combatant(players: &Players, id: u64) -> Option<Combatant> {
+    let (stats, ..) = players.iter().find(|(stats, ..)| stats.player_id == id)?;
+    Some(Combatant { player_id: id, name: stats.name.clone(), team: stats.team? })
+}
+
+fn apply_damage(
+    mut damaged: EventReader<PlayerDamaged>,
+    mut kills: EventWriter<KillEvent>,
+    mut players: Players,
+    mut game: Option<ResMut<GameServer>>,
+) {
+    for hurt in damaged.read() {
+        let Some((mut stats, mut state, mut received)) =
+            players.iter_mut().find(|(stats, ..)| stats.player_id == hurt.victim)
+        else {
+            continue;
+        };
+        if !stats.alive {
+            continue;
+        }
+        let amount = hurt.amount.min(state.health);
+        state.health -= amount;
+        if let Some(attacker) = hurt.attacker {
+            *received.0.entry(attacker).or_default() += amount;
+        }
+        let killed = state.health == 0;
+        if killed {
+            stats.alive = false;
+        }
+        // den som gjort mest skada förutom mördaren, om det räcker
+        let assister = received
+            .0
+            .iter()
+            .filter(|&(&id, &damage)| Some(id) != hurt.attacker && id != hurt.victim && damage >= ASSIST_DAMAGE)
+            .max_by_key(|&(_, &damage)| damage)
+            .map(|(&id, _)| id);
+
+        if let Some(game) = &mut game {
+            let source = hurt.source.map(|source| source.to_array());
+            game.send_message(hurt.victim, &ServerMessage::Damage { amount, source });
+            if let Some(attacker) = hurt.attacker.filter(|&attacker| attacker != hurt.victim) {
+                game.send_message(attacker, &ServerMessage::Hit(HitConfirmed { headshot: hurt.headshot, killed }));
+            }
+        }
+        if !killed {
+            continue;
+        }
+        // åskådare syns inte i killfeeden
+        let Some(victim) = combatant(&players, hurt.victim) else {
+            continue;
+        };
+        let killer = hurt.attacker.filter(|&id| id != hurt.victim).and_then(|id| combatant(&players, id));
+        if let Some(killer) = killer.as_ref().filter(|killer| killer.team != victim.team) {
+            if let Some((mut stats, ..)) = players.iter_mut().find(|(stats, ..)| stats.player_id == killer.player_id) {
+                stats.money = (stats.money + KILL_REWARD).min(MAX_MONEY);
+            }
+        }
+        let kill = KillEvent {
+            killer,
+            assister: assister.and_then(|id| combatant(&players, id)),
+            victim,
+            weapon: hurt.weapon.clone(),
+            headshot: hurt.headshot,
+        };
+        if let Some(game) = &mut game {
+            game.broadcast_message(&ServerMessage::Kill(kill.clone()));
+        }
+        kills.send(kill);
+    }
+}
+
+/// Alla i laget är döda, och det fanns någon i det
+fn wiped(players: &Players, team: Team) -> bool {
+    let mut members = players.iter().filter(|(stats, ..)| stats.team == Some(team)).peekable();
+    members.peek().is_some() && members.all(|(stats, ..)| !stats.alive)
+}
+
+fn start_round(round: &mut RoundState, config: &GameConfig, players: &mut Players) {
+    round.phase = RoundPhase::FreezeTime;
+    round.time_left = config.get_float("mp_freezetime").unwrap_or(5.0);
+    for (mut stats, mut state, mut received) in players {
+        stats.alive = true;
+        // en ny match börjar om pengarna
+        if round.round == 1 {
+            stats.money = START_MONEY;
+        }
+        state.health = 100;
+        received.0.clear();
+    }
+}
+
+fn end_round(round: &mut RoundState, winner: Team, players: &mut Players) {
+    round.phase = RoundPhase::Ended(winner);
+    round.time_left = ROUND_END_TIME;
+    match winner {
+        Team::CounterTerrorist => round.score_ct += 1,
+        Team::Terrorist => round.score_t += 1,
+    }
+    for (mut stats, ..) in players.iter_mut().filter(|(stats, ..)| stats.team.is_some()) {
+        let reward = if stats.team == Some(winner) { WIN_REWARD } else { LOSS_REWARD };
+        stats.money = (stats.money + reward).min(MAX_MONEY);
+    }
+}
+
+fn run_rounds(config: Res<GameConfig>, mut round: ResMut<RoundState>, mut players: Players) {
+    match round.phase {
+        // matchen börjar när någon har kommit med i ett lag
+        RoundPhase::Warmup => {
+            if players.iter().any(|(stats, ..)| stats.team.is_some()) {
+                *round = RoundState {
+                    round: 1,
+                    max_rounds: config.get_int("mp_maxrounds").unwrap_or(24).max(1) as u32,
+                    ..default()
+                };
+                start_round(&mut round, &config, &mut players);
+            }
+        }
+        RoundPhase::Live => {
+            let winner = if wiped(&players, Team::Terrorist) {
+                Some(Team::CounterTerrorist)
+            } else if wiped(&players, Team::CounterTerrorist) {
+                Some(Team::Terrorist)
+            } else if round.time_left <= 0.0 {
+                // tiden tog slut, försvararna vinner
+                Some(Team::CounterTerrorist)
+            } else {
+                None
+            };
+            if let Some(winner) = winner {
+                end_round(&mut round, winner, &mut players);
+            }
+        }
+        _ if round.time_left > 0.0 => {}
+        RoundPhase::FreezeTime => {
+            round.phase = RoundPhase::Live;
+            round.time_left = config.get_float("mp_roundtime").unwrap_or(115.0);
+        }
+        RoundPhase::Ended(_) => {
+            if round.is_match_over() {
+                // tillbaka till warmup, nästa frame börjar en ny match
+                *round = RoundState { max_rounds: round.max_rounds, ..default() };
+            } else {
+                round.round += 1;
+                start_round(&mut round, &config, &mut players);
+            }
+        }
+    }
+}
+
+/// Keeps what the scoreboard also shows the same in the player's own state.
+fn sync_player_states(mut players: Query<(&PlayerStats, &mut LocalPlayerState), Changed<PlayerStats>>) {
+    for (stats, mut state) in &mut players {
+        let synced = LocalPlayerState {
+            name: stats.name.clone(),
+            team: stats.team,
+            alive: stats.alive,
+            money: stats.money,
+            ..state.clone()
+        };
+        // bara riktiga ändringar skickas
+        state.set_if_neq(synced);
+    }
+}
+
+fn send_player_states(players: Query<&LocalPlayerState, Changed<LocalPlayerState>>, game: Option<ResMut<GameServer>>) {
+    let Some(mut game) = game else {
+        return;
+    };
+    for state in &players {
+        game.send_message(state.player_id, &ServerMessage::PlayerState(state.clone()));
+    }
+}
+
+/// `sent` is the round as it was last sent; the clocks run on by themselves in between.
+fn send_round(
+    mut connected: EventReader<PlayerConnected>,
+    round: Res<RoundState>,
+    game: Option<ResMut<GameServer>>,
+    mut sent: Local<Option<RoundState>>,
+) {
+    let Some(mut game) = game else {
+        connected.clear();
+        return;
+    };
+    let moved_on = sent.as_ref().is_none_or(|sent| {
+        sent.phase != round.phase
+            || sent.round != round.round
+            || sent.max_rounds != round.max_rounds
+            || (sent.score_ct, sent.score_t) != (round.score_ct, round.score_t)
+            || discriminant(&sent.bomb) != discriminant(&round.bomb)
+    });
+    if moved_on {
+        game.broadcast_message(&ServerMessage::Round(round.clone()));
+        *sent = Some(round.clone());
+    }
+    for &PlayerConnected { player_id } in connected.read() {
+        game.send_message(player_id, &ServerMessage::Round(round.clone()));
+    }
+}
+
+/// Takes the round, our own state and what happens to us from the server.
+pub struct ClientGameStatePlugin;
+
+impl Plugin for ClientGameStatePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<GameStatePlugin>() {
+            app.add_plugins(GameStatePlugin);
+        }
+        app.add_event::<ServerMessage>().add_systems(Update, (receive_game_state, forget_game_state).chain());
+    }
+}
+
+fn receive_game_state(
+    mut messages: EventReader<ServerMessage>,
+    mut round: ResMut<RoundState>,
+    mut player: ResMut<LocalPlayerState>,
+    mut kills: EventWriter<KillEvent>,
+    mut hits: EventWriter<HitConfirmed>,
+    mut damage: EventWriter<DamageTaken>,
+) {
+    for message in messages.read() {
+        match message {
+            ServerMessage::Round(new) => *round = new.clone(),
+            ServerMessage::PlayerState(new) => *player = new.clone(),
+            ServerMessage::Kill(kill) => {
+                kills.send(kill.clone());
+            }
+            ServerMessage::Hit(hit) => {
+                hits.send(*hit);
+            }
+            ServerMessage::Damage { amount, source } => {
+                damage.send(DamageTaken { amount: *amount, source: source.map(Vec3::from_array) });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The round and our state are the server's, so they go when we leave.
+fn forget_game_state(
+    connection: Res<GameConnection>,
+    mut round: ResMut<RoundState>,
+    mut player: ResMut<LocalPlayerState>,
+    mut was_connected: Local<bool>,
+) {
+    let connected = connection.is_connected();
+    if *was_connected && !connected {
+        *round = RoundState::default();
+        *player = LocalPlayerState::default();
+    }
+    *was_connected = connected;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_player(app: &mut App, player_id: u64, team: Team, alive: bool) {
+        let stats = PlayerStats { player_id, name: format!("p{player_id}"), team: Some(team), alive, ..default() };
+        let state = LocalPlayerState { player_id, team: Some(team), alive, ..default() };
+        app.world_mut().spawn((stats, state, DamageReceived::default()));
+    }
+
+    fn player(app: &mut App, player_id: u64) -> (PlayerStats, LocalPlayerState) {
+        let mut players = app.world_mut().query::<(&PlayerStats, &LocalPlayerState)>();
+        let (stats, state) = players.iter(app.world()).find(|(stats, _)| stats.player_id == player_id).unwrap();
+        (stats.clone(), state.clone())
+    }
+
+    fn hurt(attacker: u64, victim: u64, amount: u32) -> PlayerDamaged {
+        PlayerDamaged { attacker: Some(attacker), victim, amount, weapon: "ak47".into(), headshot: false, source: None }
+    }
+
+    #[test]
+    fn a_kill_goes_to_the_killer_and_the_assister() {
+        let mut app = App::new();
+        app.add_event::<PlayerDamaged>().add_event::<KillEvent>().add_systems(Update, apply_damage);
+        spawn_player(&mut app, 1, Team::Terrorist, true);
+        spawn_player(&mut app, 2, Team::CounterTerrorist, true);
+        spawn_player(&mut app, 3, Team::CounterTerrorist, true);
+
+        app.world_mut().send_event(hurt(2, 1, 50));
+        app.world_mut().send_event(hurt(3, 1, 80));
+        // döda kan inte skadas igen
+        app.world_mut().send_event(hurt(3, 1, 10));
+        app.update();
+
+        let (stats, state) = player(&mut app, 1);
+        assert!(!stats.alive);
+        assert_eq!(state.health, 0);
+        assert_eq!(player(&mut app, 3).0.money, KILL_REWARD);
+        let kills: Vec<_> = app.world_mut().resource_mut::<Events<KillEvent>>().drain().collect();
+        assert_eq!(kills.len(), 1);
+        assert_eq!(kills[0].killer.as_ref().map(|k| k.player_id), Some(3));
+        assert_eq!(kills[0].assister.as_ref().map(|a| a.player_id), Some(2));
+        assert_eq!(kills[0].victim.player_id, 1);
+    }
+
+    #[test]
+    fn a_wiped_team_loses_the_round_and_everyone_comes_back() {
+        let mut app = App::new();
+        let round = RoundState { phase: RoundPhase::Live, round: 1, max_rounds: 24, time_left: 50.0, ..default() };
+        app.insert_resource(GameConfig::default()).insert_resource(round).add_systems(Update, run_rounds);
+        spawn_player(&mut app, 1, Team::CounterTerrorist, true);
+        spawn_player(&mut app, 2, Team::Terrorist, false);
+
+        app.update();
+        let round = app.world().resource::<RoundState>();
+        assert_eq!(round.phase, RoundPhase::Ended(Team::CounterTerrorist));
+        assert_eq!((round.score_ct, round.score_t), (1, 0));
+        assert_eq!(player(&mut app, 1).0.money, WIN_REWARD);
+        assert_eq!(player(&mut app, 2).0.money, LOSS_REWARD);
+
+        app.world_mut().resource_mut::<RoundState>().time_left = 0.0;
+        app.update();
+        let round = app.world().resource::<RoundState>();
+        assert_eq!((round.phase, round.round), (RoundPhase::FreezeTime, 2));
+        let (stats, state) = player(&mut app, 2);
+        assert!(stats.alive);
+        assert_eq!(state.health, 100);
+    }
+
+    #[test]
+    fn the_hud_state_comes_from_the_server() {
+        let mut app = App::new();
+        app.insert_resource(GameConfig::default())
+            .init_resource::<Time>()
+            .init_resource::<GameConnection>()
+            .add_plugins(ClientGameStatePlugin);
+
+        let round = RoundState { phase: RoundPhase::Live, round: 3, time_left: 60.0, ..default() };
+        let player = LocalPlayerState { player_id: 7, health: 35, money: 2400, ..default() };
+        app.world_mut().send_event(ServerMessage::Round(round));
+        app.world_mut().send_event(ServerMessage::PlayerState(player.clone()));
+        app.world_mut().send_event(ServerMessage::Damage { amount: 65, source: Some([1.0, 2.0, 3.0]) });
+        app.update();
+
+        assert_eq!(app.world().resource::<RoundState>().round, 3);
+        assert_eq!(*app.world().resource::<LocalPlayerState>(), player);
+        let damage: Vec<_> = app.world_mut().resource_mut::<Events<DamageTaken>>().drain().collect();
+        assert_eq!(damage.len(), 1);
+        assert_eq!(damage[0].source, Some(Vec3::new(1.0, 2.0, 3.0)));
+    }
+}
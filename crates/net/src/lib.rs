@@ -1,5 +1,22 @@
+pub mod query;
+pub mod master;
+pub mod browser;
+pub mod matchmaking;
+pub mod profile;
+pub mod auth;
+pub mod connection;
+pub mod skins;
+pub mod chat;
+pub mod cvars;
+pub mod stats;
+pub mod game_state;
+
 pub mod protocol {
+    use bevy::prelude::Event;
     use serde::{Serialize, Deserialize};
+    use shared::chat::{ChatMessage, SendChat};
+    use shared::game_state::{HitConfirmed, KillEvent, LocalPlayerState, PlayerStats, RoundState, Team};
+    use shared::items::WeaponSkin;
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Command {
@@ -7,9 +24,45 @@ pub mod protocol {
         pub input: String, // TODO: byt mot enum
     }
 
+    /// Sent reliably by a player; the server knows who from the connection.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub enum ClientMessage {
+        Command(Command),
+        /// The name to show in chat, sent on joining and whenever it changes.
+        Name(String),
+        /// Chat for the server to check and pass on.
+        Chat(SendChat),
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Snapshot {
         pub tick: u32,
         pub state: String, // TODO: serialiserat world state
     }
+
+    /// Sent reliably, unlike snapshots; only when something changes.
+    #[derive(Event, Debug, Clone, Serialize, Deserialize)]
+    pub enum ServerMessage {
+        /// What `player_id` has equipped, empty once they've left.
+        Skins { player_id: u64, skins: Vec<(Team, WeaponSkin)> },
+        /// Chat the server let through to us, or a notice for us alone.
+        Chat(ChatMessage),
+        /// Names and values of the server's REPLICATED cvars: all of them when we join,
+        /// then the ones that change.
+        Cvars(Vec<(String, String)>),
+        /// Someone's line on the scoreboard, whenever it changes.
+        Stats(PlayerStats),
+        /// `player_id` left the server.
+        PlayerLeft { player_id: u64 },
+        /// The round, when it moves on and to players who join.
+        Round(RoundState),
+        /// Our own state, whenever it changes.
+        PlayerState(LocalPlayerState),
+        /// Someone died, for the kill feed.
+        Kill(KillEvent),
+        /// One of our shots hit someone.
+        Hit(HitConfirmed),
+        /// We were hurt, from `source` if it came from somewhere.
+        Damage { amount: u32, source: Option<[f32; 3]> },
+    }
 }
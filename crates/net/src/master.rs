@@ -0,0 +1,81 @@
+//! Master server protocol, for finding servers outside the LAN.
+//!
+//! Game servers send a [`MasterPacket::Heartbeat`] from their query socket every
+//! [`HEARTBEAT_INTERVAL`], so the master learns the address clients should query. The master
+//! answers with a [`MasterPacket::Challenge`] and only lists the address once the server has
+//! sent it back in a [`MasterPacket::ChallengeReply`], so nobody can list an address they
+//! don't get packets on. Clients
+//! send a [`MasterPacket::ListRequest`] and get the registered addresses back in one or more
+//! [`MasterPacket::ListReply`] packets. The master itself is `apps/master`.
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::query::{MAX_PACKET_SIZE, REQUEST_SIZE};
+
+pub const MASTER_PORT: u16 = 27010;
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Servers that miss this many heartbeats in a row are dropped from the list.
+pub const MISSED_HEARTBEATS: u32 = 3;
+/// Addresses per reply, keeps every reply below `MAX_PACKET_SIZE`.
+pub const SERVERS_PER_REPLY: usize = 48;
+
+const MAGIC: &[u8; 4] = b"FPSM";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MasterPacket {
+    Heartbeat,
+    /// Svaret på en heartbeat, servern skickar tillbaka det för att bli listad
+    Challenge { challenge: u64 },
+    ChallengeReply { challenge: u64 },
+    /// Ber om listan; `token` kommer tillbaka i varje svar
+    ListRequest { token: u32 },
+    /// `last` is set on the final reply of a list
+    ListReply { token: u32, servers: Vec<SocketAddr>, last: bool },
+}
+
+impl MasterPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serde::encode_to_vec(self, bincode::config::standard()).unwrap_or_default());
+        // samma skydd mot förstärkning som frågorna
+        if self.is_request() {
+            bytes.resize(REQUEST_SIZE, 0);
+        }
+        bytes
+    }
+
+    /// `None` for anything that isn't a well-formed master packet.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC)?;
+        let config = bincode::config::standard().with_limit::<MAX_PACKET_SIZE>();
+        let (packet, _) = bincode::serde::decode_from_slice::<MasterPacket, _>(body, config).ok()?;
+        if packet.is_request() && bytes.len() < REQUEST_SIZE {
+            return None;
+        }
+        Some(packet)
+    }
+
+    /// Packets the master answers, padded to `REQUEST_SIZE`.
+    fn is_request(&self) -> bool {
+        matches!(self, MasterPacket::Heartbeat | MasterPacket::ListRequest { .. })
+    }
+
+    /// Splits a server list into replies that each fit in a packet.
+    pub fn list_replies(token: u32, servers: &[SocketAddr]) -> Vec<MasterPacket> {
+        if servers.is_empty() {
+            return vec![MasterPacket::ListReply { token, servers: Vec::new(), last: true }];
+        }
+        let chunks = servers.chunks(SERVERS_PER_REPLY);
+        let count = chunks.len();
+        chunks
+            .enumerate()
+            .map(|(i, chunk)| MasterPacket::ListReply {
+                token,
+                servers: chunk.to_vec(),
+                last: i + 1 == count,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,444 @@
+//! Matchmaking protocol and the client side of it.
+//!
+//! The party leader's client sends [`MatchmakingPacket::Enqueue`] to the matchmaker
+//! (`apps/matchmaker`) and keeps sending it every [`KEEPALIVE_INTERVAL`] while searching; a
+//! party that goes quiet for [`SESSION_TIMEOUT`] leaves the queue. When a match is formed every
+//! party gets a [`MatchmakingPacket::MatchFound`] and has [`READY_CHECK_SECONDS`] to accept.
+//! Once everybody has, the matchmaker hands the match to a game server from its pool with
+//! [`MatchmakingPacket::Allocate`] and sends each party its [`ConnectTicket`]s. When the match
+//! is over the server sends [`MatchmakingPacket::Release`] with the score, the matchmaker
+//! rates the players and the server goes back in the pool.
+//!
+//! `Enqueue` carries the leader's session from the auth service, and for a party of more than
+//! one the [`PartyPass`] the profile service gave for it, so nobody can queue players that
+//! aren't in their party. The rest of the search is tied to the address the party queued
+//! from. Ranks aren't secret: anyone may ask for anyone's with `RankRequest`.
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::game_state::Team;
+use shared::lobby::{GameMode, Lobby};
+use shared::maps::MapId;
+use shared::rating::RankSummary;
+
+use crate::auth::{AuthSession, PartyPass, Session, SessionPlugin};
+use crate::profile::ProfileClient;
+use crate::query::{receive_datagrams, resolve_address_cvar, MAX_PACKET_SIZE};
+
+pub const MATCHMAKER_PORT: u16 = 27012;
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(2);
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+pub const READY_CHECK_SECONDS: u32 = 20;
+/// How often the client asks for its rank.
+pub const RANK_REFRESH: Duration = Duration::from_secs(60);
+
+const MAGIC: &[u8; 4] = b"FPSW";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartyMember {
+    pub id: u64,
+    pub name: String,
+}
+
+/// Lets one player into one match. The game server gets the same tickets with the
+/// allocation and only lets in players holding one; the player brings theirs in the
+/// connect token (see `auth::JoinData`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectTicket {
+    pub match_id: u64,
+    pub player_id: u64,
+    pub team: Team,
+    pub token: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MatchmakingPacket {
+    // klient -> matchmaker
+    /// Joins the queue, or keeps the party in it. An empty `maps` takes any map. `pass` is
+    /// needed for more than one player.
+    Enqueue { party: Vec<PartyMember>, mode: GameMode, maps: Vec<MapId>, session: Session, pass: Option<PartyPass> },
+    Cancel,
+    Accept { match_id: u64 },
+    Decline { match_id: u64 },
+    RankRequest { player_id: u64 },
+
+    // matchmaker -> klient
+    Searching { players_in_queue: u32 },
+    MatchFound { match_id: u64, mode: GameMode, map: MapId, seconds_left: u32 },
+    ReadyCheck { match_id: u64, accepted: u32, needed: u32 },
+    /// Somebody didn't accept. `requeued` parties keep their place in the queue.
+    MatchCancelled { match_id: u64, requeued: bool },
+    Tickets { server: SocketAddr, map: MapId, tickets: Vec<ConnectTicket> },
+    Rejected { reason: String },
+    RankReply { player_id: u64, summary: RankSummary },
+
+    // matchmaker <-> spelserver
+    Allocate { match_id: u64, mode: GameMode, map: MapId, tickets: Vec<ConnectTicket> },
+    /// The match is over. `rounds` are the rounds won by CT and T, `None` if it never finished
+    /// and shouldn't be rated.
+    Release { match_id: u64, rounds: Option<[u32; 2]> },
+}
+
+impl MatchmakingPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serde::encode_to_vec(self, bincode::config::standard()).unwrap_or_default());
+        bytes
+    }
+
+    /// `None` for anything that isn't a well-formed matchmaking packet.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC)?;
+        let config = bincode::config::standard().with_limit::<MAX_PACKET_SIZE>();
+        bincode::serde::decode_from_slice(body, config).ok().map(|(packet, _)| packet)
+    }
+}
+
+/// The match a game server was given by the matchmaker.
+#[derive(Resource, Debug, Clone)]
+pub struct AllocatedMatch {
+    pub match_id: u64,
+    pub mode: GameMode,
+    pub map: MapId,
+    /// Bara spelare med en av dessa får komma in
+    pub tickets: Vec<ConnectTicket>,
+    pub matchmaker: SocketAddr,
+}
+
+impl AllocatedMatch {
+    /// Whether `ticket` is `player_id`'s ticket for this match.
+    pub fn admits(&self, player_id: u64, ticket: Option<u64>) -> bool {
+        self.tickets.iter().any(|t| t.player_id == player_id && Some(t.token) == ticket)
+    }
+
+    /// Tells the matchmaker the match is over so the server goes back in the pool.
+    pub fn release(&self, socket: &UdpSocket, rounds: Option<[u32; 2]>) {
+        let packet = MatchmakingPacket::Release { match_id: self.match_id, rounds };
+        if let Err(err) = socket.send_to(&packet.encode(), self.matchmaker) {
+            warn!("could not release match {}: {err}", self.match_id);
+        }
+    }
+}
+
+/// Where our party is in matchmaking.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum MatchmakingStatus {
+    #[default]
+    Idle,
+    Searching { since: Instant, players_in_queue: u32 },
+    MatchFound {
+        match_id: u64,
+        mode: GameMode,
+        map: MapId,
+        deadline: Instant,
+        accepted_by_us: bool,
+        accepted: u32,
+        needed: u32,
+    },
+    Ready { server: SocketAddr, map: MapId, tickets: Vec<ConnectTicket> },
+}
+
+/// What the UI asks the matchmaking client for.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub enum MatchmakingRequest {
+    Search { party: Vec<PartyMember>, mode: GameMode, maps: Vec<MapId> },
+    Cancel,
+    Accept,
+    Decline,
+}
+
+#[derive(Resource)]
+pub struct MatchmakingClient {
+    socket: Option<UdpSocket>,
+    /// Där `cl_matchmaker` pekar, slås bara upp när den ändras
+    matchmaker: Option<SocketAddr>,
+    pub status: MatchmakingStatus,
+    /// Vad vi köar med, skickas om som keepalive
+    enqueue: Option<MatchmakingPacket>,
+    last_sent: Option<Instant>,
+    last_heard: Option<Instant>,
+    /// Why the last search ended, for the UI
+    pub last_error: Option<String>,
+    /// Our rank, `None` until the matchmaker has answered
+    pub rank: Option<RankSummary>,
+}
+
+impl Default for MatchmakingClient {
+    fn default() -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).and_then(|s| s.set_nonblocking(true).map(|_| s));
+        if let Err(err) = &socket {
+            error!("matchmaking can't open a socket: {err}");
+        }
+        MatchmakingClient {
+            socket: socket.ok(),
+            matchmaker: None,
+            status: MatchmakingStatus::Idle,
+            enqueue: None,
+            last_sent: None,
+            last_heard: None,
+            last_error: None,
+            rank: None,
+        }
+    }
+}
+
+impl MatchmakingClient {
+    fn send(&self, packet: &MatchmakingPacket, to: SocketAddr) {
+        if let Some(socket) = &self.socket {
+            if let Err(err) = socket.send_to(&packet.encode(), to) {
+                warn!("matchmaking: {err}");
+            }
+        }
+    }
+
+    fn stop(&mut self, error: Option<String>) {
+        self.status = MatchmakingStatus::Idle;
+        self.enqueue = None;
+        self.last_error = error;
+    }
+
+    fn handle(&mut self, packet: MatchmakingPacket, now: Instant) {
+        self.last_heard = Some(now);
+        match packet {
+            MatchmakingPacket::Searching { players_in_queue } => match &mut self.status {
+                MatchmakingStatus::Searching { players_in_queue: count, .. } => *count = players_in_queue,
+                // en avbruten match där vi accepterade lägger oss tillbaka i kön
+                status => {
+                    *status = MatchmakingStatus::Searching { since: now, players_in_queue };
+                }
+            },
+            MatchmakingPacket::MatchFound { match_id, mode, map, seconds_left } => {
+                self.status = MatchmakingStatus::MatchFound {
+                    match_id,
+                    mode,
+                    map,
+                    deadline: now + Duration::from_secs(seconds_left as u64),
+                    accepted_by_us: false,
+                    accepted: 0,
+                    needed: 0,
+                };
+            }
+            MatchmakingPacket::ReadyCheck { match_id: id, accepted: count, needed: total } => {
+                if let MatchmakingStatus::MatchFound { match_id, accepted, needed, .. } = &mut self.status {
+                    if *match_id == id {
+                        *accepted = count;
+                        *needed = total;
+                    }
+                }
+            }
+            MatchmakingPacket::MatchCancelled { requeued, .. } => {
+                if requeued {
+                    self.status = MatchmakingStatus::Searching { since: now, players_in_queue: 0 };
+                } else {
+                    self.stop(Some("match was not accepted".into()));
+                }
+            }
+            MatchmakingPacket::Tickets { server, map, tickets } => {
+                self.enqueue = None;
+                self.status = MatchmakingStatus::Ready { server, map, tickets };
+            }
+            MatchmakingPacket::Rejected { reason } => self.stop(Some(reason)),
+            MatchmakingPacket::RankReply { summary, .. } => self.rank = Some(summary),
+            _ => {}
+        }
+    }
+}
+
+pub struct MatchmakingPlugin;
+
+impl Plugin for MatchmakingPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<SessionPlugin>() {
+            app.add_plugins(SessionPlugin);
+        }
+        app.register_cvar(
+            CvarDef::string("cl_matchmaker", "localhost")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Matchmaking service competitive games are found through"),
+        )
+        .init_resource::<MatchmakingClient>()
+        .add_event::<MatchmakingRequest>()
+        .add_systems(
+            Update,
+            (
+                resolve_address_cvar("cl_matchmaker", MATCHMAKER_PORT, |client: &mut MatchmakingClient, address| {
+                    client.matchmaker = address;
+                }),
+                handle_matchmaking_requests,
+                request_rank,
+                poll_matchmaker,
+            )
+                .chain(),
+        );
+    }
+}
+
+fn handle_matchmaking_requests(
+    mut requests: EventReader<MatchmakingRequest>,
+    auth: Res<AuthSession>,
+    profiles: Option<Res<ProfileClient>>,
+    mut client: ResMut<MatchmakingClient>,
+) {
+    for request in requests.read() {
+        let Some(matchmaker) = client.matchmaker else {
+            client.stop(Some("no matchmaking service set (cl_matchmaker)".into()));
+            continue;
+        };
+        let now = Instant::now();
+        match request.clone() {
+            MatchmakingRequest::Search { party, mode, maps } => {
+                let Some(session) = auth.session() else {
+                    let reason = auth.last_error.clone().unwrap_or_else(|| "not logged in yet (cl_auth)".into());
+                    client.stop(Some(reason));
+                    continue;
+                };
+                // matchmakern kollar själv att passet gäller just det här partyt
+                let pass = profiles.as_ref().and_then(|p| p.party_pass.clone()).filter(|_| party.len() > 1);
+                let packet = MatchmakingPacket::Enqueue { party, mode, maps, session, pass };
+                client.send(&packet, matchmaker);
+                client.enqueue = Some(packet);
+                client.last_sent = Some(now);
+                client.last_heard = Some(now);
+                client.last_error = None;
+                client.status = MatchmakingStatus::Searching { since: now, players_in_queue: 0 };
+            }
+            MatchmakingRequest::Cancel => {
+                client.send(&MatchmakingPacket::Cancel, matchmaker);
+                client.stop(None);
+            }
+            MatchmakingRequest::Accept => {
+                if let MatchmakingStatus::MatchFound { match_id, accepted_by_us, .. } = &mut client.status {
+                    *accepted_by_us = true;
+                    let packet = MatchmakingPacket::Accept { match_id: *match_id };
+                    client.send(&packet, matchmaker);
+                }
+            }
+            MatchmakingRequest::Decline => {
+                if let MatchmakingStatus::MatchFound { match_id, .. } = client.status {
+                    client.send(&MatchmakingPacket::Decline { match_id }, matchmaker);
+                    client.stop(None);
+                }
+            }
+        }
+    }
+}
+
+/// Asks for our rank now and then; it changes after every rated match.
+fn request_rank(
+    lobby: Option<Res<Lobby>>,
+    client: Res<MatchmakingClient>,
+    mut last: Local<Option<Instant>>,
+) {
+    if last.is_some_and(|t| t.elapsed() < RANK_REFRESH) {
+        return;
+    }
+    *last = Some(Instant::now());
+    let (Some(lobby), Some(matchmaker)) = (lobby, client.matchmaker) else {
+        return;
+    };
+    client.send(&MatchmakingPacket::RankRequest { player_id: lobby.local_id() }, matchmaker);
+}
+
+fn poll_matchmaker(mut client: ResMut<MatchmakingClient>) {
+    let now = Instant::now();
+    let matchmaker = client.matchmaker;
+    // bara matchmakern får skicka oss till en server eller avbryta sökningen
+    let packets: Vec<MatchmakingPacket> = client
+        .socket
+        .as_ref()
+        .map(receive_datagrams)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, from)| Some(*from) == matchmaker)
+        .filter_map(|(bytes, _)| MatchmakingPacket::decode(&bytes))
+        .collect();
+    // bara när något kommit eller vi söker, annars räknas resursen som ändrad varje frame
+    if packets.is_empty() && client.enqueue.is_none() {
+        return;
+    }
+    for packet in packets {
+        client.handle(packet, now);
+    }
+    if client.enqueue.is_none() {
+        return;
+    }
+
+    if client.last_heard.is_some_and(|t| now.duration_since(t) > SESSION_TIMEOUT) {
+        client.stop(Some("matchmaking service isn't answering".into()));
+        return;
+    }
+    if let MatchmakingStatus::MatchFound { deadline, accepted_by_us: false, .. } = client.status {
+        if now > deadline {
+            client.stop(Some("match was not accepted".into()));
+            return;
+        }
+    }
+    // keepalive medan vi söker
+    if client.last_sent.is_some_and(|t| now.duration_since(t) >= KEEPALIVE_INTERVAL) {
+        if let (Some(packet), Some(matchmaker)) = (client.enqueue.clone(), client.matchmaker) {
+            client.send(&packet, matchmaker);
+            client.last_sent = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn ticket(player_id: u64, token: u64) -> ConnectTicket {
+        ConnectTicket { match_id: 1, player_id, team: Team::CounterTerrorist, token }
+    }
+
+    #[test]
+    fn a_match_only_admits_players_with_their_own_ticket() {
+        let allocated = AllocatedMatch {
+            match_id: 1,
+            mode: GameMode::Competitive,
+            map: MapId::BoxArena,
+            tickets: vec![ticket(1, 11), ticket(2, 22)],
+            matchmaker: ([127, 0, 0, 1], MATCHMAKER_PORT).into(),
+        };
+        assert!(allocated.admits(1, Some(11)));
+        assert!(allocated.admits(2, Some(22)));
+        // någon annans biljett, ingen biljett eller en spelare som inte är med
+        assert!(!allocated.admits(1, Some(22)));
+        assert!(!allocated.admits(1, None));
+        assert!(!allocated.admits(3, Some(11)));
+    }
+
+    #[test]
+    fn only_the_matchmaker_can_send_us_to_a_server() {
+        let local = |socket: &UdpSocket| SocketAddr::from(([127, 0, 0, 1], socket.local_addr().unwrap().port()));
+        let matchmaker = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut world = World::new();
+        let mut client = MatchmakingClient { matchmaker: Some(local(&matchmaker)), ..default() };
+        client.enqueue = Some(MatchmakingPacket::Cancel);
+        client.status = MatchmakingStatus::Searching { since: Instant::now(), players_in_queue: 0 };
+        let us = local(client.socket.as_ref().unwrap());
+        world.insert_resource(client);
+
+        let tickets = |server: SocketAddr| MatchmakingPacket::Tickets { server, map: MapId::BoxArena, tickets: vec![] };
+        let evil: SocketAddr = ([10, 6, 6, 6], 27015).into();
+        spoofer.send_to(&tickets(evil).encode(), us).unwrap();
+        spoofer.send_to(&MatchmakingPacket::Rejected { reason: "nope".into() }.encode(), us).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        world.run_system_once(poll_matchmaker);
+        let client = world.resource::<MatchmakingClient>();
+        assert!(matches!(client.status, MatchmakingStatus::Searching { .. }));
+        assert_eq!(client.last_error, None);
+
+        let real: SocketAddr = ([127, 0, 0, 1], 27015).into();
+        matchmaker.send_to(&tickets(real).encode(), us).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        world.run_system_once(poll_matchmaker);
+        let status = &world.resource::<MatchmakingClient>().status;
+        assert!(matches!(status, MatchmakingStatus::Ready { server, .. } if *server == real), "{status:?}");
+    }
+}
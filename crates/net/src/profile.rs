@@ -0,0 +1,1335 @@
+//! Player profiles: the protocol to the profile service and the client side of it.
+//!
+//! The profile service (`apps/profiles`) owns every player's name, career stats, match
+//! history, friends and inventory. Clients ask for their own with the `Get*` requests and
+//! keep the answers in [`ProfileClient`] for the menus. Lists come back in one or more
+//! replies with `last` set on the final one. The matchmaker reports finished matches with
+//! [`ProfilePacket::RecordMatch`], which the service only takes from addresses it trusts.
+//! Everything a player asks for is sent [`ProfilePacket::Signed`] with their session from the
+//! auth service (see `auth`), and the service only does it for the player the session is for.
+//!
+//! The service is also where friends find each other. Clients send their [`Presence`] every
+//! [`PRESENCE_INTERVAL`] and the service pushes party invites and party lobbies back to the
+//! address it came from. A party's [`Lobby`] is hosted on the service: members send their
+//! `LobbyRequest`s there and get the whole lobby back after every change. Lobby chat goes
+//! there too while it hosts the lobby, and comes back to every member as a `ChatMessage`.
+//! The leader gets a [`PartyPass`] for the party from the service, which the matchmaker wants
+//! before it queues more than one player.
+//!
+//! Inventories hold cosmetic items (see `shared::items`). Players equip skins into loadout
+//! slots, one per team and weapon, and use stickers and name tags up on their skins. Items
+//! are only handed out by trusted addresses with [`ProfilePacket::GrantItem`].
+//!
+//! Cases are opened and match drops rolled on the service, with the provably fair rolls of
+//! `shared::loot`. [`ProfilePacket::GetSeeds`] gives the commitment to the server seed our
+//! next rolls use, [`ProfilePacket::SetClientSeed`] reveals it and starts a new one, and
+//! [`ProfilePacket::GetRolls`] sends our past rolls back with their seeds once revealed, so
+//! anyone can redo them.
+//!
+//! Items change hands on the service too, either through a trade offer between friends or
+//! by being listed on the market for credits. Every transfer is checked in full before
+//! anything moves and is saved in one go with the wallets, so an item always has exactly one
+//! owner. Items that changed hands are on a [`TRADE_HOLD`] before they can move again, and
+//! every sale and trade is kept in the players' transaction history.
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use shared::chat::{ChatChannel, ChatMessage, ChatPlugin, SendChat};
+use shared::console::RegisterCommandExt;
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::game_state::Team;
+use shared::items::{item_def, EquippedSkins, ItemDef, ItemsPlugin, Weapon, WeaponSkin, MAX_NAME_TAG_LENGTH};
+use shared::lobby::{GameMode, Lobby, LobbyRequest};
+use shared::loot::{drop_odds, RollKind, RollRecord, MAX_CLIENT_SEED_LENGTH, MAX_DROPS_PER_WEEK};
+use shared::maps::MapId;
+use shared::types::AppState;
+
+use crate::auth::{to_hex, unix_now, AuthSession, PartyPass, Session, SessionPlugin};
+use crate::browser::CurrentServer;
+use crate::query::{receive_datagrams, resolve_address_cvar, MAX_PACKET_SIZE, REQUEST_SIZE};
+
+pub const PROFILE_PORT: u16 = 27014;
+/// Matches kept in each player's history.
+pub const MATCH_HISTORY_LENGTH: usize = 20;
+/// List entries per reply, keeps every reply below `MAX_PACKET_SIZE`.
+pub const ENTRIES_PER_REPLY: usize = 10;
+/// Items per reply; stickers and name tags make them bigger than other entries.
+pub const ITEMS_PER_REPLY: usize = 4;
+/// Rolls kept for each player to look back on.
+pub const ROLL_HISTORY_LENGTH: usize = 50;
+/// How long an item that changed hands can't be traded or listed again.
+pub const TRADE_HOLD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Share of a sale's price the market keeps, in percent.
+pub const MARKET_FEE_PERCENT: u64 = 5;
+/// Highest price an item can be listed for, in credits.
+pub const MAX_PRICE: u64 = 100_000;
+/// Items on each side of a trade offer.
+pub const MAX_TRADE_ITEMS: usize = 4;
+/// Trade offers a player can have waiting for an answer at once.
+pub const MAX_OPEN_OFFERS: usize = 10;
+/// Listings sent for one request, cheapest first.
+pub const MAX_LISTINGS_SENT: usize = 40;
+/// Days of price history the charts show.
+pub const PRICE_HISTORY_DAYS: u64 = 30;
+pub const MAX_NAME_LENGTH: usize = 32;
+/// How often the client fetches its profile again.
+pub const PROFILE_REFRESH: Duration = Duration::from_secs(60);
+/// How often the client fetches its friends again, so their presence stays fresh.
+pub const FRIENDS_REFRESH: Duration = Duration::from_secs(10);
+/// How often the client tells the service it's still there.
+pub const PRESENCE_INTERVAL: Duration = Duration::from_secs(10);
+/// A player the service hasn't heard from in this long is offline and leaves their party.
+pub const PRESENCE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a party invite can be answered.
+pub const INVITE_TIMEOUT: Duration = Duration::from_secs(60);
+/// The leader asks for a new party pass when theirs has less than this left.
+const PARTY_PASS_RENEWAL: Duration = Duration::from_secs(2 * 60);
+/// Hur ofta vi frågar igen efter ett pass som inte kommit
+const PARTY_PASS_RETRY: Duration = Duration::from_secs(2);
+
+const MAGIC: &[u8; 4] = b"FPSP";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CareerStats {
+    pub matches: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    pub rounds_won: u32,
+    pub rounds_lost: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub player_id: u64,
+    /// Empty until the player has set one
+    pub name: String,
+    pub stats: CareerStats,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchRecord {
+    pub match_id: u64,
+    pub mode: GameMode,
+    pub map: MapId,
+    pub team: Team,
+    pub rounds_won: u32,
+    pub rounds_lost: u32,
+    /// Unix time the match ended
+    pub finished: u64,
+}
+
+/// What a player is up to, as their friends see it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Presence {
+    #[default]
+    Offline,
+    InMenus,
+    /// `server` is `None` in a game only we can see, e.g. offline practice
+    InGame { map: MapId, server: Option<SocketAddr> },
+}
+
+impl fmt::Display for Presence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Presence::Offline => write!(f, "Offline"),
+            Presence::InMenus => write!(f, "In menus"),
+            Presence::InGame { map, .. } => write!(f, "Playing {}", map.label()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FriendStatus {
+    #[default]
+    Accepted,
+    /// We asked, they haven't answered
+    Outgoing,
+    /// They asked us
+    Incoming,
+}
+
+impl FriendStatus {
+    pub const ALL: [FriendStatus; 3] = [FriendStatus::Accepted, FriendStatus::Outgoing, FriendStatus::Incoming];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Friend {
+    pub player_id: u64,
+    pub name: String,
+    pub status: FriendStatus,
+    /// Always `Offline` until the friend request is accepted
+    pub presence: Presence,
+}
+
+/// A party invite waiting for an answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartyInvite {
+    pub from: u64,
+    pub name: String,
+    pub received: Instant,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InventoryItem {
+    pub item_id: u64,
+    /// Which item this is, e.g. `skin_ak47_redline`, see `shared::items`
+    pub definition: String,
+    /// Unix time it was received
+    pub acquired: u64,
+    // bara för skins
+    pub wear: f32,
+    pub pattern: u16,
+    pub stickers: Vec<String>,
+    pub name_tag: Option<String>,
+    /// Unix time it can be traded or listed again, 0 if it never changed hands
+    pub tradable_after: u64,
+}
+
+impl InventoryItem {
+    /// `None` for items made before their definition was removed.
+    pub fn def(&self) -> Option<&'static ItemDef> {
+        item_def(&self.definition)
+    }
+
+    /// The skin as it looks on the weapon, `None` unless the item is a skin.
+    pub fn skin(&self) -> Option<WeaponSkin> {
+        let skin = WeaponSkin {
+            definition: self.definition.clone(),
+            wear: self.wear,
+            pattern: self.pattern,
+            stickers: self.stickers.clone(),
+            name_tag: self.name_tag.clone(),
+        };
+        skin.weapon().map(|_| skin)
+    }
+
+    pub fn display_name(&self) -> String {
+        match (self.skin(), self.def()) {
+            (Some(skin), _) => skin.display_name(),
+            (None, Some(def)) => def.name.to_string(),
+            (None, None) => self.definition.clone(),
+        }
+    }
+}
+
+/// What the player's next roll will be made from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollSeeds {
+    /// Hash of the secret server seed
+    pub commitment: [u8; 32],
+    pub client_seed: String,
+    /// Nonce of the next roll
+    pub nonce: u64,
+}
+
+/// An item for sale on the market.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Listing {
+    pub listing_id: u64,
+    pub seller: u64,
+    pub seller_name: String,
+    pub item: InventoryItem,
+    /// In credits, what the buyer pays; the seller gets it less the fee
+    pub price: u64,
+    pub created: u64,
+}
+
+/// What the market keeps of a sale at `price`.
+pub fn market_fee(price: u64) -> u64 {
+    price * MARKET_FEE_PERCENT / 100
+}
+
+/// Items and credits `from` offers `to` in exchange for some of theirs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeOffer {
+    pub offer_id: u64,
+    pub from: u64,
+    pub from_name: String,
+    pub to: u64,
+    pub to_name: String,
+    /// What `from` gives
+    pub give: Vec<InventoryItem>,
+    pub give_credits: u64,
+    /// What `from` wants from `to`
+    pub take: Vec<InventoryItem>,
+    pub take_credits: u64,
+    pub created: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionKind {
+    /// `from` sold to `to` on the market
+    Sale,
+    /// `to` accepted a trade offer from `from`
+    Trade,
+    /// `to` was given `from_credits` by the service
+    Grant,
+}
+
+impl TransactionKind {
+    pub const ALL: [TransactionKind; 3] = [TransactionKind::Sale, TransactionKind::Trade, TransactionKind::Grant];
+}
+
+/// A sale or trade as it happened; items are given as item id and definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub tx_id: u64,
+    pub kind: TransactionKind,
+    pub time: u64,
+    pub from: u64,
+    pub to: u64,
+    pub from_items: Vec<(u64, String)>,
+    pub to_items: Vec<(u64, String)>,
+    pub from_credits: u64,
+    pub to_credits: u64,
+    /// Kept by the market, out of what `from` got
+    pub fee: u64,
+}
+
+/// The sales of one item on one day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PricePoint {
+    /// Days since the Unix epoch
+    pub day: u64,
+    pub low: u64,
+    pub high: u64,
+    pub average: u64,
+    pub volume: u32,
+}
+
+/// A skin in one of a player's loadout slots.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EquippedSkin {
+    pub team: Team,
+    pub item_id: u64,
+    pub skin: WeaponSkin,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProfilePacket {
+    // klient -> profiltjänst
+    /// One of the requests below, made as the session's player. The service takes the ones
+    /// made as a player no other way.
+    Signed { session: Session, packet: Box<ProfilePacket> },
+    GetProfile { player_id: u64 },
+    GetMatchHistory { player_id: u64 },
+    GetFriends { player_id: u64 },
+    GetInventory { player_id: u64 },
+    /// Anyone's loadout can be asked for, game servers show it to the other players.
+    GetLoadout { player_id: u64 },
+    /// Sets the name, creating the profile if there is none.
+    SetName { player_id: u64, name: String },
+    /// Heartbeat; the service sends everything it pushes to us to where this came from.
+    Presence { player_id: u64, presence: Presence },
+    /// Friend request to the player with this name, or accepts theirs if they asked first.
+    AddFriend { player_id: u64, name: String },
+    AcceptFriend { player_id: u64, friend_id: u64 },
+    /// Removes a friend, or cancels or declines a friend request.
+    RemoveFriend { player_id: u64, friend_id: u64 },
+    /// Invites a friend to our party. `lobby` is what the party starts from if we aren't in one.
+    InviteToParty { player_id: u64, friend_id: u64, lobby: Lobby },
+    /// Joins the party `friend_id` is in, if we're invited or its permissions let us.
+    JoinParty { player_id: u64, friend_id: u64 },
+    DeclineInvite { player_id: u64, friend_id: u64 },
+    PartyRequest { player_id: u64, request: LobbyRequest },
+    /// Lobby chat for the party we're in.
+    PartyChat { player_id: u64, text: String },
+    /// Puts a skin in the slot for its weapon on `team`.
+    Equip { player_id: u64, item_id: u64, team: Team },
+    Unequip { player_id: u64, team: Team, weapon: Weapon },
+    /// Uses up the sticker item `sticker_id` on the skin `item_id`.
+    ApplySticker { player_id: u64, item_id: u64, sticker_id: u64 },
+    /// Uses up the name tag item `tag_id` to name the skin `item_id`.
+    ApplyNameTag { player_id: u64, item_id: u64, tag_id: u64, name: String },
+    /// Uses up the case item `item_id` and adds what it opened into.
+    OpenCase { player_id: u64, item_id: u64 },
+    GetSeeds { player_id: u64 },
+    /// Reveals the server seed used so far and starts a new one, with this client seed.
+    SetClientSeed { player_id: u64, client_seed: String },
+    /// Our last `ROLL_HISTORY_LENGTH` rolls.
+    GetRolls { player_id: u64 },
+    GetWallet { player_id: u64 },
+    /// Listings of `definition`, or of everything, cheapest first, and all of ours.
+    GetListings { player_id: u64, definition: Option<String> },
+    /// Offers we've sent and offers sent to us.
+    GetOffers { player_id: u64 },
+    /// Every sale and trade we were part of, newest first.
+    GetTransactions { player_id: u64 },
+    GetPriceHistory { player_id: u64, definition: String },
+    CreateListing { player_id: u64, item_id: u64, price: u64 },
+    CancelListing { player_id: u64, listing_id: u64 },
+    /// `price` is what we saw; if the listing costs anything else it isn't bought.
+    BuyListing { player_id: u64, listing_id: u64, price: u64 },
+    SendOffer { player_id: u64, to: u64, give: Vec<u64>, give_credits: u64, take: Vec<u64>, take_credits: u64 },
+    AcceptOffer { player_id: u64, offer_id: u64 },
+    /// Declines an offer to us, or takes back one we sent.
+    DeclineOffer { player_id: u64, offer_id: u64 },
+    /// A pass for queueing with the party we're in now.
+    GetPartyPass { player_id: u64 },
+
+    // profiltjänst -> klient
+    /// Players without a profile get an empty one back.
+    Profile { info: ProfileInfo },
+    /// Newest first
+    MatchHistory { player_id: u64, matches: Vec<MatchRecord>, last: bool },
+    Friends { player_id: u64, friends: Vec<Friend>, last: bool },
+    Inventory { player_id: u64, items: Vec<InventoryItem>, last: bool },
+    Loadout { player_id: u64, skins: Vec<EquippedSkin>, last: bool },
+    Error { reason: String },
+    PartyInvite { from: u64, name: String },
+    /// The party lobby after a change, sent to every member.
+    Party { lobby: Lobby },
+    /// We left, were kicked or the party broke up.
+    PartyEnded,
+    /// Party chat, or a notice for us about our own.
+    Chat { message: ChatMessage },
+    Seeds { player_id: u64, seeds: RollSeeds },
+    /// Newest first, with the server seed filled in where it has been revealed.
+    Rolls { player_id: u64, rolls: Vec<RollRecord>, last: bool },
+    CaseOpened { item: InventoryItem, roll: RollRecord },
+    /// Pushed when a match we played dropped something for us.
+    ItemDropped { item: InventoryItem },
+    Wallet { player_id: u64, balance: u64 },
+    Listings { listings: Vec<Listing>, last: bool },
+    Offers { player_id: u64, offers: Vec<TradeOffer>, last: bool },
+    Transactions { player_id: u64, transactions: Vec<Transaction>, last: bool },
+    /// Oldest day first, only days with sales
+    PriceHistory { definition: String, points: Vec<PricePoint> },
+    /// Pushed when an offer to or from us, or one of our listings, changed.
+    TradesChanged,
+    PartyPass { pass: PartyPass },
+
+    // matchmaker -> profiltjänst
+    /// `rounds` and `teams` are CT first.
+    RecordMatch { match_id: u64, mode: GameMode, map: MapId, rounds: [u32; 2], teams: [Vec<u64>; 2] },
+    /// Gives the player a new copy of the item, with its wear and pattern rolled by the service.
+    GrantItem { player_id: u64, definition: String },
+    GrantCredits { player_id: u64, amount: u64 },
+}
+
+impl ProfilePacket {
+    /// The requests whose answers can be bigger than the request itself.
+    fn is_request(&self) -> bool {
+        if let ProfilePacket::Signed { packet, .. } = self {
+            return packet.is_request();
+        }
+        matches!(
+            self,
+            ProfilePacket::GetProfile { .. }
+                | ProfilePacket::GetMatchHistory { .. }
+                | ProfilePacket::GetFriends { .. }
+                | ProfilePacket::GetInventory { .. }
+                | ProfilePacket::GetLoadout { .. }
+                | ProfilePacket::Presence { .. }
+                | ProfilePacket::OpenCase { .. }
+                | ProfilePacket::GetSeeds { .. }
+                | ProfilePacket::SetClientSeed { .. }
+                | ProfilePacket::GetRolls { .. }
+                | ProfilePacket::GetWallet { .. }
+                | ProfilePacket::GetListings { .. }
+                | ProfilePacket::GetOffers { .. }
+                | ProfilePacket::GetTransactions { .. }
+                | ProfilePacket::GetPriceHistory { .. }
+                | ProfilePacket::GetPartyPass { .. }
+        )
+    }
+
+    /// The player a request is made as, which it needs a session for. `None` for what
+    /// anyone may ask, what only trusted addresses may send and what the service sends.
+    pub fn sender(&self) -> Option<u64> {
+        match self {
+            ProfilePacket::GetProfile { player_id }
+            | ProfilePacket::GetMatchHistory { player_id }
+            | ProfilePacket::GetFriends { player_id }
+            | ProfilePacket::GetInventory { player_id }
+            | ProfilePacket::SetName { player_id, .. }
+            | ProfilePacket::Presence { player_id, .. }
+            | ProfilePacket::AddFriend { player_id, .. }
+            | ProfilePacket::AcceptFriend { player_id, .. }
+            | ProfilePacket::RemoveFriend { player_id, .. }
+            | ProfilePacket::InviteToParty { player_id, .. }
+            | ProfilePacket::JoinParty { player_id, .. }
+            | ProfilePacket::DeclineInvite { player_id, .. }
+            | ProfilePacket::PartyRequest { player_id, .. }
+            | ProfilePacket::PartyChat { player_id, .. }
+            | ProfilePacket::Equip { player_id, .. }
+            | ProfilePacket::Unequip { player_id, .. }
+            | ProfilePacket::ApplySticker { player_id, .. }
+            | ProfilePacket::ApplyNameTag { player_id, .. }
+            | ProfilePacket::OpenCase { player_id, .. }
+            | ProfilePacket::GetSeeds { player_id }
+            | ProfilePacket::SetClientSeed { player_id, .. }
+            | ProfilePacket::GetRolls { player_id }
+            | ProfilePacket::GetWallet { player_id }
+            | ProfilePacket::GetListings { player_id, .. }
+            | ProfilePacket::GetOffers { player_id }
+            | ProfilePacket::GetTransactions { player_id }
+            | ProfilePacket::GetPriceHistory { player_id, .. }
+            | ProfilePacket::CreateListing { player_id, .. }
+            | ProfilePacket::CancelListing { player_id, .. }
+            | ProfilePacket::BuyListing { player_id, .. }
+            | ProfilePacket::SendOffer { player_id, .. }
+            | ProfilePacket::AcceptOffer { player_id, .. }
+            | ProfilePacket::DeclineOffer { player_id, .. }
+            | ProfilePacket::GetPartyPass { player_id } => Some(*player_id),
+            // inpackade paket kontrolleras för sig
+            ProfilePacket::Signed { .. }
+            | ProfilePacket::GetLoadout { .. }
+            | ProfilePacket::Profile { .. }
+            | ProfilePacket::MatchHistory { .. }
+            | ProfilePacket::Friends { .. }
+            | ProfilePacket::Inventory { .. }
+            | ProfilePacket::Loadout { .. }
+            | ProfilePacket::Error { .. }
+            | ProfilePacket::PartyInvite { .. }
+            | ProfilePacket::Party { .. }
+            | ProfilePacket::PartyEnded
+            | ProfilePacket::Chat { .. }
+            | ProfilePacket::Seeds { .. }
+            | ProfilePacket::Rolls { .. }
+            | ProfilePacket::CaseOpened { .. }
+            | ProfilePacket::ItemDropped { .. }
+            | ProfilePacket::Wallet { .. }
+            | ProfilePacket::Listings { .. }
+            | ProfilePacket::Offers { .. }
+            | ProfilePacket::Transactions { .. }
+            | ProfilePacket::PriceHistory { .. }
+            | ProfilePacket::TradesChanged
+            | ProfilePacket::PartyPass { .. }
+            | ProfilePacket::RecordMatch { .. }
+            | ProfilePacket::GrantItem { .. }
+            | ProfilePacket::GrantCredits { .. } => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serde::encode_to_vec(self, bincode::config::standard()).unwrap_or_default());
+        // samma skydd mot förstärkning som frågorna
+        if self.is_request() && bytes.len() < REQUEST_SIZE {
+            bytes.resize(REQUEST_SIZE, 0);
+        }
+        bytes
+    }
+
+    /// `None` for anything that isn't a well-formed profile packet.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC)?;
+        let config = bincode::config::standard().with_limit::<MAX_PACKET_SIZE>();
+        let (packet, _) = bincode::serde::decode_from_slice::<ProfilePacket, _>(body, config).ok()?;
+        if packet.is_request() && bytes.len() < REQUEST_SIZE {
+            return None;
+        }
+        Some(packet)
+    }
+
+    /// Splits a list into replies that each fit in a packet.
+    pub fn list_replies<T: Clone>(items: &[T], reply: impl Fn(Vec<T>, bool) -> ProfilePacket) -> Vec<ProfilePacket> {
+        Self::chunked_replies(items, ENTRIES_PER_REPLY, reply)
+    }
+
+    /// Like `list_replies`, with `per_reply` entries in each.
+    pub fn chunked_replies<T: Clone>(
+        items: &[T],
+        per_reply: usize,
+        reply: impl Fn(Vec<T>, bool) -> ProfilePacket,
+    ) -> Vec<ProfilePacket> {
+        if items.is_empty() {
+            return vec![reply(Vec::new(), true)];
+        }
+        let chunks = items.chunks(per_reply);
+        let count = chunks.len();
+        chunks.enumerate().map(|(i, chunk)| reply(chunk.to_vec(), i + 1 == count)).collect()
+    }
+}
+
+/// What the UI asks the profile client for.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub enum ProfileRequest {
+    /// Fetches everything again
+    Refresh,
+    SetName(String),
+    AddFriend(String),
+    AcceptFriend(u64),
+    RemoveFriend(u64),
+    InviteToParty(u64),
+    JoinParty(u64),
+    DeclineInvite(u64),
+    Equip { item_id: u64, team: Team },
+    Unequip { team: Team, weapon: Weapon },
+    ApplySticker { item_id: u64, sticker_id: u64 },
+    ApplyNameTag { item_id: u64, tag_id: u64, name: String },
+    OpenCase(u64),
+    SetClientSeed(String),
+    /// Fetches our rolls, they aren't part of `Refresh`
+    FetchRolls,
+    /// Fetches the listings of one item, or of everything
+    FetchListings(Option<String>),
+    FetchPriceHistory(String),
+    CreateListing { item_id: u64, price: u64 },
+    CancelListing(u64),
+    BuyListing { listing_id: u64, price: u64 },
+    SendOffer { to: u64, give: Vec<u64>, give_credits: u64, take: Vec<u64>, take_credits: u64 },
+    AcceptOffer(u64),
+    DeclineOffer(u64),
+}
+
+/// Our own profile as the profile service last told us.
+#[derive(Resource)]
+pub struct ProfileClient {
+    socket: Option<UdpSocket>,
+    /// Där `cl_profiles` pekar, slås bara upp när den ändras
+    address: Option<SocketAddr>,
+    /// Från `AuthSession`, allt vi skickar går med den
+    session: Option<Session>,
+    /// For queueing with our party, kept while we lead one the service hosts
+    pub party_pass: Option<PartyPass>,
+    /// `None` until the service has answered
+    pub profile: Option<ProfileInfo>,
+    pub matches: Vec<MatchRecord>,
+    pub friends: Vec<Friend>,
+    pub inventory: Vec<InventoryItem>,
+    pub loadout: Vec<EquippedSkin>,
+    pub seeds: Option<RollSeeds>,
+    /// Newest first, once fetched with `ProfileRequest::FetchRolls`
+    pub rolls: Vec<RollRecord>,
+    /// Opened cases the unboxing screen hasn't shown yet
+    pub unboxed: Vec<(InventoryItem, RollRecord)>,
+    /// Match drops nobody has been told about yet
+    pub drops: Vec<InventoryItem>,
+    /// Credits, `None` until the service has answered
+    pub balance: Option<u64>,
+    pub listings: Vec<Listing>,
+    /// The item `listings` are for, `None` for everything
+    pub listings_for: Option<String>,
+    pub offers: Vec<TradeOffer>,
+    pub transactions: Vec<Transaction>,
+    pub price_history: Option<(String, Vec<PricePoint>)>,
+    /// Unanswered party invites, oldest first
+    pub invites: Vec<PartyInvite>,
+    /// Listor under mottagning, byts in när sista svaret kommit
+    incoming_matches: Vec<MatchRecord>,
+    incoming_friends: Vec<Friend>,
+    incoming_inventory: Vec<InventoryItem>,
+    incoming_loadout: Vec<EquippedSkin>,
+    incoming_rolls: Vec<RollRecord>,
+    incoming_listings: Vec<Listing>,
+    incoming_offers: Vec<TradeOffer>,
+    incoming_transactions: Vec<Transaction>,
+    last_refresh: Option<Instant>,
+    last_friends_refresh: Option<Instant>,
+    pub last_error: Option<String>,
+}
+
+impl Default for ProfileClient {
+    fn default() -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).and_then(|s| s.set_nonblocking(true).map(|_| s));
+        if let Err(err) = &socket {
+            error!("profile client can't open a socket: {err}");
+        }
+        ProfileClient {
+            socket: socket.ok(),
+            address: None,
+            session: None,
+            party_pass: None,
+            profile: None,
+            matches: Vec::new(),
+            friends: Vec::new(),
+            inventory: Vec::new(),
+            loadout: Vec::new(),
+            seeds: None,
+            rolls: Vec::new(),
+            unboxed: Vec::new(),
+            drops: Vec::new(),
+            balance: None,
+            listings: Vec::new(),
+            listings_for: None,
+            offers: Vec::new(),
+            transactions: Vec::new(),
+            price_history: None,
+            invites: Vec::new(),
+            incoming_matches: Vec::new(),
+            incoming_friends: Vec::new(),
+            incoming_inventory: Vec::new(),
+            incoming_loadout: Vec::new(),
+            incoming_rolls: Vec::new(),
+            incoming_listings: Vec::new(),
+            incoming_offers: Vec::new(),
+            incoming_transactions: Vec::new(),
+            last_refresh: None,
+            last_friends_refresh: None,
+            last_error: None,
+        }
+    }
+}
+
+impl ProfileClient {
+    /// The service, once we have a session to talk to it with.
+    fn service(&self) -> Option<SocketAddr> {
+        self.session.and(self.address)
+    }
+
+    fn send(&self, packet: &ProfilePacket, to: SocketAddr) {
+        // allt vi skickar gäller oss själva, så vår session räcker
+        let Some(session) = self.session else {
+            return;
+        };
+        let packet = ProfilePacket::Signed { session, packet: Box::new(packet.clone()) };
+        if let Some(socket) = &self.socket {
+            if let Err(err) = socket.send_to(&packet.encode(), to) {
+                warn!("profiles: {err}");
+            }
+        }
+    }
+
+    fn refresh(&mut self, player_id: u64, service: SocketAddr) {
+        self.last_refresh = Some(Instant::now());
+        self.incoming_matches.clear();
+        self.incoming_inventory.clear();
+        self.incoming_loadout.clear();
+        self.incoming_offers.clear();
+        self.incoming_transactions.clear();
+        self.send(&ProfilePacket::GetProfile { player_id }, service);
+        self.send(&ProfilePacket::GetMatchHistory { player_id }, service);
+        self.send(&ProfilePacket::GetInventory { player_id }, service);
+        self.send(&ProfilePacket::GetLoadout { player_id }, service);
+        self.send(&ProfilePacket::GetSeeds { player_id }, service);
+        self.send(&ProfilePacket::GetWallet { player_id }, service);
+        self.send(&ProfilePacket::GetOffers { player_id }, service);
+        self.send(&ProfilePacket::GetTransactions { player_id }, service);
+        self.fetch_listings(player_id, service);
+        self.refresh_friends(player_id, service);
+    }
+
+    fn refresh_friends(&mut self, player_id: u64, service: SocketAddr) {
+        self.last_friends_refresh = Some(Instant::now());
+        self.incoming_friends.clear();
+        self.send(&ProfilePacket::GetFriends { player_id }, service);
+    }
+
+    fn fetch_listings(&mut self, player_id: u64, service: SocketAddr) {
+        self.incoming_listings.clear();
+        self.send(&ProfilePacket::GetListings { player_id, definition: self.listings_for.clone() }, service);
+    }
+
+    /// Our listing of the item, if it's on the market.
+    pub fn listing_of(&self, item_id: u64) -> Option<&Listing> {
+        self.listings.iter().find(|l| l.item.item_id == item_id)
+    }
+
+    /// The friend whose name is `name`, ignoring case.
+    pub fn friend_by_name(&self, name: &str) -> Option<&Friend> {
+        self.friends.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn item(&self, item_id: u64) -> Option<&InventoryItem> {
+        self.inventory.iter().find(|i| i.item_id == item_id)
+    }
+
+    /// The teams whose slot for the item's weapon it is in.
+    pub fn equipped_for(&self, item_id: u64) -> Vec<Team> {
+        self.loadout.iter().filter(|e| e.item_id == item_id).map(|e| e.team).collect()
+    }
+
+    fn handle(&mut self, packet: ProfilePacket) {
+        match packet {
+            ProfilePacket::Profile { info } => self.profile = Some(info),
+            ProfilePacket::MatchHistory { matches, last, .. } => {
+                self.incoming_matches.extend(matches);
+                if last {
+                    self.matches = std::mem::take(&mut self.incoming_matches);
+                }
+            }
+            ProfilePacket::Friends { friends, last, .. } => {
+                self.incoming_friends.extend(friends);
+                if last {
+                    self.friends = std::mem::take(&mut self.incoming_friends);
+                }
+            }
+            ProfilePacket::Inventory { items, last, .. } => {
+                self.incoming_inventory.extend(items);
+                if last {
+                    self.inventory = std::mem::take(&mut self.incoming_inventory);
+                }
+            }
+            ProfilePacket::Loadout { skins, last, .. } => {
+                self.incoming_loadout.extend(skins);
+                if last {
+                    self.loadout = std::mem::take(&mut self.incoming_loadout);
+                }
+            }
+            ProfilePacket::Seeds { seeds, .. } => self.seeds = Some(seeds),
+            ProfilePacket::Rolls { rolls, last, .. } => {
+                self.incoming_rolls.extend(rolls);
+                if last {
+                    self.rolls = std::mem::take(&mut self.incoming_rolls);
+                }
+            }
+            ProfilePacket::CaseOpened { item, roll } => self.unboxed.push((item, roll)),
+            ProfilePacket::ItemDropped { item } => {
+                // kommer mitt i en match, inventariet hämtas inte om förrän senare
+                self.inventory.push(item.clone());
+                self.drops.push(item);
+            }
+            ProfilePacket::Wallet { balance, .. } => self.balance = Some(balance),
+            ProfilePacket::Listings { listings, last } => {
+                self.incoming_listings.extend(listings);
+                if last {
+                    self.listings = std::mem::take(&mut self.incoming_listings);
+                }
+            }
+            ProfilePacket::Offers { offers, last, .. } => {
+                self.incoming_offers.extend(offers);
+                if last {
+                    self.offers = std::mem::take(&mut self.incoming_offers);
+                }
+            }
+            ProfilePacket::Transactions { transactions, last, .. } => {
+                self.incoming_transactions.extend(transactions);
+                if last {
+                    self.transactions = std::mem::take(&mut self.incoming_transactions);
+                }
+            }
+            ProfilePacket::PriceHistory { definition, points } => self.price_history = Some((definition, points)),
+            // hämtas om nästa frame
+            ProfilePacket::TradesChanged => self.last_refresh = None,
+            ProfilePacket::PartyPass { pass } => self.party_pass = Some(pass),
+            ProfilePacket::Error { reason } => {
+                warn!("profiles: {reason}");
+                self.last_error = Some(reason);
+            }
+            ProfilePacket::PartyInvite { from, name } => {
+                self.invites.retain(|i| i.from != from);
+                self.invites.push(PartyInvite { from, name, received: Instant::now() });
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct ProfilePlugin;
+
+impl Plugin for ProfilePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ItemsPlugin>() {
+            app.add_plugins(ItemsPlugin);
+        }
+        if !app.is_plugin_added::<ChatPlugin>() {
+            app.add_plugins(ChatPlugin);
+        }
+        if !app.is_plugin_added::<SessionPlugin>() {
+            app.add_plugins(SessionPlugin);
+        }
+        app.register_cvar(
+            CvarDef::string("cl_profiles", "localhost")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Profile service your name, stats, friends and inventory are kept on"),
+        )
+        .register_console_command("friend_add", "friend_add <name>: send a friend request", cmd_friend_add)
+        .register_console_command("friend_remove", "friend_remove <name>: remove a friend", cmd_friend_remove)
+        .register_console_command("party_invite", "party_invite <friend>: invite a friend", cmd_party_invite)
+        .register_console_command("party_join", "party_join <friend>: join a friend's party", cmd_party_join)
+        .register_console_command("party_leave", "party_leave: leave your party", cmd_party_leave)
+        .register_console_command("rolls", "rolls: list and check your case and drop rolls", cmd_rolls)
+        .register_console_command(
+            "client_seed",
+            "client_seed <seed>: reveal the server seed and start a new one",
+            cmd_client_seed,
+        )
+        .register_console_command("drop_odds", "drop_odds: the chance of each match drop", cmd_drop_odds)
+        .register_console_command(
+            "trade_offer",
+            "trade_offer <friend> <give ids|-> <take ids|-> [give credits] [take credits]: send a trade offer",
+            cmd_trade_offer,
+        )
+        .init_resource::<ProfileClient>()
+        .add_event::<ProfileRequest>()
+        .add_systems(
+            Update,
+            (
+                resolve_address_cvar("cl_profiles", PROFILE_PORT, |client: &mut ProfileClient, address| {
+                    client.address = address;
+                }),
+                sync_session,
+                handle_profile_requests,
+                forward_party_requests,
+                forward_party_chat,
+                sync_profile_name,
+                send_presence,
+                poll_profiles,
+                request_party_pass,
+                expire_invites,
+                sync_local_skins,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Takes the session from `AuthSession` whenever it gets a new one.
+fn sync_session(auth: Res<AuthSession>, mut client: ResMut<ProfileClient>) {
+    let session = auth.session();
+    if client.session != session {
+        client.session = session;
+    }
+}
+
+fn handle_profile_requests(
+    mut requests: EventReader<ProfileRequest>,
+    lobby: Option<Res<Lobby>>,
+    mut client: ResMut<ProfileClient>,
+) {
+    let player_id = lobby.as_ref().map_or(0, |l| l.local_id());
+    let service = client.service();
+    let mut refresh = client.last_refresh.is_none_or(|t| t.elapsed() >= PROFILE_REFRESH);
+    let mut refresh_friends = client.last_friends_refresh.is_none_or(|t| t.elapsed() >= FRIENDS_REFRESH);
+
+    for request in requests.read() {
+        let Some(service) = service else {
+            continue;
+        };
+        let packet = match request {
+            ProfileRequest::Refresh => {
+                refresh = true;
+                continue;
+            }
+            ProfileRequest::SetName(name) => {
+                refresh = true;
+                let name: String = name.chars().take(MAX_NAME_LENGTH).collect();
+                ProfilePacket::SetName { player_id, name }
+            }
+            ProfileRequest::AddFriend(name) => ProfilePacket::AddFriend { player_id, name: name.clone() },
+            ProfileRequest::AcceptFriend(friend_id) => ProfilePacket::AcceptFriend { player_id, friend_id: *friend_id },
+            ProfileRequest::RemoveFriend(friend_id) => ProfilePacket::RemoveFriend { player_id, friend_id: *friend_id },
+            ProfileRequest::InviteToParty(friend_id) => {
+                let Some(lobby) = &lobby else {
+                    continue;
+                };
+                ProfilePacket::InviteToParty { player_id, friend_id: *friend_id, lobby: Lobby::clone(lobby) }
+            }
+            ProfileRequest::JoinParty(friend_id) => {
+                client.invites.retain(|i| i.from != *friend_id);
+                ProfilePacket::JoinParty { player_id, friend_id: *friend_id }
+            }
+            ProfileRequest::DeclineInvite(friend_id) => {
+                client.invites.retain(|i| i.from != *friend_id);
+                ProfilePacket::DeclineInvite { player_id, friend_id: *friend_id }
+            }
+            ProfileRequest::Equip { item_id, team } => {
+                refresh = true;
+                ProfilePacket::Equip { player_id, item_id: *item_id, team: *team }
+            }
+            ProfileRequest::Unequip { team, weapon } => {
+                refresh = true;
+                ProfilePacket::Unequip { player_id, team: *team, weapon: *weapon }
+            }
+            ProfileRequest::ApplySticker { item_id, sticker_id } => {
+                refresh = true;
+                ProfilePacket::ApplySticker { player_id, item_id: *item_id, sticker_id: *sticker_id }
+            }
+            ProfileRequest::ApplyNameTag { item_id, tag_id, name } => {
+                refresh = true;
+                let name: String = name.trim().chars().take(MAX_NAME_TAG_LENGTH).collect();
+                ProfilePacket::ApplyNameTag { player_id, item_id: *item_id, tag_id: *tag_id, name }
+            }
+            ProfileRequest::OpenCase(item_id) => {
+                refresh = true;
+                ProfilePacket::OpenCase { player_id, item_id: *item_id }
+            }
+            ProfileRequest::SetClientSeed(client_seed) => {
+                // rullningarna kommer tillbaka med det avslöjade fröet
+                client.incoming_rolls.clear();
+                let client_seed: String = client_seed.trim().chars().take(MAX_CLIENT_SEED_LENGTH).collect();
+                client.send(&ProfilePacket::SetClientSeed { player_id, client_seed }, service);
+                ProfilePacket::GetRolls { player_id }
+            }
+            ProfileRequest::FetchRolls => {
+                client.incoming_rolls.clear();
+                ProfilePacket::GetRolls { player_id }
+            }
+            ProfileRequest::FetchListings(definition) => {
+                client.listings_for = definition.clone();
+                client.fetch_listings(player_id, service);
+                continue;
+            }
+            ProfileRequest::FetchPriceHistory(definition) => {
+                ProfilePacket::GetPriceHistory { player_id, definition: definition.clone() }
+            }
+            ProfileRequest::CreateListing { item_id, price } => {
+                refresh = true;
+                ProfilePacket::CreateListing { player_id, item_id: *item_id, price: *price }
+            }
+            ProfileRequest::CancelListing(listing_id) => {
+                refresh = true;
+                ProfilePacket::CancelListing { player_id, listing_id: *listing_id }
+            }
+            ProfileRequest::BuyListing { listing_id, price } => {
+                refresh = true;
+                ProfilePacket::BuyListing { player_id, listing_id: *listing_id, price: *price }
+            }
+            ProfileRequest::SendOffer { to, give, give_credits, take, take_credits } => {
+                refresh = true;
+                ProfilePacket::SendOffer {
+                    player_id,
+                    to: *to,
+                    give: give.clone(),
+                    give_credits: *give_credits,
+                    take: take.clone(),
+                    take_credits: *take_credits,
+                }
+            }
+            ProfileRequest::AcceptOffer(offer_id) => {
+                refresh = true;
+                ProfilePacket::AcceptOffer { player_id, offer_id: *offer_id }
+            }
+            ProfileRequest::DeclineOffer(offer_id) => {
+                refresh = true;
+                ProfilePacket::DeclineOffer { player_id, offer_id: *offer_id }
+            }
+        };
+        // vänlistan ändras av det mesta, hämta om den direkt
+        refresh_friends = true;
+        client.send(&packet, service);
+    }
+    let Some(service) = service else {
+        return;
+    };
+    if refresh {
+        client.refresh(player_id, service);
+    } else if refresh_friends {
+        client.refresh_friends(player_id, service);
+    }
+}
+
+/// Sends our lobby requests to the service while it hosts our party.
+fn forward_party_requests(
+    mut requests: EventReader<LobbyRequest>,
+    lobby: Option<Res<Lobby>>,
+    client: Res<ProfileClient>,
+) {
+    let (Some(lobby), Some(service)) = (lobby, client.service()) else {
+        requests.clear();
+        return;
+    };
+    if !lobby.is_remote() {
+        requests.clear();
+        return;
+    }
+    for request in requests.read() {
+        client.send(&ProfilePacket::PartyRequest { player_id: lobby.local_id(), request: request.clone() }, service);
+    }
+}
+
+/// Sends our lobby chat to the service while it hosts our party.
+fn forward_party_chat(
+    mut requests: EventReader<SendChat>,
+    lobby: Option<Res<Lobby>>,
+    client: Res<ProfileClient>,
+) {
+    let (Some(lobby), Some(service)) = (lobby, client.service()) else {
+        requests.clear();
+        return;
+    };
+    if !lobby.is_remote() {
+        requests.clear();
+        return;
+    }
+    for request in requests.read().filter(|r| r.channel == ChatChannel::Lobby) {
+        client.send(&ProfilePacket::PartyChat { player_id: lobby.local_id(), text: request.text.clone() }, service);
+    }
+}
+
+/// Keeps the name on the profile the same as the `name` cvar.
+fn sync_profile_name(
+    lobby: Option<Res<Lobby>>,
+    client: Res<ProfileClient>,
+    mut requests: EventWriter<ProfileRequest>,
+    mut sent: Local<Option<String>>,
+) {
+    let (Some(lobby), Some(profile)) = (lobby, &client.profile) else {
+        return;
+    };
+    let Some(name) = lobby.local_member().map(|m| &m.name) else {
+        return;
+    };
+    if profile.name != *name && sent.as_ref() != Some(name) {
+        *sent = Some(name.clone());
+        requests.send(ProfileRequest::SetName(name.clone()));
+    }
+}
+
+/// Tells the service what we're doing every `PRESENCE_INTERVAL`, and straight away when it changes.
+fn send_presence(
+    lobby: Option<Res<Lobby>>,
+    state: Option<Res<State<AppState>>>,
+    server: Option<Res<CurrentServer>>,
+    client: Res<ProfileClient>,
+    mut sent: Local<Option<(Presence, Instant)>>,
+) {
+    let (Some(lobby), Some(service)) = (lobby, client.service()) else {
+        return;
+    };
+    let presence = match state.as_deref().map(State::get) {
+        Some(AppState::InGame) => Presence::InGame { map: lobby.map, server: server.and_then(|s| s.0) },
+        _ => Presence::InMenus,
+    };
+    if sent.is_some_and(|(last, at)| last == presence && at.elapsed() < PRESENCE_INTERVAL) {
+        return;
+    }
+    *sent = Some((presence, Instant::now()));
+    client.send(&ProfilePacket::Presence { player_id: lobby.local_id(), presence }, service);
+}
+
+fn poll_profiles(
+    mut client: ResMut<ProfileClient>,
+    mut lobby: Option<ResMut<Lobby>>,
+    mut chat: EventWriter<ChatMessage>,
+) {
+    let packets: Vec<ProfilePacket> = client
+        .socket
+        .as_ref()
+        .map(receive_datagrams)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(bytes, _)| ProfilePacket::decode(&bytes))
+        .collect();
+    // bara när något kommit, annars räknas resursen som ändrad varje frame
+    for packet in packets {
+        match (packet, lobby.as_deref_mut()) {
+            (ProfilePacket::Party { lobby: snapshot }, Some(lobby)) => lobby.update_from(snapshot),
+            (ProfilePacket::PartyEnded, Some(lobby)) => lobby.go_solo(),
+            (ProfilePacket::Chat { message }, _) => {
+                chat.send(message);
+            }
+            (packet, _) => client.handle(packet),
+        }
+    }
+}
+
+/// Shows our loadout on our own weapons, also before any game server has sent it.
+fn sync_local_skins(lobby: Option<Res<Lobby>>, client: Res<ProfileClient>, mut skins: ResMut<EquippedSkins>) {
+    let Some(lobby) = lobby else {
+        return;
+    };
+    if !client.is_changed() && !lobby.is_changed() {
+        return;
+    }
+    let loadout: Vec<(Team, WeaponSkin)> = client.loadout.iter().map(|e| (e.team, e.skin.clone())).collect();
+    if skins.get(lobby.local_id()) != loadout.as_slice() {
+        skins.set(lobby.local_id(), loadout);
+    }
+}
+
+/// Keeps a pass for our party while we lead one the service hosts, for the matchmaker.
+fn request_party_pass(lobby: Option<Res<Lobby>>, client: Res<ProfileClient>, mut last: Local<Option<Instant>>) {
+    let (Some(lobby), Some(service)) = (lobby, client.service()) else {
+        return;
+    };
+    if !lobby.is_remote() || lobby.leader != lobby.local_id() {
+        return;
+    }
+    let mut members: Vec<u64> = lobby.members.iter().map(|m| m.id).collect();
+    members.sort_unstable();
+    let renew_at = unix_now() + PARTY_PASS_RENEWAL.as_secs();
+    if client.party_pass.as_ref().is_some_and(|p| p.members == members && p.expires > renew_at) {
+        return;
+    }
+    if last.is_some_and(|t| t.elapsed() < PARTY_PASS_RETRY) {
+        return;
+    }
+    *last = Some(Instant::now());
+    client.send(&ProfilePacket::GetPartyPass { player_id: lobby.local_id() }, service);
+}
+
+fn expire_invites(mut client: ResMut<ProfileClient>) {
+    if client.invites.iter().any(|i| i.received.elapsed() >= INVITE_TIMEOUT) {
+        client.invites.retain(|i| i.received.elapsed() < INVITE_TIMEOUT);
+    }
+}
+
+fn cmd_friend_add(world: &mut World, args: &[String]) -> Result<String, String> {
+    let [name] = args else {
+        return Err("usage: friend_add <name>".into());
+    };
+    world.send_event(ProfileRequest::AddFriend(name.clone()));
+    Ok(String::new())
+}
+
+/// Looks up a friend by name for the console commands.
+fn friend_id(world: &World, name: Option<&String>, usage: &str) -> Result<u64, String> {
+    let name = name.ok_or_else(|| format!("usage: {usage}"))?;
+    world
+        .resource::<ProfileClient>()
+        .friend_by_name(name)
+        .map(|f| f.player_id)
+        .ok_or_else(|| format!("{name} isn't on your friends list"))
+}
+
+fn cmd_friend_remove(world: &mut World, args: &[String]) -> Result<String, String> {
+    let id = friend_id(world, args.first(), "friend_remove <name>")?;
+    world.send_event(ProfileRequest::RemoveFriend(id));
+    Ok(String::new())
+}
+
+fn cmd_party_invite(world: &mut World, args: &[String]) -> Result<String, String> {
+    let id = friend_id(world, args.first(), "party_invite <friend>")?;
+    world.send_event(ProfileRequest::InviteToParty(id));
+    Ok(String::new())
+}
+
+fn cmd_party_join(world: &mut World, args: &[String]) -> Result<String, String> {
+    let id = friend_id(world, args.first(), "party_join <friend>")?;
+    world.send_event(ProfileRequest::JoinParty(id));
+    Ok(String::new())
+}
+
+fn cmd_party_leave(world: &mut World, _args: &[String]) -> Result<String, String> {
+    if !world.get_resource::<Lobby>().is_some_and(|l| l.is_remote()) {
+        return Err("you aren't in a party".into());
+    }
+    world.send_event(LobbyRequest::Leave);
+    Ok(String::new())
+}
+
+fn describe_roll(roll: &RollRecord) -> String {
+    let name = |id: &str| item_def(id).map_or(id.to_string(), |d| d.name.to_string());
+    let what = match &roll.kind {
+        RollKind::Case(case) => name(case),
+        RollKind::Drop => "match drop".into(),
+    };
+    let outcome = roll.outcome.as_ref().map_or("nothing".into(), |o| name(&o.definition));
+    let check = match roll.verify() {
+        None => "seed not revealed yet",
+        Some(true) => "verified",
+        Some(false) => "DOES NOT MATCH",
+    };
+    format!("#{} {what}: {outcome} ({check})", roll.nonce)
+}
+
+fn cmd_rolls(world: &mut World, _args: &[String]) -> Result<String, String> {
+    world.send_event(ProfileRequest::FetchRolls);
+    let client = world.resource::<ProfileClient>();
+    let Some(seeds) = &client.seeds else {
+        return Err("the profile service hasn't answered".into());
+    };
+    let mut lines = vec![format!(
+        "server seed hash {}, client seed \"{}\", next nonce {}",
+        to_hex(&seeds.commitment),
+        seeds.client_seed,
+        seeds.nonce
+    )];
+    if client.rolls.is_empty() {
+        lines.push("fetching your rolls, run rolls again in a moment".into());
+    }
+    lines.extend(client.rolls.iter().map(describe_roll));
+    Ok(lines.join("\n"))
+}
+
+fn cmd_client_seed(world: &mut World, args: &[String]) -> Result<String, String> {
+    let [seed] = args else {
+        return Err("usage: client_seed <seed>".into());
+    };
+    if seed.chars().count() > MAX_CLIENT_SEED_LENGTH {
+        return Err(format!("client seeds are at most {MAX_CLIENT_SEED_LENGTH} characters"));
+    }
+    world.send_event(ProfileRequest::SetClientSeed(seed.clone()));
+    Ok("the old server seed is revealed, run rolls to check what it rolled".into())
+}
+
+fn cmd_drop_odds(_world: &mut World, _args: &[String]) -> Result<String, String> {
+    let mut lines: Vec<String> = drop_odds()
+        .into_iter()
+        .map(|(id, chance)| format!("{}: {:.2}%", item_def(id).map_or(id, |d| d.name), chance * 100.0))
+        .collect();
+    lines.push(format!("at most {MAX_DROPS_PER_WEEK} drops a week"));
+    Ok(lines.join("\n"))
+}
+
+/// Item ids separated by commas, `-` for none.
+fn item_ids(text: &str) -> Result<Vec<u64>, String> {
+    if text == "-" {
+        return Ok(Vec::new());
+    }
+    text.split(',').map(|id| id.trim().parse().map_err(|_| format!("{id} isn't an item id"))).collect()
+}
+
+fn cmd_trade_offer(world: &mut World, args: &[String]) -> Result<String, String> {
+    const USAGE: &str = "usage: trade_offer <friend> <give ids|-> <take ids|-> [give credits] [take credits]";
+    let (Some(give), Some(take)) = (args.get(1), args.get(2)) else {
+        return Err(USAGE.into());
+    };
+    let to = friend_id(world, args.first(), USAGE)?;
+    let credits = |arg: Option<&String>| arg.map_or(Ok(0), |c| c.parse().map_err(|_| format!("{c} isn't an amount")));
+    world.send_event(ProfileRequest::SendOffer {
+        to,
+        give: item_ids(give)?,
+        give_credits: credits(args.get(3))?,
+        take: item_ids(take)?,
+        take_credits: credits(args.get(4))?,
+    });
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn friend(player_id: u64) -> Friend {
+        Friend { player_id, name: format!("friend {player_id}"), status: FriendStatus::Accepted, presence: default() }
+    }
+
+    #[test]
+    fn lists_are_split_and_only_swapped_in_when_the_last_reply_comes() {
+        let friends: Vec<Friend> = (1..=25).map(friend).collect();
+        let replies = ProfilePacket::list_replies(&friends, |friends, last| ProfilePacket::Friends {
+            player_id: 1,
+            friends,
+            last,
+        });
+        assert_eq!(replies.len(), 3);
+
+        let mut client = ProfileClient { friends: vec![friend(99)], ..default() };
+        for reply in replies {
+            // den gamla listan syns tills hela den nya har kommit
+            assert_eq!(client.friends, [friend(99)]);
+            client.handle(reply);
+        }
+        assert_eq!(client.friends, friends);
+
+        // en tom lista är ett svar med last satt
+        let empty = ProfilePacket::list_replies(&[], |friends, last| ProfilePacket::Friends {
+            player_id: 1,
+            friends,
+            last,
+        });
+        assert_eq!(empty.len(), 1);
+        client.handle(empty.into_iter().next().unwrap());
+        assert!(client.friends.is_empty());
+    }
+
+    #[test]
+    fn signed_requests_are_padded_and_answers_are_not() {
+        let session = Session::issue(&[1; 32], 1, 1000);
+        let request = ProfilePacket::Signed { session, packet: Box::new(ProfilePacket::GetFriends { player_id: 1 }) };
+        let bytes = request.encode();
+        assert_eq!(bytes.len(), REQUEST_SIZE);
+        assert_eq!(ProfilePacket::decode(&bytes), Some(request));
+        assert_eq!(ProfilePacket::decode(&bytes[..REQUEST_SIZE - 1]), None);
+
+        let answer = ProfilePacket::Wallet { player_id: 1, balance: 5 };
+        assert!(answer.encode().len() < REQUEST_SIZE);
+        assert_eq!(ProfilePacket::decode(&answer.encode()), Some(answer));
+    }
+
+    #[test]
+    fn the_service_is_only_used_with_a_session() {
+        let mut client = ProfileClient { address: Some(([127, 0, 0, 1], PROFILE_PORT).into()), ..default() };
+        assert_eq!(client.service(), None);
+        client.session = Some(Session::issue(&[1; 32], 1, 1000));
+        assert_eq!(client.service(), client.address);
+    }
+}
@@ -0,0 +1,342 @@
+//! Server query protocol over UDP.
+//!
+//! A client sends an [`QueryPacket::InfoRequest`] to the query port and the server answers
+//! with a [`QueryPacket::InfoReply`] carrying its [`ServerInfo`]. The same request sent as a
+//! broadcast finds servers on the LAN. Packets are a 4 byte magic followed by the packet in
+//! bincode. Requests are padded to [`REQUEST_SIZE`] so a spoofed request never gets back more
+//! bytes than it sent.
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Instant;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use shared::config::GameConfig;
+use shared::cvars::{CvarChanged, CvarDef, CvarFlags, RegisterCvarExt};
+use shared::game_state::{PlayerStats, RoundState};
+use shared::lobby::{GameMode, Lobby};
+use shared::maps::MapId;
+use shared::startup::StartupConfigPlugin;
+
+use crate::connection::GAME_PORT;
+use crate::master::{MasterPacket, HEARTBEAT_INTERVAL, MASTER_PORT};
+use crate::matchmaking::{AllocatedMatch, MatchmakingPacket, MATCHMAKER_PORT};
+
+pub const QUERY_PORT: u16 = 27016;
+/// Requests shorter than this are dropped.
+pub const REQUEST_SIZE: usize = 256;
+/// Largest packet we read or send.
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+const MAGIC: &[u8; 4] = b"FPSQ";
+
+/// Game version, servers with another one are shown but can't be joined.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    /// Port the game itself is played on, see `connection`
+    pub port: u16,
+    pub map: MapId,
+    pub mode: GameMode,
+    pub players: u8,
+    pub max_players: u8,
+    pub password: bool,
+    pub tick_rate: u16,
+    pub version: String,
+}
+
+impl ServerInfo {
+    pub fn is_full(&self) -> bool {
+        self.players >= self.max_players
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum QueryPacket {
+    /// `token` comes back in the reply so answers can be matched to requests
+    InfoRequest { token: u32 },
+    InfoReply { token: u32, info: ServerInfo },
+}
+
+impl QueryPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serde::encode_to_vec(self, bincode::config::standard()).unwrap_or_default());
+        if matches!(self, QueryPacket::InfoRequest { .. }) {
+            bytes.resize(REQUEST_SIZE, 0);
+        }
+        bytes
+    }
+
+    /// `None` for anything that isn't a well-formed query packet.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC)?;
+        let config = bincode::config::standard().with_limit::<MAX_PACKET_SIZE>();
+        let (packet, _) = bincode::serde::decode_from_slice::<QueryPacket, _>(body, config).ok()?;
+        if matches!(packet, QueryPacket::InfoRequest { .. }) && bytes.len() < REQUEST_SIZE {
+            return None;
+        }
+        Some(packet)
+    }
+}
+
+/// Reads every waiting datagram from a non-blocking socket.
+pub(crate) fn receive_datagrams(socket: &UdpSocket) -> Vec<(Vec<u8>, SocketAddr)> {
+    let mut datagrams = Vec::new();
+    let mut buf = [0u8; MAX_PACKET_SIZE];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => datagrams.push((buf[..len].to_vec(), from)),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            // t.ex. ICMP port unreachable från en server som inte svarar, Windows rapporterar det här
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => continue,
+            Err(err) => {
+                warn!("query socket: {err}");
+                break;
+            }
+        }
+    }
+    datagrams
+}
+
+/// Resolves `host` or `host:port`, using `default_port` when none is given.
+pub fn resolve_address(text: &str, default_port: u16) -> Option<SocketAddr> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if let Ok(addr) = text.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    // IPv6 utan port har kolon men inga hakparenteser
+    let with_port = match text.parse::<std::net::IpAddr>() {
+        Ok(ip) => return Some(SocketAddr::new(ip, default_port)),
+        Err(_) if text.contains(':') => text.to_string(),
+        Err(_) => format!("{text}:{default_port}"),
+    };
+    with_port.to_socket_addrs().ok()?.next()
+}
+
+/// A system that looks the address in cvar `name` up at startup and whenever the cvar
+/// changes, and hands the result to `apply`. Never every frame: a host name means a blocking
+/// DNS lookup.
+pub fn resolve_address_cvar<R: Resource>(
+    name: &'static str,
+    default_port: u16,
+    apply: fn(&mut R, Option<SocketAddr>),
+) -> impl FnMut(EventReader<CvarChanged>, Res<GameConfig>, ResMut<R>, Local<bool>) {
+    move |mut changes, config, mut target, mut resolved| {
+        let changed = changes.read().any(|c| c.name == name);
+        if *resolved && !changed {
+            return;
+        }
+        *resolved = true;
+        let host = config.get_str(name).unwrap_or("");
+        let address = resolve_address(host, default_port);
+        if address.is_none() && !host.trim().is_empty() {
+            warn!("can't resolve {name} {host}");
+        }
+        apply(&mut target, address);
+    }
+}
+
+/// Answers queries on a dedicated server and registers it with the master server.
+pub struct QueryServerPlugin;
+
+impl Plugin for QueryServerPlugin {
+    fn build(&self, app: &mut App) {
+        // sv_matchmaker och sv_master slås upp igen när de ändras
+        if !app.is_plugin_added::<StartupConfigPlugin>() {
+            app.add_plugins(StartupConfigPlugin);
+        }
+        app.register_cvar(
+            CvarDef::string("hostname", "FPS Server")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Server name shown in the server browser"),
+        )
+        .register_cvar(
+            CvarDef::string("sv_password", "")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Password needed to join, empty for none"),
+        )
+        .register_cvar(
+            CvarDef::int("sv_maxplayers", 10)
+                .int_range(1, 64)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Players the server takes"),
+        )
+        .register_cvar(
+            CvarDef::int("sv_query_port", QUERY_PORT as i32)
+                .int_range(1024, 65535)
+                .flags(CvarFlags::ARCHIVE)
+                .description("UDP port the server answers browser queries on"),
+        )
+        .register_cvar(
+            CvarDef::string("sv_matchmaker", "localhost")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Matchmaker allowed to give the server matches, empty to take none"),
+        )
+        .register_cvar(
+            CvarDef::string("sv_master", "localhost")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Master server to register with, empty to stay off the internet list"),
+        )
+        .init_resource::<QuerySocket>()
+        .init_resource::<TrustedMatchmaker>()
+        .init_resource::<MasterServer>()
+        .add_systems(Startup, bind_query_socket)
+        .add_systems(
+            Update,
+            (
+                resolve_address_cvar("sv_matchmaker", MATCHMAKER_PORT, |trusted: &mut TrustedMatchmaker, address| {
+                    trusted.0 = address;
+                }),
+                resolve_address_cvar("sv_master", MASTER_PORT, |master: &mut MasterServer, address| {
+                    master.0 = address;
+                }),
+                answer_queries,
+                send_heartbeats,
+                report_match_result,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+struct QuerySocket(Option<UdpSocket>);
+
+/// Där `sv_matchmaker` pekar; matcher från någon annan ignoreras
+#[derive(Resource, Default)]
+struct TrustedMatchmaker(Option<SocketAddr>);
+
+/// Där `sv_master` pekar, bara den får skicka oss challenges
+#[derive(Resource, Default)]
+struct MasterServer(Option<SocketAddr>);
+
+fn bind_query_socket(config: Res<GameConfig>, mut socket: ResMut<QuerySocket>) {
+    let port = config.get_int("sv_query_port").unwrap_or(QUERY_PORT as i32) as u16;
+    socket.0 = match UdpSocket::bind(("0.0.0.0", port)).and_then(|s| s.set_nonblocking(true).map(|_| s)) {
+        Ok(s) => {
+            info!("answering server queries on port {port}");
+            Some(s)
+        }
+        Err(err) => {
+            error!("could not open query port {port}: {err}");
+            None
+        }
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+fn answer_queries(
+    mut commands: Commands,
+    socket: Res<QuerySocket>,
+    matchmaker: Res<TrustedMatchmaker>,
+    master: Res<MasterServer>,
+    config: Res<GameConfig>,
+    mut lobby: Option<ResMut<Lobby>>,
+    mut round: Option<ResMut<RoundState>>,
+    time: Res<Time<Fixed>>,
+    players: Query<&PlayerStats>,
+) {
+    let Some(socket) = &socket.0 else {
+        return;
+    };
+    let mut requests = Vec::new();
+    for (bytes, from) in receive_datagrams(socket) {
+        if let Some(packet) = QueryPacket::decode(&bytes) {
+            requests.push((packet, from));
+            continue;
+        }
+        if let Some(MasterPacket::Challenge { challenge }) = MasterPacket::decode(&bytes) {
+            // annars kunde vem som helst få oss att skicka paket vart som helst
+            if master.0 == Some(from) {
+                let reply = MasterPacket::ChallengeReply { challenge };
+                if let Err(err) = socket.send_to(&reply.encode(), from) {
+                    warn!("challenge reply to {from} failed: {err}");
+                }
+            }
+            continue;
+        }
+        let Some(MatchmakingPacket::Allocate { match_id, mode, map, tickets }) = MatchmakingPacket::decode(&bytes)
+        else {
+            continue;
+        };
+        // en förfalskad match skulle ta över servern, och den riktiga släpps aldrig
+        if matchmaker.0 != Some(from) {
+            warn!("ignoring a match from {from}, sv_matchmaker is somewhere else");
+            continue;
+        }
+        // matchmakern har gett oss en match
+        info!("match {match_id} allocated by {from}: {} on {}", mode.label(), map.label());
+        if let Some(lobby) = &mut lobby {
+            lobby.map = map;
+            lobby.mode = mode;
+        }
+        // förra matchens resultat ska inte räknas för den här
+        if let Some(round) = &mut round {
+            **round = RoundState { max_rounds: round.max_rounds, ..default() };
+        }
+        commands.insert_resource(AllocatedMatch { match_id, mode, map, tickets, matchmaker: from });
+    }
+    if requests.is_empty() {
+        return;
+    }
+
+    let info = ServerInfo {
+        name: config.get_str("hostname").unwrap_or("FPS Server").to_string(),
+        port: config.get_int("sv_port").unwrap_or(GAME_PORT as i32) as u16,
+        map: lobby.as_ref().map_or(MapId::Tutorial, |l| l.map),
+        mode: lobby.as_ref().map_or(GameMode::default(), |l| l.mode),
+        players: players.iter().count().min(u8::MAX as usize) as u8,
+        max_players: config.get_int("sv_maxplayers").unwrap_or(10).clamp(1, 255) as u8,
+        password: config.get_str("sv_password").is_some_and(|p| !p.is_empty()),
+        tick_rate: (1.0 / time.timestep().as_secs_f64()).round() as u16,
+        version: VERSION.to_string(),
+    };
+    for (packet, from) in requests {
+        if let QueryPacket::InfoRequest { token } = packet {
+            let reply = QueryPacket::InfoReply { token, info: info.clone() };
+            if let Err(err) = socket.send_to(&reply.encode(), from) {
+                warn!("could not answer query from {from}: {err}");
+            }
+        }
+    }
+}
+
+/// Heartbeats go out from the query socket so the master lists the port clients query.
+/// The master's challenge comes back to that socket and `answer_queries` replies to it.
+fn send_heartbeats(socket: Res<QuerySocket>, master: Res<MasterServer>, mut last: Local<Option<Instant>>) {
+    let (Some(socket), Some(master)) = (&socket.0, master.0) else {
+        return;
+    };
+    if last.is_some_and(|t| t.elapsed() < HEARTBEAT_INTERVAL) {
+        return;
+    }
+    *last = Some(Instant::now());
+    if let Err(err) = socket.send_to(&MasterPacket::Heartbeat.encode(), master) {
+        warn!("heartbeat to {master} failed: {err}");
+    }
+}
+
+/// Sends the final score of a matchmade match back to the matchmaker, which rates it and
+/// gives the server a new match.
+fn report_match_result(
+    mut commands: Commands,
+    socket: Res<QuerySocket>,
+    allocated: Option<Res<AllocatedMatch>>,
+    round: Option<Res<RoundState>>,
+) {
+    let (Some(socket), Some(allocated), Some(round)) = (&socket.0, allocated, round) else {
+        return;
+    };
+    if !round.is_match_over() {
+        return;
+    }
+    info!("match {} over, {}-{}", allocated.match_id, round.score_ct, round.score_t);
+    allocated.release(socket, Some([round.score_ct, round.score_t]));
+    commands.remove_resource::<AllocatedMatch>();
+}
@@ -0,0 +1,156 @@
+//! Getting everyone's skins onto everyone's screens.
+//!
+//! The game server asks the profile service for the loadout of every player that connects
+//! and passes it on to all players with [`ServerMessage::Skins`]. Since it comes from the
+//! profile service, nobody can show a skin they don't own. Clients keep what they're sent in
+//! `EquippedSkins`; our own loadout is put there by the profile client as well, so it shows
+//! offline too.
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+
+use bevy::prelude::*;
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::items::{EquippedSkins, ItemsPlugin};
+
+use crate::connection::{GameServer, PlayerConnected, PlayerDisconnected};
+use crate::profile::{EquippedSkin, ProfilePacket, PROFILE_PORT};
+use crate::protocol::ServerMessage;
+use crate::query::{receive_datagrams, resolve_address_cvar};
+
+/// Asks the profile service for the loadouts of the players on the server.
+#[derive(Resource)]
+struct LoadoutFetcher {
+    socket: Option<UdpSocket>,
+    /// Där `sv_profiles` pekar, slås bara upp när den ändras
+    service: Option<SocketAddr>,
+    /// Loadouts som håller på att tas emot
+    incoming: HashMap<u64, Vec<EquippedSkin>>,
+}
+
+impl Default for LoadoutFetcher {
+    fn default() -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).and_then(|s| s.set_nonblocking(true).map(|_| s));
+        if let Err(err) = &socket {
+            error!("can't open a socket for loadouts: {err}");
+        }
+        LoadoutFetcher { socket: socket.ok(), service: None, incoming: HashMap::new() }
+    }
+}
+
+pub struct ServerSkinsPlugin;
+
+impl Plugin for ServerSkinsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ItemsPlugin>() {
+            app.add_plugins(ItemsPlugin);
+        }
+        app.register_cvar(
+            CvarDef::string("sv_profiles", "localhost")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Profile service the players' loadouts are fetched from"),
+        )
+        .init_resource::<LoadoutFetcher>()
+        .add_systems(
+            Update,
+            (
+                resolve_address_cvar("sv_profiles", PROFILE_PORT, |fetcher: &mut LoadoutFetcher, address| {
+                    fetcher.service = address;
+                }),
+                fetch_loadouts,
+                receive_loadouts,
+                forget_loadouts,
+            )
+                .chain(),
+        );
+    }
+}
+
+fn fetch_loadouts(
+    mut connected: EventReader<PlayerConnected>,
+    fetcher: Res<LoadoutFetcher>,
+    skins: Res<EquippedSkins>,
+    game: Option<ResMut<GameServer>>,
+) {
+    let Some(mut game) = game else {
+        connected.clear();
+        return;
+    };
+    for &PlayerConnected { player_id } in connected.read() {
+        if let (Some(socket), Some(service)) = (&fetcher.socket, fetcher.service) {
+            if let Err(err) = socket.send_to(&ProfilePacket::GetLoadout { player_id }.encode(), service) {
+                warn!("can't fetch the loadout of {player_id}: {err}");
+            }
+        }
+        // den som kommer in får allas skins, sina egna kommer när tjänsten svarat
+        for (other, equipped) in skins.iter() {
+            game.send_message(player_id, &ServerMessage::Skins { player_id: other, skins: equipped.to_vec() });
+        }
+    }
+}
+
+fn receive_loadouts(
+    mut fetcher: ResMut<LoadoutFetcher>,
+    mut skins: ResMut<EquippedSkins>,
+    game: Option<ResMut<GameServer>>,
+) {
+    let (Some(mut game), Some(socket)) = (game, &fetcher.socket) else {
+        return;
+    };
+    let packets = receive_datagrams(socket);
+    for (bytes, from) in packets {
+        if Some(from) != fetcher.service {
+            continue;
+        }
+        let Some(ProfilePacket::Loadout { player_id, skins: part, last }) = ProfilePacket::decode(&bytes) else {
+            continue;
+        };
+        fetcher.incoming.entry(player_id).or_default().extend(part);
+        if !last {
+            continue;
+        }
+        let loadout = fetcher.incoming.remove(&player_id).unwrap_or_default();
+        if !game.is_connected(player_id) {
+            continue;
+        }
+        let equipped: Vec<_> = loadout.into_iter().map(|e| (e.team, e.skin)).collect();
+        game.broadcast_message(&ServerMessage::Skins { player_id, skins: equipped.clone() });
+        skins.set(player_id, equipped);
+    }
+}
+
+fn forget_loadouts(
+    mut disconnected: EventReader<PlayerDisconnected>,
+    mut fetcher: ResMut<LoadoutFetcher>,
+    mut skins: ResMut<EquippedSkins>,
+    game: Option<ResMut<GameServer>>,
+) {
+    let Some(mut game) = game else {
+        disconnected.clear();
+        return;
+    };
+    for &PlayerDisconnected { player_id } in disconnected.read() {
+        fetcher.incoming.remove(&player_id);
+        skins.set(player_id, Vec::new());
+        game.broadcast_message(&ServerMessage::Skins { player_id, skins: Vec::new() });
+    }
+}
+
+/// Keeps `EquippedSkins` up to date with what the game server sends.
+pub struct ClientSkinsPlugin;
+
+impl Plugin for ClientSkinsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ItemsPlugin>() {
+            app.add_plugins(ItemsPlugin);
+        }
+        app.add_event::<ServerMessage>().add_systems(Update, receive_skins);
+    }
+}
+
+fn receive_skins(mut messages: EventReader<ServerMessage>, mut skins: ResMut<EquippedSkins>) {
+    for message in messages.read() {
+        if let ServerMessage::Skins { player_id, skins: equipped } = message {
+            skins.set(*player_id, equipped.clone());
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! The scoreboard: every player's `PlayerStats`, kept by the game server.
+//!
+//! The server has an entity with [`PlayerStats`] for each connected player, named from
+//! [`ClientMessage::Name`], on the team the match gave them (or else the smaller one) and
+//! with the ping of their connection. `MatchStatsPlugin` adds up kills, deaths and assists on it. Whenever a
+//! player's stats change they go to everybody with [`ServerMessage::Stats`], and a player
+//! who joins gets everyone's. Clients keep an entity per player with the stats they were
+//! sent, which is what the scoreboard shows.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use shared::game_state::{MatchStatsPlugin, PlayerStats, Team};
+
+use crate::connection::{GameConnection, GameServer, PlayerConnected, PlayerDisconnected, PlayerMessage};
+use crate::matchmaking::AllocatedMatch;
+use crate::profile::MAX_NAME_LENGTH;
+use crate::protocol::{ClientMessage, ServerMessage};
+
+/// Pengar vid start, som i CS
+pub(crate) const START_MONEY: u32 = 800;
+/// How often pings are brought up to date.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keeps the players' stats on the game server and sends them out.
+pub struct ServerStatsPlugin;
+
+impl Plugin for ServerStatsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<MatchStatsPlugin>() {
+            app.add_plugins(MatchStatsPlugin);
+        }
+        app.add_systems(Update, (track_players, name_players, update_pings, send_stats).chain());
+    }
+}
+
+fn track_players(
+    mut commands: Commands,
+    mut connected: EventReader<PlayerConnected>,
+    mut disconnected: EventReader<PlayerDisconnected>,
+    allocated: Option<Res<AllocatedMatch>>,
+    players: Query<(Entity, &PlayerStats)>,
+    game: Option<ResMut<GameServer>>,
+) {
+    let Some(mut game) = game else {
+        connected.clear();
+        disconnected.clear();
+        return;
+    };
+    let mut team_sizes = HashMap::new();
+    for team in players.iter().filter_map(|(_, stats)| stats.team) {
+        *team_sizes.entry(team).or_default() += 1;
+    }
+    for &PlayerConnected { player_id } in connected.read() {
+        // den nya spelaren får allas rader, sin egen skickas när den dykt upp
+        for (_, stats) in &players {
+            game.send_message(player_id, &ServerMessage::Stats(stats.clone()));
+        }
+        let ticket = allocated.as_ref().and_then(|a| a.tickets.iter().find(|t| t.player_id == player_id));
+        let team = ticket.map_or_else(|| smaller_team(&team_sizes), |t| t.team);
+        *team_sizes.entry(team).or_default() += 1;
+        commands.spawn(PlayerStats {
+            player_id,
+            name: format!("Player {player_id}"),
+            team: Some(team),
+            money: START_MONEY,
+            alive: true,
+            ..default()
+        });
+    }
+    for &PlayerDisconnected { player_id } in disconnected.read() {
+        for (entity, _) in players.iter().filter(|(_, stats)| stats.player_id == player_id) {
+            commands.entity(entity).despawn();
+        }
+        game.broadcast_message(&ServerMessage::PlayerLeft { player_id });
+    }
+}
+
+/// Det lag som har minst spelare, CT om de är lika många
+fn smaller_team(sizes: &HashMap<Team, usize>) -> Team {
+    let size = |team| sizes.get(&team).copied().unwrap_or(0);
+    if size(Team::Terrorist) < size(Team::CounterTerrorist) {
+        Team::Terrorist
+    } else {
+        Team::CounterTerrorist
+    }
+}
+
+fn name_players(mut messages: EventReader<PlayerMessage>, mut players: Query<&mut PlayerStats>) {
+    for PlayerMessage { player_id, message } in messages.read() {
+        let ClientMessage::Name(name) = message else {
+            continue;
+        };
+        let name: String = name.trim().chars().take(MAX_NAME_LENGTH).collect();
+        if name.is_empty() {
+            continue;
+        }
+        for mut stats in players.iter_mut().filter(|s| s.player_id == *player_id) {
+            stats.name = name.clone();
+        }
+    }
+}
+
+fn update_pings(
+    game: Option<Res<GameServer>>,
+    mut players: Query<&mut PlayerStats>,
+    mut last: Local<Option<Instant>>,
+) {
+    let Some(game) = game else {
+        return;
+    };
+    if last.is_some_and(|t| t.elapsed() < PING_INTERVAL) {
+        return;
+    }
+    *last = Some(Instant::now());
+    for mut stats in &mut players {
+        let ping = game.ping(stats.player_id).unwrap_or(0);
+        // bara riktiga ändringar, annars skickas allas rader varje sekund
+        if stats.ping != ping {
+            stats.ping = ping;
+        }
+    }
+}
+
+fn send_stats(changed: Query<&PlayerStats, Changed<PlayerStats>>, game: Option<ResMut<GameServer>>) {
+    let Some(mut game) = game else {
+        return;
+    };
+    for stats in &changed {
+        game.broadcast_message(&ServerMessage::Stats(stats.clone()));
+    }
+}
+
+/// Keeps the stats the server sends on entities of our own, for the scoreboard.
+pub struct ClientStatsPlugin;
+
+impl Plugin for ClientStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ServerMessage>().add_systems(Update, (receive_stats, forget_stats).chain());
+    }
+}
+
+fn receive_stats(
+    mut commands: Commands,
+    mut messages: EventReader<ServerMessage>,
+    mut players: Query<(Entity, &mut PlayerStats)>,
+) {
+    // senaste raden per spelare, None om de har gått
+    let mut latest = HashMap::new();
+    for message in messages.read() {
+        match message {
+            ServerMessage::Stats(stats) => latest.insert(stats.player_id, Some(stats.clone())),
+            ServerMessage::PlayerLeft { player_id } => latest.insert(*player_id, None),
+            _ => continue,
+        };
+    }
+    for (entity, mut stats) in &mut players {
+        match latest.remove(&stats.player_id) {
+            Some(Some(new)) => *stats = new,
+            Some(None) => commands.entity(entity).despawn(),
+            None => {}
+        }
+    }
+    for stats in latest.into_values().flatten() {
+        commands.spawn(stats);
+    }
+}
+
+/// The scoreboard is the server's, so it goes when we leave.
+fn forget_stats(mut commands: Commands, connection: Res<GameConnection>, players: Query<Entity, With<PlayerStats>>) {
+    if connection.is_connected() {
+        return;
+    }
+    for entity in &players {
+        commands.entity(entity).despawn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(player_id: u64, kills: u32) -> PlayerStats {
+        PlayerStats { player_id, name: format!("p{player_id}"), kills, ..default() }
+    }
+
+    fn scoreboard(app: &mut App) -> Vec<(u64, u32)> {
+        let mut rows: Vec<_> =
+            app.world_mut().query::<&PlayerStats>().iter(app.world()).map(|s| (s.player_id, s.kills)).collect();
+        rows.sort_unstable();
+        rows
+    }
+
+    #[test]
+    fn the_scoreboard_follows_what_the_server_sends() {
+        let mut app = App::new();
+        app.add_event::<ServerMessage>().add_systems(Update, receive_stats);
+
+        // flera rader för samma spelare i en frame blir en entitet
+        app.world_mut().send_event(ServerMessage::Stats(stats(1, 0)));
+        app.world_mut().send_event(ServerMessage::Stats(stats(1, 1)));
+        app.world_mut().send_event(ServerMessage::Stats(stats(2, 0)));
+        app.update();
+        assert_eq!(scoreboard(&mut app), [(1, 1), (2, 0)]);
+
+        app.world_mut().send_event(ServerMessage::Stats(stats(2, 3)));
+        app.world_mut().send_event(ServerMessage::PlayerLeft { player_id: 1 });
+        app.update();
+        assert_eq!(scoreboard(&mut app), [(2, 3)]);
+    }
+
+    #[test]
+    fn the_scoreboard_goes_when_we_leave() {
+        let mut app = App::new();
+        app.init_resource::<GameConnection>().add_systems(Update, forget_stats);
+        app.world_mut().spawn(stats(1, 0));
+        app.update();
+        assert!(scoreboard(&mut app).is_empty());
+    }
+}
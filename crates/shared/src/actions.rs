@@ -0,0 +1,309 @@
+//! Gameplay actions and the key bindings that drive them.
+//!
+//! Bindings map a key to a console command, like `bind w +forward`. Commands naming an
+//! [`Action`] are held for as long as the key is down and show up in [`ActionState`]; any
+//! other bound command is queued to the console when the key is pressed, and a `+command`
+//! also gets its `-command` when the key is released.
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+use crate::cfg;
+use crate::config::GameConfig;
+use crate::console::{ConsoleCommandsPlugin, ConsoleQueue};
+use crate::keys::InputKey;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    Forward,
+    Back,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Crouch,
+    Walk,
+    Fire,
+    Aim,
+    Reload,
+    Use,
+    Buy,
+    Scoreboard,
+}
+
+impl Action {
+    /// In the order they are listed in the key bindings menu.
+    pub const ALL: [Action; 13] = [
+        Action::Forward,
+        Action::Back,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Crouch,
+        Action::Walk,
+        Action::Fire,
+        Action::Aim,
+        Action::Reload,
+        Action::Use,
+        Action::Buy,
+        Action::Scoreboard,
+    ];
+
+    /// The command that is bound to a key, same names as in Source where there is one.
+    pub fn command(self) -> &'static str {
+        match self {
+            Action::Forward => "+forward",
+            Action::Back => "+back",
+            Action::MoveLeft => "+moveleft",
+            Action::MoveRight => "+moveright",
+            Action::Jump => "+jump",
+            Action::Crouch => "+duck",
+            Action::Walk => "+speed",
+            Action::Fire => "+attack",
+            Action::Aim => "+attack2",
+            Action::Reload => "+reload",
+            Action::Use => "+use",
+            Action::Buy => "+buy",
+            Action::Scoreboard => "+showscores",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Forward => "Move Forward",
+            Action::Back => "Move Back",
+            Action::MoveLeft => "Strafe Left",
+            Action::MoveRight => "Strafe Right",
+            Action::Jump => "Jump",
+            Action::Crouch => "Crouch",
+            Action::Walk => "Walk",
+            Action::Fire => "Fire",
+            Action::Aim => "Aim",
+            Action::Reload => "Reload",
+            Action::Use => "Use",
+            Action::Buy => "Buy Menu",
+            Action::Scoreboard => "Scoreboard",
+        }
+    }
+
+    pub fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Forward => &["w", "uparrow"],
+            Action::Back => &["s", "downarrow"],
+            Action::MoveLeft => &["a", "leftarrow"],
+            Action::MoveRight => &["d", "rightarrow"],
+            Action::Jump => &["space", "joy_a"],
+            Action::Crouch => &["ctrl", "joy_b"],
+            Action::Walk => &["shift", "joy_lstick"],
+            Action::Fire => &["mouse1", "joy_rt"],
+            Action::Aim => &["mouse2", "joy_lt"],
+            Action::Reload => &["r", "joy_x"],
+            Action::Use => &["e", "joy_y"],
+            Action::Buy => &["b", "joy_up"],
+            Action::Scoreboard => &["tab", "joy_back"],
+        }
+    }
+
+    pub fn from_command(command: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|a| a.command() == command)
+    }
+}
+
+/// Default keys for commands that aren't actions.
+const DEFAULT_COMMAND_KEYS: &[(&str, &str)] = &[("y", "messagemode"), ("u", "messagemode2")];
+
+/// The bindings a fresh config starts with.
+pub fn default_bindings() -> impl Iterator<Item = (&'static str, &'static str)> {
+    Action::ALL
+        .into_iter()
+        .flat_map(|action| action.default_keys().iter().map(move |key| (*key, action.command())))
+        .chain(DEFAULT_COMMAND_KEYS.iter().copied())
+}
+
+/// Actions held this frame, rebuilt from the bindings in `PreUpdate`.
+#[derive(Resource, Default, Debug)]
+pub struct ActionState {
+    held: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    just_released: HashSet<Action>,
+    /// Vänster spak, x framåt och y höger precis som `PlayerInput::movement`
+    stick: Vec2,
+    blocked_by: HashSet<&'static str>,
+}
+
+/// Stick input below this is treated as zero.
+const STICK_DEADZONE: f32 = 0.15;
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.held.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        self.just_released.contains(&action)
+    }
+
+    /// Movement with x forward and y right, from the move actions and the left stick.
+    pub fn movement(&self) -> Vec2 {
+        let axis = |pos: Action, neg: Action| self.pressed(pos) as i32 as f32 - self.pressed(neg) as i32 as f32;
+        let keys = Vec2::new(axis(Action::Forward, Action::Back), axis(Action::MoveRight, Action::MoveLeft));
+        (keys + self.stick).clamp_length_max(1.0)
+    }
+
+    /// Stops actions while something else owns the keyboard, like the console or the
+    /// rebinding screen. Each `source` blocks until it unblocks itself.
+    pub fn set_blocked(&mut self, source: &'static str, blocked: bool) {
+        if blocked {
+            self.blocked_by.insert(source);
+        } else {
+            self.blocked_by.remove(source);
+        }
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        !self.blocked_by.is_empty()
+    }
+
+    pub fn is_blocked_by(&self, source: &str) -> bool {
+        self.blocked_by.contains(source)
+    }
+
+    fn update(&mut self, held: HashSet<Action>, stick: Vec2) {
+        self.just_pressed = held.difference(&self.held).copied().collect();
+        self.just_released = self.held.difference(&held).copied().collect();
+        self.held = held;
+        self.stick = stick;
+    }
+}
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ConsoleCommandsPlugin>() {
+            app.add_plugins(ConsoleCommandsPlugin);
+        }
+        app.init_resource::<ActionState>()
+            .add_systems(PreUpdate, update_action_state.after(InputSystem));
+    }
+}
+
+/// Whether `key` is down, and whether it went down or up this frame.
+struct KeyState {
+    pressed: bool,
+    just_pressed: bool,
+    just_released: bool,
+}
+
+fn key_state(
+    key: InputKey,
+    keys: &ButtonInput<KeyCode>,
+    mouse: &ButtonInput<MouseButton>,
+    gamepads: &Gamepads,
+    pad_buttons: &ButtonInput<GamepadButton>,
+) -> KeyState {
+    match key {
+        InputKey::Key(code) => KeyState {
+            pressed: keys.pressed(code),
+            just_pressed: keys.just_pressed(code),
+            just_released: keys.just_released(code),
+        },
+        InputKey::Mouse(button) => KeyState {
+            pressed: mouse.pressed(button),
+            just_pressed: mouse.just_pressed(button),
+            just_released: mouse.just_released(button),
+        },
+        InputKey::Gamepad(button_type) => {
+            let buttons = gamepads.iter().map(|gamepad| GamepadButton::new(gamepad, button_type));
+            let mut state = KeyState {
+                pressed: false,
+                just_pressed: false,
+                just_released: false,
+            };
+            for button in buttons {
+                state.pressed |= pad_buttons.pressed(button);
+                state.just_pressed |= pad_buttons.just_pressed(button);
+                state.just_released |= pad_buttons.just_released(button);
+            }
+            state
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_action_state(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    pad_axes: Res<Axis<GamepadAxis>>,
+    config: Res<GameConfig>,
+    mut actions: ResMut<ActionState>,
+    mut queue: ResMut<ConsoleQueue>,
+) {
+    if actions.is_blocked() {
+        actions.update(HashSet::new(), Vec2::ZERO);
+        return;
+    }
+
+    let mut held = HashSet::new();
+    for (key, command) in config.bindings() {
+        let state = key_state(key, &keys, &mouse, &gamepads, &pad_buttons);
+        if let Some(action) = Action::from_command(command) {
+            if state.pressed {
+                held.insert(action);
+            }
+        } else if state.just_pressed {
+            queue.0.push(command.to_string());
+        } else if state.just_released {
+            // som i Source: `+kommando` får sitt `-kommando` när knappen släpps
+            if let Ok([statement]) = cfg::parse_line(command).as_deref() {
+                if let Some(name) = statement[0].strip_prefix('+') {
+                    let mut release = statement.clone();
+                    release[0] = format!("-{name}");
+                    queue.0.push(cfg::join(&release));
+                }
+            }
+        }
+    }
+
+    let mut stick = Vec2::ZERO;
+    for gamepad in gamepads.iter() {
+        let x = pad_axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX)).unwrap_or(0.0);
+        let y = pad_axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY)).unwrap_or(0.0);
+        let value = Vec2::new(y, x);
+        if value.length() > stick.length() {
+            stick = value;
+        }
+    }
+    if stick.length() < STICK_DEADZONE {
+        stick = Vec2::ZERO;
+    }
+
+    actions.update(held, stick);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_read_back_from_their_commands() {
+        for action in Action::ALL {
+            assert_eq!(Action::from_command(action.command()), Some(action));
+        }
+        assert_eq!(Action::from_command("messagemode"), None);
+    }
+
+    #[test]
+    fn default_bindings_use_bindable_key_names() {
+        for (key, command) in default_bindings() {
+            let parsed = InputKey::from_name(key).unwrap_or_else(|| panic!("{key} for {command} has no key"));
+            // det sparade namnet måste vara samma som i standardbindningen
+            assert_eq!(parsed.name(), key);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! The `.cfg` grammar shared by `config.cfg`, `exec` and the console.
+//!
+//! A line holds statements separated by `;`. Each statement is a list of arguments split on
+//! whitespace, where double quotes group words into one argument (`\"` and `\\` escape inside
+//! quotes). `//` outside quotes starts a comment that runs to the end of the line.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CfgError {
+    /// 1-based, 0 when parsing a single line
+    pub line: usize,
+    pub column: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for CfgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+        } else {
+            write!(f, "column {}: {}", self.column, self.message)
+        }
+    }
+}
+
+impl std::error::Error for CfgError {}
+
+/// One statement from a file, with the line it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub line: usize,
+    pub args: Vec<String>,
+}
+
+/// Parses one line into its statements.
+pub fn parse_line(line: &str) -> Result<Vec<Vec<String>>, CfgError> {
+    let mut statements = Vec::new();
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut has_arg = false;
+    let mut quote_start = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if quote_start.is_some() {
+            match c {
+                '"' => quote_start = None,
+                '\\' if matches!(chars.peek(), Some((_, '"' | '\\'))) => {
+                    current.push(chars.next().map(|(_, c)| c).unwrap_or('\\'));
+                }
+                c => current.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                quote_start = Some(i);
+                has_arg = true;
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => break,
+            ';' => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+                if !args.is_empty() {
+                    statements.push(std::mem::take(&mut args));
+                }
+            }
+            c if c.is_whitespace() => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+
+    if let Some(start) = quote_start {
+        return Err(CfgError {
+            line: 0,
+            column: line[..start].chars().count() + 1,
+            message: "unterminated quote",
+        });
+    }
+    if has_arg {
+        args.push(current);
+    }
+    if !args.is_empty() {
+        statements.push(args);
+    }
+    Ok(statements)
+}
+
+/// Parses a whole file. Lines that fail to parse are skipped and reported, so one typo
+/// doesn't throw away the rest of the file.
+pub fn parse(text: &str) -> (Vec<Statement>, Vec<CfgError>) {
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        match parse_line(line) {
+            Ok(parsed) => statements.extend(parsed.into_iter().map(|args| Statement { line: i + 1, args })),
+            Err(err) => errors.push(CfgError { line: i + 1, ..err }),
+        }
+    }
+    (statements, errors)
+}
+
+/// Quotes `value` so that [`parse_line`] reads it back as a single argument.
+pub fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+/// Joins arguments back into a line that parses to the same arguments.
+pub fn join(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && !arg.contains(|c: char| c.is_whitespace() || matches!(c, '"' | ';' | '\\'))
+                && !arg.contains("//");
+            if plain { arg.clone() } else { quote(arg) }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The command line in `bind <key> <command...>`: a single quoted argument is already a
+/// whole command line, several arguments are joined back into one.
+pub fn command_text(args: &[String]) -> String {
+    match args {
+        [single] => single.clone(),
+        _ => join(args),
+    }
+}
+
+/// The `// comment` part of a line, if any, so it can be kept when the line is rewritten.
+pub fn trailing_comment(line: &str) -> Option<&str> {
+    let mut in_quotes = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if in_quotes => {
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            '/' if !in_quotes && matches!(chars.peek(), Some((_, '/'))) => return Some(&line[i..]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// What a saved statement replaces: the cvar name, or the key for `bind`/`unbind`.
+fn identity(args: &[String]) -> &[String] {
+    match args[0].as_str() {
+        "bind" | "unbind" => &args[..args.len().min(2)],
+        _ => &args[..1],
+    }
+}
+
+/// Rewrites a cfg file with new statements while keeping its comments, blank lines and order.
+///
+/// A line holding just one statement with the same identity as one of `statements` (the same
+/// cvar, or `bind` of the same key) is replaced by it and keeps its trailing comment. Later
+/// lines with that identity are dropped, and so are lines for which `owned` returns true that
+/// nothing replaced. Statements the file didn't mention are appended in the order given, and
+/// all other lines are kept as they are.
+pub fn rewrite(existing: &str, statements: &[Vec<String>], owned: impl Fn(&[String]) -> bool) -> String {
+    let mut written = vec![false; statements.len()];
+    let mut out = String::new();
+
+    for line in existing.lines() {
+        let parsed = parse_line(line);
+        let single = match parsed.as_deref() {
+            Ok([statement]) => Some(statement),
+            _ => None,
+        };
+        let target = single.and_then(|statement| {
+            statements.iter().position(|s| identity(s) == identity(statement))
+        });
+        match target {
+            Some(i) if written[i] => continue,
+            Some(i) => {
+                written[i] = true;
+                out.push_str(&join(&statements[i]));
+                if let Some(comment) = trailing_comment(line) {
+                    out.push(' ');
+                    out.push_str(comment);
+                }
+            }
+            None if single.is_some_and(|statement| owned(statement)) => continue,
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+
+    for (i, statement) in statements.iter().enumerate() {
+        if !written[i] {
+            out.push_str(&join(statement));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Vec<Vec<String>> {
+        parse_line(text).unwrap()
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        assert_eq!(line("bind q \"say hello world\""), [args(&["bind", "q", "say hello world"])]);
+        assert_eq!(line("name \"\""), [args(&["name", ""])]);
+        assert_eq!(line("a\"b c\"d"), [args(&["ab cd"])]);
+    }
+
+    #[test]
+    fn escapes_only_work_inside_quotes() {
+        assert_eq!(line(r#"say "a \"b\" c\\d""#), [args(&["say", r#"a "b" c\d"#])]);
+        // andra backslash-sekvenser lämnas som de är
+        assert_eq!(line(r#"path "C:\games\n""#), [args(&["path", r"C:\games\n"])]);
+        assert_eq!(line(r"path C:\games"), [args(&["path", r"C:\games"])]);
+    }
+
+    #[test]
+    fn semicolons_split_statements() {
+        assert_eq!(line("a 1;b 2 ; ;c"), [args(&["a", "1"]), args(&["b", "2"]), args(&["c"])]);
+        assert_eq!(line("say \"a;b\""), [args(&["say", "a;b"])]);
+        assert!(line("  ;  ").is_empty());
+    }
+
+    #[test]
+    fn comments_run_to_the_end_of_the_line() {
+        assert_eq!(line("rate 128 // snabbare; rate 64"), [args(&["rate", "128"])]);
+        assert_eq!(line("url \"http://example.com\""), [args(&["url", "http://example.com"])]);
+        assert_eq!(line("a/b"), [args(&["a/b"])]);
+        assert!(line("// bara en kommentar").is_empty());
+        assert_eq!(trailing_comment("bind q \"a // b\" // c"), Some("// c"));
+    }
+
+    #[test]
+    fn errors_have_a_line_and_column() {
+        let err = parse_line("say \"oops").unwrap_err();
+        assert_eq!((err.line, err.column), (0, 5));
+        assert_eq!(err.to_string(), "column 5: unterminated quote");
+
+        let (statements, errors) = parse("a 1\n\nb \"2\nc 3 // \"");
+        assert_eq!(statements, [
+            Statement { line: 1, args: args(&["a", "1"]) },
+            Statement { line: 4, args: args(&["c", "3"]) },
+        ]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "line 3, column 3: unterminated quote");
+    }
+
+    #[test]
+    fn join_round_trips() {
+        for list in [&["bind", "q", "say \"hi\"; jump"][..], &["a", "", "b//c", "back\\slash", "plain"]] {
+            let list = args(list);
+            assert_eq!(line(&join(&list)), vec![list]);
+        }
+        assert_eq!(command_text(&args(&["+jump"])), "+jump");
+        assert_eq!(command_text(&args(&["say", "hi there"])), "say \"hi there\"");
+    }
+
+    #[test]
+    fn rewrite_keeps_comments_and_order() {
+        let existing = "// mina inställningar\nsensitivity 1 // låg\nunknown_cvar 5\nbind q +jump\nbind q +duck\n";
+        let statements = [args(&["sensitivity", "2.5"]), args(&["bind", "e", "+use"]), args(&["fov", "90"])];
+        let owned = |a: &[String]| a[0] == "bind";
+        assert_eq!(
+            rewrite(existing, &statements, owned),
+            "// mina inställningar\nsensitivity 2.5 // låg\nunknown_cvar 5\nbind e +use\nfov 90\n"
+        );
+    }
+}
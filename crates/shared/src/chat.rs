@@ -0,0 +1,417 @@
+//! Text chat for the lobby and the match.
+//!
+//! Players ask to say something with a [`SendChat`]. Whoever hosts the game runs it through
+//! the [`ChatFilter`] (flood and length limits, optional word list) and hands out the
+//! resulting [`ChatMessage`] to everybody who may see it. Every message that reaches us ends
+//! up in the [`ChatLog`].
+//!
+//! Alone we host our own chat. On a game server the match chat goes there instead (see
+//! [`RemoteChat`]), and while the party service hosts our lobby, lobby chat goes there; the
+//! net layer sends the requests on and turns the answers into `ChatMessage`s.
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{cfg_path, GameConfig};
+use crate::console::RegisterCommandExt;
+use crate::cvars::{CvarChanged, CvarDef, CvarFlags, RegisterCvarExt};
+use crate::game_state::{LocalPlayerState, Team};
+use crate::lobby::Lobby;
+
+/// Messages kept in the log.
+pub const MAX_CHAT_HISTORY: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChatChannel {
+    /// Everybody on the server
+    All,
+    /// Only your own team
+    Team,
+    /// The party in the play menu
+    Lobby,
+    /// Notices from the server itself, nobody can send these
+    Server,
+}
+
+impl ChatChannel {
+    pub fn label(self) -> &'static str {
+        match self {
+            ChatChannel::All => "All",
+            ChatChannel::Team => "Team",
+            ChatChannel::Lobby => "Lobby",
+            ChatChannel::Server => "Server",
+        }
+    }
+}
+
+/// Something we want to say, sent to whoever hosts the game.
+#[derive(Event, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SendChat {
+    pub channel: ChatChannel,
+    pub text: String,
+}
+
+/// A message the host let through.
+#[derive(Event, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub channel: ChatChannel,
+    /// `None` for server notices
+    pub sender: Option<String>,
+    pub team: Option<Team>,
+    pub text: String,
+}
+
+impl ChatMessage {
+    pub fn notice(text: impl Into<String>) -> Self {
+        ChatMessage {
+            channel: ChatChannel::Server,
+            sender: None,
+            team: None,
+            text: text.into(),
+        }
+    }
+
+    /// Whether a player on `team` gets this message. Team chat only goes to the sender's team.
+    pub fn visible_to(&self, team: Option<Team>) -> bool {
+        self.channel != ChatChannel::Team || (self.team.is_some() && self.team == team)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatError {
+    Empty,
+    /// Too many messages too fast; seconds left until the next one is allowed
+    Flooding(f32),
+    NoTeam,
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChatError::Empty => write!(f, "nothing to say"),
+            ChatError::Flooding(wait) => write!(f, "you are sending messages too fast, wait {wait:.0}s"),
+            ChatError::NoTeam => write!(f, "you are not on a team"),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+/// Set by the net layer while we're on a game server, which then hosts the match chat.
+#[derive(Resource, Default)]
+pub struct RemoteChat(pub bool);
+
+/// The host's checks on incoming chat.
+#[derive(Resource, Default)]
+pub struct ChatFilter {
+    /// När varje avsändare senast skrev något
+    recent: HashMap<u64, VecDeque<f64>>,
+    words: Vec<String>,
+}
+
+impl ChatFilter {
+    /// Replaces the filtered word list. Words are matched whole and without regard to case.
+    pub fn set_words<I: IntoIterator<Item = String>>(&mut self, words: I) {
+        self.words = words.into_iter().map(|w| w.to_lowercase()).collect();
+    }
+
+    /// Checks a message from `sender` at time `now` (seconds) and returns the text to pass on:
+    /// trimmed, cut to `max_length` characters and with filtered words starred out.
+    pub fn check(
+        &mut self,
+        sender: u64,
+        text: &str,
+        now: f64,
+        limits: &ChatLimits,
+    ) -> Result<String, ChatError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ChatError::Empty);
+        }
+
+        let recent = self.recent.entry(sender).or_default();
+        while recent.front().is_some_and(|t| now - t >= limits.flood_window as f64) {
+            recent.pop_front();
+        }
+        if limits.flood_limit > 0 && recent.len() >= limits.flood_limit {
+            let wait = recent[0] + limits.flood_window as f64 - now;
+            return Err(ChatError::Flooding(wait.max(1.0) as f32));
+        }
+        recent.push_back(now);
+
+        let text: String = text.chars().take(limits.max_length).collect();
+        Ok(self.censor(&text))
+    }
+
+    fn censor(&self, text: &str) -> String {
+        if self.words.is_empty() {
+            return text.to_string();
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut word = String::new();
+        let flush = |word: &mut String, out: &mut String| {
+            if self.words.contains(&word.to_lowercase()) {
+                out.extend(word.chars().map(|_| '*'));
+            } else {
+                out.push_str(word);
+            }
+            word.clear();
+        };
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut out);
+                out.push(c);
+            }
+        }
+        flush(&mut word, &mut out);
+        out
+    }
+
+    /// Glömmer en spelare som lämnat
+    pub fn forget(&mut self, sender: u64) {
+        self.recent.remove(&sender);
+    }
+}
+
+/// The chat limits from the `sv_chat_*` cvars.
+pub struct ChatLimits {
+    pub max_length: usize,
+    pub flood_limit: usize,
+    pub flood_window: f32,
+}
+
+impl ChatLimits {
+    pub fn from_config(config: &GameConfig) -> Self {
+        ChatLimits {
+            max_length: config.get_int("sv_chat_max_length").unwrap_or(127).max(1) as usize,
+            flood_limit: config.get_int("sv_chat_flood_limit").unwrap_or(4).max(0) as usize,
+            flood_window: config.get_float("sv_chat_flood_window").unwrap_or(5.0),
+        }
+    }
+}
+
+/// Messages that reached us, oldest first.
+#[derive(Resource, Default)]
+pub struct ChatLog {
+    lines: VecDeque<ChatLine>,
+}
+
+pub struct ChatLine {
+    pub message: ChatMessage,
+    /// `Time::elapsed_seconds` när meddelandet kom
+    pub received: f32,
+}
+
+impl ChatLog {
+    pub fn push(&mut self, message: ChatMessage, received: f32) {
+        self.lines.push_back(ChatLine { message, received });
+        while self.lines.len() > MAX_CHAT_HISTORY {
+            self.lines.pop_front();
+        }
+    }
+
+    pub fn lines(&self) -> impl DoubleEndedIterator<Item = &ChatLine> {
+        self.lines.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_cvar(
+            CvarDef::int("sv_chat_max_length", 127)
+                .int_range(1, 255)
+                .description("Longest chat message in characters, longer ones are cut"),
+        )
+        .register_cvar(
+            CvarDef::int("sv_chat_flood_limit", 4)
+                .int_range(0, 20)
+                .description("Chat messages a player may send per sv_chat_flood_window, 0 for no limit"),
+        )
+        .register_cvar(
+            CvarDef::float("sv_chat_flood_window", 5.0)
+                .range(1.0, 60.0)
+                .description("Seconds the chat flood limit counts over"),
+        )
+        .register_cvar(
+            CvarDef::string("sv_chat_filter", "")
+                .flags(CvarFlags::ARCHIVE)
+                .description("File in the config folder with words to star out, one per line"),
+        )
+        .register_console_command("say", "Say something to everybody", say)
+        .register_console_command("say_team", "Say something to your team", say_team)
+        .init_resource::<ChatFilter>()
+        .init_resource::<ChatLog>()
+        .init_resource::<RemoteChat>()
+        .add_event::<SendChat>()
+        .add_event::<ChatMessage>()
+        .add_systems(Startup, load_chat_filter)
+        .add_systems(
+            Update,
+            (
+                reload_chat_filter,
+                handle_chat_requests.after(reload_chat_filter),
+                record_chat_messages.after(handle_chat_requests),
+            ),
+        );
+    }
+}
+
+fn say(world: &mut World, args: &[String]) -> Result<String, String> {
+    send_from_console(world, ChatChannel::All, args)
+}
+
+fn say_team(world: &mut World, args: &[String]) -> Result<String, String> {
+    send_from_console(world, ChatChannel::Team, args)
+}
+
+fn send_from_console(world: &mut World, channel: ChatChannel, args: &[String]) -> Result<String, String> {
+    if args.is_empty() {
+        return Err("usage: say <message>".into());
+    }
+    world.send_event(SendChat {
+        channel,
+        text: args.join(" "),
+    });
+    Ok(String::new())
+}
+
+fn read_filter_words(file: &str) -> Vec<String> {
+    if file.is_empty() {
+        return Vec::new();
+    }
+    match fs::read_to_string(cfg_path(file)) {
+        Ok(text) => text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with("//"))
+            .map(str::to_string)
+            .collect(),
+        Err(err) => {
+            warn!("could not read chat filter {file}: {err}");
+            Vec::new()
+        }
+    }
+}
+
+fn load_chat_filter(config: Res<GameConfig>, mut filter: ResMut<ChatFilter>) {
+    filter.set_words(read_filter_words(config.get_str("sv_chat_filter").unwrap_or("")));
+}
+
+fn reload_chat_filter(
+    mut changes: EventReader<CvarChanged>,
+    config: Res<GameConfig>,
+    mut filter: ResMut<ChatFilter>,
+) {
+    if changes.read().any(|c| c.name == "sv_chat_filter") {
+        filter.set_words(read_filter_words(config.get_str("sv_chat_filter").unwrap_or("")));
+    }
+}
+
+/// Hosts our own messages. Like the lobby, an online game sends them to the server instead
+/// and the server's answers come back as `ChatMessage`s.
+#[allow(clippy::too_many_arguments)]
+fn handle_chat_requests(
+    time: Res<Time<Real>>,
+    config: Res<GameConfig>,
+    remote: Res<RemoteChat>,
+    lobby: Option<Res<Lobby>>,
+    player: Option<Res<LocalPlayerState>>,
+    mut requests: EventReader<SendChat>,
+    mut filter: ResMut<ChatFilter>,
+    mut messages: EventWriter<ChatMessage>,
+) {
+    let sender = lobby.as_ref().map_or(0, |l| l.local_id());
+    let name = config.get_str("name").unwrap_or("Player").to_string();
+    let team = player.and_then(|p| p.team);
+    let limits = ChatLimits::from_config(&config);
+
+    for request in requests.read() {
+        let hosted_elsewhere = match request.channel {
+            ChatChannel::Lobby => lobby.as_ref().is_some_and(|l| l.is_remote()),
+            _ => remote.0,
+        };
+        if hosted_elsewhere {
+            continue;
+        }
+        let result = match request.channel {
+            ChatChannel::Server => continue,
+            ChatChannel::Team if team.is_none() => Err(ChatError::NoTeam),
+            _ => filter.check(sender, &request.text, time.elapsed_seconds_f64(), &limits),
+        };
+        match result {
+            Ok(text) => {
+                messages.send(ChatMessage {
+                    channel: request.channel,
+                    sender: Some(name.clone()),
+                    team,
+                    text,
+                });
+            }
+            // bara avsändaren får veta varför
+            Err(err) => {
+                messages.send(ChatMessage::notice(err.to_string()));
+            }
+        }
+    }
+}
+
+fn record_chat_messages(
+    time: Res<Time<Real>>,
+    mut messages: EventReader<ChatMessage>,
+    mut log: ResMut<ChatLog>,
+) {
+    for message in messages.read() {
+        info!("[{}] {}: {}", message.channel.label(), message.sender.as_deref().unwrap_or("Server"), message.text);
+        log.push(message.clone(), time.elapsed_seconds());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_length: usize) -> ChatLimits {
+        ChatLimits { max_length, flood_limit: 0, flood_window: 5.0 }
+    }
+
+    fn filter(words: &[&str]) -> ChatFilter {
+        let mut filter = ChatFilter::default();
+        filter.set_words(words.iter().map(|w| w.to_string()));
+        filter
+    }
+
+    #[test]
+    fn filtered_words_are_starred_whole_and_in_any_case() {
+        let mut filter = filter(&["Noob"]);
+        let checked = filter.check(1, "NOOB, noobs and a noob!", 0.0, &limits(127));
+        // bara hela ord, "noobs" är ett annat ord
+        assert_eq!(checked, Ok("****, noobs and a ****!".to_string()));
+        assert_eq!(filter.check(1, "ingen fara", 0.0, &limits(127)), Ok("ingen fara".to_string()));
+    }
+
+    #[test]
+    fn messages_are_trimmed_and_empty_ones_refused() {
+        let mut filter = filter(&[]);
+        assert_eq!(filter.check(1, "  gg  \n", 0.0, &limits(127)), Ok("gg".to_string()));
+        assert_eq!(filter.check(1, " \t ", 0.0, &limits(127)), Err(ChatError::Empty));
+    }
+
+    #[test]
+    fn long_messages_are_cut_on_characters_not_bytes() {
+        let mut filter = filter(&["åäö"]);
+        assert_eq!(filter.check(1, "héllo wörld", 0.0, &limits(7)), Ok("héllo w".to_string()));
+        // ett filtrerat ord med flerbytestecken blir en stjärna per tecken
+        assert_eq!(filter.check(1, "ÅÄÖ 🙂🙂🙂", 0.0, &limits(5)), Ok("*** 🙂".to_string()));
+    }
+}
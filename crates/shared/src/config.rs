@@ -1,56 +1,394 @@
 use bevy::prelude::*;
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, path::PathBuf};
 
+use crate::actions::default_bindings;
+use crate::cfg;
+use crate::cvars::{CvarDef, CvarError, CvarFlags, CvarValue};
+use crate::keys::InputKey;
+
+/// Hur djupt `exec` i en cfg-fil får nästla sig innan vi antar en loop
+pub const MAX_EXEC_DEPTH: u32 = 8;
+
+/// All registered cvars and their current values.
+///
+/// Values read from `config.cfg` are kept as raw strings until the matching cvar is
+/// registered, so plugins can register their cvars after the file has been loaded.
 #[derive(Resource, Debug, Clone)]
 pub struct GameConfig {
-    pub values: HashMap<String, String>,
+    defs: BTreeMap<String, CvarDef>,
+    values: HashMap<String, CvarValue>,
+    /// Statements from the files that aren't bindings, in file order. `name value` lines wait
+    /// here for a cvar called `name`, the rest are run once the console exists.
+    statements: Vec<Vec<String>>,
+    /// Key name -> command line, keyed by [`InputKey::name`]
+    bindings: BTreeMap<String, String>,
+    changed: Vec<String>,
+    /// Satt av nätverket medan vi är anslutna till en server, då styr servern alla REPLICATED-cvars.
+    pub server_authoritative: bool,
 }
 
 impl Default for GameConfig {
     fn default() -> Self {
-        let mut values = HashMap::new();
-        values.insert("cl_fullscreen".into(), "1".into());
-        values.insert("cl_resolution".into(), "1920x1080".into());
-        values.insert("cl_vsync".into(), "0".into());
-        Self { values }
+        let mut config = Self {
+            defs: BTreeMap::new(),
+            values: HashMap::new(),
+            statements: Vec::new(),
+            bindings: default_bindings().map(|(k, c)| (k.to_string(), c.to_string())).collect(),
+            changed: Vec::new(),
+            server_authoritative: false,
+        };
+        config.register(
+            CvarDef::bool("cl_fullscreen", true)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Run the game in fullscreen"),
+        );
+        config.register(
+            CvarDef::string("cl_resolution", "1920x1080")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Window resolution as WIDTHxHEIGHT"),
+        );
+        config.register(
+            CvarDef::bool("cl_vsync", false)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Wait for vertical sync"),
+        );
+        config.register(
+            CvarDef::bool("sv_cheats", false)
+                .flags(CvarFlags::REPLICATED)
+                .description("Allow cheat-protected cvars to be changed"),
+        );
+        config
     }
 }
 
 impl GameConfig {
     pub fn load() -> Self {
-        let path = config_path();
-        if let Ok(data) = fs::read_to_string(&path) {
-            let mut values = HashMap::new();
-            for line in data.lines() {
-                if let Some((key, val)) = parse_line(line) {
-                    values.insert(key.to_string(), val.to_string());
+        let mut config = Self::default();
+        config.read_file("config.cfg", 0);
+        // builtin-cvars är redan registrerade, så deras värden används direkt
+        let names: Vec<String> = config.defs.keys().cloned().collect();
+        for name in names {
+            config.apply_pending(&name);
+        }
+        config.changed.clear();
+        config
+    }
+
+    /// Reads a cfg file into `statements`, following `exec` into other files.
+    fn read_file(&mut self, file: &str, depth: u32) {
+        if let Ok(data) = fs::read_to_string(cfg_path(file)) {
+            self.read_text(file, &data, depth);
+        }
+    }
+
+    fn read_text(&mut self, file: &str, data: &str, depth: u32) {
+        let (statements, errors) = cfg::parse(data);
+        for err in errors {
+            warn!("{file}: {err}");
+        }
+        for statement in statements {
+            match statement.args.as_slice() {
+                [exec, included] if exec == "exec" => {
+                    if depth >= MAX_EXEC_DEPTH {
+                        warn!("{file}:{}: exec {included} nested too deep, skipped", statement.line);
+                    } else {
+                        self.read_file(included, depth + 1);
+                    }
                 }
+                [bind, key, command @ ..] if bind == "bind" && !command.is_empty() => {
+                    if let Err(err) = self.bind_name(key, &cfg::command_text(command)) {
+                        warn!("{file}:{}: {err}", statement.line);
+                    }
+                }
+                [unbind, key] if unbind == "unbind" => {
+                    if let Err(err) = self.unbind_name(key) {
+                        warn!("{file}:{}: {err}", statement.line);
+                    }
+                }
+                [unbindall] if unbindall == "unbindall" => self.bindings.clear(),
+                _ => self.statements.push(statement.args),
             }
-            return Self { values };
         }
-        Self::default()
     }
 
+    /// Writes every `ARCHIVE` cvar and the key bindings to `config.cfg`, sorted by name.
+    /// Comments and lines the config doesn't own (unknown cvars, aliases) are kept where
+    /// the user put them.
     pub fn save(&self) {
-        let mut out = String::new();
-        for (key, val) in &self.values {
-            out.push_str(&format!("{} \"{}\"\n", key, val));
+        let mut statements: Vec<Vec<String>> = self
+            .defs
+            .iter()
+            .filter(|(_, def)| def.flags.contains(CvarFlags::ARCHIVE))
+            .map(|(name, _)| vec![name.clone(), self.values[name].to_string()])
+            .collect();
+        // standardbindningar som användaren tagit bort måste sparas som unbind
+        for (key, _) in default_bindings() {
+            if !self.bindings.contains_key(key) {
+                statements.push(vec!["unbind".into(), key.into()]);
+            }
+        }
+        for (key, command) in &self.bindings {
+            statements.push(vec!["bind".into(), key.clone(), command.clone()]);
+        }
+
+        let path = config_path();
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        let owned = |args: &[String]| matches!(args[0].as_str(), "bind" | "unbind" | "unbindall");
+        if let Err(err) = fs::write(&path, cfg::rewrite(&existing, &statements, owned)) {
+            warn!("couldn't write {}: {err}", path.display());
         }
-        let _ = fs::write(config_path(), out);
     }
-}
 
-fn parse_line(line: &str) -> Option<(&str, &str)> {
-    let mut parts = line.splitn(2, ' ');
-    let key = parts.next()?.trim();
-    let val = parts.next()?.trim().trim_matches('"');
-    Some((key, val))
+    /// All key bindings, sorted by key name.
+    pub fn bindings(&self) -> impl Iterator<Item = (InputKey, &str)> {
+        self.bindings
+            .iter()
+            .filter_map(|(key, command)| Some((InputKey::from_name(key)?, command.as_str())))
+    }
+
+    pub fn binding(&self, key: InputKey) -> Option<&str> {
+        self.bindings.get(&key.name()).map(String::as_str)
+    }
+
+    /// Keys bound to exactly `command`, sorted by key name.
+    pub fn keys_for(&self, command: &str) -> Vec<InputKey> {
+        self.bindings().filter(|(_, c)| *c == command).map(|(key, _)| key).collect()
+    }
+
+    /// Binds `key` and returns the command it was bound to before, if any.
+    pub fn bind(&mut self, key: InputKey, command: &str) -> Option<String> {
+        self.bindings.insert(key.name(), command.to_string())
+    }
+
+    pub fn unbind(&mut self, key: InputKey) -> Option<String> {
+        self.bindings.remove(&key.name())
+    }
+
+    pub fn unbind_all(&mut self) {
+        self.bindings.clear();
+    }
+
+    /// Puts every binding back to the defaults.
+    pub fn reset_bindings(&mut self) {
+        self.bindings = default_bindings().map(|(k, c)| (k.to_string(), c.to_string())).collect();
+    }
+
+    /// [`GameConfig::bind`] for a key name typed by the user.
+    pub fn bind_name(&mut self, key: &str, command: &str) -> Result<Option<String>, String> {
+        let key = InputKey::from_name(key).ok_or_else(|| format!("\"{key}\" isn't a valid key"))?;
+        Ok(self.bind(key, command))
+    }
+
+    pub fn unbind_name(&mut self, key: &str) -> Result<Option<String>, String> {
+        let key = InputKey::from_name(key).ok_or_else(|| format!("\"{key}\" isn't a valid key"))?;
+        Ok(self.unbind(key))
+    }
+
+    /// Takes the statements from the config files that should be run as console commands,
+    /// including `name value` lines where `name` turned out to be a command and not a cvar.
+    pub fn take_commands(&mut self, is_command: impl Fn(&str) -> bool) -> Vec<String> {
+        let (commands, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.statements)
+            .into_iter()
+            .partition(|args| args.len() != 2 || is_command(&args[0]));
+        self.statements = rest;
+        commands.iter().map(|args| cfg::join(args)).collect()
+    }
+
+    /// Registers a cvar. A value for it from `config.cfg` is applied right away and
+    /// reported if it does not fit the definition.
+    pub fn register(&mut self, def: CvarDef) {
+        if self.defs.contains_key(&def.name) {
+            warn!("cvar {} registered twice, replacing the old definition", def.name);
+        }
+        let name = def.name.clone();
+        self.values.insert(name.clone(), def.default.clone());
+        self.defs.insert(name.clone(), def);
+        self.apply_pending(&name);
+    }
+
+    /// Applies the file's `name value` lines for the cvar in order, so the last one wins.
+    fn apply_pending(&mut self, name: &str) {
+        let (lines, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.statements)
+            .into_iter()
+            .partition(|args| matches!(args.as_slice(), [n, _] if n == name));
+        self.statements = rest;
+        let def = &self.defs[name];
+        for args in lines {
+            match def.kind.parse(name, &args[1]) {
+                Ok(value) => {
+                    self.values.insert(name.to_string(), value);
+                }
+                Err(err) => warn!("config.cfg: {err}, ignored"),
+            }
+        }
+    }
+
+    /// Values from `config.cfg` that no registered cvar has claimed.
+    pub fn unknown(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.statements.iter().filter_map(|args| match args.as_slice() {
+            [name, value] => Some((name.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+
+    pub fn def(&self, name: &str) -> Option<&CvarDef> {
+        self.defs.get(name)
+    }
+
+    /// All registered cvars, sorted by name.
+    pub fn defs(&self) -> impl Iterator<Item = &CvarDef> {
+        self.defs.values()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CvarValue> {
+        self.values.get(name)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.values.get(name)? {
+            CvarValue::Bool(b) => Some(*b),
+            CvarValue::Int(i) => Some(*i != 0),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        match self.values.get(name)? {
+            CvarValue::Int(i) => Some(*i),
+            CvarValue::Bool(b) => Some(*b as i32),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.values.get(name)? {
+            CvarValue::Float(v) => Some(*v),
+            CvarValue::Int(i) => Some(*i as f32),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.values.get(name)? {
+            CvarValue::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Sets a cvar from user input, enforcing its flags, type and range.
+    pub fn set(&mut self, name: &str, raw: &str) -> Result<(), CvarError> {
+        let def = self.defs.get(name).ok_or_else(|| CvarError::Unknown(name.to_string()))?;
+
+        if def.flags.contains(CvarFlags::READ_ONLY) {
+            return Err(CvarError::ReadOnly(name.to_string()));
+        }
+        if def.flags.contains(CvarFlags::CHEAT) && !self.get_bool("sv_cheats").unwrap_or(false) {
+            return Err(CvarError::CheatProtected(name.to_string()));
+        }
+        if def.flags.contains(CvarFlags::REPLICATED) && self.server_authoritative {
+            return Err(CvarError::ServerControlled(name.to_string()));
+        }
+        self.set_unchecked(name, raw)
+    }
+
+    /// Sets a replicated cvar to the value sent by the server, ignoring its other flags.
+    /// The rest are the player's own, the server can't set them.
+    pub fn set_replicated(&mut self, name: &str, raw: &str) -> Result<(), CvarError> {
+        let def = self.defs.get(name).ok_or_else(|| CvarError::Unknown(name.to_string()))?;
+        if !def.flags.contains(CvarFlags::REPLICATED) {
+            return Err(CvarError::NotReplicated(name.to_string()));
+        }
+        self.set_unchecked(name, raw)
+    }
+
+    /// Name and value of every REPLICATED cvar, sorted by name, for the server to send.
+    pub fn replicated(&self) -> Vec<(String, String)> {
+        self.defs
+            .values()
+            .filter(|def| def.flags.contains(CvarFlags::REPLICATED))
+            .filter_map(|def| Some((def.name.clone(), self.values.get(&def.name)?.to_string())))
+            .collect()
+    }
+
+    /// Restores a cvar to its registered default.
+    pub fn reset(&mut self, name: &str) -> Result<(), CvarError> {
+        let default = self
+            .defs
+            .get(name)
+            .map(|def| def.default.to_string())
+            .ok_or_else(|| CvarError::Unknown(name.to_string()))?;
+        self.set(name, &default)
+    }
+
+    fn set_unchecked(&mut self, name: &str, raw: &str) -> Result<(), CvarError> {
+        let def = self.defs.get(name).ok_or_else(|| CvarError::Unknown(name.to_string()))?;
+        let value = def.kind.parse(name, raw)?;
+        if self.values.get(name) != Some(&value) {
+            self.values.insert(name.to_string(), value);
+            self.changed.push(name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Names of cvars changed since the last call, drained by `emit_cvar_changes`.
+    pub fn take_changes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.changed)
+    }
 }
 
-fn config_path() -> PathBuf {
+/// Katalogen där config.cfg och andra .cfg-filer för `exec` ligger
+pub fn config_dir() -> PathBuf {
     let mut dir = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
     dir.push("rust_fps_net");
     fs::create_dir_all(&dir).ok();
-    dir.push("config.cfg");
     dir
 }
+
+/// Path of a cfg file in [`config_dir`], adding `.cfg` if `file` has no extension.
+pub fn cfg_path(file: &str) -> PathBuf {
+    let mut path = config_dir().join(file);
+    if path.extension().is_none() {
+        path.set_extension("cfg");
+    }
+    path
+}
+
+fn config_path() -> PathBuf {
+    cfg_path("config.cfg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> GameConfig {
+        let mut config = GameConfig::default();
+        config.read_text("test.cfg", text, 0);
+        config
+    }
+
+    #[test]
+    fn repeated_statements_keep_their_order() {
+        let mut config = read("echo one\nalias x \"jump\"\necho two; echo one\ncl_vsync 1\n");
+        let commands = config.take_commands(|name| name == "echo");
+        assert_eq!(commands, ["echo one", "alias x jump", "echo two", "echo one"]);
+        // cl_vsync är inget kommando, värdet väntar på att sättas
+        assert_eq!(config.unknown().collect::<Vec<_>>(), [("cl_vsync", "1")]);
+    }
+
+    #[test]
+    fn the_last_value_for_a_cvar_wins() {
+        let mut config = read("rate 100\nrate 150 // bättre\nrate fast\n");
+        config.register(CvarDef::int("rate", 128).int_range(64, 256));
+        assert_eq!(config.get_int("rate"), Some(150));
+        assert_eq!(config.unknown().count(), 0);
+    }
+
+    #[test]
+    fn bindings_are_read_in_order() {
+        let config = read("unbindall\nbind q \"say hi; say bye\"\nbind q +jump\nbind f +use\nunbind f\n");
+        let bindings: Vec<_> = config.bindings().map(|(key, command)| (key.name(), command.to_string())).collect();
+        assert_eq!(bindings, [("q".to_string(), "+jump".to_string())]);
+    }
+}
@@ -0,0 +1,432 @@
+//! Console command registry and executor.
+//!
+//! Commands are plain functions registered by name from any plugin. A command line is split
+//! into statements on `;`, and each statement runs either a registered command or, if the
+//! first word names a cvar, prints or sets that cvar. Nothing here needs a window, so the
+//! same executor is used by the in-game console, key bindings and `exec`.
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+
+use crate::cfg;
+use crate::config::{cfg_path, GameConfig, MAX_EXEC_DEPTH};
+use crate::keys::InputKey;
+
+/// Returns the text to print, or an error message.
+pub type CommandHandler = fn(&mut World, &[String]) -> Result<String, String>;
+
+#[derive(Clone)]
+pub struct ConsoleCommand {
+    pub name: String,
+    pub help: String,
+    pub handler: CommandHandler,
+}
+
+#[derive(Resource, Default, Clone)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, ConsoleCommand>,
+}
+
+impl CommandRegistry {
+    pub fn register(&mut self, name: &str, help: &str, handler: CommandHandler) {
+        self.commands.insert(
+            name.to_string(),
+            ConsoleCommand {
+                name: name.to_string(),
+                help: help.to_string(),
+                handler,
+            },
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ConsoleCommand> {
+        self.commands.get(name)
+    }
+
+    /// All commands, sorted by name.
+    pub fn commands(&self) -> impl Iterator<Item = &ConsoleCommand> {
+        self.commands.values()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleLevel {
+    /// Raden användaren skrev, visas med `]` framför
+    Input,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsoleLine {
+    pub level: ConsoleLevel,
+    pub text: String,
+}
+
+const MAX_LOG_LINES: usize = 1000;
+
+/// Scrollback shown in the console, oldest line first.
+#[derive(Resource, Default, Debug)]
+pub struct ConsoleLog {
+    lines: VecDeque<ConsoleLine>,
+}
+
+impl ConsoleLog {
+    pub fn push(&mut self, level: ConsoleLevel, text: &str) {
+        for line in text.lines() {
+            if self.lines.len() == MAX_LOG_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(ConsoleLine {
+                level,
+                text: line.to_string(),
+            });
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &ConsoleLine> {
+        self.lines.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+/// Command lines waiting to be executed, run once per frame by `run_console_queue`.
+#[derive(Resource, Default, Debug)]
+pub struct ConsoleQueue(pub Vec<String>);
+
+#[derive(Resource, Default)]
+struct ExecDepth(u32);
+
+pub struct ConsoleCommandsPlugin;
+
+impl Plugin for ConsoleCommandsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CommandRegistry>()
+            .init_resource::<ConsoleLog>()
+            .init_resource::<ConsoleQueue>()
+            .init_resource::<ExecDepth>()
+            .register_console_command("help", "help [command]: list commands or describe one", cmd_help)
+            .register_console_command("cvarlist", "cvarlist [prefix]: list cvars and their values", cmd_cvarlist)
+            .register_console_command("echo", "echo <text>: print text to the console", cmd_echo)
+            .register_console_command("exec", "exec <file.cfg>: run a config file", cmd_exec)
+            .register_console_command("bind", "bind <key> [command]: bind a key or show its binding", cmd_bind)
+            .register_console_command("unbind", "unbind <key>: remove a key binding", cmd_unbind)
+            .register_console_command("unbindall", "unbindall: remove all key bindings", cmd_unbindall)
+            .register_console_command("reset", "reset <cvar>: restore a cvar to its default", cmd_reset)
+            .register_console_command("host_writeconfig", "host_writeconfig: save config.cfg", cmd_writeconfig)
+            .register_console_command("clear", "clear: clear the console", cmd_clear)
+            .register_console_command("quit", "quit: exit the game", cmd_quit)
+            .add_systems(Startup, queue_config_commands)
+            .add_systems(Update, run_console_queue);
+    }
+}
+
+/// Lets any plugin add console commands while the app is being built.
+pub trait RegisterCommandExt {
+    fn register_console_command(&mut self, name: &str, help: &str, handler: CommandHandler) -> &mut Self;
+}
+
+impl RegisterCommandExt for App {
+    fn register_console_command(&mut self, name: &str, help: &str, handler: CommandHandler) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_insert_with(CommandRegistry::default)
+            .register(name, help, handler);
+        self
+    }
+}
+
+/// Runs every statement in `line` and returns the output of each one.
+pub fn execute(world: &mut World, line: &str) -> Vec<Result<String, String>> {
+    match cfg::parse_line(line) {
+        Ok(statements) => execute_statements(world, statements),
+        Err(err) => vec![Err(err.to_string())],
+    }
+}
+
+fn execute_statements(world: &mut World, statements: Vec<Vec<String>>) -> Vec<Result<String, String>> {
+    statements
+        .into_iter()
+        .map(|statement| execute_statement(world, &statement))
+        .collect()
+}
+
+fn execute_statement(world: &mut World, statement: &[String]) -> Result<String, String> {
+    let Some((name, args)) = statement.split_first() else {
+        return Ok(String::new());
+    };
+
+    let handler = world
+        .get_resource::<CommandRegistry>()
+        .and_then(|registry| registry.get(name))
+        .map(|command| command.handler);
+    if let Some(handler) = handler {
+        return handler(world, args);
+    }
+
+    let mut config = world
+        .get_resource_mut::<GameConfig>()
+        .ok_or_else(|| format!("Unknown command \"{name}\""))?;
+    if config.def(name).is_none() {
+        return Err(format!("Unknown command \"{name}\""));
+    }
+    match args.first() {
+        Some(value) => config.set(name, value).map(|_| String::new()).map_err(|e| e.to_string()),
+        None => Ok(describe_cvar(&config, name)),
+    }
+}
+
+fn describe_cvar(config: &GameConfig, name: &str) -> String {
+    let (Some(def), Some(value)) = (config.def(name), config.get(name)) else {
+        return String::new();
+    };
+    let mut out = format!("\"{}\" = \"{}\" (def. \"{}\")", name, value, def.default);
+    if !def.description.is_empty() {
+        out.push_str(&format!(" - {}", def.description));
+    }
+    out
+}
+
+/// Command and cvar names starting with `partial`, sorted.
+pub fn completions(registry: &CommandRegistry, config: &GameConfig, partial: &str) -> Vec<String> {
+    let partial = partial.to_ascii_lowercase();
+    let mut names: Vec<String> = registry
+        .commands()
+        .map(|c| c.name.clone())
+        .chain(config.defs().map(|d| d.name.clone()))
+        .filter(|name| name.starts_with(&partial))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Longest prefix shared by all `names`, used to extend the input on tab.
+pub fn common_prefix(names: &[String]) -> String {
+    let Some(first) = names.first() else {
+        return String::new();
+    };
+    let mut prefix = first.as_str();
+    for name in &names[1..] {
+        while !name.starts_with(prefix) {
+            let mut chars = prefix.chars();
+            chars.next_back();
+            prefix = chars.as_str();
+        }
+    }
+    prefix.to_string()
+}
+
+/// Queues `bind` and other commands found in `config.cfg`, once every plugin has registered
+/// its commands.
+fn queue_config_commands(
+    mut config: ResMut<GameConfig>,
+    registry: Res<CommandRegistry>,
+    mut queue: ResMut<ConsoleQueue>,
+) {
+    let commands = config.take_commands(|name| registry.get(name).is_some());
+    queue.0.extend(commands);
+}
+
+/// Executes queued command lines and writes their output to the [`ConsoleLog`].
+pub fn run_console_queue(world: &mut World) {
+    let lines = std::mem::take(&mut world.resource_mut::<ConsoleQueue>().0);
+    for line in lines {
+        let results = execute(world, &line);
+        let mut log = world.resource_mut::<ConsoleLog>();
+        for result in results {
+            match result {
+                Ok(out) if out.is_empty() => {}
+                Ok(out) => log.push(ConsoleLevel::Info, &out),
+                Err(err) => log.push(ConsoleLevel::Error, &err),
+            }
+        }
+    }
+}
+
+fn cmd_help(world: &mut World, args: &[String]) -> Result<String, String> {
+    let registry = world.resource::<CommandRegistry>();
+    if let Some(name) = args.first() {
+        return registry
+            .get(name)
+            .map(|c| c.help.clone())
+            .ok_or_else(|| format!("Unknown command \"{name}\""));
+    }
+    Ok(registry.commands().map(|c| c.help.as_str()).collect::<Vec<_>>().join("\n"))
+}
+
+fn cmd_cvarlist(world: &mut World, args: &[String]) -> Result<String, String> {
+    let config = world.resource::<GameConfig>();
+    let prefix = args.first().map(String::as_str).unwrap_or("");
+    let lines: Vec<String> = config
+        .defs()
+        .filter(|d| d.name.starts_with(prefix))
+        .map(|d| describe_cvar(config, &d.name))
+        .collect();
+    Ok(format!("{}\n{} cvars", lines.join("\n"), lines.len()))
+}
+
+fn cmd_echo(_world: &mut World, args: &[String]) -> Result<String, String> {
+    Ok(args.join(" "))
+}
+
+fn cmd_exec(world: &mut World, args: &[String]) -> Result<String, String> {
+    let file = args.first().ok_or("usage: exec <file.cfg>")?;
+    let data = fs::read_to_string(cfg_path(file)).map_err(|_| format!("Couldn't exec {file}"))?;
+
+    if world.resource::<ExecDepth>().0 >= MAX_EXEC_DEPTH {
+        return Err(format!("exec {file}: nested too deep, is a file exec'ing itself?"));
+    }
+    let (statements, parse_errors) = cfg::parse(&data);
+    let mut errors: Vec<String> = parse_errors.iter().map(|err| format!("{file}: {err}")).collect();
+
+    world.resource_mut::<ExecDepth>().0 += 1;
+    for statement in statements {
+        for result in execute_statements(world, vec![statement.args]) {
+            if let Err(err) = result {
+                errors.push(format!("{file}:{}: {err}", statement.line));
+            }
+        }
+    }
+    world.resource_mut::<ExecDepth>().0 -= 1;
+
+    if errors.is_empty() {
+        Ok(format!("Executed {file}"))
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn cmd_bind(world: &mut World, args: &[String]) -> Result<String, String> {
+    let key_name = args.first().ok_or("usage: bind <key> [command]")?;
+    let key = InputKey::from_name(key_name).ok_or_else(|| format!("\"{key_name}\" isn't a valid key"))?;
+    let mut config = world.resource_mut::<GameConfig>();
+    if args.len() == 1 {
+        return Ok(match config.binding(key) {
+            Some(command) => format!("\"{}\" = \"{}\"", key.name(), command),
+            None => format!("\"{}\" is not bound", key.name()),
+        });
+    }
+    config.bind(key, &cfg::command_text(&args[1..]));
+    Ok(String::new())
+}
+
+fn cmd_unbind(world: &mut World, args: &[String]) -> Result<String, String> {
+    let key_name = args.first().ok_or("usage: unbind <key>")?;
+    world.resource_mut::<GameConfig>().unbind_name(key_name)?;
+    Ok(String::new())
+}
+
+fn cmd_unbindall(world: &mut World, _args: &[String]) -> Result<String, String> {
+    world.resource_mut::<GameConfig>().unbind_all();
+    Ok(String::new())
+}
+
+fn cmd_reset(world: &mut World, args: &[String]) -> Result<String, String> {
+    let name = args.first().ok_or("usage: reset <cvar>")?;
+    world
+        .resource_mut::<GameConfig>()
+        .reset(name)
+        .map(|_| String::new())
+        .map_err(|e| e.to_string())
+}
+
+fn cmd_writeconfig(world: &mut World, _args: &[String]) -> Result<String, String> {
+    world.resource::<GameConfig>().save();
+    Ok("Wrote config.cfg".into())
+}
+
+fn cmd_clear(world: &mut World, _args: &[String]) -> Result<String, String> {
+    world.resource_mut::<ConsoleLog>().clear();
+    Ok(String::new())
+}
+
+fn cmd_quit(world: &mut World, _args: &[String]) -> Result<String, String> {
+    world.send_event(AppExit::Success);
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cvars::CvarDef;
+
+    /// Svarar med argumenten den fick, åtskilda med |
+    fn cmd_args(_world: &mut World, args: &[String]) -> Result<String, String> {
+        Ok(args.join("|"))
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        let mut registry = CommandRegistry::default();
+        registry.register("args", "args <...>: show the arguments", cmd_args);
+        registry.register("echo", "echo <text>: print text to the console", cmd_echo);
+        registry.register("reset", "reset <cvar>: restore a cvar to its default", cmd_reset);
+        world.insert_resource(registry);
+        let mut config = GameConfig::default();
+        config.register(CvarDef::float("sensitivity", 2.0).range(0.1, 10.0).description("Mouse sensitivity"));
+        world.insert_resource(config);
+        world
+    }
+
+    fn run(world: &mut World, line: &str) -> Vec<Result<String, String>> {
+        execute(world, line)
+    }
+
+    #[test]
+    fn commands_get_their_arguments() {
+        let mut world = world();
+        assert_eq!(run(&mut world, "args a  b\tc"), [Ok("a|b|c".to_string())]);
+        assert_eq!(run(&mut world, "args \"a b\" \"\" c"), [Ok("a b||c".to_string())]);
+        assert_eq!(run(&mut world, r#"args "say \"hi\"" "back\\slash""#), [Ok(r#"say "hi"|back\slash"#.to_string())]);
+        assert_eq!(run(&mut world, "   "), []);
+    }
+
+    #[test]
+    fn statements_split_on_semicolons_outside_quotes() {
+        let mut world = world();
+        assert_eq!(
+            run(&mut world, "args a; args b;;echo \"c; d\" // args e"),
+            [Ok("a".to_string()), Ok("b".to_string()), Ok("c; d".to_string())]
+        );
+    }
+
+    #[test]
+    fn cvars_are_read_and_set() {
+        let mut world = world();
+        assert_eq!(run(&mut world, "sensitivity 3; sensitivity"), [
+            Ok(String::new()),
+            Ok("\"sensitivity\" = \"3\" (def. \"2\") - Mouse sensitivity".to_string())
+        ]);
+        let results = run(&mut world, "sensitivity 11; reset sensitivity");
+        assert!(results[0].as_ref().unwrap_err().contains("outside the range"));
+        assert_eq!(results[1], Ok(String::new()));
+        assert_eq!(world.resource::<GameConfig>().get_float("sensitivity"), Some(2.0));
+    }
+
+    #[test]
+    fn unknown_commands_and_bad_quotes_are_errors() {
+        let mut world = world();
+        assert_eq!(run(&mut world, "nope 1; args ok"), [
+            Err("Unknown command \"nope\"".to_string()),
+            Ok("ok".to_string())
+        ]);
+        assert_eq!(run(&mut world, "args \"open"), [Err("column 6: unterminated quote".to_string())]);
+    }
+
+    #[test]
+    fn completion_covers_commands_and_cvars() {
+        let world = world();
+        let (registry, config) = (world.resource::<CommandRegistry>(), world.resource::<GameConfig>());
+        assert_eq!(completions(registry, config, "E"), ["echo"]);
+        assert_eq!(completions(registry, config, "cl_"), ["cl_fullscreen", "cl_resolution", "cl_vsync"]);
+        let names = completions(registry, config, "cl_");
+        assert_eq!(common_prefix(&names), "cl_");
+        assert_eq!(common_prefix(&["sensitivity".to_string(), "sens".to_string()]), "sens");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
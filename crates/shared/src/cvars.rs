@@ -0,0 +1,380 @@
+//! Typed console variables (cvars).
+//!
+//! A cvar is registered once with a [`CvarDef`] describing its type, default, range and
+//! flags. Values are stored in [`GameConfig`](crate::config::GameConfig), which validates
+//! every change against the definition.
+use bevy::prelude::*;
+use std::fmt;
+use std::ops::BitOr;
+
+use crate::config::GameConfig;
+
+/// Sätts av `GameConfig` varje gång ett värde faktiskt ändras.
+#[derive(Event, Debug, Clone)]
+pub struct CvarChanged {
+    pub name: String,
+    pub value: CvarValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CvarValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    /// Used by both string and enum cvars.
+    Str(String),
+}
+
+impl fmt::Display for CvarValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // samma format som Source-motorn, 1/0 istället för true/false
+            CvarValue::Bool(b) => write!(f, "{}", if *b { "1" } else { "0" }),
+            CvarValue::Int(i) => write!(f, "{i}"),
+            CvarValue::Float(v) => write!(f, "{v}"),
+            CvarValue::Str(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CvarKind {
+    Bool,
+    Int { min: i32, max: i32 },
+    Float { min: f32, max: f32 },
+    Str,
+    /// One of a fixed set of strings, compared case-insensitively.
+    Enum(&'static [&'static str]),
+}
+
+impl CvarKind {
+    /// Parses `raw` into a value of this kind, checking ranges and enum choices.
+    pub fn parse(&self, name: &str, raw: &str) -> Result<CvarValue, CvarError> {
+        let raw = raw.trim();
+        let malformed = |expected: String| CvarError::Malformed {
+            name: name.to_string(),
+            value: raw.to_string(),
+            expected,
+        };
+
+        match self {
+            CvarKind::Bool => match raw.to_ascii_lowercase().as_str() {
+                "1" | "true" | "on" => Ok(CvarValue::Bool(true)),
+                "0" | "false" | "off" => Ok(CvarValue::Bool(false)),
+                _ => Err(malformed("0 or 1".into())),
+            },
+            CvarKind::Int { min, max } => {
+                let v: i32 = raw.parse().map_err(|_| malformed("an integer".into()))?;
+                if v < *min || v > *max {
+                    return Err(CvarError::OutOfRange {
+                        name: name.to_string(),
+                        value: raw.to_string(),
+                        min: min.to_string(),
+                        max: max.to_string(),
+                    });
+                }
+                Ok(CvarValue::Int(v))
+            }
+            CvarKind::Float { min, max } => {
+                let v: f32 = raw
+                    .parse()
+                    .ok()
+                    .filter(|v: &f32| v.is_finite())
+                    .ok_or_else(|| malformed("a number".into()))?;
+                if v < *min || v > *max {
+                    return Err(CvarError::OutOfRange {
+                        name: name.to_string(),
+                        value: raw.to_string(),
+                        min: min.to_string(),
+                        max: max.to_string(),
+                    });
+                }
+                Ok(CvarValue::Float(v))
+            }
+            CvarKind::Str => Ok(CvarValue::Str(raw.to_string())),
+            CvarKind::Enum(choices) => choices
+                .iter()
+                .find(|c| c.eq_ignore_ascii_case(raw))
+                .map(|c| CvarValue::Str(c.to_string()))
+                .ok_or_else(|| malformed(format!("one of {}", choices.join(", ")))),
+        }
+    }
+}
+
+/// Bit flags controlling how a cvar may be changed and whether it is saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CvarFlags(u8);
+
+impl CvarFlags {
+    pub const NONE: Self = Self(0);
+    /// Saved to `config.cfg`.
+    pub const ARCHIVE: Self = Self(1 << 0);
+    /// Can only be changed while `sv_cheats` is enabled.
+    pub const CHEAT: Self = Self(1 << 1);
+    /// Owned by the server and sent to clients, which cannot change it themselves.
+    pub const REPLICATED: Self = Self(1 << 2);
+    /// Cannot be changed at runtime at all.
+    pub const READ_ONLY: Self = Self(1 << 3);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for CvarFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Registration data for one cvar, e.g.
+/// `CvarDef::float("sensitivity", 2.0).range(0.01, 20.0).flags(CvarFlags::ARCHIVE)`.
+#[derive(Debug, Clone)]
+pub struct CvarDef {
+    pub name: String,
+    pub kind: CvarKind,
+    pub default: CvarValue,
+    pub description: String,
+    pub flags: CvarFlags,
+}
+
+impl CvarDef {
+    fn new(name: &str, kind: CvarKind, default: CvarValue) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            default,
+            description: String::new(),
+            flags: CvarFlags::NONE,
+        }
+    }
+
+    pub fn bool(name: &str, default: bool) -> Self {
+        Self::new(name, CvarKind::Bool, CvarValue::Bool(default))
+    }
+
+    pub fn int(name: &str, default: i32) -> Self {
+        Self::new(name, CvarKind::Int { min: i32::MIN, max: i32::MAX }, CvarValue::Int(default))
+    }
+
+    pub fn float(name: &str, default: f32) -> Self {
+        Self::new(name, CvarKind::Float { min: f32::MIN, max: f32::MAX }, CvarValue::Float(default))
+    }
+
+    pub fn string(name: &str, default: &str) -> Self {
+        Self::new(name, CvarKind::Str, CvarValue::Str(default.to_string()))
+    }
+
+    pub fn enumeration(name: &str, choices: &'static [&'static str], default: &str) -> Self {
+        Self::new(name, CvarKind::Enum(choices), CvarValue::Str(default.to_string()))
+    }
+
+    /// Sets the allowed range of a float cvar.
+    pub fn range(mut self, min: f32, max: f32) -> Self {
+        match &mut self.kind {
+            CvarKind::Float { min: lo, max: hi } => {
+                *lo = min;
+                *hi = max;
+            }
+            _ => warn!("{}: range is for float cvars, use int_range", self.name),
+        }
+        self
+    }
+
+    /// Sets the allowed range of an int cvar.
+    pub fn int_range(mut self, min: i32, max: i32) -> Self {
+        match &mut self.kind {
+            CvarKind::Int { min: lo, max: hi } => {
+                *lo = min;
+                *hi = max;
+            }
+            _ => warn!("{}: int_range is for int cvars, use range", self.name),
+        }
+        self
+    }
+
+    pub fn flags(mut self, flags: CvarFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CvarError {
+    Unknown(String),
+    Malformed { name: String, value: String, expected: String },
+    OutOfRange { name: String, value: String, min: String, max: String },
+    ReadOnly(String),
+    CheatProtected(String),
+    ServerControlled(String),
+    /// The server tried to set a cvar that isn't REPLICATED.
+    NotReplicated(String),
+}
+
+impl fmt::Display for CvarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CvarError::Unknown(name) => write!(f, "unknown cvar \"{name}\""),
+            CvarError::Malformed { name, value, expected } => {
+                write!(f, "{name}: \"{value}\" is not valid, expected {expected}")
+            }
+            CvarError::OutOfRange { name, value, min, max } => {
+                write!(f, "{name}: {value} is outside the range {min}..{max}")
+            }
+            CvarError::ReadOnly(name) => write!(f, "{name} is read-only"),
+            CvarError::CheatProtected(name) => write!(f, "{name} requires sv_cheats 1"),
+            CvarError::ServerControlled(name) => write!(f, "{name} is controlled by the server"),
+            CvarError::NotReplicated(name) => write!(f, "{name} can't be set by the server"),
+        }
+    }
+}
+
+impl std::error::Error for CvarError {}
+
+/// Lets any plugin register its cvars while the app is being built.
+pub trait RegisterCvarExt {
+    fn register_cvar(&mut self, def: CvarDef) -> &mut Self;
+}
+
+impl RegisterCvarExt for App {
+    fn register_cvar(&mut self, def: CvarDef) -> &mut Self {
+        if !self.world().contains_resource::<GameConfig>() {
+            self.insert_resource(GameConfig::load());
+        }
+        self.world_mut().resource_mut::<GameConfig>().register(def);
+        self
+    }
+}
+
+/// Turns the changes recorded by `GameConfig` into [`CvarChanged`] events.
+pub fn emit_cvar_changes(mut config: ResMut<GameConfig>, mut events: EventWriter<CvarChanged>) {
+    // bypass så att GameConfig inte räknas som ändrad varje frame
+    for name in config.bypass_change_detection().take_changes() {
+        if let Some(value) = config.get(&name) {
+            events.send(CvarChanged {
+                name,
+                value: value.clone(),
+            });
+        }
+    }
+}
+
+/// Warns about values in `config.cfg` that no plugin registered a cvar for.
+pub fn report_unknown_cvars(config: Res<GameConfig>) {
+    for (name, value) in config.unknown() {
+        warn!("config.cfg: unknown cvar \"{name}\" (\"{value}\") ignored");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GameConfig {
+        let mut config = GameConfig::default();
+        config.register(CvarDef::float("sensitivity", 2.0).range(0.1, 10.0));
+        config.register(CvarDef::int("rate", 128).int_range(64, 256));
+        config.register(CvarDef::enumeration("crosshair_style", &["classic", "dynamic"], "classic"));
+        config.register(CvarDef::bool("noclip", false).flags(CvarFlags::CHEAT));
+        config.register(CvarDef::string("version", "1.0").flags(CvarFlags::READ_ONLY));
+        config.take_changes();
+        config
+    }
+
+    #[test]
+    fn set_parses_by_kind() {
+        let mut config = config();
+        config.set("sensitivity", "3.5").unwrap();
+        config.set("rate", " 192 ").unwrap();
+        config.set("crosshair_style", "DYNAMIC").unwrap();
+        config.set("cl_vsync", "on").unwrap();
+        assert_eq!(config.get_float("sensitivity"), Some(3.5));
+        assert_eq!(config.get_int("rate"), Some(192));
+        assert_eq!(config.get_str("crosshair_style"), Some("dynamic"));
+        assert_eq!(config.get_bool("cl_vsync"), Some(true));
+        assert_eq!(config.get("cl_vsync").unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn values_outside_the_range_are_rejected() {
+        let mut config = config();
+        assert!(matches!(config.set("sensitivity", "10.5"), Err(CvarError::OutOfRange { .. })));
+        assert!(matches!(config.set("rate", "63"), Err(CvarError::OutOfRange { .. })));
+        assert!(matches!(config.set("sensitivity", "NaN"), Err(CvarError::Malformed { .. })));
+        assert!(matches!(config.set("rate", "1.5"), Err(CvarError::Malformed { .. })));
+        assert!(matches!(config.set("crosshair_style", "dot"), Err(CvarError::Malformed { .. })));
+        // gränserna själva går bra
+        config.set("sensitivity", "10").unwrap();
+        config.set("rate", "64").unwrap();
+        assert_eq!(config.get_float("sensitivity"), Some(10.0));
+        assert_eq!(config.get_int("rate"), Some(64));
+        config.set("sensitivity", "-1").unwrap_err();
+        assert_eq!(config.get_float("sensitivity"), Some(10.0));
+    }
+
+    #[test]
+    fn int_ranges_are_exact() {
+        let mut config = config();
+        // 2^24 + 1 går inte att skriva som f32
+        config.register(CvarDef::int("big", 0).int_range(0, 16_777_217));
+        config.set("big", "16777217").unwrap();
+        assert!(matches!(config.set("big", "16777218"), Err(CvarError::OutOfRange { .. })));
+    }
+
+    #[test]
+    fn reset_restores_the_default() {
+        let mut config = config();
+        config.set("sensitivity", "5").unwrap();
+        config.reset("sensitivity").unwrap();
+        assert_eq!(config.get_float("sensitivity"), Some(2.0));
+        assert_eq!(config.take_changes(), ["sensitivity", "sensitivity"]);
+    }
+
+    #[test]
+    fn unknown_cvars_are_errors() {
+        let mut config = config();
+        assert_eq!(config.set("nope", "1"), Err(CvarError::Unknown("nope".into())));
+        assert_eq!(config.reset("nope"), Err(CvarError::Unknown("nope".into())));
+        assert_eq!(config.get("nope"), None);
+    }
+
+    #[test]
+    fn flags_are_enforced() {
+        let mut config = config();
+        assert_eq!(config.set("version", "2.0"), Err(CvarError::ReadOnly("version".into())));
+        assert_eq!(config.set("noclip", "1"), Err(CvarError::CheatProtected("noclip".into())));
+        config.set("sv_cheats", "1").unwrap();
+        config.set("noclip", "1").unwrap();
+
+        config.server_authoritative = true;
+        assert_eq!(config.set("sv_cheats", "0"), Err(CvarError::ServerControlled("sv_cheats".into())));
+        config.set_replicated("sv_cheats", "0").unwrap();
+        assert_eq!(config.get_bool("sv_cheats"), Some(false));
+        // servern får bara sätta det den äger
+        assert_eq!(config.set_replicated("rate", "100"), Err(CvarError::NotReplicated("rate".into())));
+        assert_eq!(config.replicated(), [("sv_cheats".to_string(), "0".to_string())]);
+    }
+
+    #[test]
+    fn only_real_changes_become_events() {
+        let mut config = config();
+        config.set("rate", "128").unwrap();
+        config.set("rate", "100").unwrap();
+        config.set("rate", "100").unwrap();
+        config.set("rate", "300").unwrap_err();
+
+        let mut app = App::new();
+        app.add_event::<CvarChanged>().insert_resource(config).add_systems(Update, emit_cvar_changes);
+        app.update();
+        let events = app.world().resource::<Events<CvarChanged>>();
+        let changes: Vec<_> = events.get_reader().read(events).map(|e| (e.name.clone(), e.value.clone())).collect();
+        assert_eq!(changes, [("rate".to_string(), CvarValue::Int(100))]);
+    }
+}
@@ -0,0 +1,262 @@
+//! Match state shown on the HUD and the scoreboard.
+//!
+//! The game server runs the rounds and keeps every player's state, and the client fills
+//! these resources and raises the events from what it is sent (see `net::game_state`).
+//! The clocks count down here between updates so timers don't stutter. [`PlayerStats`]
+//! are kept by the game server too, one entity per player, and sent to the clients for
+//! their scoreboards (see `net::stats`).
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Team {
+    CounterTerrorist,
+    Terrorist,
+}
+
+impl Team {
+    pub fn label(self) -> &'static str {
+        match self {
+            Team::CounterTerrorist => "CT",
+            Team::Terrorist => "T",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ammo {
+    pub magazine: u32,
+    pub magazine_size: u32,
+    pub reserve: u32,
+}
+
+/// The local player's own status. On the game server each player's entity has theirs.
+#[derive(Resource, Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalPlayerState {
+    pub player_id: u64,
+    pub name: String,
+    pub team: Option<Team>,
+    pub alive: bool,
+    pub health: u32,
+    pub armor: u32,
+    pub helmet: bool,
+    pub money: u32,
+    pub weapon: Option<String>,
+    /// `None` för vapen utan ammo, t.ex. kniven
+    pub ammo: Option<Ammo>,
+    /// Current spread of the weapon in degrees, as the server works it out.
+    pub inaccuracy: f32,
+    pub has_bomb: bool,
+}
+
+impl Default for LocalPlayerState {
+    fn default() -> Self {
+        LocalPlayerState {
+            player_id: 0,
+            name: String::new(),
+            team: None,
+            alive: true,
+            health: 100,
+            armor: 0,
+            helmet: false,
+            money: 800,
+            weapon: None,
+            ammo: None,
+            inaccuracy: 0.0,
+            has_bomb: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RoundPhase {
+    #[default]
+    Warmup,
+    FreezeTime,
+    Live,
+    Ended(Team),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum BombStatus {
+    #[default]
+    Carried,
+    Dropped,
+    Planted {
+        site: char,
+        time_left: f32,
+        /// Hur långt en desarmering har kommit, 0..1
+        defuse_progress: Option<f32>,
+    },
+    Defused,
+    Exploded,
+}
+
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoundState {
+    pub phase: RoundPhase,
+    pub round: u32,
+    pub max_rounds: u32,
+    pub score_ct: u32,
+    pub score_t: u32,
+    /// Seconds left of the current phase.
+    pub time_left: f32,
+    pub bomb: BombStatus,
+}
+
+impl RoundState {
+    pub fn score(&self, team: Team) -> u32 {
+        match team {
+            Team::CounterTerrorist => self.score_ct,
+            Team::Terrorist => self.score_t,
+        }
+    }
+
+    /// One team has won more than half the match, or every round has been played.
+    pub fn is_match_over(&self) -> bool {
+        let to_win = self.max_rounds / 2 + 1;
+        let decided = self.score_ct >= to_win || self.score_t >= to_win;
+        let played_out = self.score_ct + self.score_t >= self.max_rounds;
+        self.max_rounds > 0 && (decided || played_out)
+    }
+}
+
+/// Scoreboard stats for one player. The server keeps them and clients get a copy.
+#[derive(Component, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub player_id: u64,
+    pub name: String,
+    /// `None` för åskådare
+    pub team: Option<Team>,
+    pub kills: u32,
+    pub deaths: u32,
+    pub assists: u32,
+    pub score: i32,
+    pub mvps: u32,
+    pub money: u32,
+    /// Round trip time in milliseconds.
+    pub ping: u32,
+    pub alive: bool,
+}
+
+/// A player named in the kill feed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Combatant {
+    pub player_id: u64,
+    pub name: String,
+    pub team: Team,
+}
+
+#[derive(Event, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KillEvent {
+    /// `None` when the victim died on their own, e.g. from falling.
+    pub killer: Option<Combatant>,
+    pub assister: Option<Combatant>,
+    pub victim: Combatant,
+    pub weapon: String,
+    pub headshot: bool,
+}
+
+/// A shot from the local player hit someone.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HitConfirmed {
+    pub headshot: bool,
+    pub killed: bool,
+}
+
+/// The local player took damage.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct DamageTaken {
+    pub amount: u32,
+    /// Where the damage came from, `None` for fall damage and the like.
+    pub source: Option<Vec3>,
+}
+
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_cvar(
+            CvarDef::float("mp_freezetime", 5.0)
+                .range(0.0, 60.0)
+                .flags(CvarFlags::REPLICATED)
+                .description("Seconds at the start of a round before anyone can move"),
+        )
+        .register_cvar(
+            CvarDef::float("mp_roundtime", 115.0)
+                .range(10.0, 600.0)
+                .flags(CvarFlags::REPLICATED)
+                .description("Length of a round in seconds"),
+        )
+        .register_cvar(
+            CvarDef::int("mp_maxrounds", 24)
+                .int_range(1, 99)
+                .flags(CvarFlags::REPLICATED)
+                .description("Rounds in a match"),
+        )
+        .init_resource::<LocalPlayerState>()
+            .init_resource::<RoundState>()
+            .add_event::<KillEvent>()
+            .add_event::<HitConfirmed>()
+            .add_event::<DamageTaken>()
+            .add_systems(Update, tick_round_clock);
+    }
+}
+
+const KILL_SCORE: i32 = 2;
+const ASSIST_SCORE: i32 = 1;
+
+/// Keeps `PlayerStats` up to date from kills, wherever the player entities live.
+pub struct MatchStatsPlugin;
+
+impl Plugin for MatchStatsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<GameStatePlugin>() {
+            app.add_plugins(GameStatePlugin);
+        }
+        app.add_systems(Update, record_kill_stats);
+    }
+}
+
+fn record_kill_stats(mut kills: EventReader<KillEvent>, mut players: Query<&mut PlayerStats>) {
+    for kill in kills.read() {
+        let victim = &kill.victim;
+        for mut stats in &mut players {
+            if stats.player_id == victim.player_id {
+                stats.deaths += 1;
+                stats.alive = false;
+            }
+            if let Some(killer) = &kill.killer {
+                if stats.player_id == killer.player_id && killer.player_id != victim.player_id {
+                    if killer.team == victim.team {
+                        // lagdödande ger minuspoäng
+                        stats.score -= KILL_SCORE;
+                    } else {
+                        stats.kills += 1;
+                        stats.score += KILL_SCORE;
+                    }
+                }
+            }
+            if let Some(assister) = &kill.assister {
+                if stats.player_id == assister.player_id && assister.team != victim.team {
+                    stats.assists += 1;
+                    stats.score += ASSIST_SCORE;
+                }
+            }
+        }
+    }
+}
+
+fn tick_round_clock(time: Res<Time>, mut round: ResMut<RoundState>) {
+    let dt = time.delta_seconds();
+    if round.time_left > 0.0 {
+        round.time_left = (round.time_left - dt).max(0.0);
+    }
+    if let BombStatus::Planted { time_left, .. } = &mut round.bomb {
+        if *time_left > 0.0 {
+            *time_left = (*time_left - dt).max(0.0);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Cosmetic items: what there is, and the skins players put on their weapons.
+//!
+//! Item definitions are compiled in and looked up by id with [`item_def`]. An item in
+//! someone's inventory names its definition and carries what's unique to that copy, which
+//! for a skin is its [`WeaponSkin`]: wear, pattern seed, stickers and name tag. Skins only
+//! change how a weapon looks. [`EquippedSkins`] holds what every player we know of has
+//! equipped, for us from the profile service and for the others from the game server.
+//! Skins come in [`Rarity`] tiers, which is what the odds of cases are given in (see `loot`).
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::game_state::Team;
+
+/// Stickers that fit on one weapon.
+pub const MAX_STICKERS: usize = 4;
+pub const MAX_NAME_TAG_LENGTH: usize = 20;
+/// Pattern seeds go from 0 up to this.
+pub const PATTERNS: u16 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Weapon {
+    Ak47,
+    M4a4,
+    Awp,
+    Deagle,
+    Glock,
+    Usp,
+    Knife,
+}
+
+impl Weapon {
+    pub const ALL: [Weapon; 7] =
+        [Weapon::Ak47, Weapon::M4a4, Weapon::Awp, Weapon::Deagle, Weapon::Glock, Weapon::Usp, Weapon::Knife];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Weapon::Ak47 => "AK-47",
+            Weapon::M4a4 => "M4A4",
+            Weapon::Awp => "AWP",
+            Weapon::Deagle => "Desert Eagle",
+            Weapon::Glock => "Glock-18",
+            Weapon::Usp => "USP-S",
+            Weapon::Knife => "Knife",
+        }
+    }
+
+    /// Horizontal FOV in degrees through the scope, `None` for weapons without one.
+    pub fn zoom_fov(self) -> Option<f32> {
+        match self {
+            Weapon::Awp => Some(40.0),
+            _ => None,
+        }
+    }
+
+    /// The teams that can buy it, and so have a loadout slot for it.
+    pub fn teams(self) -> &'static [Team] {
+        match self {
+            Weapon::Ak47 | Weapon::Glock => &[Team::Terrorist],
+            Weapon::M4a4 | Weapon::Usp => &[Team::CounterTerrorist],
+            Weapon::Awp | Weapon::Deagle | Weapon::Knife => &[Team::CounterTerrorist, Team::Terrorist],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Rarity {
+    MilSpec,
+    Restricted,
+    Classified,
+    Covert,
+    RareSpecial,
+}
+
+impl Rarity {
+    pub const ALL: [Rarity; 5] =
+        [Rarity::MilSpec, Rarity::Restricted, Rarity::Classified, Rarity::Covert, Rarity::RareSpecial];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Rarity::MilSpec => "Mil-Spec",
+            Rarity::Restricted => "Restricted",
+            Rarity::Classified => "Classified",
+            Rarity::Covert => "Covert",
+            Rarity::RareSpecial => "Rare Special",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Rarity::MilSpec => Color::srgb(0.29, 0.41, 1.0),
+            Rarity::Restricted => Color::srgb(0.53, 0.28, 1.0),
+            Rarity::Classified => Color::srgb(0.83, 0.18, 0.9),
+            Rarity::Covert => Color::srgb(0.92, 0.29, 0.29),
+            Rarity::RareSpecial => Color::srgb(0.89, 0.68, 0.22),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemKind {
+    /// `color` is the finish when new, in sRGB
+    Skin { weapon: Weapon, rarity: Rarity, color: [f32; 3] },
+    Sticker { color: [f32; 3] },
+    /// Renames a weapon skin, used up when applied
+    NameTag,
+    /// Opens into one of the skins in `contents`, see `loot::case_odds`
+    Case { contents: &'static [&'static str] },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItemDef {
+    /// What inventories and the profile service call it; never change a shipped one
+    pub id: &'static str,
+    pub name: &'static str,
+    pub kind: ItemKind,
+}
+
+const fn skin(id: &'static str, name: &'static str, weapon: Weapon, rarity: Rarity, color: [f32; 3]) -> ItemDef {
+    ItemDef { id, name, kind: ItemKind::Skin { weapon, rarity, color } }
+}
+
+const fn sticker(id: &'static str, name: &'static str, color: [f32; 3]) -> ItemDef {
+    ItemDef { id, name, kind: ItemKind::Sticker { color } }
+}
+
+pub const ITEMS: &[ItemDef] = &[
+    skin("skin_ak47_redline", "AK-47 | Redline", Weapon::Ak47, Rarity::Classified, [0.62, 0.08, 0.08]),
+    skin("skin_ak47_jungle", "AK-47 | Jungle Spray", Weapon::Ak47, Rarity::MilSpec, [0.25, 0.38, 0.18]),
+    skin("skin_m4a4_desert", "M4A4 | Desert Storm", Weapon::M4a4, Rarity::MilSpec, [0.72, 0.62, 0.42]),
+    skin("skin_m4a4_cobalt", "M4A4 | Cobalt", Weapon::M4a4, Rarity::Restricted, [0.12, 0.25, 0.65]),
+    skin("skin_awp_ember", "AWP | Ember", Weapon::Awp, Rarity::Covert, [0.85, 0.38, 0.08]),
+    skin("skin_deagle_blaze", "Desert Eagle | Blaze", Weapon::Deagle, Rarity::Classified, [0.9, 0.55, 0.1]),
+    skin("skin_glock_candy", "Glock-18 | Candy Apple", Weapon::Glock, Rarity::MilSpec, [0.78, 0.1, 0.2]),
+    skin("skin_usp_night", "USP-S | Night Ops", Weapon::Usp, Rarity::Restricted, [0.1, 0.1, 0.14]),
+    skin("skin_knife_fade", "Knife | Fade", Weapon::Knife, Rarity::RareSpecial, [0.75, 0.3, 0.7]),
+    sticker("sticker_crown", "Sticker | Crown", [0.95, 0.78, 0.2]),
+    sticker("sticker_skull", "Sticker | Skull", [0.9, 0.9, 0.88]),
+    sticker("sticker_heart", "Sticker | Heart", [0.9, 0.15, 0.3]),
+    ItemDef { id: "name_tag", name: "Name Tag", kind: ItemKind::NameTag },
+    ItemDef {
+        id: "case_weapon_1",
+        name: "Weapon Case",
+        kind: ItemKind::Case {
+            contents: &[
+                "skin_ak47_jungle",
+                "skin_m4a4_desert",
+                "skin_glock_candy",
+                "skin_m4a4_cobalt",
+                "skin_usp_night",
+                "skin_ak47_redline",
+                "skin_deagle_blaze",
+                "skin_awp_ember",
+                "skin_knife_fade",
+            ],
+        },
+    },
+];
+
+pub fn item_def(id: &str) -> Option<&'static ItemDef> {
+    ITEMS.iter().find(|def| def.id == id)
+}
+
+impl ItemDef {
+    /// `None` for everything but skins.
+    pub fn rarity(&self) -> Option<Rarity> {
+        match self.kind {
+            ItemKind::Skin { rarity, .. } => Some(rarity),
+            _ => None,
+        }
+    }
+}
+
+/// The wear ranges skins are sorted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exterior {
+    FactoryNew,
+    MinimalWear,
+    FieldTested,
+    WellWorn,
+    BattleScarred,
+}
+
+impl Exterior {
+    pub fn from_wear(wear: f32) -> Self {
+        match wear {
+            w if w < 0.07 => Exterior::FactoryNew,
+            w if w < 0.15 => Exterior::MinimalWear,
+            w if w < 0.38 => Exterior::FieldTested,
+            w if w < 0.45 => Exterior::WellWorn,
+            _ => Exterior::BattleScarred,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Exterior::FactoryNew => "Factory New",
+            Exterior::MinimalWear => "Minimal Wear",
+            Exterior::FieldTested => "Field-Tested",
+            Exterior::WellWorn => "Well-Worn",
+            Exterior::BattleScarred => "Battle-Scarred",
+        }
+    }
+}
+
+/// One copy of a skin, as it is on the weapon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeaponSkin {
+    /// Id of the skin's `ItemDef`
+    pub definition: String,
+    /// 0 is fresh out of the box, 1 is as worn as it gets
+    pub wear: f32,
+    /// Shifts the finish a little so no two copies look quite the same
+    pub pattern: u16,
+    /// Sticker definition ids, at most `MAX_STICKERS`
+    pub stickers: Vec<String>,
+    pub name_tag: Option<String>,
+}
+
+impl WeaponSkin {
+    pub fn weapon(&self) -> Option<Weapon> {
+        match item_def(&self.definition)?.kind {
+            ItemKind::Skin { weapon, .. } => Some(weapon),
+            _ => None,
+        }
+    }
+
+    pub fn exterior(&self) -> Exterior {
+        Exterior::from_wear(self.wear)
+    }
+
+    /// The name tag in quotes if it has one, else the skin's name and exterior.
+    pub fn display_name(&self) -> String {
+        match (&self.name_tag, item_def(&self.definition)) {
+            (Some(tag), _) => format!("\"{tag}\""),
+            (None, Some(def)) => format!("{} ({})", def.name, self.exterior().label()),
+            (None, None) => self.definition.clone(),
+        }
+    }
+
+    /// Base colour and roughness of the finish after wear and pattern.
+    pub fn finish(&self) -> Option<(Color, f32)> {
+        let ItemKind::Skin { color: [r, g, b], .. } = item_def(&self.definition)?.kind else {
+            return None;
+        };
+        // mönstret ljusar eller mörkar upp lite, slitage drar mot grått
+        let shade = 0.85 + 0.3 * f32::from(self.pattern % PATTERNS) / f32::from(PATTERNS);
+        let wear = self.wear.clamp(0.0, 1.0) * 0.6;
+        let channel = |c: f32| ((c * shade) * (1.0 - wear) + 0.35 * wear).clamp(0.0, 1.0);
+        Some((Color::srgb(channel(r), channel(g), channel(b)), 0.3 + 0.6 * self.wear.clamp(0.0, 1.0)))
+    }
+}
+
+/// The skins players have equipped, by player id.
+#[derive(Resource, Debug, Default)]
+pub struct EquippedSkins {
+    players: HashMap<u64, Vec<(Team, WeaponSkin)>>,
+}
+
+impl EquippedSkins {
+    /// Replaces what `player_id` has equipped; an empty list forgets them.
+    pub fn set(&mut self, player_id: u64, skins: Vec<(Team, WeaponSkin)>) {
+        if skins.is_empty() {
+            self.players.remove(&player_id);
+        } else {
+            self.players.insert(player_id, skins);
+        }
+    }
+
+    pub fn get(&self, player_id: u64) -> &[(Team, WeaponSkin)] {
+        self.players.get(&player_id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &[(Team, WeaponSkin)])> {
+        self.players.iter().map(|(id, skins)| (*id, skins.as_slice()))
+    }
+
+    /// The skin `player_id` has on `weapon` when playing `team`. Without a team, the first
+    /// skin equipped on the weapon for either.
+    pub fn skin(&self, player_id: u64, team: Option<Team>, weapon: Weapon) -> Option<&WeaponSkin> {
+        self.get(player_id)
+            .iter()
+            .filter(|(t, _)| team.is_none_or(|team| team == *t))
+            .map(|(_, skin)| skin)
+            .find(|skin| skin.weapon() == Some(weapon))
+    }
+}
+
+pub struct ItemsPlugin;
+
+impl Plugin for ItemsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EquippedSkins>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skin(definition: &str) -> WeaponSkin {
+        WeaponSkin {
+            definition: definition.to_string(),
+            wear: 0.1,
+            pattern: 0,
+            stickers: Vec::new(),
+            name_tag: None,
+        }
+    }
+
+    #[test]
+    fn without_a_team_the_skin_for_either_team_is_used() {
+        let mut skins = EquippedSkins::default();
+        skins.set(7, vec![
+            (Team::CounterTerrorist, skin("skin_awp_ember")),
+            (Team::Terrorist, skin("skin_ak47_redline")),
+        ]);
+        // awp:n finns bara för CT men används ändå utan lag
+        let awp = skins.skin(7, None, Weapon::Awp).unwrap();
+        assert_eq!(awp.definition, "skin_awp_ember");
+        assert_eq!(skins.skin(7, None, Weapon::Ak47).unwrap().definition, "skin_ak47_redline");
+        // med lag räknas bara det lagets skins
+        assert!(skins.skin(7, Some(Team::Terrorist), Weapon::Awp).is_none());
+        assert!(skins.skin(7, Some(Team::CounterTerrorist), Weapon::Awp).is_some());
+        assert!(skins.skin(8, None, Weapon::Awp).is_none());
+    }
+}
@@ -0,0 +1,250 @@
+use bevy::prelude::*;
+
+/// A keyboard key, mouse button or gamepad button that can be bound, named like in Source
+/// configs (`w`, `space`, `mouse1`, `f1`, `joy_a`...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputKey {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// Samma knapp på alla anslutna handkontroller
+    Gamepad(GamepadButtonType),
+}
+
+const GAMEPAD_BUTTONS: &[(&str, GamepadButtonType)] = &[
+    ("joy_a", GamepadButtonType::South),
+    ("joy_b", GamepadButtonType::East),
+    ("joy_x", GamepadButtonType::West),
+    ("joy_y", GamepadButtonType::North),
+    ("joy_c", GamepadButtonType::C),
+    ("joy_z", GamepadButtonType::Z),
+    ("joy_lb", GamepadButtonType::LeftTrigger),
+    ("joy_rb", GamepadButtonType::RightTrigger),
+    ("joy_lt", GamepadButtonType::LeftTrigger2),
+    ("joy_rt", GamepadButtonType::RightTrigger2),
+    ("joy_back", GamepadButtonType::Select),
+    ("joy_start", GamepadButtonType::Start),
+    ("joy_guide", GamepadButtonType::Mode),
+    ("joy_lstick", GamepadButtonType::LeftThumb),
+    ("joy_rstick", GamepadButtonType::RightThumb),
+    ("joy_up", GamepadButtonType::DPadUp),
+    ("joy_down", GamepadButtonType::DPadDown),
+    ("joy_left", GamepadButtonType::DPadLeft),
+    ("joy_right", GamepadButtonType::DPadRight),
+];
+
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("space", KeyCode::Space),
+    ("tab", KeyCode::Tab),
+    ("enter", KeyCode::Enter),
+    ("escape", KeyCode::Escape),
+    ("backspace", KeyCode::Backspace),
+    ("shift", KeyCode::ShiftLeft),
+    ("rshift", KeyCode::ShiftRight),
+    ("ctrl", KeyCode::ControlLeft),
+    ("rctrl", KeyCode::ControlRight),
+    ("alt", KeyCode::AltLeft),
+    ("ralt", KeyCode::AltRight),
+    ("capslock", KeyCode::CapsLock),
+    ("uparrow", KeyCode::ArrowUp),
+    ("downarrow", KeyCode::ArrowDown),
+    ("leftarrow", KeyCode::ArrowLeft),
+    ("rightarrow", KeyCode::ArrowRight),
+    ("ins", KeyCode::Insert),
+    ("del", KeyCode::Delete),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pgup", KeyCode::PageUp),
+    ("pgdn", KeyCode::PageDown),
+    ("`", KeyCode::Backquote),
+    ("-", KeyCode::Minus),
+    ("=", KeyCode::Equal),
+    ("[", KeyCode::BracketLeft),
+    ("]", KeyCode::BracketRight),
+    ("\\", KeyCode::Backslash),
+    ("semicolon", KeyCode::Semicolon),
+    ("'", KeyCode::Quote),
+    (",", KeyCode::Comma),
+    (".", KeyCode::Period),
+    ("/", KeyCode::Slash),
+    ("kp_0", KeyCode::Numpad0),
+    ("kp_1", KeyCode::Numpad1),
+    ("kp_2", KeyCode::Numpad2),
+    ("kp_3", KeyCode::Numpad3),
+    ("kp_4", KeyCode::Numpad4),
+    ("kp_5", KeyCode::Numpad5),
+    ("kp_6", KeyCode::Numpad6),
+    ("kp_7", KeyCode::Numpad7),
+    ("kp_8", KeyCode::Numpad8),
+    ("kp_9", KeyCode::Numpad9),
+    ("kp_plus", KeyCode::NumpadAdd),
+    ("kp_minus", KeyCode::NumpadSubtract),
+    ("kp_multiply", KeyCode::NumpadMultiply),
+    ("kp_slash", KeyCode::NumpadDivide),
+    ("kp_enter", KeyCode::NumpadEnter),
+    ("kp_del", KeyCode::NumpadDecimal),
+    ("numlock", KeyCode::NumLock),
+    ("scrolllock", KeyCode::ScrollLock),
+    ("pause", KeyCode::Pause),
+    ("printscreen", KeyCode::PrintScreen),
+    ("lwin", KeyCode::SuperLeft),
+    ("rwin", KeyCode::SuperRight),
+    ("menu", KeyCode::ContextMenu),
+];
+
+const LETTERS: [KeyCode; 26] = [
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF,
+    KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL,
+    KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR,
+    KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX,
+    KeyCode::KeyY, KeyCode::KeyZ,
+];
+
+const DIGITS: [KeyCode; 10] = [
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+];
+
+const F_KEYS: [KeyCode; 24] = [
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::F13, KeyCode::F14, KeyCode::F15, KeyCode::F16, KeyCode::F17, KeyCode::F18,
+    KeyCode::F19, KeyCode::F20, KeyCode::F21, KeyCode::F22, KeyCode::F23, KeyCode::F24,
+];
+
+impl InputKey {
+    /// Parses a key name, case-insensitively.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+
+        if let Some(n) = name.strip_prefix("mouse") {
+            return match n.parse::<u16>().ok()? {
+                1 => Some(InputKey::Mouse(MouseButton::Left)),
+                2 => Some(InputKey::Mouse(MouseButton::Right)),
+                3 => Some(InputKey::Mouse(MouseButton::Middle)),
+                4 => Some(InputKey::Mouse(MouseButton::Back)),
+                5 => Some(InputKey::Mouse(MouseButton::Forward)),
+                // extra musknappar har inga egna namn
+                n if n > 5 => Some(InputKey::Mouse(MouseButton::Other(n))),
+                _ => None,
+            };
+        }
+        if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<usize>().ok()) {
+            return F_KEYS.get(n.wrapping_sub(1)).map(|k| InputKey::Key(*k));
+        }
+
+        if let Some((_, button)) = GAMEPAD_BUTTONS.iter().find(|(n, _)| *n == name) {
+            return Some(InputKey::Gamepad(*button));
+        }
+        if let Some(n) = name.strip_prefix("joy_").and_then(|n| n.parse().ok()) {
+            return Some(InputKey::Gamepad(GamepadButtonType::Other(n)));
+        }
+
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if c.is_ascii_lowercase() {
+                return Some(InputKey::Key(LETTERS[(c as u8 - b'a') as usize]));
+            }
+            if c.is_ascii_digit() {
+                return Some(InputKey::Key(DIGITS[(c as u8 - b'0') as usize]));
+            }
+        }
+
+        NAMED_KEYS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, k)| InputKey::Key(*k))
+    }
+
+    /// The name shown in menus, e.g. `W`, `MOUSE1` or `JOY_A`.
+    pub fn label(&self) -> String {
+        self.name().to_uppercase()
+    }
+
+    /// Whether the key has a name [`InputKey::from_name`] reads back, so a binding to it
+    /// survives saving and loading the config.
+    pub fn is_bindable(&self) -> bool {
+        InputKey::from_name(&self.name()) == Some(*self)
+    }
+
+    /// The canonical name, the inverse of [`InputKey::from_name`] for bindable keys. Other
+    /// keys get a name for display only.
+    pub fn name(&self) -> String {
+        match self {
+            InputKey::Mouse(button) => match button {
+                MouseButton::Left => "mouse1".into(),
+                MouseButton::Right => "mouse2".into(),
+                MouseButton::Middle => "mouse3".into(),
+                MouseButton::Back => "mouse4".into(),
+                MouseButton::Forward => "mouse5".into(),
+                MouseButton::Other(n) => format!("mouse{n}"),
+            },
+            InputKey::Gamepad(button) => GAMEPAD_BUTTONS
+                .iter()
+                .find(|(_, b)| b == button)
+                .map(|(n, _)| n.to_string())
+                .unwrap_or_else(|| match button {
+                    GamepadButtonType::Other(n) => format!("joy_{n}"),
+                    _ => format!("joy_{button:?}").to_lowercase(),
+                }),
+            InputKey::Key(code) => {
+                if let Some(i) = LETTERS.iter().position(|k| k == code) {
+                    return ((b'a' + i as u8) as char).to_string();
+                }
+                if let Some(i) = DIGITS.iter().position(|k| k == code) {
+                    return i.to_string();
+                }
+                if let Some(i) = F_KEYS.iter().position(|k| k == code) {
+                    return format!("f{}", i + 1);
+                }
+                NAMED_KEYS
+                    .iter()
+                    .find(|(_, k)| k == code)
+                    .map(|(n, _)| n.to_string())
+                    .unwrap_or_else(|| format!("{code:?}").to_lowercase())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_named() -> impl Iterator<Item = InputKey> {
+        let keys = LETTERS.iter().chain(&DIGITS).chain(&F_KEYS).chain(NAMED_KEYS.iter().map(|(_, k)| k));
+        let mouse = [MouseButton::Left, MouseButton::Right, MouseButton::Middle, MouseButton::Back];
+        let mouse = mouse.into_iter().chain([MouseButton::Forward, MouseButton::Other(8)]);
+        keys.map(|k| InputKey::Key(*k))
+            .chain(mouse.map(InputKey::Mouse))
+            .chain(GAMEPAD_BUTTONS.iter().map(|(_, b)| InputKey::Gamepad(*b)))
+            .chain([InputKey::Gamepad(GamepadButtonType::Other(20))])
+    }
+
+    #[test]
+    fn every_named_key_reads_back_from_its_name() {
+        for key in all_named() {
+            assert!(key.is_bindable(), "{key:?} is saved as {}", key.name());
+            assert_eq!(InputKey::from_name(&key.label()), Some(key));
+        }
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let mut names: Vec<String> = all_named().map(|k| k.name()).collect();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn keys_without_a_name_are_not_bindable() {
+        // namnet blir "launchmail", som inte går att läsa tillbaka
+        assert!(!InputKey::Key(KeyCode::LaunchMail).is_bindable());
+        assert!(!InputKey::Mouse(MouseButton::Other(3)).is_bindable());
+        assert_eq!(InputKey::from_name("numpadadd"), None);
+        assert_eq!(InputKey::from_name("KP_PLUS"), Some(InputKey::Key(KeyCode::NumpadAdd)));
+        assert_eq!(InputKey::from_name("f0"), None);
+        assert_eq!(InputKey::from_name("mouse0"), None);
+    }
+}
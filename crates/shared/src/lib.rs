@@ -1,7 +1,18 @@
 pub mod types;
 pub mod config;
+pub mod cfg;
+pub mod cvars;
+pub mod console;
+pub mod keys;
+pub mod actions;
 pub mod startup;
 pub mod maps;
+pub mod game_state;
+pub mod lobby;
+pub mod chat;
+pub mod rating;
+pub mod items;
+pub mod loot;
 pub mod components;
 
 pub use types::AppState;
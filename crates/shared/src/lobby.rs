@@ -0,0 +1,467 @@
+//! Lobby a party sits in before a match.
+//!
+//! Whoever hosts the lobby owns the `Lobby` and sends all of it to every member after each
+//! change. Members ask for changes with `LobbyRequest`s, which the host checks with
+//! `Lobby::apply` so only the leader can change the settings or start the match. Alone we
+//! host our own lobby; in a party the profile service hosts it (see `net::profile`).
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cfg;
+use crate::config::{cfg_path, GameConfig};
+use crate::cvars::{CvarChanged, CvarDef, CvarFlags, RegisterCvarExt};
+use crate::maps::MapId;
+
+pub const MAX_LOBBY_MEMBERS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GameMode {
+    #[default]
+    Competitive,
+    Wingman,
+    Casual,
+    Deathmatch,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 4] = [GameMode::Competitive, GameMode::Wingman, GameMode::Casual, GameMode::Deathmatch];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GameMode::Competitive => "Competitive",
+            GameMode::Wingman => "Wingman",
+            GameMode::Casual => "Casual",
+            GameMode::Deathmatch => "Deathmatch",
+        }
+    }
+
+    /// Players per team for modes that go through matchmaking, `None` for the ones the
+    /// lobby starts by itself.
+    pub fn matchmaking_team_size(self) -> Option<usize> {
+        match self {
+            GameMode::Competitive => Some(5),
+            GameMode::Wingman => Some(2),
+            GameMode::Casual | GameMode::Deathmatch => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LobbyPermissions {
+    #[default]
+    InviteOnly,
+    FriendsCanJoin,
+    Public,
+}
+
+impl LobbyPermissions {
+    pub const ALL: [LobbyPermissions; 3] =
+        [LobbyPermissions::InviteOnly, LobbyPermissions::FriendsCanJoin, LobbyPermissions::Public];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LobbyPermissions::InviteOnly => "Invited Friends Only",
+            LobbyPermissions::FriendsCanJoin => "Friends Can Join",
+            LobbyPermissions::Public => "Public",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LobbyMember {
+    pub id: u64,
+    pub name: String,
+    pub ready: bool,
+}
+
+/// The game server matchmaking put the party on, with every member's ticket for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchedServer {
+    pub match_id: u64,
+    pub server: SocketAddr,
+    pub map: MapId,
+    /// Player id and ticket token; the server only lets in players with theirs
+    pub tickets: Vec<(u64, u64)>,
+}
+
+impl MatchedServer {
+    pub fn ticket(&self, player_id: u64) -> Option<u64> {
+        self.tickets.iter().find(|(id, _)| *id == player_id).map(|(_, token)| *token)
+    }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lobby {
+    pub leader: u64,
+    pub members: Vec<LobbyMember>,
+    pub map: MapId,
+    pub mode: GameMode,
+    pub permissions: LobbyPermissions,
+    /// Set by the leader's start request; every member heads into the match when they see it.
+    pub starting: bool,
+    /// Set by the leader when matchmaking found the party a server; every member joins it.
+    pub matched: Option<MatchedServer>,
+    /// Vårt eget id, skickas inte med
+    #[serde(skip)]
+    local_id: u64,
+    /// Värden är någon annan, ändringar skickas dit
+    #[serde(skip)]
+    remote: bool,
+}
+
+impl Lobby {
+    /// A lobby with just the local player in it, as its leader.
+    pub fn solo(id: u64, name: &str) -> Self {
+        Lobby {
+            leader: id,
+            members: vec![LobbyMember { id, name: name.to_string(), ready: true }],
+            map: MapId::Tutorial,
+            mode: GameMode::default(),
+            permissions: LobbyPermissions::default(),
+            starting: false,
+            matched: None,
+            local_id: id,
+            remote: false,
+        }
+    }
+
+    /// Takes over the state the host sent, keeping track of who we are.
+    pub fn update_from(&mut self, snapshot: Lobby) {
+        *self = Lobby { local_id: self.local_id, remote: true, ..snapshot };
+    }
+
+    /// Back to hosting a lobby of our own after leaving a party, keeping its settings.
+    pub fn go_solo(&mut self) {
+        let name = self.local_member().map_or_else(|| "Player".to_string(), |m| m.name.clone());
+        *self = Lobby {
+            map: self.map,
+            mode: self.mode,
+            permissions: self.permissions,
+            ..Lobby::solo(self.local_id, &name)
+        };
+    }
+
+    pub fn local_id(&self) -> u64 {
+        self.local_id
+    }
+
+    /// Hosted by someone else, so requests have to go there.
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    pub fn local_is_leader(&self) -> bool {
+        self.leader == self.local_id
+    }
+
+    pub fn member(&self, id: u64) -> Option<&LobbyMember> {
+        self.members.iter().find(|m| m.id == id)
+    }
+
+    pub fn local_member(&self) -> Option<&LobbyMember> {
+        self.member(self.local_id)
+    }
+
+    /// Everybody except the leader, who starts the match, has readied up.
+    pub fn all_ready(&self) -> bool {
+        self.members.iter().all(|m| m.ready || m.id == self.leader)
+    }
+
+    pub fn can_start(&self, id: u64) -> bool {
+        self.leader == id && self.all_ready() && !self.starting
+    }
+
+    fn member_mut(&mut self, id: u64) -> Result<&mut LobbyMember, LobbyError> {
+        self.members.iter_mut().find(|m| m.id == id).ok_or(LobbyError::NotMember(id))
+    }
+
+    /// Checks and applies a request from member `from`.
+    pub fn apply(&mut self, from: u64, request: &LobbyRequest) -> Result<(), LobbyError> {
+        let leader_only = !matches!(request, LobbyRequest::Join(_) | LobbyRequest::Leave | LobbyRequest::SetReady(_));
+        if leader_only && from != self.leader {
+            return Err(LobbyError::NotLeader);
+        }
+
+        match request {
+            LobbyRequest::Join(name) => {
+                if let Ok(member) = self.member_mut(from) {
+                    member.name = name.clone();
+                } else if self.members.len() >= MAX_LOBBY_MEMBERS {
+                    return Err(LobbyError::Full);
+                } else {
+                    self.members.push(LobbyMember { id: from, name: name.clone(), ready: false });
+                }
+            }
+            LobbyRequest::Leave => self.remove(from)?,
+            LobbyRequest::SetReady(ready) => self.member_mut(from)?.ready = *ready,
+            LobbyRequest::SetMap(map) => {
+                self.map = *map;
+                self.unready();
+            }
+            LobbyRequest::SetMode(mode) => {
+                self.mode = *mode;
+                self.unready();
+            }
+            LobbyRequest::SetPermissions(permissions) => self.permissions = *permissions,
+            LobbyRequest::Kick(id) => {
+                if *id == from {
+                    return Err(LobbyError::NotMember(*id));
+                }
+                self.remove(*id)?;
+            }
+            LobbyRequest::Promote(id) => {
+                self.member_mut(*id)?;
+                self.leader = *id;
+            }
+            LobbyRequest::Start => {
+                if !self.all_ready() {
+                    return Err(LobbyError::NotReady);
+                }
+                self.starting = true;
+            }
+            LobbyRequest::Matched(matched) => self.matched = Some(matched.clone()),
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u64) -> Result<(), LobbyError> {
+        let index = self.members.iter().position(|m| m.id == id).ok_or(LobbyError::NotMember(id))?;
+        self.members.remove(index);
+        // den som varit med längst tar över
+        if self.leader == id {
+            if let Some(next) = self.members.first() {
+                self.leader = next.id;
+            }
+        }
+        Ok(())
+    }
+
+    /// Nya inställningar måste bekräftas igen
+    fn unready(&mut self) {
+        let leader = self.leader;
+        for member in self.members.iter_mut().filter(|m| m.id != leader) {
+            member.ready = false;
+        }
+    }
+}
+
+/// A change a member asks the lobby host for.
+#[derive(Event, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LobbyRequest {
+    Join(String),
+    Leave,
+    SetReady(bool),
+    SetMap(MapId),
+    SetMode(GameMode),
+    SetPermissions(LobbyPermissions),
+    Kick(u64),
+    Promote(u64),
+    Start,
+    /// Passes the server matchmaking found, and the party's tickets for it, on to everybody.
+    Matched(MatchedServer),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LobbyError {
+    NotLeader,
+    NotMember(u64),
+    Full,
+    NotReady,
+}
+
+impl fmt::Display for LobbyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LobbyError::NotLeader => write!(f, "only the lobby leader can do that"),
+            LobbyError::NotMember(id) => write!(f, "player {id} isn't in the lobby"),
+            LobbyError::Full => write!(f, "the lobby is full"),
+            LobbyError::NotReady => write!(f, "everybody has to be ready first"),
+        }
+    }
+}
+
+impl std::error::Error for LobbyError {}
+
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_cvar(
+            CvarDef::string("name", "Player")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Your player name"),
+        );
+        let name = app
+            .world()
+            .resource::<GameConfig>()
+            .get_str("name")
+            .unwrap_or("Player")
+            .to_string();
+        app.insert_resource(Lobby::solo(local_player_id(), &name))
+            .add_event::<LobbyRequest>()
+            .add_systems(Update, (handle_lobby_requests, sync_local_name));
+    }
+}
+
+/// Our player id, made up the first time the game runs and kept in `player.cfg`. It is also
+/// the account id, proven to the auth service by the secret in `account.cfg`.
+pub fn local_player_id() -> u64 {
+    let path = cfg_path("player");
+    let text = fs::read_to_string(&path).unwrap_or_default();
+    let (statements, _) = cfg::parse(&text);
+    let saved = statements.iter().find_map(|s| match s.args.as_slice() {
+        [key, id] if key == "player_id" => id.parse().ok(),
+        _ => None,
+    });
+    if let Some(id) = saved {
+        return id;
+    }
+    // 0 är reserverat för "ingen"
+    let id = RandomState::new().hash_one(SystemTime::now()).max(1);
+    if let Err(err) = fs::write(&path, cfg::join(&["player_id".to_string(), id.to_string()]) + "\n") {
+        warn!("couldn't write {}: {err}", path.display());
+    }
+    id
+}
+
+/// Applies our own requests. Alone we host the lobby ourselves; once the lobby is hosted
+/// elsewhere the net layer sends the requests there instead and writes back the result.
+fn handle_lobby_requests(mut requests: EventReader<LobbyRequest>, mut lobby: ResMut<Lobby>) {
+    if lobby.is_remote() {
+        requests.clear();
+        return;
+    }
+    for request in requests.read() {
+        let from = lobby.local_id();
+        if let Err(err) = lobby.apply(from, request) {
+            warn!("lobby: {err}");
+        }
+    }
+}
+
+fn sync_local_name(
+    mut changes: EventReader<CvarChanged>,
+    config: Res<GameConfig>,
+    mut requests: EventWriter<LobbyRequest>,
+) {
+    if changes.read().any(|c| c.name == "name") {
+        if let Some(name) = config.get_str("name") {
+            requests.send(LobbyRequest::Join(name.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ledaren 1 med spelarna 2 och 3, ingen av dem redo
+    fn party() -> Lobby {
+        let mut lobby = Lobby::solo(1, "leader");
+        for id in [2, 3] {
+            lobby.apply(id, &LobbyRequest::Join(format!("player {id}"))).unwrap();
+        }
+        lobby
+    }
+
+    #[test]
+    fn only_the_leader_changes_settings_and_starts() {
+        let mut lobby = party();
+        for request in [
+            LobbyRequest::SetMap(MapId::BoxArena),
+            LobbyRequest::SetMode(GameMode::Wingman),
+            LobbyRequest::SetPermissions(LobbyPermissions::Public),
+            LobbyRequest::Kick(3),
+            LobbyRequest::Promote(2),
+            LobbyRequest::Start,
+            LobbyRequest::Matched(matched()),
+        ] {
+            assert_eq!(lobby.apply(2, &request), Err(LobbyError::NotLeader), "{request:?}");
+        }
+        assert_eq!(lobby, party());
+
+        lobby.apply(1, &LobbyRequest::SetMap(MapId::BoxArena)).unwrap();
+        lobby.apply(1, &LobbyRequest::SetPermissions(LobbyPermissions::Public)).unwrap();
+        assert_eq!((lobby.map, lobby.permissions), (MapId::BoxArena, LobbyPermissions::Public));
+    }
+
+    fn matched() -> MatchedServer {
+        MatchedServer {
+            match_id: 7,
+            server: ([127, 0, 0, 1], 27015).into(),
+            map: MapId::BoxArena,
+            tickets: vec![(1, 11), (2, 22), (3, 33)],
+        }
+    }
+
+    #[test]
+    fn the_leader_passes_the_match_and_everybodys_ticket_on() {
+        let mut lobby = party();
+        lobby.apply(1, &LobbyRequest::Matched(matched())).unwrap();
+        let matched = lobby.matched.as_ref().unwrap();
+        assert_eq!([1, 2, 3].map(|id| matched.ticket(id)), [Some(11), Some(22), Some(33)]);
+        assert_eq!(matched.ticket(4), None);
+    }
+
+    #[test]
+    fn starting_needs_everybody_but_the_leader_ready() {
+        let mut lobby = party();
+        assert_eq!(lobby.apply(1, &LobbyRequest::Start), Err(LobbyError::NotReady));
+        lobby.apply(2, &LobbyRequest::SetReady(true)).unwrap();
+        assert_eq!(lobby.apply(1, &LobbyRequest::Start), Err(LobbyError::NotReady));
+        lobby.apply(3, &LobbyRequest::SetReady(true)).unwrap();
+        assert!(lobby.can_start(1));
+        assert!(!lobby.can_start(2));
+        lobby.apply(1, &LobbyRequest::Start).unwrap();
+        assert!(lobby.starting);
+        assert!(!lobby.can_start(1));
+    }
+
+    #[test]
+    fn new_settings_have_to_be_readied_again() {
+        let mut lobby = party();
+        lobby.apply(2, &LobbyRequest::SetReady(true)).unwrap();
+        lobby.apply(1, &LobbyRequest::SetMode(GameMode::Deathmatch)).unwrap();
+        assert!(!lobby.member(2).unwrap().ready);
+        // ledaren behöver aldrig vara redo
+        assert!(lobby.member(1).unwrap().ready);
+    }
+
+    #[test]
+    fn the_oldest_member_takes_over_when_the_leader_leaves() {
+        let mut lobby = party();
+        lobby.apply(1, &LobbyRequest::Leave).unwrap();
+        assert_eq!(lobby.leader, 2);
+        assert!(lobby.member(1).is_none());
+        lobby.apply(2, &LobbyRequest::SetMap(MapId::BoxArena)).unwrap();
+
+        // att sparka sig själv eller någon som inte är med går inte
+        assert_eq!(lobby.apply(2, &LobbyRequest::Kick(2)), Err(LobbyError::NotMember(2)));
+        assert_eq!(lobby.apply(2, &LobbyRequest::Kick(9)), Err(LobbyError::NotMember(9)));
+        lobby.apply(2, &LobbyRequest::Kick(3)).unwrap();
+        assert_eq!(lobby.members.iter().map(|m| m.id).collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn the_lobby_is_full_at_the_member_cap() {
+        let mut lobby = Lobby::solo(1, "leader");
+        for id in 2..=MAX_LOBBY_MEMBERS as u64 {
+            lobby.apply(id, &LobbyRequest::Join("player".into())).unwrap();
+        }
+        let late = MAX_LOBBY_MEMBERS as u64 + 1;
+        assert_eq!(lobby.apply(late, &LobbyRequest::Join("late".into())), Err(LobbyError::Full));
+        assert_eq!(lobby.members.len(), MAX_LOBBY_MEMBERS);
+
+        // den som redan är med får byta namn även när det är fullt
+        lobby.apply(2, &LobbyRequest::Join("renamed".into())).unwrap();
+        assert_eq!(lobby.member(2).unwrap().name, "renamed");
+        assert_eq!(lobby.members.len(), MAX_LOBBY_MEMBERS);
+    }
+}
@@ -0,0 +1,222 @@
+//! Cases, drops and the rolls that decide what comes out of them.
+//!
+//! Rolls are provably fair. The profile service makes up a server seed for each player and
+//! publishes its hash, the commitment, before using it. Each roll hashes the server seed with
+//! the player's own client seed and a nonce that goes up by one per roll. When the player
+//! picks a new client seed the old server seed is revealed, and [`RollRecord::verify`] can
+//! then redo every roll made with it. The odds here are everything a roll depends on and are
+//! what the menus show.
+use serde::{Deserialize, Serialize};
+
+use crate::items::{item_def, ItemDef, ItemKind, Rarity, PATTERNS};
+
+pub const SEED_BYTES: usize = 32;
+pub const MAX_CLIENT_SEED_LENGTH: usize = 32;
+/// Chance of a drop at the end of a match, until the weekly cap is reached.
+pub const DROP_CHANCE: f64 = 0.35;
+pub const MAX_DROPS_PER_WEEK: u32 = 5;
+/// How often each tier comes out of a case, out of 10000. Tiers a case doesn't have are left out.
+pub const RARITY_WEIGHTS: [(Rarity, u32); 5] = [
+    (Rarity::MilSpec, 7992),
+    (Rarity::Restricted, 1598),
+    (Rarity::Classified, 320),
+    (Rarity::Covert, 64),
+    (Rarity::RareSpecial, 26),
+];
+/// What a drop can be and how often.
+pub const DROP_POOL: &[(&str, u32)] = &[
+    ("case_weapon_1", 50),
+    ("sticker_crown", 10),
+    ("sticker_skull", 10),
+    ("sticker_heart", 10),
+    ("name_tag", 5),
+    ("skin_ak47_jungle", 5),
+    ("skin_m4a4_desert", 5),
+    ("skin_glock_candy", 5),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RollKind {
+    /// Opening the case with this definition id
+    Case(String),
+    /// The chance of a drop after a match
+    Drop,
+}
+
+/// The item a roll came to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub definition: String,
+    pub wear: f32,
+    pub pattern: u16,
+}
+
+/// What the server seed is published as before it's used.
+pub fn commitment(seed: &[u8; SEED_BYTES]) -> [u8; 32] {
+    *blake3::hash(seed).as_bytes()
+}
+
+/// The random bytes of one roll.
+struct Dice([u8; 32]);
+
+impl Dice {
+    fn new(seed: &[u8; SEED_BYTES], client_seed: &str, nonce: u64) -> Self {
+        Dice(*blake3::keyed_hash(seed, format!("{client_seed}:{nonce}").as_bytes()).as_bytes())
+    }
+
+    /// A number in 0..1 made from the `i`th eight of the bytes, `i` below 4.
+    fn unit(&self, i: usize) -> f64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.0[i * 8..i * 8 + 8]);
+        (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Picks one of the weighted entries, `unit` in 0..1.
+fn pick<T: Copy>(entries: &[(T, u32)], unit: f64) -> Option<T> {
+    let total: u32 = entries.iter().map(|(_, weight)| weight).sum();
+    let mut target = unit * f64::from(total);
+    for &(entry, weight) in entries {
+        if target < f64::from(weight) {
+            return Some(entry);
+        }
+        target -= f64::from(weight);
+    }
+    // avrundning kan lämna en liten rest
+    entries.last().map(|(entry, _)| *entry)
+}
+
+fn skins_of(contents: &[&'static str], rarity: Rarity) -> Vec<&'static str> {
+    contents.iter().copied().filter(|id| item_def(id).and_then(ItemDef::rarity) == Some(rarity)).collect()
+}
+
+fn case_tiers(contents: &[&'static str]) -> Vec<(Rarity, u32)> {
+    RARITY_WEIGHTS.iter().copied().filter(|&(rarity, _)| !skins_of(contents, rarity).is_empty()).collect()
+}
+
+/// The tiers the case opens into and the chance of each; each skin in a tier is as likely.
+pub fn case_odds(case: &ItemDef) -> Vec<(Rarity, f64)> {
+    let ItemKind::Case { contents } = case.kind else {
+        return Vec::new();
+    };
+    let tiers = case_tiers(contents);
+    let total: u32 = tiers.iter().map(|(_, weight)| weight).sum();
+    tiers.into_iter().map(|(rarity, weight)| (rarity, f64::from(weight) / f64::from(total))).collect()
+}
+
+/// The chance of each item dropping after a match.
+pub fn drop_odds() -> Vec<(&'static str, f64)> {
+    let total: u32 = DROP_POOL.iter().map(|(_, weight)| weight).sum();
+    DROP_POOL.iter().map(|&(id, weight)| (id, DROP_CHANCE * f64::from(weight) / f64::from(total))).collect()
+}
+
+/// What the roll with these seeds and nonce comes to; `None` when nothing drops.
+pub fn roll(kind: &RollKind, seed: &[u8; SEED_BYTES], client_seed: &str, nonce: u64) -> Option<Outcome> {
+    let dice = Dice::new(seed, client_seed, nonce);
+    let definition = match kind {
+        RollKind::Case(case) => {
+            let ItemKind::Case { contents } = item_def(case)?.kind else {
+                return None;
+            };
+            let tier = skins_of(contents, pick(&case_tiers(contents), dice.unit(0))?);
+            let index = (dice.unit(1) * tier.len() as f64) as usize;
+            *tier.get(index.min(tier.len().saturating_sub(1)))?
+        }
+        RollKind::Drop => {
+            if dice.unit(0) >= DROP_CHANCE {
+                return None;
+            }
+            pick(DROP_POOL, dice.unit(1))?
+        }
+    };
+    // slitage och mönster bara för skins
+    let skin = matches!(item_def(definition)?.kind, ItemKind::Skin { .. });
+    Some(Outcome {
+        definition: definition.to_string(),
+        wear: if skin { dice.unit(2) as f32 } else { 0.0 },
+        pattern: if skin { (dice.unit(3) * f64::from(PATTERNS)) as u16 } else { 0 },
+    })
+}
+
+/// One roll as the profile service made it, enough to redo it once the seed is out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RollRecord {
+    pub nonce: u64,
+    pub kind: RollKind,
+    pub commitment: [u8; 32],
+    pub client_seed: String,
+    pub outcome: Option<Outcome>,
+    /// The server seed, once it's been revealed
+    pub seed: Option<[u8; SEED_BYTES]>,
+}
+
+impl RollRecord {
+    /// `None` while the seed is secret, then whether it matches the commitment and gives the
+    /// same outcome.
+    pub fn verify(&self) -> Option<bool> {
+        let seed = self.seed?;
+        let same = roll(&self.kind, &seed, &self.client_seed, self.nonce) == self.outcome;
+        Some(commitment(&seed) == self.commitment && same)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; SEED_BYTES] = [7; SEED_BYTES];
+
+    fn record(kind: RollKind, nonce: u64) -> RollRecord {
+        RollRecord {
+            nonce,
+            commitment: commitment(&SEED),
+            client_seed: "lucky".to_string(),
+            outcome: roll(&kind, &SEED, "lucky", nonce),
+            kind,
+            seed: Some(SEED),
+        }
+    }
+
+    #[test]
+    fn verify_accepts_real_rolls_and_catches_tampering() {
+        let case = record(RollKind::Case("case_weapon_1".to_string()), 3);
+        assert!(case.outcome.is_some());
+        assert_eq!(case.verify(), Some(true));
+        // så länge fröet är hemligt går det inte att säga något
+        assert_eq!(RollRecord { seed: None, ..case.clone() }.verify(), None);
+
+        let mut outcome = case.outcome.clone().unwrap();
+        outcome.wear = (outcome.wear + 0.1) % 1.0;
+        assert_eq!(RollRecord { outcome: Some(outcome), ..case.clone() }.verify(), Some(false));
+        assert_eq!(RollRecord { nonce: 4, ..case.clone() }.verify(), Some(false));
+        assert_eq!(RollRecord { client_seed: "unlucky".to_string(), ..case.clone() }.verify(), Some(false));
+        // ett annat frö stämmer inte med det som publicerades
+        assert_eq!(RollRecord { seed: Some([8; SEED_BYTES]), ..case }.verify(), Some(false));
+    }
+
+    #[test]
+    fn drops_verify_whether_or_not_something_dropped() {
+        let rolls: Vec<RollRecord> = (0..50).map(|nonce| record(RollKind::Drop, nonce)).collect();
+        assert!(rolls.iter().any(|r| r.outcome.is_some()));
+        assert!(rolls.iter().any(|r| r.outcome.is_none()));
+        assert!(rolls.iter().all(|r| r.verify() == Some(true)));
+
+        let nothing = rolls.iter().find(|r| r.outcome.is_none()).unwrap();
+        let claimed = Outcome { definition: "skin_ak47_jungle".to_string(), wear: 0.0, pattern: 0 };
+        assert_eq!(RollRecord { outcome: Some(claimed), ..nothing.clone() }.verify(), Some(false));
+    }
+
+    #[test]
+    fn the_odds_add_up() {
+        let case = item_def("case_weapon_1").unwrap();
+        let odds = case_odds(case);
+        assert!(!odds.is_empty());
+        assert!((odds.iter().map(|(_, p)| p).sum::<f64>() - 1.0).abs() < 1e-9);
+        // inget annat än lådor har odds
+        assert!(case_odds(item_def("sticker_crown").unwrap()).is_empty());
+
+        // det som droppar plus chansen att inget droppar
+        let drops: f64 = drop_odds().iter().map(|(_, p)| p).sum();
+        assert!((drops + (1.0 - DROP_CHANCE) - 1.0).abs() < 1e-9);
+    }
+}
@@ -1,5 +1,18 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MapId {
     Tutorial,
     BoxArena,
 }
+
+impl MapId {
+    pub const ALL: [MapId; 2] = [MapId::Tutorial, MapId::BoxArena];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MapId::Tutorial => "Tutorial Map",
+            MapId::BoxArena => "Box Arena",
+        }
+    }
+}
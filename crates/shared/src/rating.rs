@@ -0,0 +1,496 @@
+//! Skill ratings and the ranks shown for them.
+//!
+//! Ratings are Glicko-2: every player has a rating, a deviation saying how sure we are of it
+//! and a volatility saying how erratic their results are. A match is one rating period where
+//! each player meets the average of the other team, and the score they get depends on the
+//! round difference so a 16-2 moves ratings more than a 16-14. The deviation grows while a
+//! player is away, and a rank is only shown after the placement matches and goes away again
+//! after [`RANK_DECAY_DAYS`] without playing.
+//!
+//! Everything here is pure so the matchmaker, which owns the ratings, can keep them in a
+//! [`RatingStore`] and save it however it likes.
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cfg::{self, CfgError};
+
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+/// How fast volatility may change; 0.3 to 1.2 in Glickman's paper, lower is steadier.
+pub const TAU: f64 = 0.5;
+
+/// Matches played before a rank is shown.
+pub const PLACEMENT_MATCHES: u32 = 10;
+/// Days without a match before the rank is hidden again.
+pub const RANK_DECAY_DAYS: u64 = 28;
+/// Length of an idle rating period, the deviation grows once per period away.
+pub const RATING_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+/// Glicko-2 skalar om till det här
+const SCALE: f64 = 173.7178;
+const CONVERGENCE: f64 = 0.000001;
+
+/// One player's Glicko-2 rating on the usual 1500 scale.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating { rating: DEFAULT_RATING, deviation: DEFAULT_DEVIATION, volatility: DEFAULT_VOLATILITY }
+    }
+}
+
+impl Rating {
+    fn mu(&self) -> f64 {
+        (self.rating - DEFAULT_RATING) / SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+
+    /// The rating after `periods` rating periods without games.
+    pub fn idle(self, periods: u64) -> Rating {
+        let phi = (self.phi().powi(2) + periods as f64 * self.volatility.powi(2)).sqrt();
+        Rating { deviation: (phi * SCALE).min(DEFAULT_DEVIATION), ..self }
+    }
+
+    /// The rating after one period with `results`, each an opponent and our score against
+    /// them (1 win, 0.5 draw, 0 loss, or anything in between).
+    pub fn update(self, results: &[(Rating, f64)]) -> Rating {
+        if results.is_empty() {
+            return self.idle(1);
+        }
+        let mu = self.mu();
+        let phi = self.phi();
+
+        let mut v_inv = 0.0;
+        let mut delta_sum = 0.0;
+        for (opponent, score) in results {
+            let g = g(opponent.phi());
+            let e = expected(mu, opponent.mu(), opponent.phi());
+            v_inv += g * g * e * (1.0 - e);
+            delta_sum += g * (score - e);
+        }
+        let v = 1.0 / v_inv;
+        let delta = v * delta_sum;
+
+        let volatility = new_volatility(phi, self.volatility, v, delta);
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * delta_sum;
+
+        Rating {
+            rating: new_mu * SCALE + DEFAULT_RATING,
+            deviation: (new_phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, mu_j: f64, phi_j: f64) -> f64 {
+    1.0 / (1.0 + (-g(phi_j) * (mu - mu_j)).exp())
+}
+
+/// Step 5 of Glickman's paper, solved with the Illinois algorithm.
+fn new_volatility(phi: f64, sigma: f64, v: f64, delta: f64) -> f64 {
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2)) - (x - a) / (TAU * TAU)
+    };
+
+    let mut lower = a;
+    let mut upper = if delta * delta > phi * phi + v {
+        (delta * delta - phi * phi - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+    let mut f_lower = f(lower);
+    let mut f_upper = f(upper);
+    while (upper - lower).abs() > CONVERGENCE {
+        let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+        let f_c = f(c);
+        if f_c * f_upper <= 0.0 {
+            lower = upper;
+            f_lower = f_upper;
+        } else {
+            f_lower /= 2.0;
+        }
+        upper = c;
+        f_upper = f_c;
+    }
+    (lower / 2.0).exp()
+}
+
+/// Competitive skill groups, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Rank {
+    Silver1,
+    Silver2,
+    Silver3,
+    Silver4,
+    SilverElite,
+    SilverEliteMaster,
+    GoldNova1,
+    GoldNova2,
+    GoldNova3,
+    GoldNovaMaster,
+    MasterGuardian1,
+    MasterGuardian2,
+    MasterGuardianElite,
+    DistinguishedMasterGuardian,
+    LegendaryEagle,
+    LegendaryEagleMaster,
+    SupremeMasterFirstClass,
+    GlobalElite,
+}
+
+/// Rating där Silver 2 börjar, sedan en rank per `RANK_STEP`
+const RANK_FLOOR: f64 = 900.0;
+const RANK_STEP: f64 = 75.0;
+
+impl Rank {
+    pub const ALL: [Rank; 18] = [
+        Rank::Silver1,
+        Rank::Silver2,
+        Rank::Silver3,
+        Rank::Silver4,
+        Rank::SilverElite,
+        Rank::SilverEliteMaster,
+        Rank::GoldNova1,
+        Rank::GoldNova2,
+        Rank::GoldNova3,
+        Rank::GoldNovaMaster,
+        Rank::MasterGuardian1,
+        Rank::MasterGuardian2,
+        Rank::MasterGuardianElite,
+        Rank::DistinguishedMasterGuardian,
+        Rank::LegendaryEagle,
+        Rank::LegendaryEagleMaster,
+        Rank::SupremeMasterFirstClass,
+        Rank::GlobalElite,
+    ];
+
+    /// Silver 1 below 900, then a rank every 75 points up to Global Elite from 2100.
+    pub fn from_rating(rating: f64) -> Rank {
+        let index = ((rating - RANK_FLOOR) / RANK_STEP).floor() + 1.0;
+        Rank::ALL[index.clamp(0.0, (Rank::ALL.len() - 1) as f64) as usize]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Rank::Silver1 => "Silver 1",
+            Rank::Silver2 => "Silver 2",
+            Rank::Silver3 => "Silver 3",
+            Rank::Silver4 => "Silver 4",
+            Rank::SilverElite => "Silver Elite",
+            Rank::SilverEliteMaster => "Silver Elite Master",
+            Rank::GoldNova1 => "Gold Nova 1",
+            Rank::GoldNova2 => "Gold Nova 2",
+            Rank::GoldNova3 => "Gold Nova 3",
+            Rank::GoldNovaMaster => "Gold Nova Master",
+            Rank::MasterGuardian1 => "Master Guardian 1",
+            Rank::MasterGuardian2 => "Master Guardian 2",
+            Rank::MasterGuardianElite => "Master Guardian Elite",
+            Rank::DistinguishedMasterGuardian => "Distinguished Master Guardian",
+            Rank::LegendaryEagle => "Legendary Eagle",
+            Rank::LegendaryEagleMaster => "Legendary Eagle Master",
+            Rank::SupremeMasterFirstClass => "Supreme Master First Class",
+            Rank::GlobalElite => "Global Elite",
+        }
+    }
+}
+
+/// What a player is shown about their own rating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RankSummary {
+    /// `None` while placing or after being away too long
+    pub rank: Option<Rank>,
+    pub placement_left: u32,
+    pub wins: u32,
+}
+
+impl RankSummary {
+    pub fn label(&self) -> String {
+        match self.rank {
+            Some(rank) => rank.label().to_string(),
+            None if self.placement_left > 0 => format!("Unranked ({} placement matches left)", self.placement_left),
+            None => "Expired, play a match to get it back".to_string(),
+        }
+    }
+}
+
+/// A player's rating with what's needed for placement and decay.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct PlayerRating {
+    pub skill: Rating,
+    pub matches: u32,
+    pub wins: u32,
+    /// Unix time of the last rated match, 0 for never
+    pub last_played: u64,
+}
+
+impl PlayerRating {
+    fn idle_periods(&self, now: u64) -> u64 {
+        if self.matches == 0 {
+            return 0;
+        }
+        now.saturating_sub(self.last_played) / RATING_PERIOD_SECS
+    }
+
+    /// The rating with the deviation grown for the time since the last match.
+    pub fn current(&self, now: u64) -> Rating {
+        self.skill.idle(self.idle_periods(now))
+    }
+
+    pub fn placement_left(&self) -> u32 {
+        PLACEMENT_MATCHES.saturating_sub(self.matches)
+    }
+
+    pub fn rank(&self, now: u64) -> Option<Rank> {
+        let away_days = now.saturating_sub(self.last_played) / (24 * 60 * 60);
+        if self.placement_left() > 0 || away_days >= RANK_DECAY_DAYS {
+            return None;
+        }
+        Some(Rank::from_rating(self.skill.rating))
+    }
+
+    pub fn summary(&self, now: u64) -> RankSummary {
+        RankSummary { rank: self.rank(now), placement_left: self.placement_left(), wins: self.wins }
+    }
+
+    /// Rating the matchmaker groups players by.
+    pub fn matchmaking_rating(&self) -> u32 {
+        self.skill.rating.round().max(0.0) as u32
+    }
+}
+
+/// Score for the team that won `rounds_for` to `rounds_against`: 0.75 for the narrowest
+/// win up to 1.0 for a clean sweep, the mirror of that for the losers and 0.5 for a draw.
+pub fn match_score(rounds_for: u32, rounds_against: u32) -> f64 {
+    let total = rounds_for + rounds_against;
+    if total == 0 || rounds_for == rounds_against {
+        return 0.5;
+    }
+    let margin = rounds_for.abs_diff(rounds_against) as f64 / total as f64;
+    let winner = 0.75 + 0.25 * margin;
+    if rounds_for > rounds_against { winner } else { 1.0 - winner }
+}
+
+/// Rates a finished match. `rounds` are the rounds each team won, in the same order as
+/// `teams`. Every player is rated against the average of the other team.
+pub fn rate_match(teams: [&[PlayerRating]; 2], rounds: [u32; 2], now: u64) -> [Vec<PlayerRating>; 2] {
+    let current = teams.map(|team| team.iter().map(|p| p.current(now)).collect::<Vec<_>>());
+    let averages = [team_average(&current[0]), team_average(&current[1])];
+
+    let mut rated = [Vec::new(), Vec::new()];
+    for side in 0..2 {
+        let opponent = averages[1 - side];
+        let score = match_score(rounds[side], rounds[1 - side]);
+        for (player, skill) in teams[side].iter().zip(&current[side]) {
+            rated[side].push(PlayerRating {
+                skill: skill.update(&[(opponent, score)]),
+                matches: player.matches + 1,
+                wins: player.wins + u32::from(rounds[side] > rounds[1 - side]),
+                last_played: now,
+            });
+        }
+    }
+    rated
+}
+
+/// A team as one opponent: mean rating and the root mean square of the deviations.
+fn team_average(team: &[Rating]) -> Rating {
+    if team.is_empty() {
+        return Rating::default();
+    }
+    let n = team.len() as f64;
+    Rating {
+        rating: team.iter().map(|r| r.rating).sum::<f64>() / n,
+        deviation: (team.iter().map(|r| r.deviation.powi(2)).sum::<f64>() / n).sqrt(),
+        volatility: team.iter().map(|r| r.volatility).sum::<f64>() / n,
+    }
+}
+
+/// Every player's rating, saved in the cfg grammar as one
+/// `player <id> <rating> <deviation> <volatility> <matches> <wins> <last played>` per line.
+#[derive(Debug, Clone, Default)]
+pub struct RatingStore {
+    players: HashMap<u64, PlayerRating>,
+}
+
+impl RatingStore {
+    /// Players we have never seen start at the default rating.
+    pub fn get(&self, id: u64) -> PlayerRating {
+        self.players.get(&id).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, id: u64, rating: PlayerRating) {
+        self.players.insert(id, rating);
+    }
+
+    pub fn parse(text: &str) -> (Self, Vec<CfgError>) {
+        let (statements, mut errors) = cfg::parse(text);
+        let mut store = RatingStore::default();
+        for statement in statements {
+            match parse_player(&statement.args) {
+                Some((id, rating)) => store.set(id, rating),
+                None => errors.push(CfgError { line: statement.line, column: 1, message: "bad player rating" }),
+            }
+        }
+        (store, errors)
+    }
+
+    pub fn to_cfg(&self) -> String {
+        let mut ids: Vec<&u64> = self.players.keys().collect();
+        ids.sort();
+        let mut text = String::new();
+        for id in ids {
+            let p = &self.players[id];
+            text.push_str(&format!(
+                "player {id} {:.3} {:.3} {:.8} {} {} {}\n",
+                p.skill.rating, p.skill.deviation, p.skill.volatility, p.matches, p.wins, p.last_played
+            ));
+        }
+        text
+    }
+}
+
+fn parse_player(args: &[String]) -> Option<(u64, PlayerRating)> {
+    let [kind, id, rating, deviation, volatility, matches, wins, last_played] = args else {
+        return None;
+    };
+    if kind != "player" {
+        return None;
+    }
+    let rating = PlayerRating {
+        skill: Rating {
+            rating: rating.parse().ok()?,
+            deviation: deviation.parse().ok()?,
+            volatility: volatility.parse().ok()?,
+        },
+        matches: matches.parse().ok()?,
+        wins: wins.parse().ok()?,
+        last_played: last_played.parse().ok()?,
+    };
+    Some((id.parse().ok()?, rating))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating { rating, deviation, ..Rating::default() }
+    }
+
+    #[test]
+    fn matches_glickmans_worked_example() {
+        let player = rating(1500.0, 200.0);
+        let results = [(rating(1400.0, 30.0), 1.0), (rating(1550.0, 100.0), 0.0), (rating(1700.0, 300.0), 0.0)];
+        let rated = player.update(&results);
+        assert!((rated.rating - 1464.06).abs() < 0.01, "{}", rated.rating);
+        assert!((rated.deviation - 151.52).abs() < 0.01, "{}", rated.deviation);
+        assert!((rated.volatility - 0.05999).abs() < 0.00001, "{}", rated.volatility);
+    }
+
+    #[test]
+    fn the_round_difference_decides_the_score() {
+        assert_eq!(match_score(16, 16), 0.5);
+        assert_eq!(match_score(0, 0), 0.5);
+        assert_eq!(match_score(16, 0), 1.0);
+        assert!((match_score(16, 14) - 0.7667).abs() < 0.0001);
+        assert!(match_score(16, 2) > match_score(16, 14));
+        // förlorarna får spegelbilden
+        assert_eq!(match_score(2, 16), 1.0 - match_score(16, 2));
+    }
+
+    #[test]
+    fn ranks_start_at_900_and_end_at_2100() {
+        assert_eq!(Rank::from_rating(0.0), Rank::Silver1);
+        assert_eq!(Rank::from_rating(899.9), Rank::Silver1);
+        assert_eq!(Rank::from_rating(900.0), Rank::Silver2);
+        assert_eq!(Rank::from_rating(974.9), Rank::Silver2);
+        assert_eq!(Rank::from_rating(2099.9), Rank::SupremeMasterFirstClass);
+        assert_eq!(Rank::from_rating(2100.0), Rank::GlobalElite);
+        assert_eq!(Rank::from_rating(5000.0), Rank::GlobalElite);
+    }
+
+    #[test]
+    fn the_deviation_grows_while_away_but_never_past_350() {
+        let skill = rating(1800.0, 60.0);
+        assert!(skill.idle(10).deviation > skill.idle(1).deviation);
+        assert_eq!(skill.idle(1).rating, 1800.0);
+        assert_eq!(skill.idle(100_000).deviation, DEFAULT_DEVIATION);
+
+        let player = PlayerRating { skill, matches: 20, wins: 10, last_played: 0 };
+        assert_eq!(player.current(DAY - 1), skill.idle(0));
+        assert_eq!(player.current(10 * DAY), skill.idle(10));
+    }
+
+    #[test]
+    fn ranks_show_after_placement_and_hide_after_a_break() {
+        let now = 100 * DAY;
+        let mut player = PlayerRating { skill: rating(1200.0, 80.0), matches: 9, wins: 5, last_played: now };
+        assert_eq!(player.rank(now), None);
+        assert_eq!(player.summary(now).placement_left, 1);
+
+        player.matches = PLACEMENT_MATCHES;
+        assert_eq!(player.rank(now), Some(Rank::SilverEliteMaster));
+        assert_eq!(player.rank(now + (RANK_DECAY_DAYS - 1) * DAY), Some(Rank::SilverEliteMaster));
+        assert_eq!(player.rank(now + RANK_DECAY_DAYS * DAY), None);
+        assert_eq!(player.summary(now + RANK_DECAY_DAYS * DAY).label(), "Expired, play a match to get it back");
+    }
+
+    #[test]
+    fn winners_go_up_and_losers_go_down() {
+        let team = [PlayerRating::default(); 5];
+        let [winners, losers] = rate_match([&team, &team], [16, 10], DAY);
+        assert!(winners.iter().all(|p| p.skill.rating > DEFAULT_RATING && p.wins == 1 && p.matches == 1));
+        assert!(losers.iter().all(|p| p.skill.rating < DEFAULT_RATING && p.wins == 0 && p.last_played == DAY));
+    }
+
+    #[test]
+    fn the_store_round_trips_through_cfg() {
+        let mut store = RatingStore::default();
+        let player = PlayerRating {
+            skill: Rating { rating: 1612.5, deviation: 80.25, volatility: 0.0599 },
+            matches: 42,
+            wins: 23,
+            last_played: 1_700_000_000,
+        };
+        store.set(7, player);
+        store.set(3, PlayerRating::default());
+
+        let text = store.to_cfg();
+        assert!(text.starts_with("player 3 "));
+        let (parsed, errors) = RatingStore::parse(&text);
+        assert!(errors.is_empty());
+        assert_eq!(parsed.get(7), player);
+        assert_eq!(parsed.get(3), PlayerRating::default());
+        assert_eq!(parsed.to_cfg(), text);
+        // okända spelare får standardbetyget
+        assert_eq!(parsed.get(99), PlayerRating::default());
+
+        let (_, errors) = RatingStore::parse("player 1 1500\nplayer 2 x 350 0.06 0 0 0\n");
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), [1, 2]);
+    }
+}
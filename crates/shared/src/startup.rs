@@ -1,13 +1,20 @@
 use bevy::prelude::*;
-use bevy::window::{Window, WindowMode};
+use bevy::window::{PresentMode, Window, WindowMode};
 use crate::config::GameConfig;
+use crate::cvars::{emit_cvar_changes, report_unknown_cvars, CvarChanged};
 
 pub struct StartupConfigPlugin;
 
 impl Plugin for StartupConfigPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(GameConfig::load())
-           .add_systems(Startup, apply_config_on_startup);
+        // plugins som registrerar cvars kan redan ha skapat configen
+        if !app.world().contains_resource::<GameConfig>() {
+            app.insert_resource(GameConfig::load());
+        }
+        app.add_event::<CvarChanged>()
+           .add_systems(Startup, apply_config_on_startup)
+           .add_systems(PostStartup, report_unknown_cvars)
+           .add_systems(PreUpdate, emit_cvar_changes);
     }
 }
 
@@ -16,21 +23,37 @@ fn apply_config_on_startup(
     config: Res<GameConfig>,
 ) {
     if let Ok(mut window) = windows.get_single_mut() {
-        // Fullscreen
-        let fullscreen = config.values.get("cl_fullscreen").map(|s| s == "1").unwrap_or(false);
-        window.mode = if fullscreen {
-            WindowMode::Fullscreen
-        } else {
-            WindowMode::Windowed
-        };
+        apply_window_config(&mut window, &config);
+    }
+}
 
-        // Resolution
-        if let Some(res) = config.values.get("cl_resolution") {
-            if let Some((w, h)) = res.split_once('x') {
-                if let (Ok(w), Ok(h)) = (w.parse::<f32>(), h.parse::<f32>()) {
-                    window.resolution.set(w, h);
-                }
-            }
-        }
+/// Sets window mode, resolution and vsync from `cl_fullscreen`, `cl_resolution` and
+/// `cl_vsync`. Used at startup and whenever one of them changes.
+pub fn apply_window_config(window: &mut Window, config: &GameConfig) {
+    // Fullscreen
+    let fullscreen = config.get_bool("cl_fullscreen").unwrap_or(false);
+    window.mode = if fullscreen {
+        WindowMode::Fullscreen
+    } else {
+        WindowMode::Windowed
+    };
+
+    // Resolution
+    if let Some((w, h)) = config.get_str("cl_resolution").and_then(parse_resolution) {
+        window.resolution.set(w, h);
     }
+
+    // VSync
+    window.present_mode = if config.get_bool("cl_vsync").unwrap_or(true) {
+        PresentMode::AutoVsync
+    } else {
+        PresentMode::AutoNoVsync
+    };
+}
+
+/// Parses `WIDTHxHEIGHT`, e.g. `1920x1080`.
+pub fn parse_resolution(res: &str) -> Option<(f32, f32)> {
+    let (w, h) = res.split_once('x')?;
+    let (w, h) = (w.trim().parse::<f32>().ok()?, h.trim().parse::<f32>().ok()?);
+    (w >= 1.0 && h >= 1.0).then_some((w, h))
 }
@@ -14,9 +14,12 @@ pub struct SelectedMap(pub MapId);
 pub enum AppState {
     #[default]
     Loading,
+    /// Något asset saknas eller gick inte att läsa, visar felskärmen
+    LoadingFailed,
     MainMenu,
     PlayMenu,
     InventoryMenu,
+    ServerBrowser,
     OptionsMenu(OptionsSubState),
     InGame,
 }
@@ -30,4 +33,29 @@ pub enum OptionsSubState {
     VideoSettings,
     AudioSettings,
     Credits,
-}
\ No newline at end of file
+}
+/// Menyer ovanpå spelet. Världen ligger kvar och simuleringen fortsätter under dem
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, SubStates)]
+#[source(AppState = AppState::InGame)]
+pub enum InGameMenu {
+    #[default]
+    Closed,
+    Paused,
+    Options(OptionsSubState),
+}
+
+/// The options screen being shown, from the main menu or on top of the game.
+pub fn options_screen(state: &AppState, menu: Option<&InGameMenu>) -> Option<OptionsSubState> {
+    match (state, menu) {
+        (AppState::OptionsMenu(sub), _) => Some(sub.clone()),
+        (_, Some(InGameMenu::Options(sub))) => Some(sub.clone()),
+        _ => None,
+    }
+}
+
+/// Run condition for systems that belong to one options screen, wherever it is open.
+pub fn in_options_screen(
+    sub: OptionsSubState,
+) -> impl Fn(Res<State<AppState>>, Option<Res<State<InGameMenu>>>) -> bool + Clone {
+    move |state, menu| options_screen(state.get(), menu.as_deref().map(State::get)) == Some(sub.clone())
+}
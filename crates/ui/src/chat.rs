@@ -0,0 +1,190 @@
+//! In-game chat: recent messages above the HUD and the box you type in.
+//!
+//! `messagemode` opens the box for all chat and `messagemode2` for team chat, bound to
+//! `y` and `u` by default. The lobby chat lives in the play menu.
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use shared::actions::{ActionState, ActionsPlugin};
+use shared::chat::{ChatChannel, ChatLog, ChatMessage, ChatPlugin, SendChat};
+use shared::config::GameConfig;
+use shared::console::RegisterCommandExt;
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::types::InGameMenu;
+use shared::AppState;
+
+use crate::hud::team_color;
+
+/// Lines shown while the box is open.
+const OPEN_LINES: usize = 12;
+
+pub struct ChatUiPlugin;
+
+impl Plugin for ChatUiPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ChatPlugin>() {
+            app.add_plugins(ChatPlugin);
+        }
+        if !app.is_plugin_added::<ActionsPlugin>() {
+            app.add_plugins(ActionsPlugin);
+        }
+        app.register_cvar(
+            CvarDef::float("hud_saytext_time", 12.0)
+                .range(0.0, 60.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Seconds a chat message stays on screen"),
+        )
+        .register_console_command("messagemode", "Open the chat box", messagemode)
+        .register_console_command("messagemode2", "Open the team chat box", messagemode2)
+        .init_resource::<ChatInput>()
+        .add_systems(
+            Update,
+            (block_actions_while_typing, chat_ui.run_if(in_state(InGameMenu::Closed)))
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        )
+        .add_systems(OnExit(AppState::InGame), close_chat);
+    }
+}
+
+#[derive(Resource, Default)]
+struct ChatInput {
+    /// Kanalen vi skriver i, `None` när rutan är stängd
+    channel: Option<ChatChannel>,
+    text: String,
+    /// Tangenten som öppnade rutan ska inte hamna i texten
+    just_opened: bool,
+}
+
+fn messagemode(world: &mut World, _args: &[String]) -> Result<String, String> {
+    open_chat(world, ChatChannel::All)
+}
+
+fn messagemode2(world: &mut World, _args: &[String]) -> Result<String, String> {
+    open_chat(world, ChatChannel::Team)
+}
+
+fn open_chat(world: &mut World, channel: ChatChannel) -> Result<String, String> {
+    let in_game = world
+        .get_resource::<State<InGameMenu>>()
+        .is_some_and(|menu| *menu.get() == InGameMenu::Closed);
+    if !in_game {
+        return Err("chat only opens in game".into());
+    }
+    let mut input = world.resource_mut::<ChatInput>();
+    input.channel = Some(channel);
+    input.text.clear();
+    input.just_opened = true;
+    Ok(String::new())
+}
+
+fn close_chat(mut input: ResMut<ChatInput>, mut actions: ResMut<ActionState>) {
+    input.channel = None;
+    input.text.clear();
+    actions.set_blocked("chat", false);
+}
+
+/// Körs före chat_ui så att Escape som stänger rutan inte också öppnar pausmenyn
+fn block_actions_while_typing(input: Res<ChatInput>, mut actions: ResMut<ActionState>) {
+    if input.is_changed() {
+        actions.set_blocked("chat", input.channel.is_some());
+    }
+}
+
+pub(crate) fn message_text(message: &ChatMessage) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+    let format = |color| egui::TextFormat {
+        font_id: egui::FontId::proportional(14.0),
+        color,
+        ..default()
+    };
+    let name_color = message.team.map_or(egui::Color32::from_rgb(200, 200, 200), team_color);
+    match &message.sender {
+        Some(sender) => {
+            let prefix = match message.channel {
+                ChatChannel::Team => "(Team) ",
+                _ => "",
+            };
+            job.append(&format!("{prefix}{sender}: "), 0.0, format(name_color));
+            job.append(&message.text, 0.0, format(egui::Color32::from_rgb(235, 235, 235)));
+        }
+        None => job.append(&message.text, 0.0, format(egui::Color32::from_rgb(240, 200, 80))),
+    }
+    job
+}
+
+fn chat_ui(
+    mut contexts: EguiContexts,
+    config: Res<GameConfig>,
+    time: Res<Time<Real>>,
+    log: Res<ChatLog>,
+    mut input: ResMut<ChatInput>,
+    mut send: EventWriter<SendChat>,
+) {
+    let scale = config.get_float("hud_scaling").unwrap_or(1.0);
+    let show_for = config.get_float("hud_saytext_time").unwrap_or(12.0);
+    let now = time.elapsed_seconds();
+    let open = input.channel.is_some();
+
+    // lobbychatten hör till spelmenyn
+    let lines: Vec<_> = log
+        .lines()
+        .rev()
+        .filter(|line| line.message.channel != ChatChannel::Lobby)
+        .take_while(|line| open || now - line.received < show_for)
+        .take(OPEN_LINES)
+        .collect();
+    if lines.is_empty() && !open {
+        return;
+    }
+
+    let ctx = contexts.ctx_mut();
+    egui::Area::new(egui::Id::new("chat"))
+        .anchor(egui::Align2::LEFT_BOTTOM, [16.0 * scale, -120.0 * scale])
+        .show(ctx, |ui| {
+            ui.set_width(420.0 * scale);
+            let frame = if open {
+                egui::Frame::none()
+                    .fill(egui::Color32::from_black_alpha(140))
+                    .rounding(4.0)
+                    .inner_margin(8.0)
+            } else {
+                egui::Frame::none().inner_margin(8.0)
+            };
+            frame.show(ui, |ui| {
+                for line in lines.iter().rev() {
+                    ui.label(message_text(&line.message));
+                }
+                let Some(channel) = input.channel else {
+                    return;
+                };
+
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new(format!("{}:", channel.label())).strong());
+                    if input.just_opened {
+                        // hoppa över en frame så att `y` inte skrivs in
+                        input.just_opened = false;
+                        return;
+                    }
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut input.text)
+                            .desired_width(f32::INFINITY)
+                            .lock_focus(true),
+                    );
+                    response.request_focus();
+                });
+
+                let (enter, escape) =
+                    ui.input(|i| (i.key_pressed(egui::Key::Enter), i.key_pressed(egui::Key::Escape)));
+                if enter {
+                    let text = std::mem::take(&mut input.text);
+                    if !text.trim().is_empty() {
+                        send.send(SendChat { channel, text });
+                    }
+                    input.channel = None;
+                } else if escape {
+                    input.text.clear();
+                    input.channel = None;
+                }
+            });
+        });
+}
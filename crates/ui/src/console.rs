@@ -0,0 +1,275 @@
+//! In-game developer console, toggled with the key left of `1`.
+//!
+//! The window is only the front end: submitted lines go into [`ConsoleQueue`] and are run by
+//! `shared::console`, which also owns the command registry and the scrollback.
+use bevy::log::tracing_subscriber::{layer::Context, Layer};
+use bevy::log::BoxedLayer;
+use bevy::prelude::*;
+use bevy::utils::tracing::field::{Field, Visit};
+use bevy::utils::tracing::{Event as TracingEvent, Level, Subscriber};
+use bevy_egui::{egui, EguiContexts};
+use shared::actions::ActionState;
+use shared::config::GameConfig;
+use shared::console::{
+    common_prefix, completions, CommandRegistry, ConsoleCommandsPlugin, ConsoleLevel,
+    ConsoleLine, ConsoleLog, ConsoleQueue,
+};
+use std::fmt::Debug;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ConsoleCommandsPlugin>() {
+            app.add_plugins(ConsoleCommandsPlugin);
+        }
+        app.init_resource::<ConsoleState>().add_systems(
+            Update,
+            (
+                toggle_console,
+                mirror_log_lines,
+                console_ui.after(toggle_console),
+            ),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ConsoleState {
+    pub open: bool,
+    input: String,
+    history: Vec<String>,
+    /// Index i `history` när man bläddrar med pil upp/ner
+    history_pos: Option<usize>,
+    suggestions: Vec<String>,
+}
+
+const MAX_HISTORY: usize = 100;
+
+fn toggle_console(
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut state: ResMut<ConsoleState>,
+    actions: Option<ResMut<ActionState>>,
+) {
+    if keys.just_pressed(KeyCode::Backquote) {
+        state.open = !state.open;
+    } else if state.open && keys.just_pressed(KeyCode::Escape) {
+        state.open = false;
+        // förbrukad, annars öppnar samma tryck pausmenyn
+        keys.clear_just_pressed(KeyCode::Escape);
+    }
+    // bundna knappar ska inte skjuta eller gå medan man skriver
+    if let Some(mut actions) = actions {
+        if state.is_changed() {
+            actions.set_blocked("console", state.open);
+        }
+    }
+}
+
+fn console_ui(
+    mut contexts: EguiContexts,
+    mut state: ResMut<ConsoleState>,
+    mut log: ResMut<ConsoleLog>,
+    registry: Res<CommandRegistry>,
+    config: Res<GameConfig>,
+    mut queue: ResMut<ConsoleQueue>,
+) {
+    if !state.open {
+        return;
+    }
+    let ctx = contexts.ctx_mut();
+    let input_id = egui::Id::new("console_input");
+
+    egui::Window::new("Console")
+        .default_size([640.0, 400.0])
+        .collapsible(false)
+        .show(ctx, |ui| {
+            let log_height = ui.available_height() - 48.0;
+            egui::ScrollArea::vertical()
+                .max_height(log_height)
+                .stick_to_bottom(true)
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    for line in log.lines() {
+                        ui.label(console_line_text(line));
+                    }
+                });
+
+            if !state.suggestions.is_empty() {
+                ui.label(
+                    egui::RichText::new(state.suggestions.join("  "))
+                        .small()
+                        .color(egui::Color32::GRAY),
+                );
+            }
+
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut state.input)
+                    .id(input_id)
+                    .desired_width(f32::INFINITY)
+                    .lock_focus(true),
+            );
+            // tangenten som öppnar konsolen ska inte hamna i textfältet
+            if state.input.contains('`') {
+                state.input.retain(|c| c != '`');
+            }
+            response.request_focus();
+
+            let (enter, tab, up, down) = ui.input(|i| {
+                (
+                    i.key_pressed(egui::Key::Enter),
+                    i.key_pressed(egui::Key::Tab),
+                    i.key_pressed(egui::Key::ArrowUp),
+                    i.key_pressed(egui::Key::ArrowDown),
+                )
+            });
+
+            if enter {
+                submit(&mut state, &mut log, &mut queue);
+            } else if tab {
+                complete(&mut state, &registry, &config);
+                move_cursor_to_end(ui.ctx(), input_id, &state.input);
+            } else if up || down {
+                browse_history(&mut state, up);
+                move_cursor_to_end(ui.ctx(), input_id, &state.input);
+            } else if response.changed() {
+                state.suggestions.clear();
+                state.history_pos = None;
+            }
+        });
+}
+
+fn console_line_text(line: &ConsoleLine) -> egui::RichText {
+    let (text, color) = match line.level {
+        ConsoleLevel::Input => (format!("] {}", line.text), egui::Color32::from_rgb(160, 200, 255)),
+        ConsoleLevel::Info => (line.text.clone(), egui::Color32::from_rgb(220, 220, 220)),
+        ConsoleLevel::Warn => (line.text.clone(), egui::Color32::from_rgb(240, 200, 80)),
+        ConsoleLevel::Error => (line.text.clone(), egui::Color32::from_rgb(240, 90, 90)),
+    };
+    egui::RichText::new(text).monospace().color(color)
+}
+
+fn submit(state: &mut ConsoleState, log: &mut ConsoleLog, queue: &mut ConsoleQueue) {
+    let line = std::mem::take(&mut state.input);
+    state.suggestions.clear();
+    state.history_pos = None;
+    if line.trim().is_empty() {
+        return;
+    }
+    if state.history.last() != Some(&line) {
+        state.history.push(line.clone());
+        if state.history.len() > MAX_HISTORY {
+            state.history.remove(0);
+        }
+    }
+    log.push(ConsoleLevel::Input, &line);
+    queue.0.push(line);
+}
+
+/// Completes the last statement on the line, like `sv_ch` -> `sv_cheats `.
+fn complete(state: &mut ConsoleState, registry: &CommandRegistry, config: &GameConfig) {
+    let start = state.input.rfind(';').map(|i| i + 1).unwrap_or(0);
+    let statement = state.input[start..].trim_start();
+    if statement.contains(' ') {
+        return;
+    }
+    let matches = completions(registry, config, statement);
+    let head = state.input[..state.input.len() - statement.len()].to_string();
+
+    match matches.as_slice() {
+        [] => state.suggestions.clear(),
+        [only] => {
+            state.input = format!("{head}{only} ");
+            state.suggestions.clear();
+        }
+        _ => {
+            state.input = format!("{head}{}", common_prefix(&matches));
+            state.suggestions = matches;
+        }
+    }
+}
+
+fn browse_history(state: &mut ConsoleState, up: bool) {
+    if state.history.is_empty() {
+        return;
+    }
+    let last = state.history.len() - 1;
+    state.history_pos = match (state.history_pos, up) {
+        (None, true) => Some(last),
+        (None, false) => None,
+        (Some(i), true) => Some(i.saturating_sub(1)),
+        (Some(i), false) if i < last => Some(i + 1),
+        (Some(_), false) => None,
+    };
+    state.input = state
+        .history_pos
+        .map(|i| state.history[i].clone())
+        .unwrap_or_default();
+}
+
+fn move_cursor_to_end(ctx: &egui::Context, id: egui::Id, text: &str) {
+    if let Some(mut edit_state) = egui::TextEdit::load_state(ctx, id) {
+        let end = egui::text::CCursor::new(text.chars().count());
+        edit_state
+            .cursor
+            .set_char_range(Some(egui::text::CCursorRange::one(end)));
+        edit_state.store(ctx, id);
+    }
+}
+
+/// Log lines captured by [`console_log_layer`], waiting to be copied into the console.
+#[derive(Resource)]
+struct LogMirror(Mutex<Receiver<ConsoleLine>>);
+
+/// Mirrors `info!`, `warn!` and `error!` output into the console.
+/// Pass it as `LogPlugin::custom_layer` when adding `DefaultPlugins`.
+pub fn console_log_layer(app: &mut App) -> Option<BoxedLayer> {
+    let (sender, receiver) = channel();
+    app.insert_resource(LogMirror(Mutex::new(receiver)));
+    Some(Box::new(ConsoleLogLayer { sender }))
+}
+
+fn mirror_log_lines(mirror: Option<Res<LogMirror>>, mut log: ResMut<ConsoleLog>) {
+    let Some(mirror) = mirror else {
+        return;
+    };
+    let Ok(receiver) = mirror.0.lock() else {
+        return;
+    };
+    for line in receiver.try_iter() {
+        log.push(line.level, &line.text);
+    }
+}
+
+struct ConsoleLogLayer {
+    sender: Sender<ConsoleLine>,
+}
+
+impl<S: Subscriber> Layer<S> for ConsoleLogLayer {
+    fn on_event(&self, event: &TracingEvent<'_>, _ctx: Context<'_, S>) {
+        let level = match *event.metadata().level() {
+            Level::ERROR => ConsoleLevel::Error,
+            Level::WARN => ConsoleLevel::Warn,
+            Level::INFO => ConsoleLevel::Info,
+            _ => return,
+        };
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let _ = self.sender.send(ConsoleLine {
+            level,
+            text: message.0,
+        });
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
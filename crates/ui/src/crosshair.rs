@@ -0,0 +1,384 @@
+//! Crosshair settings and renderer.
+//!
+//! Every setting is a cvar, so the crosshair can be tuned from the options menu or the
+//! console. `crosshair_export` prints a share code holding all of them and
+//! `crosshair_import` applies one.
+use std::fmt;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use shared::config::GameConfig;
+use shared::console::RegisterCommandExt;
+use shared::cvars::{CvarDef, CvarError, CvarFlags, RegisterCvarExt};
+use shared::game_state::{GameStatePlugin, LocalPlayerState};
+use shared::types::InGameMenu;
+
+pub const CROSSHAIR_COLORS: &[&str] = &["green", "yellow", "blue", "cyan", "red", "white"];
+pub const CROSSHAIR_STYLES: &[&str] = &["static", "dynamic"];
+
+pub struct CrosshairPlugin;
+
+impl Plugin for CrosshairPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<GameStatePlugin>() {
+            app.add_plugins(GameStatePlugin);
+        }
+        app.register_cvar(
+            CvarDef::enumeration("cl_crosshairstyle", CROSSHAIR_STYLES, "static")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Static, or dynamic to open up with the weapon's spread"),
+        )
+        .register_cvar(
+            CvarDef::float("cl_crosshairsize", 5.0)
+                .range(0.5, 20.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Length of the crosshair lines"),
+        )
+        .register_cvar(
+            CvarDef::float("cl_crosshairgap", 0.0)
+                .range(-5.0, 10.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Space between the crosshair lines and the center"),
+        )
+        .register_cvar(
+            CvarDef::float("cl_crosshairthickness", 1.0)
+                .range(0.5, 6.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Thickness of the crosshair lines"),
+        )
+        .register_cvar(
+            CvarDef::bool("cl_crosshair_drawoutline", true)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Draw a black outline around the crosshair"),
+        )
+        .register_cvar(
+            CvarDef::float("cl_crosshair_outlinethickness", 1.0)
+                .range(0.5, 3.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Thickness of the crosshair outline"),
+        )
+        .register_cvar(
+            CvarDef::bool("cl_crosshairdot", false)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Draw a dot in the middle of the crosshair"),
+        )
+        .register_cvar(
+            CvarDef::enumeration("cl_crosshaircolor", CROSSHAIR_COLORS, "green")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Crosshair color"),
+        )
+        .register_cvar(
+            CvarDef::int("cl_crosshairalpha", 200)
+                .int_range(0, 255)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Crosshair opacity, 0-255"),
+        )
+        .register_console_command(
+            "crosshair_export",
+            "Print a share code for the current crosshair",
+            cmd_crosshair_export,
+        )
+        .register_console_command(
+            "crosshair_import",
+            "Apply a crosshair share code: crosshair_import <code>",
+            cmd_crosshair_import,
+        )
+        .add_systems(Update, draw_crosshair.run_if(in_state(InGameMenu::Closed)));
+    }
+}
+
+/// All crosshair cvars in one place. Lengths are in steps of half a point, which is also
+/// the precision a share code keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrosshairSettings {
+    pub dynamic: bool,
+    pub size: f32,
+    pub gap: f32,
+    pub thickness: f32,
+    pub outline: bool,
+    pub outline_thickness: f32,
+    pub dot: bool,
+    /// Index into `CROSSHAIR_COLORS`.
+    pub color: usize,
+    pub alpha: u8,
+}
+
+impl CrosshairSettings {
+    pub fn from_config(config: &GameConfig) -> Self {
+        CrosshairSettings {
+            dynamic: config.get_str("cl_crosshairstyle") == Some("dynamic"),
+            size: config.get_float("cl_crosshairsize").unwrap_or(5.0),
+            gap: config.get_float("cl_crosshairgap").unwrap_or(0.0),
+            thickness: config.get_float("cl_crosshairthickness").unwrap_or(1.0),
+            outline: config.get_bool("cl_crosshair_drawoutline").unwrap_or(true),
+            outline_thickness: config.get_float("cl_crosshair_outlinethickness").unwrap_or(1.0),
+            dot: config.get_bool("cl_crosshairdot").unwrap_or(false),
+            color: config
+                .get_str("cl_crosshaircolor")
+                .and_then(|c| CROSSHAIR_COLORS.iter().position(|name| *name == c))
+                .unwrap_or(0),
+            alpha: config.get_int("cl_crosshairalpha").unwrap_or(200).clamp(0, 255) as u8,
+        }
+    }
+
+    /// Writes every setting to its cvar. Nothing is written unless every value is valid.
+    pub fn apply(&self, config: &mut GameConfig) -> Result<(), CvarError> {
+        let flag = |b: bool| if b { "1" } else { "0" }.to_string();
+        let values = [
+            ("cl_crosshairstyle", CROSSHAIR_STYLES[self.dynamic as usize].to_string()),
+            ("cl_crosshairsize", self.size.to_string()),
+            ("cl_crosshairgap", self.gap.to_string()),
+            ("cl_crosshairthickness", self.thickness.to_string()),
+            ("cl_crosshair_drawoutline", flag(self.outline)),
+            ("cl_crosshair_outlinethickness", self.outline_thickness.to_string()),
+            ("cl_crosshairdot", flag(self.dot)),
+            ("cl_crosshaircolor", CROSSHAIR_COLORS.get(self.color).copied().unwrap_or_default().to_string()),
+            ("cl_crosshairalpha", self.alpha.to_string()),
+        ];
+        // en kod med för stor storlek ska inte lämna halva inställningarna ändrade
+        for (name, value) in &values {
+            let def = config.def(name).ok_or_else(|| CvarError::Unknown(name.to_string()))?;
+            def.kind.parse(name, value)?;
+        }
+        for (name, value) in values {
+            config.set(name, &value)?;
+        }
+        Ok(())
+    }
+
+    pub fn color32(&self) -> egui::Color32 {
+        let (r, g, b) = match CROSSHAIR_COLORS[self.color] {
+            "yellow" => (250, 250, 50),
+            "blue" => (50, 110, 250),
+            "cyan" => (50, 250, 250),
+            "red" => (250, 50, 50),
+            "white" => (255, 255, 255),
+            _ => (50, 250, 50),
+        };
+        egui::Color32::from_rgba_unmultiplied(r, g, b, self.alpha)
+    }
+
+    /// Compact code like `CH-XXXXX-XXXXX-XXXXX` holding every setting.
+    pub fn to_share_code(&self) -> String {
+        let half_steps = |v: f32, min: f32| ((v - min) * 2.0).round().clamp(0.0, 255.0) as u8;
+        let flags = self.dynamic as u8 | (self.outline as u8) << 1 | (self.dot as u8) << 2;
+        let mut bytes = [
+            SHARE_CODE_VERSION,
+            flags,
+            self.color as u8,
+            self.alpha,
+            half_steps(self.size, 0.0),
+            half_steps(self.gap, GAP_MIN),
+            half_steps(self.thickness, 0.0),
+            half_steps(self.outline_thickness, 0.0),
+            0,
+        ];
+        bytes[8] = checksum(&bytes[..8]);
+
+        // 72 bitar, utfyllda till 75 så de blir 15 tecken
+        let bits = bytes.iter().fold(0u128, |acc, b| acc << 8 | *b as u128) << 3;
+        let chars: Vec<char> = (0..15)
+            .rev()
+            .map(|i| SHARE_CODE_ALPHABET[(bits >> (i * 5)) as usize & 31] as char)
+            .collect();
+        let groups: Vec<String> = chars.chunks(5).map(|c| c.iter().collect()).collect();
+        format!("CH-{}", groups.join("-"))
+    }
+
+    pub fn from_share_code(code: &str) -> Result<Self, ShareCodeError> {
+        let code = code.trim();
+        let body = code
+            .strip_prefix("CH-")
+            .or_else(|| code.strip_prefix("ch-"))
+            .unwrap_or(code);
+        let mut bits = 0u128;
+        let mut count = 0;
+        for c in body.chars().filter(|c| *c != '-') {
+            // samma förväxlingsbara tecken som Crockford base32 tillåter
+            let c = match c.to_ascii_uppercase() {
+                'O' => '0',
+                'I' | 'L' => '1',
+                c => c,
+            };
+            let value = SHARE_CODE_ALPHABET
+                .iter()
+                .position(|a| *a as char == c)
+                .ok_or(ShareCodeError::Malformed)?;
+            bits = bits << 5 | value as u128;
+            count += 1;
+        }
+        if count != 15 || bits & 0b111 != 0 {
+            return Err(ShareCodeError::Malformed);
+        }
+        let bits = bits >> 3;
+        let bytes: Vec<u8> = (0..9).rev().map(|i| (bits >> (i * 8)) as u8).collect();
+
+        if checksum(&bytes[..8]) != bytes[8] {
+            return Err(ShareCodeError::Checksum);
+        }
+        if bytes[0] != SHARE_CODE_VERSION {
+            return Err(ShareCodeError::Version(bytes[0]));
+        }
+        if bytes[2] as usize >= CROSSHAIR_COLORS.len() {
+            return Err(ShareCodeError::Malformed);
+        }
+        let from_half_steps = |b: u8, min: f32| b as f32 / 2.0 + min;
+        Ok(CrosshairSettings {
+            dynamic: bytes[1] & 1 != 0,
+            outline: bytes[1] & 2 != 0,
+            dot: bytes[1] & 4 != 0,
+            color: bytes[2] as usize,
+            alpha: bytes[3],
+            size: from_half_steps(bytes[4], 0.0),
+            gap: from_half_steps(bytes[5], GAP_MIN),
+            thickness: from_half_steps(bytes[6], 0.0),
+            outline_thickness: from_half_steps(bytes[7], 0.0),
+        })
+    }
+}
+
+const SHARE_CODE_VERSION: u8 = 1;
+const SHARE_CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const GAP_MIN: f32 = -5.0;
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0x5a, |acc: u8, b| acc.rotate_left(3) ^ b)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareCodeError {
+    Malformed,
+    Checksum,
+    Version(u8),
+}
+
+impl fmt::Display for ShareCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShareCodeError::Malformed => write!(f, "not a crosshair code"),
+            ShareCodeError::Checksum => write!(f, "the code has a typo in it"),
+            ShareCodeError::Version(v) => write!(f, "crosshair code version {v} isn't supported"),
+        }
+    }
+}
+
+impl std::error::Error for ShareCodeError {}
+
+fn cmd_crosshair_export(world: &mut World, _args: &[String]) -> Result<String, String> {
+    Ok(CrosshairSettings::from_config(world.resource::<GameConfig>()).to_share_code())
+}
+
+fn cmd_crosshair_import(world: &mut World, args: &[String]) -> Result<String, String> {
+    let code = args.first().ok_or("usage: crosshair_import <code>")?;
+    let settings = CrosshairSettings::from_share_code(code).map_err(|err| err.to_string())?;
+    let mut config = world.resource_mut::<GameConfig>();
+    settings.apply(&mut config).map_err(|err| err.to_string())?;
+    config.save();
+    Ok("crosshair applied".into())
+}
+
+fn draw_crosshair(
+    mut contexts: EguiContexts,
+    config: Res<GameConfig>,
+    player: Res<LocalPlayerState>,
+    cameras: Query<&Projection, With<Camera3d>>,
+) {
+    if !player.alive {
+        return;
+    }
+    let settings = CrosshairSettings::from_config(&config);
+    let ctx = contexts.ctx_mut();
+    let screen = ctx.screen_rect();
+    let center = screen.center();
+
+    let mut gap = (4.0 + settings.gap * 2.0).max(0.0);
+    if settings.dynamic {
+        // spridningen i grader omräknad till punkter på skärmen
+        if let Ok(Projection::Perspective(perspective)) = cameras.get_single() {
+            let spread = player.inaccuracy.to_radians().tan() / (perspective.fov / 2.0).tan();
+            gap += spread * screen.height() / 2.0;
+        }
+    }
+    let length = settings.size * 2.0;
+    let thickness = settings.thickness * 2.0;
+    let half = thickness / 2.0;
+
+    let mut rects = vec![
+        egui::Rect::from_min_max(center + egui::vec2(gap, -half), center + egui::vec2(gap + length, half)),
+        egui::Rect::from_min_max(center + egui::vec2(-gap - length, -half), center + egui::vec2(-gap, half)),
+        egui::Rect::from_min_max(center + egui::vec2(-half, gap), center + egui::vec2(half, gap + length)),
+        egui::Rect::from_min_max(center + egui::vec2(-half, -gap - length), center + egui::vec2(half, -gap)),
+    ];
+    if settings.dot {
+        rects.push(egui::Rect::from_center_size(center, egui::vec2(thickness, thickness)));
+    }
+
+    let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("crosshair")));
+    if settings.outline {
+        let outline = egui::Color32::from_black_alpha(settings.alpha);
+        for rect in &rects {
+            painter.rect_filled(rect.expand(settings.outline_thickness), 0.0, outline);
+        }
+    }
+    for rect in rects {
+        painter.rect_filled(rect, 0.0, settings.color32());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> CrosshairSettings {
+        CrosshairSettings {
+            dynamic: true,
+            size: 3.5,
+            gap: -1.5,
+            thickness: 0.5,
+            outline: false,
+            outline_thickness: 2.0,
+            dot: true,
+            color: 4,
+            alpha: 180,
+        }
+    }
+
+    fn config() -> GameConfig {
+        let mut app = App::new();
+        app.insert_resource(GameConfig::default()).add_plugins(CrosshairPlugin);
+        app.world_mut().remove_resource::<GameConfig>().unwrap()
+    }
+
+    #[test]
+    fn share_codes_round_trip() {
+        let code = settings().to_share_code();
+        assert_eq!(code.len(), "CH-XXXXX-XXXXX-XXXXX".len());
+        assert_eq!(CrosshairSettings::from_share_code(&code), Ok(settings()));
+        // gemener och förväxlingsbara tecken går också
+        let sloppy = code.to_lowercase().replace('0', "o").replace('1', "l");
+        assert_eq!(CrosshairSettings::from_share_code(&sloppy), Ok(settings()));
+    }
+
+    #[test]
+    fn typos_fail_the_checksum() {
+        let code = settings().to_share_code();
+        let mut chars: Vec<char> = code.chars().collect();
+        chars[4] = if chars[4] == 'Z' { 'Y' } else { 'Z' };
+        let typo: String = chars.into_iter().collect();
+        assert_eq!(CrosshairSettings::from_share_code(&typo), Err(ShareCodeError::Checksum));
+        assert_eq!(CrosshairSettings::from_share_code("CH-12345"), Err(ShareCodeError::Malformed));
+        assert_eq!(CrosshairSettings::from_share_code("CH-UUUUU-UUUUU-UUUUU"), Err(ShareCodeError::Malformed));
+    }
+
+    #[test]
+    fn apply_writes_everything_or_nothing() {
+        let mut config = config();
+        settings().apply(&mut config).unwrap();
+        assert_eq!(CrosshairSettings::from_config(&config), settings());
+
+        // en kod kan ha en storlek utanför cvarens gränser
+        let too_big = CrosshairSettings { size: 100.0, dynamic: false, ..settings() };
+        assert!(too_big.apply(&mut config).is_err());
+        assert_eq!(CrosshairSettings::from_config(&config), settings());
+    }
+}
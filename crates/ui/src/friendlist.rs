@@ -1,8 +1,60 @@
 use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use net::browser::{JoinServer, ServerBrowserPlugin};
+use net::profile::{Friend, FriendStatus, Presence, ProfileClient, ProfilePlugin, ProfileRequest};
+use shared::lobby::{Lobby, LobbyRequest};
+use shared::AppState;
+
+pub struct FriendListPlugin;
+
+impl Plugin for FriendListPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ProfilePlugin>() {
+            app.add_plugins(ProfilePlugin);
+        }
+        if !app.is_plugin_added::<ServerBrowserPlugin>() {
+            app.add_plugins(ServerBrowserPlugin);
+        }
+        app.add_systems(
+            Update,
+            (
+                update_friendlist,
+                friend_button_interactions,
+                party_invite_popup.run_if(not(in_state(AppState::InGame))),
+            ),
+        );
+    }
+}
 
 #[derive(Component)]
 pub struct FriendListRoot;
 
+#[derive(Component)]
+struct FriendListHeader;
+
+/// Raderna med vänner, byggs om när profilen eller lobbyn ändras
+#[derive(Component)]
+struct FriendListEntries {
+    font: Handle<Font>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FriendAction {
+    Accept,
+    /// Tar bort vännen eller avböjer/drar tillbaka en förfrågan
+    Remove,
+    Invite,
+    /// Går med i spelet vännen är i
+    Join(JoinServer),
+    LeaveParty,
+}
+
+#[derive(Component)]
+struct FriendButton {
+    friend: u64,
+    action: FriendAction,
+}
+
 pub fn spawn_friendlist(
     parent: &mut ChildBuilder,
     font_bold: &Handle<Font>,
@@ -23,24 +75,231 @@ pub fn spawn_friendlist(
             FriendListRoot,
         ))
         .with_children(|col| {
-            col.spawn(TextBundle::from_section(
-                "Friends Online (3)",
+            col.spawn((
+                TextBundle::from_section(
+                    "Friends",
+                    TextStyle {
+                        font: font_bold.clone(),
+                        font_size: 16.0,
+                        color: Color::srgb(0.9, 0.9, 0.9),
+                    },
+                ),
+                FriendListHeader,
+            ));
+
+            col.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(4.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                FriendListEntries { font: font_regular.clone() },
+            ));
+        });
+}
+
+/// Förfrågningar först, sedan de som är online, offline och sist våra obesvarade
+fn sort_key(friend: &Friend) -> (u8, String) {
+    let group = match (friend.status, friend.presence) {
+        (FriendStatus::Incoming, _) => 0,
+        (FriendStatus::Accepted, Presence::InGame { .. }) => 1,
+        (FriendStatus::Accepted, Presence::InMenus) => 2,
+        (FriendStatus::Accepted, Presence::Offline) => 3,
+        (FriendStatus::Outgoing, _) => 4,
+    };
+    (group, friend.name.to_lowercase())
+}
+
+fn friend_actions(friend: &Friend, lobby: &Lobby) -> Vec<(&'static str, FriendAction)> {
+    match (friend.status, friend.presence) {
+        (FriendStatus::Incoming, _) => vec![("ACCEPT", FriendAction::Accept), ("DECLINE", FriendAction::Remove)],
+        (FriendStatus::Outgoing, _) => vec![("CANCEL", FriendAction::Remove)],
+        (FriendStatus::Accepted, Presence::Offline) => Vec::new(),
+        (FriendStatus::Accepted, Presence::InGame { map, server: Some(addr) }) => {
+            vec![("JOIN", FriendAction::Join(JoinServer { addr, map, remember: true, ticket: None }))]
+        }
+        (FriendStatus::Accepted, _) if lobby.member(friend.player_id).is_none() => {
+            vec![("INVITE", FriendAction::Invite)]
+        }
+        (FriendStatus::Accepted, _) => Vec::new(),
+    }
+}
+
+fn presence_label(friend: &Friend) -> (String, Color) {
+    match (friend.status, friend.presence) {
+        (FriendStatus::Incoming, _) => ("Wants to be friends".into(), Color::srgb(0.9, 0.75, 0.3)),
+        (FriendStatus::Outgoing, _) => ("Request sent".into(), Color::srgb(0.5, 0.5, 0.5)),
+        (_, presence @ Presence::InGame { .. }) => (presence.to_string(), Color::srgb(0.45, 0.8, 0.35)),
+        (_, presence @ Presence::InMenus) => (presence.to_string(), Color::srgb(0.45, 0.7, 0.95)),
+        (_, presence @ Presence::Offline) => (presence.to_string(), Color::srgb(0.5, 0.5, 0.5)),
+    }
+}
+
+fn spawn_small_button(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, button: FriendButton) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    padding: UiRect::axes(Val::Px(6.0), Val::Px(2.0)),
+                    ..default()
+                },
+                background_color: Color::srgba(0.2, 0.4, 0.7, 0.8).into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|btn| {
+            btn.spawn(TextBundle::from_section(
+                label,
                 TextStyle {
-                    font: font_bold.clone(),
-                    font_size: 16.0,
-                    color: Color::srgb(0.9, 0.9, 0.9),
+                    font: font.clone(),
+                    font_size: 12.0,
+                    color: Color::WHITE,
                 },
             ));
+        });
+}
 
-            for friend in ["Alice", "Bob", "Charlie"] {
+fn update_friendlist(
+    mut commands: Commands,
+    profiles: Res<ProfileClient>,
+    lobby: Option<Res<Lobby>>,
+    mut headers: Query<&mut Text, With<FriendListHeader>>,
+    entries: Query<(Entity, &FriendListEntries)>,
+    spawned: Query<(), Added<FriendListEntries>>,
+) {
+    let lobby_changed = lobby.as_ref().is_some_and(|l| l.is_changed());
+    if !profiles.is_changed() && !lobby_changed && spawned.is_empty() {
+        return;
+    }
+    let Some(lobby) = lobby else {
+        return;
+    };
+    let accepted = profiles.friends.iter().filter(|f| f.status == FriendStatus::Accepted);
+    let online = accepted.clone().filter(|f| f.presence != Presence::Offline).count();
+    for mut text in &mut headers {
+        text.sections[0].value = format!("Friends ({online}/{})", accepted.clone().count());
+    }
+
+    let mut friends: Vec<&Friend> = profiles.friends.iter().collect();
+    friends.sort_by_key(|f| sort_key(f));
+    for (entity, list) in &entries {
+        commands.entity(entity).despawn_descendants().with_children(|col| {
+            if lobby.is_remote() {
+                let leave = FriendButton { friend: 0, action: FriendAction::LeaveParty };
+                spawn_small_button(col, &list.font, "LEAVE PARTY", leave);
+            }
+            if friends.is_empty() {
                 col.spawn(TextBundle::from_section(
-                    friend,
+                    "No friends yet, add some with friend_add <name>",
                     TextStyle {
-                        font: font_regular.clone(),
+                        font: list.font.clone(),
                         font_size: 14.0,
-                        color: Color::srgb(0.8, 0.8, 0.8),
+                        color: Color::srgb(0.5, 0.5, 0.5),
                     },
                 ));
             }
+            for friend in &friends {
+                let name = if friend.name.is_empty() { format!("#{}", friend.player_id) } else { friend.name.clone() };
+                let (status, status_color) = presence_label(friend);
+                col.spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(6.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    row.spawn(TextBundle::from_sections([
+                        TextSection::new(
+                            name + "\n",
+                            TextStyle {
+                                font: list.font.clone(),
+                                font_size: 14.0,
+                                color: Color::srgb(0.8, 0.8, 0.8),
+                            },
+                        ),
+                        TextSection::new(
+                            status,
+                            TextStyle {
+                                font: list.font.clone(),
+                                font_size: 11.0,
+                                color: status_color,
+                            },
+                        ),
+                    ]));
+                    for (label, action) in friend_actions(friend, &lobby) {
+                        spawn_small_button(row, &list.font, label, FriendButton { friend: friend.player_id, action });
+                    }
+                });
+            }
+        });
+    }
+}
+
+fn friend_button_interactions(
+    q: Query<(&Interaction, &FriendButton), Changed<Interaction>>,
+    mut requests: EventWriter<ProfileRequest>,
+    mut lobby_requests: EventWriter<LobbyRequest>,
+    mut joins: EventWriter<JoinServer>,
+) {
+    for (interaction, button) in &q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button.action {
+            FriendAction::Accept => {
+                requests.send(ProfileRequest::AcceptFriend(button.friend));
+            }
+            FriendAction::Remove => {
+                requests.send(ProfileRequest::RemoveFriend(button.friend));
+            }
+            FriendAction::Invite => {
+                requests.send(ProfileRequest::InviteToParty(button.friend));
+            }
+            FriendAction::Join(join) => {
+                joins.send(join);
+            }
+            FriendAction::LeaveParty => {
+                lobby_requests.send(LobbyRequest::Leave);
+            }
+        }
+    }
+}
+
+/// Join/decline window for the oldest party invite.
+fn party_invite_popup(
+    mut contexts: EguiContexts,
+    profiles: Res<ProfileClient>,
+    state: Res<State<AppState>>,
+    mut requests: EventWriter<ProfileRequest>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let Some(invite) = profiles.invites.first() else {
+        return;
+    };
+    egui::Window::new("PARTY INVITE")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-16.0, -16.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!("{} invited you to their party", invite.name));
+            ui.horizontal(|ui| {
+                if ui.button("JOIN").clicked() {
+                    requests.send(ProfileRequest::JoinParty(invite.from));
+                    // partyts lobby visas i spelmenyn
+                    if *state.get() != AppState::PlayMenu {
+                        next_state.set(AppState::PlayMenu);
+                    }
+                }
+                if ui.button("DECLINE").clicked() {
+                    requests.send(ProfileRequest::DeclineInvite(invite.from));
+                }
+            });
         });
 }
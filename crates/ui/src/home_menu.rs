@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use net::profile::{ProfileClient, ProfilePlugin};
 use shared::AppState;
 
 use crate::playerbox::spawn_playerbox;
@@ -8,14 +9,25 @@ pub struct HomeMenuPlugin;
 
 impl Plugin for HomeMenuPlugin {
     fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ProfilePlugin>() {
+            app.add_plugins(ProfilePlugin);
+        }
         app.add_systems(OnEnter(AppState::MainMenu), spawn_home_menu)
-           .add_systems(OnExit(AppState::MainMenu), cleanup_home_menu);
+           .add_systems(OnExit(AppState::MainMenu), cleanup_home_menu)
+           .add_systems(Update, update_career_stats.run_if(in_state(AppState::MainMenu)));
     }
 }
 
 #[derive(Component)]
 struct HomeMenuRoot;
 
+/// Texten i statistikpanelen, fylls i från profilen
+#[derive(Component)]
+struct CareerStatsText;
+
+/// Matches listed under the stats.
+const RECENT_MATCHES: usize = 5;
+
 fn spawn_home_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
     let font_bold = asset_server.load("fonts/Inter-Bold.ttf");
     let font_regular = asset_server.load("fonts/Inter-Bold.ttf");
@@ -101,13 +113,7 @@ fn spawn_home_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
             ..default()
         })
         .with_children(|right| {
-            spawn_panel(
-                right,
-                &font_bold,
-                &font_regular,
-                "Statistics",
-                "Unique Players Last Month:\n10,451,389",
-            );
+            spawn_panel_with(right, &font_bold, &font_regular, "Your Statistics", "Loading...", CareerStatsText);
 
             spawn_panel(
                 right,
@@ -135,6 +141,18 @@ fn spawn_panel(
     font_regular: &Handle<Font>,
     title: &str,
     body: &str,
+) {
+    spawn_panel_with(parent, font_bold, font_regular, title, body, ());
+}
+
+/// Som `spawn_panel`, med `marker` på brödtexten så att den kan uppdateras
+fn spawn_panel_with(
+    parent: &mut ChildBuilder,
+    font_bold: &Handle<Font>,
+    font_regular: &Handle<Font>,
+    title: &str,
+    body: &str,
+    marker: impl Bundle,
 ) {
     parent
         .spawn(NodeBundle {
@@ -156,17 +174,58 @@ fn spawn_panel(
                     color: Color::srgb(0.9, 0.9, 0.9),
                 },
             ));
-            panel.spawn(TextBundle::from_section(
-                body,
-                TextStyle {
-                    font: font_regular.clone(),
-                    font_size: 14.0,
-                    color: Color::srgb(0.8, 0.8, 0.8),
-                },
+            panel.spawn((
+                TextBundle::from_section(
+                    body,
+                    TextStyle {
+                        font: font_regular.clone(),
+                        font_size: 14.0,
+                        color: Color::srgb(0.8, 0.8, 0.8),
+                    },
+                ),
+                marker,
             ));
         });
 }
 
+fn update_career_stats(
+    profiles: Res<ProfileClient>,
+    mut texts: Query<&mut Text, With<CareerStatsText>>,
+    spawned: Query<(), Added<CareerStatsText>>,
+) {
+    if !profiles.is_changed() && spawned.is_empty() {
+        return;
+    }
+    let Some(profile) = &profiles.profile else {
+        return;
+    };
+    let stats = profile.stats;
+    let mut body = format!(
+        "Matches: {}\nWins: {}  Losses: {}  Draws: {}\nRounds: {} won, {} lost",
+        stats.matches, stats.wins, stats.losses, stats.draws, stats.rounds_won, stats.rounds_lost
+    );
+    if !profiles.matches.is_empty() {
+        body.push_str("\n\nRecent matches:");
+    }
+    for record in profiles.matches.iter().take(RECENT_MATCHES) {
+        let result = match record.rounds_won.cmp(&record.rounds_lost) {
+            std::cmp::Ordering::Greater => "W",
+            std::cmp::Ordering::Less => "L",
+            std::cmp::Ordering::Equal => "D",
+        };
+        body.push_str(&format!(
+            "\n{result} {}-{}  {} on {}",
+            record.rounds_won,
+            record.rounds_lost,
+            record.mode.label(),
+            record.map.label()
+        ));
+    }
+    for mut text in &mut texts {
+        text.sections[0].value.clone_from(&body);
+    }
+}
+
 fn cleanup_home_menu(mut commands: Commands, q: Query<Entity, With<HomeMenuRoot>>) {
     for e in &q {
         commands.entity(e).despawn_recursive();
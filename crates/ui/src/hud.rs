@@ -1,19 +1,440 @@
+//! In-game HUD, drawn with egui from the match state in `shared::game_state`.
+use std::collections::VecDeque;
+
 use bevy::prelude::*;
-use bevy_egui::{EguiContexts, egui};
+use bevy_egui::{egui, EguiContexts};
+use shared::config::GameConfig;
+use shared::cvars::{CvarDef, CvarFlags, RegisterCvarExt};
+use shared::game_state::{
+    BombStatus, DamageTaken, GameStatePlugin, HitConfirmed, KillEvent, LocalPlayerState, RoundPhase,
+    RoundState, Team,
+};
+use shared::types::InGameMenu;
+use shared::AppState;
+
+pub const HUD_COLORS: &[&str] = &["white", "green", "yellow", "cyan", "orange"];
+pub const HUD_LAYOUTS: &[&str] = &["classic", "centered"];
+
+const KILL_FEED_SECONDS: f32 = 6.0;
+const MONEY_CHANGE_SECONDS: f32 = 2.0;
+const HIT_MARKER_SECONDS: f32 = 0.25;
+const DAMAGE_INDICATOR_SECONDS: f32 = 1.5;
 
 pub struct HudPlugin;
 
 impl Plugin for HudPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, hud_ui);
+        if !app.is_plugin_added::<GameStatePlugin>() {
+            app.add_plugins(GameStatePlugin);
+        }
+        app.register_cvar(
+            CvarDef::bool("cl_drawhud", true)
+                .description("Draw the HUD"),
+        )
+        .register_cvar(
+            CvarDef::float("hud_scaling", 1.0)
+                .range(0.5, 2.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("HUD size"),
+        )
+        .register_cvar(
+            CvarDef::enumeration("cl_hud_color", HUD_COLORS, "white")
+                .flags(CvarFlags::ARCHIVE)
+                .description("HUD text color"),
+        )
+        .register_cvar(
+            CvarDef::float("cl_hud_background_alpha", 0.5)
+                .range(0.0, 1.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Opacity of the HUD panels"),
+        )
+        .register_cvar(
+            CvarDef::enumeration("cl_hud_layout", HUD_LAYOUTS, "classic")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Health and ammo in the corners or together at the bottom"),
+        )
+        .register_cvar(
+            CvarDef::int("cl_killfeed_lines", 5)
+                .int_range(0, 10)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Kill feed entries shown at once"),
+        )
+        .register_cvar(
+            CvarDef::bool("cl_hitmarkers", true)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Show a marker when your shots hit"),
+        )
+        .init_resource::<HudEffects>()
+        .add_systems(
+            Update,
+            (collect_hud_events, hud_ui)
+                .chain()
+                .run_if(in_state(InGameMenu::Closed)),
+        )
+        .add_systems(OnExit(AppState::InGame), clear_hud_effects);
+    }
+}
+
+/// Kortlivade saker på HUD:en, med hur länge de har visats
+#[derive(Resource, Default)]
+struct HudEffects {
+    kill_feed: VecDeque<(KillEvent, f32)>,
+    money: Option<u32>,
+    money_changes: Vec<(i64, f32)>,
+    hit_marker: Option<(HitConfirmed, f32)>,
+    damage: Vec<(Vec3, f32)>,
+}
+
+fn collect_hud_events(
+    time: Res<Time>,
+    player: Res<LocalPlayerState>,
+    mut kills: EventReader<KillEvent>,
+    mut hits: EventReader<HitConfirmed>,
+    mut damage: EventReader<DamageTaken>,
+    mut effects: ResMut<HudEffects>,
+) {
+    let dt = time.delta_seconds();
+    let effects = &mut *effects;
+
+    for (_, age) in &mut effects.kill_feed {
+        *age += dt;
+    }
+    effects.kill_feed.retain(|(_, age)| *age < KILL_FEED_SECONDS);
+    effects.kill_feed.extend(kills.read().map(|kill| (kill.clone(), 0.0)));
+    while effects.kill_feed.len() > 10 {
+        effects.kill_feed.pop_front();
+    }
+
+    if let Some(previous) = effects.money.replace(player.money) {
+        if previous != player.money {
+            effects.money_changes.push((player.money as i64 - previous as i64, 0.0));
+        }
+    }
+    for (_, age) in &mut effects.money_changes {
+        *age += dt;
+    }
+    effects.money_changes.retain(|(_, age)| *age < MONEY_CHANGE_SECONDS);
+
+    if let Some((_, age)) = &mut effects.hit_marker {
+        *age += dt;
+    }
+    if let Some(hit) = hits.read().last() {
+        effects.hit_marker = Some((*hit, 0.0));
+    }
+    if effects.hit_marker.is_some_and(|(_, age)| age >= HIT_MARKER_SECONDS) {
+        effects.hit_marker = None;
+    }
+
+    for (_, age) in &mut effects.damage {
+        *age += dt;
+    }
+    effects.damage.retain(|(_, age)| *age < DAMAGE_INDICATOR_SECONDS);
+    effects
+        .damage
+        .extend(damage.read().filter_map(|d| d.source).map(|source| (source, 0.0)));
+}
+
+fn clear_hud_effects(mut effects: ResMut<HudEffects>) {
+    *effects = HudEffects::default();
+}
+
+/// HUD-inställningar som läses en gång per frame
+struct HudStyle {
+    scale: f32,
+    color: egui::Color32,
+    background: egui::Color32,
+}
+
+impl HudStyle {
+    fn from_config(config: &GameConfig) -> Self {
+        let color = match config.get_str("cl_hud_color").unwrap_or("white") {
+            "green" => egui::Color32::from_rgb(120, 230, 120),
+            "yellow" => egui::Color32::from_rgb(240, 220, 110),
+            "cyan" => egui::Color32::from_rgb(110, 220, 240),
+            "orange" => egui::Color32::from_rgb(240, 160, 80),
+            _ => egui::Color32::from_rgb(235, 235, 235),
+        };
+        let alpha = config.get_float("cl_hud_background_alpha").unwrap_or(0.5);
+        HudStyle {
+            scale: config.get_float("hud_scaling").unwrap_or(1.0),
+            color,
+            background: egui::Color32::from_black_alpha((alpha * 255.0) as u8),
+        }
+    }
+
+    fn panel(&self) -> egui::Frame {
+        egui::Frame::none()
+            .fill(self.background)
+            .rounding(4.0 * self.scale)
+            .inner_margin(8.0 * self.scale)
+    }
+
+    fn text(&self, text: impl Into<String>, size: f32) -> egui::RichText {
+        egui::RichText::new(text).size(size * self.scale).color(self.color).strong()
+    }
+}
+
+const LOW_HEALTH: egui::Color32 = egui::Color32::from_rgb(230, 70, 60);
+
+pub(crate) fn team_color(team: Team) -> egui::Color32 {
+    match team {
+        Team::CounterTerrorist => egui::Color32::from_rgb(120, 160, 235),
+        Team::Terrorist => egui::Color32::from_rgb(235, 190, 90),
+    }
+}
+
+fn format_clock(seconds: f32) -> String {
+    let seconds = seconds.ceil() as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn hud_ui(
+    mut contexts: EguiContexts,
+    config: Res<GameConfig>,
+    player: Res<LocalPlayerState>,
+    round: Res<RoundState>,
+    effects: Res<HudEffects>,
+    time: Res<Time>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+) {
+    if !config.get_bool("cl_drawhud").unwrap_or(true) {
+        return;
+    }
+    let style = HudStyle::from_config(&config);
+    let ctx = contexts.ctx_mut();
+    let margin = 16.0 * style.scale;
+
+    let vitals = |ui: &mut egui::Ui| {
+        style.panel().show(ui, |ui| {
+            ui.horizontal(|ui| {
+                let health = if player.health <= 20 {
+                    style.text(format!("HP {}", player.health), 28.0).color(LOW_HEALTH)
+                } else {
+                    style.text(format!("HP {}", player.health), 28.0)
+                };
+                ui.label(health);
+                ui.add_space(12.0 * style.scale);
+                let armor = if player.helmet { "AP+H" } else { "AP" };
+                ui.label(style.text(format!("{armor} {}", player.armor), 28.0));
+            });
+        });
+    };
+    let ammo = |ui: &mut egui::Ui| {
+        if player.weapon.is_none() && player.ammo.is_none() {
+            return;
+        }
+        style.panel().show(ui, |ui| {
+            ui.vertical(|ui| {
+                if let Some(weapon) = &player.weapon {
+                    ui.label(style.text(weapon.to_uppercase(), 14.0));
+                }
+                if let Some(ammo) = player.ammo {
+                    ui.horizontal(|ui| {
+                        let magazine = style.text(ammo.magazine.to_string(), 28.0);
+                        // tomt magasin, eller nästan
+                        let magazine = if ammo.magazine * 5 <= ammo.magazine_size {
+                            magazine.color(LOW_HEALTH)
+                        } else {
+                            magazine
+                        };
+                        ui.label(magazine);
+                        ui.label(style.text(format!("/ {}", ammo.reserve), 18.0));
+                    });
+                }
+            });
+        });
+    };
+
+    if player.alive {
+        if config.get_str("cl_hud_layout") == Some("centered") {
+            egui::Area::new(egui::Id::new("hud_bottom"))
+                .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -margin])
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        vitals(ui);
+                        ammo(ui);
+                    });
+                });
+        } else {
+            egui::Area::new(egui::Id::new("hud_vitals"))
+                .anchor(egui::Align2::LEFT_BOTTOM, [margin, -margin])
+                .show(ctx, vitals);
+            egui::Area::new(egui::Id::new("hud_ammo"))
+                .anchor(egui::Align2::RIGHT_BOTTOM, [-margin, -margin])
+                .show(ctx, ammo);
+        }
     }
+
+    // Pengar, med ändringar som bleknar bort under
+    egui::Area::new(egui::Id::new("hud_money"))
+        .anchor(egui::Align2::LEFT_TOP, [margin, margin])
+        .show(ctx, |ui| {
+            style.panel().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(style.text(format!("$ {}", player.money), 22.0));
+                    if player.has_bomb {
+                        ui.label(style.text("C4", 16.0).color(LOW_HEALTH));
+                    }
+                });
+            });
+            for (amount, age) in effects.money_changes.iter().rev() {
+                let fade = 1.0 - age / MONEY_CHANGE_SECONDS;
+                let (text, color) = if *amount >= 0 {
+                    (format!("+${amount}"), egui::Color32::from_rgb(120, 220, 120))
+                } else {
+                    (format!("-${}", -amount), LOW_HEALTH)
+                };
+                ui.label(style.text(text, 16.0).color(color.gamma_multiply(fade)));
+            }
+        });
+
+    round_ui(ctx, &style, &round, time.elapsed_seconds());
+    kill_feed_ui(ctx, &style, &config, &player, &effects);
+
+    let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("hud_markers")));
+    let center = ctx.screen_rect().center();
+    if config.get_bool("cl_hitmarkers").unwrap_or(true) {
+        if let Some((hit, age)) = effects.hit_marker {
+            let color = if hit.killed {
+                LOW_HEALTH
+            } else if hit.headshot {
+                egui::Color32::from_rgb(240, 220, 110)
+            } else {
+                egui::Color32::WHITE
+            };
+            let color = color.gamma_multiply(1.0 - age / HIT_MARKER_SECONDS);
+            let (gap, length) = (6.0 * style.scale, 8.0 * style.scale);
+            for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+                let dir = egui::vec2(x, y).normalized();
+                painter.line_segment(
+                    [center + dir * gap, center + dir * (gap + length)],
+                    egui::Stroke::new(2.0 * style.scale, color),
+                );
+            }
+        }
+    }
+
+    // Skadeindikatorer, en båge runt mitten åt det håll skadan kom ifrån
+    if let Ok(camera) = cameras.get_single() {
+        let forward = camera.forward().xz().normalize_or_zero();
+        let right = camera.right().xz().normalize_or_zero();
+        let radius = 120.0 * style.scale;
+        for (source, age) in &effects.damage {
+            let dir = (*source - camera.translation()).xz();
+            let angle = dir.dot(right).atan2(dir.dot(forward));
+            let color = LOW_HEALTH.gamma_multiply(1.0 - age / DAMAGE_INDICATOR_SECONDS);
+            let points = (0..=8)
+                .map(|i| {
+                    let a = angle + (i as f32 / 8.0 - 0.5) * 0.6;
+                    center + egui::vec2(a.sin(), -a.cos()) * radius
+                })
+                .collect();
+            painter.add(egui::Shape::line(points, egui::Stroke::new(6.0 * style.scale, color)));
+        }
+    }
+}
+
+fn round_ui(ctx: &egui::Context, style: &HudStyle, round: &RoundState, elapsed: f32) {
+    egui::Area::new(egui::Id::new("hud_round"))
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0 * style.scale])
+        .show(ctx, |ui| {
+            style.panel().show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.horizontal(|ui| {
+                        let ct = Team::CounterTerrorist;
+                        let t = Team::Terrorist;
+                        ui.label(style.text(round.score(ct).to_string(), 22.0).color(team_color(ct)));
+                        ui.add_space(10.0 * style.scale);
+                        match round.bomb {
+                            BombStatus::Planted { time_left, .. } => {
+                                // blinkar snabbare när det är ont om tid
+                                let rate = if time_left < 10.0 { 8.0 } else { 3.0 };
+                                let pulse = 0.6 + 0.4 * (elapsed * rate).sin().abs();
+                                ui.label(style.text("BOMB", 22.0).color(LOW_HEALTH.gamma_multiply(pulse)));
+                            }
+                            _ => {
+                                ui.label(style.text(format_clock(round.time_left), 22.0));
+                            }
+                        }
+                        ui.add_space(10.0 * style.scale);
+                        ui.label(style.text(round.score(t).to_string(), 22.0).color(team_color(t)));
+                    });
+
+                    let status = match round.phase {
+                        RoundPhase::Warmup => Some("WARMUP".to_string()),
+                        RoundPhase::FreezeTime => Some("BUY TIME".to_string()),
+                        RoundPhase::Ended(team) => Some(format!("{} WIN", team.label())),
+                        RoundPhase::Live => None,
+                    };
+                    if let Some(status) = status {
+                        ui.label(style.text(status, 12.0));
+                    } else if round.max_rounds > 0 {
+                        ui.label(style.text(format!("ROUND {}/{}", round.round, round.max_rounds), 12.0));
+                    }
+
+                    match round.bomb {
+                        BombStatus::Planted { site, defuse_progress, .. } => {
+                            ui.label(style.text(format!("BOMB PLANTED AT {site}"), 12.0).color(LOW_HEALTH));
+                            if let Some(progress) = defuse_progress {
+                                ui.add(
+                                    egui::ProgressBar::new(progress)
+                                        .desired_width(120.0 * style.scale)
+                                        .text("DEFUSING"),
+                                );
+                            }
+                        }
+                        BombStatus::Dropped => {
+                            ui.label(style.text("BOMB DROPPED", 12.0));
+                        }
+                        BombStatus::Defused => {
+                            ui.label(style.text("BOMB DEFUSED", 12.0).color(team_color(Team::CounterTerrorist)));
+                        }
+                        BombStatus::Exploded | BombStatus::Carried => {}
+                    }
+                });
+            });
+        });
 }
 
-fn hud_ui(mut egui_ctx: EguiContexts) {
-    let ctx = egui_ctx.ctx_mut();
-    egui::Area::new("hud_placeholder")
-        .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -10.0])
+fn kill_feed_ui(
+    ctx: &egui::Context,
+    style: &HudStyle,
+    config: &GameConfig,
+    player: &LocalPlayerState,
+    effects: &HudEffects,
+) {
+    let lines = config.get_int("cl_killfeed_lines").unwrap_or(5).max(0) as usize;
+    let skip = effects.kill_feed.len().saturating_sub(lines);
+    egui::Area::new(egui::Id::new("hud_kill_feed"))
+        .anchor(egui::Align2::RIGHT_TOP, [-16.0 * style.scale, 16.0 * style.scale])
         .show(ctx, |ui| {
-            ui.label("HUD placeholder (health, ammo, etc.)");
+            ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                for (kill, age) in effects.kill_feed.iter().skip(skip) {
+                    // sista sekunden bleknar raden bort
+                    let fade = (KILL_FEED_SECONDS - age).clamp(0.0, 1.0);
+                    let own = kill.killer.as_ref().is_some_and(|killer| killer.player_id == player.player_id);
+                    let mut frame = style.panel().inner_margin(4.0 * style.scale);
+                    if own {
+                        frame = frame.stroke(egui::Stroke::new(1.5, LOW_HEALTH.gamma_multiply(fade)));
+                    }
+                    frame.show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            if let Some(killer) = &kill.killer {
+                                let color = team_color(killer.team).gamma_multiply(fade);
+                                ui.label(style.text(&killer.name, 14.0).color(color));
+                            }
+                            let weapon = if kill.headshot {
+                                format!("[{}] HS", kill.weapon)
+                            } else {
+                                format!("[{}]", kill.weapon)
+                            };
+                            ui.label(style.text(weapon, 14.0).color(style.color.gamma_multiply(fade)));
+                            let victim = &kill.victim;
+                            let color = team_color(victim.team).gamma_multiply(fade);
+                            ui.label(style.text(&victim.name, 14.0).color(color));
+                        });
+                    });
+                }
+            });
         });
 }
@@ -1,23 +1,110 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use net::profile::{
+    market_fee, InventoryItem, ProfileClient, ProfilePlugin, ProfileRequest, Transaction, TransactionKind,
+    MARKET_FEE_PERCENT, MAX_PRICE, PRICE_HISTORY_DAYS, TRADE_HOLD,
+};
+use shared::game_state::Team;
+use shared::items::{item_def, ItemDef, ItemKind, Weapon, MAX_NAME_TAG_LENGTH, MAX_STICKERS};
+use shared::loot::case_odds;
 use shared::AppState;
 
 // återanvändbara komponenter
 use crate::playerbox::spawn_playerbox;
 use crate::friendlist::spawn_friendlist;
+use crate::unboxing::{color32, Unboxing, UnboxingPlugin};
 
 pub struct InventoryMenuPlugin;
 
 impl Plugin for InventoryMenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(AppState::InventoryMenu), spawn_inventory_menu)
-           .add_systems(OnExit(AppState::InventoryMenu), cleanup_inventory_menu);
+        if !app.is_plugin_added::<ProfilePlugin>() {
+            app.add_plugins(ProfilePlugin);
+        }
+        if !app.is_plugin_added::<UnboxingPlugin>() {
+            app.add_plugins(UnboxingPlugin);
+        }
+        app.init_resource::<InventoryView>()
+           .add_systems(OnEnter(AppState::InventoryMenu), spawn_inventory_menu)
+           .add_systems(OnExit(AppState::InventoryMenu), cleanup_inventory_menu)
+           .add_systems(
+               Update,
+               (
+                   tab_button_interactions,
+                   item_button_interactions,
+                   update_inventory_content,
+                   inspect_item_popup,
+                   price_chart_window,
+               )
+                   .run_if(in_state(AppState::InventoryMenu)),
+           );
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum InventoryTab {
+    #[default]
+    Inventory,
+    Loadout,
+    Marketplace,
+}
+
+impl InventoryTab {
+    const ALL: [InventoryTab; 3] = [InventoryTab::Inventory, InventoryTab::Loadout, InventoryTab::Marketplace];
+
+    fn label(self) -> &'static str {
+        match self {
+            InventoryTab::Inventory => "Inventory",
+            InventoryTab::Loadout => "Loadout",
+            InventoryTab::Marketplace => "Marketplace",
+        }
+    }
+}
+
+/// Vald flik, det föremål som visas i inspektionsfönstret och vars prishistorik visas
+#[derive(Resource, Default)]
+struct InventoryView {
+    tab: InventoryTab,
+    inspected: Option<u64>,
+    chart: Option<&'static str>,
+}
+
 #[derive(Component)]
 struct InventoryMenuRoot;
 
-fn spawn_inventory_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+#[derive(Component)]
+struct TabButton(InventoryTab);
+
+/// Fliken som visas, byggs om när profilen eller fliken ändras
+#[derive(Component)]
+struct InventoryContent {
+    font: Handle<Font>,
+}
+
+#[derive(Component, Clone, Copy)]
+enum ItemButton {
+    Inspect(u64),
+    Unequip(Team, Weapon),
+    Buy { listing_id: u64, price: u64 },
+    CancelListing(u64),
+    /// Listings of one item, or of everything
+    Filter(Option<&'static str>),
+    Chart(&'static str),
+    AcceptOffer(u64),
+    DeclineOffer(u64),
+}
+
+const TAB_COLOR: Color = Color::srgba(0.1, 0.1, 0.15, 0.9);
+const ACTIVE_TAB_COLOR: Color = Color::srgba(0.2, 0.4, 0.7, 0.9);
+const BUTTON_COLOR: Color = Color::srgba(0.2, 0.4, 0.7, 0.8);
+const DIM_TEXT: Color = Color::srgb(0.5, 0.5, 0.5);
+const DAY: u64 = 24 * 60 * 60;
+/// Newest transactions shown under the listings.
+const HISTORY_SHOWN: usize = 12;
+
+fn spawn_inventory_menu(mut commands: Commands, asset_server: Res<AssetServer>, view: Res<InventoryView>) {
     let font = asset_server.load("fonts/Inter-Bold.ttf");
 
     // Root
@@ -56,7 +143,7 @@ fn spawn_inventory_menu(mut commands: Commands, asset_server: Res<AssetServer>)
         });
 
         // -----------------------------
-        // Right panel: inventory grid
+        // Right panel: tabs + content
         // -----------------------------
         root.spawn(NodeBundle {
             style: Style {
@@ -74,71 +161,716 @@ fn spawn_inventory_menu(mut commands: Commands, asset_server: Res<AssetServer>)
             right.spawn(NodeBundle {
                 style: Style {
                     flex_direction: FlexDirection::Row,
-                    justify_content: JustifyContent::SpaceBetween,
+                    column_gap: Val::Px(6.0),
                     padding: UiRect::all(Val::Px(6.0)),
                     ..default()
                 },
-                background_color: Color::srgba(0.1, 0.1, 0.15, 0.9).into(),
+                background_color: TAB_COLOR.into(),
                 ..default()
             })
             .with_children(|bar| {
-                for tab in ["Inventory", "Loadout", "Marketplace"] {
-                    bar.spawn(TextBundle::from_section(
-                        tab,
-                        TextStyle {
-                            font: font.clone(),
-                            font_size: 16.0,
-                            color: Color::srgb(0.9, 0.9, 0.9),
-                        },
-                    ));
-                }
-            });
-
-            // Grid
-            right.spawn(NodeBundle {
-                style: Style {
-                    display: Display::Grid,
-                    grid_template_columns: RepeatedGridTrack::flex(6, 1.0),
-                    grid_template_rows: RepeatedGridTrack::flex(5, 1.0),
-                    row_gap: Val::Px(6.0),
-                    column_gap: Val::Px(6.0),
-                    ..default()
-                },
-                ..default()
-            })
-            .with_children(|grid| {
-                for i in 0..30 {
-                    grid.spawn(NodeBundle {
-                        style: Style {
-                            width: Val::Px(100.0),
-                            height: Val::Px(100.0),
+                for tab in InventoryTab::ALL {
+                    bar.spawn((
+                        ButtonBundle {
+                            style: Style {
+                                padding: UiRect::axes(Val::Px(12.0), Val::Px(4.0)),
+                                ..default()
+                            },
+                            background_color: if tab == view.tab { ACTIVE_TAB_COLOR } else { TAB_COLOR }.into(),
                             ..default()
                         },
-                        background_color: if i % 2 == 0 {
-                            Color::srgb(0.6, 0.6, 0.2).into() // gul "låda"
-                        } else {
-                            Color::srgb(0.3, 0.3, 0.3).into() // placeholder vapen
-                        },
-                        ..default()
-                    })
-                    .with_children(|cell| {
-                        cell.spawn(TextBundle::from_section(
-                            if i % 2 == 0 { "Case" } else { "AK-47" },
+                        TabButton(tab),
+                    ))
+                    .with_children(|btn| {
+                        btn.spawn(TextBundle::from_section(
+                            tab.label(),
                             TextStyle {
                                 font: font.clone(),
-                                font_size: 12.0,
+                                font_size: 16.0,
                                 color: Color::srgb(0.9, 0.9, 0.9),
                             },
                         ));
                     });
                 }
             });
+
+            right.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(6.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                InventoryContent { font: font.clone() },
+            ));
         });
     });
 }
 
-fn cleanup_inventory_menu(mut commands: Commands, q: Query<Entity, With<InventoryMenuRoot>>) {
+fn cleanup_inventory_menu(
+    mut commands: Commands,
+    q: Query<Entity, With<InventoryMenuRoot>>,
+    mut view: ResMut<InventoryView>,
+) {
     for e in &q {
         commands.entity(e).despawn_recursive();
     }
+    view.inspected = None;
+    view.chart = None;
+}
+
+fn text_style(font: &Handle<Font>, size: f32, color: Color) -> TextStyle {
+    TextStyle {
+        font: font.clone(),
+        font_size: size,
+        color,
+    }
+}
+
+/// Färgen föremålet visas med i rutnätet.
+fn item_color(item: &InventoryItem) -> Color {
+    if let Some((color, _)) = item.skin().and_then(|s| s.finish()) {
+        return color;
+    }
+    match item.def().map(|d| d.kind) {
+        Some(ItemKind::Sticker { color: [r, g, b] }) => Color::srgb(r, g, b),
+        Some(ItemKind::NameTag) => Color::srgb(0.8, 0.75, 0.6),
+        Some(ItemKind::Case { .. }) => Color::srgb(0.85, 0.65, 0.2),
+        _ => Color::srgb(0.3, 0.3, 0.3),
+    }
+}
+
+fn update_inventory_content(
+    mut commands: Commands,
+    profiles: Res<ProfileClient>,
+    view: Res<InventoryView>,
+    contents: Query<(Entity, &InventoryContent)>,
+    spawned: Query<(), Added<InventoryContent>>,
+) {
+    if !profiles.is_changed() && !view.is_changed() && spawned.is_empty() {
+        return;
+    }
+    for (entity, content) in &contents {
+        let font = &content.font;
+        commands.entity(entity).despawn_descendants().with_children(|col| match view.tab {
+            InventoryTab::Inventory => spawn_item_grid(col, font, &profiles),
+            InventoryTab::Loadout => spawn_loadout(col, font, &profiles),
+            InventoryTab::Marketplace => spawn_marketplace(col, font, &profiles),
+        });
+    }
+}
+
+fn spawn_item_grid(parent: &mut ChildBuilder, font: &Handle<Font>, profiles: &ProfileClient) {
+    if profiles.inventory.is_empty() {
+        parent.spawn(TextBundle::from_section(
+            "Your inventory is empty",
+            text_style(font, 14.0, Color::srgb(0.5, 0.5, 0.5)),
+        ));
+        return;
+    }
+    // nyast först
+    let mut items: Vec<&InventoryItem> = profiles.inventory.iter().collect();
+    items.sort_by_key(|i| std::cmp::Reverse((i.acquired, i.item_id)));
+
+    parent.spawn(NodeBundle {
+        style: Style {
+            display: Display::Grid,
+            grid_template_columns: RepeatedGridTrack::px(6, 100.0),
+            row_gap: Val::Px(6.0),
+            column_gap: Val::Px(6.0),
+            ..default()
+        },
+        ..default()
+    })
+    .with_children(|grid| {
+        for item in items {
+            let teams: Vec<&str> = profiles.equipped_for(item.item_id).iter().map(|t| t.label()).collect();
+            grid.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Px(100.0),
+                        height: Val::Px(100.0),
+                        flex_direction: FlexDirection::Column,
+                        justify_content: JustifyContent::SpaceBetween,
+                        padding: UiRect::all(Val::Px(4.0)),
+                        border: UiRect::bottom(Val::Px(6.0)),
+                        ..default()
+                    },
+                    background_color: Color::srgb(0.18, 0.18, 0.2).into(),
+                    border_color: item_color(item).into(),
+                    ..default()
+                },
+                ItemButton::Inspect(item.item_id),
+            ))
+            .with_children(|cell| {
+                cell.spawn(TextBundle::from_section(
+                    item.display_name(),
+                    text_style(font, 12.0, Color::srgb(0.9, 0.9, 0.9)),
+                ));
+                if !teams.is_empty() {
+                    cell.spawn(TextBundle::from_section(
+                        teams.join(" "),
+                        text_style(font, 11.0, Color::srgb(0.45, 0.8, 0.35)),
+                    ));
+                }
+            });
+        }
+    });
+}
+
+fn spawn_loadout(parent: &mut ChildBuilder, font: &Handle<Font>, profiles: &ProfileClient) {
+    for weapon in Weapon::ALL {
+        parent.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(12.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(TextBundle::from_section(weapon.label(), text_style(font, 14.0, Color::srgb(0.9, 0.9, 0.9))));
+            for team in [Team::CounterTerrorist, Team::Terrorist] {
+                if !weapon.teams().contains(&team) {
+                    continue;
+                }
+                let equipped = profiles
+                    .loadout
+                    .iter()
+                    .find(|e| e.team == team && e.skin.weapon() == Some(weapon));
+                let label = equipped.map_or("Default".to_string(), |e| e.skin.display_name());
+                row.spawn(TextBundle::from_section(
+                    format!("{}: {label}", team.label()),
+                    text_style(font, 13.0, Color::srgb(0.7, 0.7, 0.7)),
+                ));
+                if equipped.is_some() {
+                    row.spawn((
+                        ButtonBundle {
+                            style: Style {
+                                padding: UiRect::axes(Val::Px(6.0), Val::Px(2.0)),
+                                ..default()
+                            },
+                            background_color: Color::srgba(0.2, 0.4, 0.7, 0.8).into(),
+                            ..default()
+                        },
+                        ItemButton::Unequip(team, weapon),
+                    ))
+                    .with_children(|btn| {
+                        btn.spawn(TextBundle::from_section("UNEQUIP", text_style(font, 12.0, Color::WHITE)));
+                    });
+                }
+            }
+        });
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// "3 days" or "5 hours", rounded up.
+fn time_left(seconds: u64) -> String {
+    match seconds {
+        s if s > DAY => format!("{} days", s.div_ceil(DAY)),
+        s => format!("{} hours", s.div_ceil(60 * 60).max(1)),
+    }
+}
+
+fn item_name(definition: &str) -> &str {
+    item_def(definition).map_or(definition, |d| d.name)
+}
+
+/// "AK-47 | Redline, Sticker | Crown and 300 credits"
+fn offer_side(names: &[&str], credits: u64) -> String {
+    let mut parts: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+    if credits > 0 {
+        parts.push(format!("{credits} credits"));
+    }
+    match parts.len() {
+        0 => "nothing".to_string(),
+        _ => parts.join(", "),
+    }
+}
+
+fn player_name(profiles: &ProfileClient, player_id: u64) -> String {
+    profiles.friends.iter().find(|f| f.player_id == player_id).map_or(format!("player {player_id}"), |f| f.name.clone())
+}
+
+fn item_names(items: &[(u64, String)]) -> Vec<&str> {
+    items.iter().map(|(_, definition)| item_name(definition)).collect()
+}
+
+/// En rad i historiken, sett från vår sida
+fn describe_transaction(profiles: &ProfileClient, me: u64, t: &Transaction) -> String {
+    let days = (unix_now() / DAY).saturating_sub(t.time / DAY);
+    let when = match days {
+        0 => "today".to_string(),
+        1 => "yesterday".to_string(),
+        n => format!("{n} days ago"),
+    };
+    let what = match t.kind {
+        TransactionKind::Grant => format!("Got {} credits", t.from_credits),
+        TransactionKind::Sale if t.to == me => format!(
+            "Bought {} from {} for {} credits",
+            offer_side(&item_names(&t.from_items), 0),
+            player_name(profiles, t.from),
+            t.to_credits,
+        ),
+        TransactionKind::Sale => format!(
+            "Sold {} for {} credits, {} to the market",
+            offer_side(&item_names(&t.from_items), 0),
+            t.to_credits,
+            t.fee,
+        ),
+        TransactionKind::Trade => {
+            let from_side = offer_side(&item_names(&t.from_items), t.from_credits);
+            let to_side = offer_side(&item_names(&t.to_items), t.to_credits);
+            let (gave, got, other) =
+                if t.from == me { (from_side, to_side, t.to) } else { (to_side, from_side, t.from) };
+            format!("Traded {gave} for {got} with {}", player_name(profiles, other))
+        }
+    };
+    format!("{what}, {when}")
+}
+
+fn spawn_small_button(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, button: ItemButton) {
+    parent.spawn((
+        ButtonBundle {
+            style: Style {
+                padding: UiRect::axes(Val::Px(6.0), Val::Px(2.0)),
+                ..default()
+            },
+            background_color: BUTTON_COLOR.into(),
+            ..default()
+        },
+        button,
+    ))
+    .with_children(|btn| {
+        btn.spawn(TextBundle::from_section(label, text_style(font, 12.0, Color::WHITE)));
+    });
+}
+
+fn row_node() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            align_items: AlignItems::Center,
+            column_gap: Val::Px(12.0),
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn spawn_heading(parent: &mut ChildBuilder, font: &Handle<Font>, text: String) {
+    parent.spawn(TextBundle::from_section(text, text_style(font, 15.0, Color::srgb(0.9, 0.9, 0.9))).with_style(
+        Style {
+            margin: UiRect::top(Val::Px(8.0)),
+            ..default()
+        },
+    ));
+}
+
+fn spawn_marketplace(parent: &mut ChildBuilder, font: &Handle<Font>, profiles: &ProfileClient) {
+    let Some(me) = profiles.profile.as_ref().map(|p| p.player_id) else {
+        parent.spawn(TextBundle::from_section("Connecting to the market...", text_style(font, 14.0, DIM_TEXT)));
+        return;
+    };
+    parent.spawn(TextBundle::from_section(
+        format!("{} credits", profiles.balance.unwrap_or(0)),
+        text_style(font, 18.0, Color::srgb(0.95, 0.8, 0.3)),
+    ));
+    parent.spawn(TextBundle::from_section(
+        format!(
+            "The market keeps {MARKET_FEE_PERCENT}% of each sale. \
+             Items can't be traded for {} days after changing hands.",
+            TRADE_HOLD.as_secs() / DAY,
+        ),
+        text_style(font, 12.0, DIM_TEXT),
+    ));
+
+    if !profiles.offers.is_empty() {
+        spawn_heading(parent, font, "Trade offers".to_string());
+    }
+    for offer in &profiles.offers {
+        let give: Vec<&str> = offer.give.iter().map(|i| item_name(&i.definition)).collect();
+        let take: Vec<&str> = offer.take.iter().map(|i| item_name(&i.definition)).collect();
+        let give = offer_side(&give, offer.give_credits);
+        let take = offer_side(&take, offer.take_credits);
+        let text = if offer.to == me {
+            format!("{} offers {give} for your {take}", offer.from_name)
+        } else {
+            format!("You offered {} {give} for their {take}", offer.to_name)
+        };
+        parent.spawn(row_node()).with_children(|row| {
+            row.spawn(TextBundle::from_section(text, text_style(font, 13.0, Color::srgb(0.8, 0.8, 0.8))));
+            if offer.to == me {
+                spawn_small_button(row, font, "ACCEPT", ItemButton::AcceptOffer(offer.offer_id));
+                spawn_small_button(row, font, "DECLINE", ItemButton::DeclineOffer(offer.offer_id));
+            } else {
+                spawn_small_button(row, font, "CANCEL", ItemButton::DeclineOffer(offer.offer_id));
+            }
+        });
+    }
+
+    let filter = profiles.listings_for.as_deref().and_then(item_def);
+    parent.spawn(row_node()).with_children(|row| {
+        spawn_heading(row, font, filter.map_or("Listings".to_string(), |d| format!("Listings of {}", d.name)));
+        if filter.is_some() {
+            spawn_small_button(row, font, "ALL", ItemButton::Filter(None));
+        }
+    });
+    if profiles.listings.is_empty() {
+        parent.spawn(TextBundle::from_section("Nothing is for sale", text_style(font, 13.0, DIM_TEXT)));
+    }
+    // våra egna först, sedan billigast först som tjänsten skickar dem
+    for listing in &profiles.listings {
+        let def = listing.item.def();
+        parent.spawn(row_node()).with_children(|row| {
+            row.spawn(TextBundle::from_section(
+                listing.item.display_name(),
+                text_style(font, 13.0, item_color(&listing.item)),
+            ));
+            if let Some(skin) = listing.item.skin() {
+                row.spawn(TextBundle::from_section(
+                    format!("{} ({:.4})", skin.exterior().label(), skin.wear),
+                    text_style(font, 12.0, DIM_TEXT),
+                ));
+            }
+            let seller = if listing.seller == me { "you".to_string() } else { listing.seller_name.clone() };
+            row.spawn(TextBundle::from_section(format!("by {seller}"), text_style(font, 12.0, DIM_TEXT)));
+            row.spawn(TextBundle::from_section(
+                format!("{} credits", listing.price),
+                text_style(font, 13.0, Color::srgb(0.95, 0.8, 0.3)),
+            ));
+            if listing.seller == me {
+                spawn_small_button(row, font, "CANCEL", ItemButton::CancelListing(listing.listing_id));
+            } else {
+                let buy = ItemButton::Buy { listing_id: listing.listing_id, price: listing.price };
+                spawn_small_button(row, font, "BUY", buy);
+            }
+            if let Some(def) = def {
+                if filter.is_none() {
+                    spawn_small_button(row, font, "MORE", ItemButton::Filter(Some(def.id)));
+                }
+                spawn_small_button(row, font, "CHART", ItemButton::Chart(def.id));
+            }
+        });
+    }
+
+    if !profiles.transactions.is_empty() {
+        spawn_heading(parent, font, "History".to_string());
+    }
+    for t in profiles.transactions.iter().take(HISTORY_SHOWN) {
+        parent.spawn(TextBundle::from_section(
+            describe_transaction(profiles, me, t),
+            text_style(font, 12.0, Color::srgb(0.7, 0.7, 0.7)),
+        ));
+    }
+}
+
+fn tab_button_interactions(
+    q: Query<(&Interaction, &TabButton), Changed<Interaction>>,
+    mut buttons: Query<(&TabButton, &mut BackgroundColor)>,
+    mut view: ResMut<InventoryView>,
+    mut requests: EventWriter<ProfileRequest>,
+) {
+    for (interaction, tab) in &q {
+        if *interaction != Interaction::Pressed || view.tab == tab.0 {
+            continue;
+        }
+        view.tab = tab.0;
+        // annonserna ändras hela tiden, hämta dem när fliken öppnas
+        if tab.0 == InventoryTab::Marketplace {
+            requests.send(ProfileRequest::FetchListings(None));
+        }
+        for (button, mut color) in &mut buttons {
+            *color = if button.0 == tab.0 { ACTIVE_TAB_COLOR } else { TAB_COLOR }.into();
+        }
+    }
+}
+
+fn item_button_interactions(
+    q: Query<(&Interaction, &ItemButton), Changed<Interaction>>,
+    mut view: ResMut<InventoryView>,
+    mut requests: EventWriter<ProfileRequest>,
+) {
+    for (interaction, button) in &q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match *button {
+            ItemButton::Inspect(item_id) => {
+                view.inspected = Some(item_id);
+            }
+            ItemButton::Unequip(team, weapon) => {
+                requests.send(ProfileRequest::Unequip { team, weapon });
+            }
+            ItemButton::Buy { listing_id, price } => {
+                requests.send(ProfileRequest::BuyListing { listing_id, price });
+            }
+            ItemButton::CancelListing(listing_id) => {
+                requests.send(ProfileRequest::CancelListing(listing_id));
+            }
+            ItemButton::Filter(definition) => {
+                requests.send(ProfileRequest::FetchListings(definition.map(str::to_string)));
+            }
+            ItemButton::Chart(definition) => {
+                view.chart = Some(definition);
+                requests.send(ProfileRequest::FetchPriceHistory(definition.to_string()));
+            }
+            ItemButton::AcceptOffer(offer_id) => {
+                requests.send(ProfileRequest::AcceptOffer(offer_id));
+            }
+            ItemButton::DeclineOffer(offer_id) => {
+                requests.send(ProfileRequest::DeclineOffer(offer_id));
+            }
+        }
+    }
+}
+
+/// Details of the inspected item, with what can be done to it.
+fn inspect_item_popup(
+    mut contexts: EguiContexts,
+    profiles: Res<ProfileClient>,
+    mut view: ResMut<InventoryView>,
+    mut requests: EventWriter<ProfileRequest>,
+    mut unboxing: ResMut<Unboxing>,
+    mut name_tag: Local<(Option<u64>, String)>,
+    mut price: Local<(Option<u64>, String)>,
+) {
+    let Some(item) = view.inspected.and_then(|id| profiles.item(id)) else {
+        return;
+    };
+    // namnet och priset som skrivs in gäller bara det föremål de skrevs för
+    if name_tag.0 != Some(item.item_id) {
+        *name_tag = (Some(item.item_id), String::new());
+        *price = (Some(item.item_id), String::new());
+    }
+    let mut chart = None;
+    let skin = item.skin();
+    let mut open = true;
+    let mut opened = false;
+    egui::Window::new(item.display_name())
+        .id(egui::Id::new("inspect_item"))
+        .collapsible(false)
+        .resizable(false)
+        .open(&mut open)
+        .anchor(egui::Align2::RIGHT_CENTER, [-16.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            market_details(ui, &profiles, item, &mut price.1, &mut requests);
+            if let Some(def) = item.def() {
+                if ui.button("PRICE HISTORY").clicked() {
+                    chart = Some(def.id);
+                }
+            }
+            ui.separator();
+            if let Some(case @ ItemDef { kind: ItemKind::Case { contents }, .. }) = item.def() {
+                case_details(ui, case, contents);
+                if ui.add_enabled(unboxing.is_idle(), egui::Button::new("OPEN")).clicked() {
+                    requests.send(ProfileRequest::OpenCase(item.item_id));
+                    unboxing.start();
+                    opened = true;
+                }
+                return;
+            }
+            let Some(skin) = &skin else {
+                ui.label(item.def().map_or("Unknown item", |d| d.name));
+                ui.label("Use it from a weapon skin");
+                return;
+            };
+            if let Some(def) = item.def() {
+                ui.label(def.name);
+            }
+            ui.label(format!("{} ({:.6})", skin.exterior().label(), skin.wear));
+            ui.label(format!("Pattern {}", skin.pattern));
+            let stickers: Vec<&str> = skin
+                .stickers
+                .iter()
+                .map(|id| item_def(id).map_or(id.as_str(), |d| d.name))
+                .collect();
+            ui.label(format!("Stickers ({}/{MAX_STICKERS}): {}", stickers.len(), stickers.join(", ")));
+
+            ui.separator();
+            let equipped = profiles.equipped_for(item.item_id);
+            ui.horizontal(|ui| {
+                for &team in skin.weapon().map_or(&[][..], Weapon::teams) {
+                    if equipped.contains(&team) {
+                        if ui.button(format!("UNEQUIP {}", team.label())).clicked() {
+                            if let Some(weapon) = skin.weapon() {
+                                requests.send(ProfileRequest::Unequip { team, weapon });
+                            }
+                        }
+                    } else if ui.button(format!("EQUIP {}", team.label())).clicked() {
+                        requests.send(ProfileRequest::Equip { item_id: item.item_id, team });
+                    }
+                }
+            });
+
+            // klistermärken och namnlappar förbrukas när de används
+            if skin.stickers.len() < MAX_STICKERS {
+                for sticker in profiles.inventory.iter().filter(|i| {
+                    matches!(i.def().map(|d| d.kind), Some(ItemKind::Sticker { .. }))
+                }) {
+                    if ui.button(format!("APPLY {}", sticker.display_name())).clicked() {
+                        requests.send(ProfileRequest::ApplySticker {
+                            item_id: item.item_id,
+                            sticker_id: sticker.item_id,
+                        });
+                    }
+                }
+            }
+            let tag = profiles.inventory.iter().find(|i| i.def().map(|d| d.kind) == Some(ItemKind::NameTag));
+            if let Some(tag) = tag {
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut name_tag.1).char_limit(MAX_NAME_TAG_LENGTH));
+                    let name = name_tag.1.trim().to_string();
+                    if ui.add_enabled(!name.is_empty(), egui::Button::new("NAME TAG")).clicked() {
+                        let (item_id, tag_id) = (item.item_id, tag.item_id);
+                        requests.send(ProfileRequest::ApplyNameTag { item_id, tag_id, name });
+                    }
+                });
+            }
+        });
+    if !open || opened {
+        view.inspected = None;
+    }
+    if let Some(definition) = chart {
+        view.chart = Some(definition);
+        requests.send(ProfileRequest::FetchPriceHistory(definition.to_string()));
+    }
+}
+
+/// Selling the item, or why it can't be sold right now.
+fn market_details(
+    ui: &mut egui::Ui,
+    profiles: &ProfileClient,
+    item: &InventoryItem,
+    price: &mut String,
+    requests: &mut EventWriter<ProfileRequest>,
+) {
+    let now = unix_now();
+    if let Some(listing) = profiles.listing_of(item.item_id) {
+        ui.horizontal(|ui| {
+            ui.label(format!("On the market for {} credits", listing.price));
+            if ui.button("CANCEL LISTING").clicked() {
+                requests.send(ProfileRequest::CancelListing(listing.listing_id));
+            }
+        });
+    } else if item.tradable_after > now {
+        ui.label(format!("Tradable in {}", time_left(item.tradable_after - now)));
+    } else {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(price).desired_width(80.0).hint_text("credits"));
+            let parsed = price.trim().parse::<u64>().ok().filter(|p| (1..=MAX_PRICE).contains(p));
+            if ui.add_enabled(parsed.is_some(), egui::Button::new("SELL")).clicked() {
+                if let Some(price) = parsed {
+                    requests.send(ProfileRequest::CreateListing { item_id: item.item_id, price });
+                }
+            }
+            if let Some(price) = parsed {
+                ui.label(format!("You get {}", price - market_fee(price)));
+            }
+        });
+    }
+}
+
+const CHART_SIZE: egui::Vec2 = egui::vec2(480.0, 220.0);
+/// Höjden längst ner där antalet sålda ritas
+const VOLUME_HEIGHT: f32 = 40.0;
+
+/// Daily prices of one item over the last `PRICE_HISTORY_DAYS`: the range as a band, the
+/// average as a line and how many sold as bars.
+fn price_chart_window(mut contexts: EguiContexts, profiles: Res<ProfileClient>, mut view: ResMut<InventoryView>) {
+    let Some(definition) = view.chart else {
+        return;
+    };
+    let mut open = true;
+    egui::Window::new("Price history")
+        .id(egui::Id::new("price_chart"))
+        .collapsible(false)
+        .resizable(false)
+        .open(&mut open)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.heading(item_name(definition));
+            let points = match &profiles.price_history {
+                Some((d, points)) if d == definition => points,
+                _ => {
+                    ui.label("Loading...");
+                    return;
+                }
+            };
+            if points.is_empty() {
+                ui.label(format!("Nothing sold in the last {PRICE_HISTORY_DAYS} days"));
+                return;
+            }
+            let (response, painter) = ui.allocate_painter(CHART_SIZE, egui::Sense::hover());
+            let rect = response.rect;
+            painter.rect_filled(rect, 4.0, egui::Color32::from_gray(20));
+            let today = unix_now() / DAY;
+            let first_day = today.saturating_sub(PRICE_HISTORY_DAYS - 1);
+            let top_price = points.iter().map(|p| p.high).max().unwrap_or(1).max(1);
+            let top_volume = points.iter().map(|p| p.volume).max().unwrap_or(1).max(1) as f32;
+            let step = rect.width() / PRICE_HISTORY_DAYS as f32;
+            let prices_bottom = rect.bottom() - VOLUME_HEIGHT - 8.0;
+            let x = |day: u64| rect.left() + (day.saturating_sub(first_day) as f32 + 0.5) * step;
+            let y = |price: u64| prices_bottom - price as f32 / top_price as f32 * (prices_bottom - rect.top() - 16.0);
+
+            let band = egui::Color32::from_rgba_unmultiplied(80, 140, 220, 90);
+            let mut averages = Vec::new();
+            for point in points {
+                let px = x(point.day);
+                painter.line_segment(
+                    [egui::pos2(px, y(point.low)), egui::pos2(px, y(point.high))],
+                    egui::Stroke::new(step * 0.6, band),
+                );
+                let height = point.volume as f32 / top_volume * VOLUME_HEIGHT;
+                painter.rect_filled(
+                    egui::Rect::from_min_max(
+                        egui::pos2(px - step * 0.3, rect.bottom() - height),
+                        egui::pos2(px + step * 0.3, rect.bottom()),
+                    ),
+                    0.0,
+                    egui::Color32::from_gray(90),
+                );
+                averages.push(egui::pos2(px, y(point.average)));
+            }
+            painter.add(egui::Shape::line(averages, egui::Stroke::new(2.0, egui::Color32::from_rgb(240, 200, 80))));
+            let small = egui::FontId::proportional(11.0);
+            let label = egui::Color32::from_gray(160);
+            let corner = rect.left_top() + egui::vec2(4.0, 2.0);
+            painter.text(corner, egui::Align2::LEFT_TOP, format!("{top_price} credits"), small.clone(), label);
+            painter.text(
+                egui::pos2(rect.left() + 4.0, prices_bottom),
+                egui::Align2::LEFT_BOTTOM,
+                format!("{PRICE_HISTORY_DAYS} days ago"),
+                small.clone(),
+                label,
+            );
+            let right = egui::pos2(rect.right() - 4.0, prices_bottom);
+            painter.text(right, egui::Align2::RIGHT_BOTTOM, "today", small, label);
+
+            let sold: u32 = points.iter().map(|p| p.volume).sum();
+            if let Some(last) = points.last() {
+                ui.label(format!("Last sold for {} credits on average, {sold} sold in total", last.average));
+            }
+        });
+    if !open {
+        view.chart = None;
+    }
+}
+
+/// What the case can open into, with the odds of each tier.
+fn case_details(ui: &mut egui::Ui, case: &ItemDef, contents: &[&'static str]) {
+    for (rarity, chance) in case_odds(case) {
+        ui.colored_label(color32(rarity.color()), format!("{} {:.2}%", rarity.label(), chance * 100.0));
+        for def in contents.iter().filter_map(|id| item_def(id)).filter(|d| d.rarity() == Some(rarity)) {
+            ui.label(format!("    {}", def.name));
+        }
+    }
+    ui.small("Every skin in a tier is as likely.");
 }
@@ -0,0 +1,337 @@
+//! Key bindings list in Options -> Keyboard / Mouse.
+//!
+//! Every action gets two key slots. Clicking a slot waits for the next key, mouse or gamepad
+//! button; a key that was already bound elsewhere is moved and the old binding reported.
+use bevy::prelude::*;
+use shared::actions::{Action, ActionState, ActionsPlugin};
+use shared::config::GameConfig;
+use shared::keys::InputKey;
+use shared::types::{in_options_screen, InGameMenu, OptionsSubState};
+use shared::AppState;
+
+const SLOTS: usize = 2;
+
+pub struct KeyBindingsPlugin;
+
+impl Plugin for KeyBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ActionsPlugin>() {
+            app.add_plugins(ActionsPlugin);
+        }
+        let sub = OptionsSubState::KeyboardMouse;
+        app.init_resource::<RebindCapture>()
+            .add_systems(OnExit(AppState::OptionsMenu(sub.clone())), cancel_capture)
+            .add_systems(OnExit(InGameMenu::Options(sub.clone())), cancel_capture)
+            .add_systems(
+                Update,
+                (
+                    capture_rebind.before(bind_button_interactions),
+                    bind_button_interactions,
+                    reset_bindings_button,
+                    update_bind_labels,
+                )
+                    .run_if(in_options_screen(sub)),
+            );
+    }
+}
+
+/// Slot som väntar på en knapp, och texten som visas under listan
+#[derive(Resource, Default)]
+struct RebindCapture {
+    waiting: Option<Waiting>,
+    status: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Waiting {
+    action: Action,
+    slot: usize,
+    /// Knappen som visades i sloten när den klickades; listan sorteras om efter varje bindning
+    replacing: Option<InputKey>,
+}
+
+#[derive(Component)]
+struct BindButton {
+    action: Action,
+    slot: usize,
+}
+
+#[derive(Component)]
+struct BindButtonText {
+    action: Action,
+    slot: usize,
+}
+
+#[derive(Component)]
+struct BindStatusText;
+
+#[derive(Component)]
+struct ResetBindingsButton;
+
+pub fn spawn_key_bindings(root: &mut ChildBuilder, font: &Handle<Font>) {
+    root.spawn(TextBundle::from_section(
+        "KEY BINDINGS",
+        TextStyle {
+            font: font.clone(),
+            font_size: 20.0,
+            color: Color::srgb(0.5, 0.7, 0.9),
+        },
+    ));
+
+    for action in Action::ALL {
+        root.spawn(NodeBundle {
+            style: Style {
+                display: Display::Flex,
+                justify_content: JustifyContent::SpaceBetween,
+                align_items: AlignItems::Center,
+                width: Val::Percent(100.0),
+                padding: UiRect::axes(Val::Px(8.0), Val::Px(2.0)),
+                ..default()
+            },
+            background_color: Color::srgba(0.05, 0.05, 0.05, 0.6).into(),
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(TextBundle::from_section(
+                action.label(),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    color: Color::srgb(0.9, 0.9, 0.9),
+                },
+            ));
+            row.spawn(NodeBundle {
+                style: Style {
+                    column_gap: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|slots| {
+                for slot in 0..SLOTS {
+                    spawn_bind_button(slots, font, action, slot);
+                }
+            });
+        });
+    }
+
+    root.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: font.clone(),
+                font_size: 14.0,
+                color: Color::srgb(0.95, 0.75, 0.3),
+            },
+        ),
+        BindStatusText,
+    ));
+
+    root.spawn((
+        ButtonBundle {
+            style: Style {
+                padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
+                align_self: AlignSelf::FlexStart,
+                ..default()
+            },
+            background_color: Color::srgba(0.1, 0.1, 0.1, 0.8).into(),
+            ..default()
+        },
+        ResetBindingsButton,
+    ))
+    .with_children(|btn| {
+        btn.spawn(TextBundle::from_section(
+            "RESTORE DEFAULTS",
+            TextStyle {
+                font: font.clone(),
+                font_size: 16.0,
+                color: Color::srgb(0.9, 0.9, 0.9),
+            },
+        ));
+    });
+}
+
+fn spawn_bind_button(parent: &mut ChildBuilder, font: &Handle<Font>, action: Action, slot: usize) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(140.0),
+                    padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: Color::srgba(0.1, 0.1, 0.1, 0.8).into(),
+                ..default()
+            },
+            BindButton { action, slot },
+        ))
+        .with_children(|btn| {
+            btn.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 16.0,
+                        color: Color::srgb(0.8, 0.8, 0.8),
+                    },
+                ),
+                BindButtonText { action, slot },
+            ));
+        });
+}
+
+fn bind_button_interactions(
+    q: Query<(&Interaction, &BindButton), Changed<Interaction>>,
+    config: Res<GameConfig>,
+    mut capture: ResMut<RebindCapture>,
+    mut actions: ResMut<ActionState>,
+) {
+    for (interaction, button) in &q {
+        if *interaction == Interaction::Pressed {
+            let replacing = config.keys_for(button.action.command()).get(button.slot).copied();
+            capture.waiting = Some(Waiting { action: button.action, slot: button.slot, replacing });
+            capture.status = format!("Press a key for {}, Escape to cancel", button.action.label());
+            actions.set_blocked("rebind", true);
+        }
+    }
+}
+
+/// Takes the first key pressed while a slot is waiting. Runs before the button system so
+/// the click that started the capture isn't taken as the new binding.
+fn capture_rebind(
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut capture: ResMut<RebindCapture>,
+    mut config: ResMut<GameConfig>,
+    mut actions: ResMut<ActionState>,
+) {
+    let Some(Waiting { action, replacing, .. }) = capture.waiting else {
+        return;
+    };
+    if keys.clear_just_pressed(KeyCode::Escape) {
+        capture.waiting = None;
+        capture.status.clear();
+        actions.set_blocked("rebind", false);
+        return;
+    }
+    let pressed = keys
+        .get_just_pressed()
+        .next()
+        .map(|k| InputKey::Key(*k))
+        .or_else(|| mouse.get_just_pressed().next().map(|b| InputKey::Mouse(*b)))
+        .or_else(|| pad_buttons.get_just_pressed().next().map(|b| InputKey::Gamepad(b.button_type)));
+    let Some(key) = pressed else {
+        return;
+    };
+    // en bindning som inte går att läsa tillbaka skulle försvinna vid nästa start
+    if !key.is_bindable() {
+        capture.status = format!("{} can't be bound, try another key", key.label());
+        return;
+    }
+
+    capture.status = rebind(&mut config, action, replacing, key).unwrap_or_default();
+    capture.waiting = None;
+    actions.set_blocked("rebind", false);
+    config.save();
+}
+
+/// Binds `key` to `action` in place of `replacing`. Returns a message when the key was
+/// taken from another command.
+fn rebind(config: &mut GameConfig, action: Action, replacing: Option<InputKey>, key: InputKey) -> Option<String> {
+    let current = config.keys_for(action.command());
+    if current.contains(&key) {
+        return None;
+    }
+    // kan ha bundits om till något annat medan vi väntade
+    if let Some(old) = replacing.filter(|old| current.contains(old)) {
+        config.unbind(old);
+    }
+    let previous = config.bind(key, action.command())?;
+    let other = Action::from_command(&previous)
+        .map(|a| a.label().to_string())
+        .unwrap_or(previous);
+    Some(format!("{} was bound to {}, it is now used for {}", key.label(), other, action.label()))
+}
+
+fn reset_bindings_button(
+    q: Query<&Interaction, (Changed<Interaction>, With<ResetBindingsButton>)>,
+    mut config: ResMut<GameConfig>,
+    mut capture: ResMut<RebindCapture>,
+) {
+    for interaction in &q {
+        if *interaction == Interaction::Pressed {
+            config.reset_bindings();
+            config.save();
+            capture.status = "Key bindings restored to defaults".into();
+        }
+    }
+}
+
+fn update_bind_labels(
+    config: Res<GameConfig>,
+    capture: Res<RebindCapture>,
+    mut labels: Query<(&BindButtonText, &mut Text), Without<BindStatusText>>,
+    mut status: Query<&mut Text, With<BindStatusText>>,
+    spawned: Query<(), Added<BindButtonText>>,
+) {
+    if !config.is_changed() && !capture.is_changed() && spawned.is_empty() {
+        return;
+    }
+    for (label, mut text) in &mut labels {
+        let waiting = capture.waiting.is_some_and(|w| w.action == label.action && w.slot == label.slot);
+        let value = if waiting {
+            "...".to_string()
+        } else {
+            config
+                .keys_for(label.action.command())
+                .get(label.slot)
+                .map(InputKey::label)
+                .unwrap_or_else(|| "-".into())
+        };
+        text.sections[0].value = value;
+    }
+    for mut text in &mut status {
+        text.sections[0].value = capture.status.clone();
+    }
+}
+
+fn cancel_capture(mut capture: ResMut<RebindCapture>, mut actions: ResMut<ActionState>) {
+    capture.waiting = None;
+    capture.status.clear();
+    actions.set_blocked("rebind", false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> InputKey {
+        InputKey::from_name(name).unwrap()
+    }
+
+    #[test]
+    fn rebind_replaces_the_clicked_key_after_the_list_is_sorted_again() {
+        let mut config = GameConfig::default();
+        assert_eq!(config.keys_for(Action::Jump.command()), [key("joy_a"), key("space")]);
+
+        // sloten med space får j, som sorteras före joy_a
+        assert_eq!(rebind(&mut config, Action::Jump, Some(key("space")), key("j")), None);
+        assert_eq!(config.keys_for(Action::Jump.command()), [key("j"), key("joy_a")]);
+        // sloten som visade joy_a när den klickades byter joy_a, inte j
+        rebind(&mut config, Action::Jump, Some(key("joy_a")), key("k"));
+        assert_eq!(config.keys_for(Action::Jump.command()), [key("j"), key("k")]);
+    }
+
+    #[test]
+    fn rebind_reports_keys_taken_from_other_actions() {
+        let mut config = GameConfig::default();
+        let message = rebind(&mut config, Action::Jump, Some(key("space")), key("w")).unwrap();
+        assert!(message.contains("it is now used for"), "{message}");
+        assert!(!config.keys_for(Action::Forward.command()).contains(&key("w")));
+        // en tom slot lägger bara till
+        rebind(&mut config, Action::Forward, None, key("i"));
+        assert_eq!(config.keys_for(Action::Forward.command()), [key("i"), key("uparrow")]);
+    }
+}
@@ -10,6 +10,17 @@ pub mod background;
 pub mod playerbox;
 pub mod friendlist;
 pub mod loading_screen;
+pub mod console;
+pub mod keybindings;
+pub mod settings;
+pub mod crosshair;
+pub mod video;
+pub mod hud;
+pub mod scoreboard;
+pub mod pause_menu;
+pub mod chat;
+pub mod server_browser;
+pub mod unboxing;
 
 pub struct UiPlugin;
 
@@ -24,6 +35,20 @@ impl Plugin for UiPlugin {
                inventory_menu::InventoryMenuPlugin,
                options_menu::OptionsMenuPlugin,
                loading_screen::LoadingScreenPlugin,
+               console::ConsolePlugin,
+               keybindings::KeyBindingsPlugin,
+               settings::SettingsPlugin,
+               crosshair::CrosshairPlugin,
+               video::VideoPlugin,
+               hud::HudPlugin,
+               scoreboard::ScoreboardPlugin,
+               pause_menu::PauseMenuPlugin,
+           ))
+           .add_plugins((
+               chat::ChatUiPlugin,
+               server_browser::ServerBrowserUiPlugin,
+               playerbox::PlayerBoxPlugin,
+               friendlist::FriendListPlugin,
            ));
     }
 }
@@ -1,20 +1,63 @@
+use bevy::asset::UntypedAssetLoadFailedEvent;
 use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
+use iyes_progress::prelude::*;
 use shared::AppState;
-use shared::maps::MapId;
 
 pub struct LoadingScreenPlugin;
 
 impl Plugin for LoadingScreenPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(AppState::Loading), spawn_loading_screen)
-            .add_systems(OnExit(AppState::Loading), cleanup_loading_screen)
-            .add_systems(Update, check_if_ready.run_if(in_state(AppState::Loading)));
+        // Andra crates lägger till sina samlingar med `configure_loading_state`
+        app.add_loading_state(
+            LoadingState::new(AppState::Loading)
+                .continue_to_state(AppState::InGame)
+                .on_failure_continue_to_state(AppState::LoadingFailed)
+                .load_collection::<FontAssets>(),
+        )
+        .add_plugins(ProgressPlugin::new(AppState::Loading))
+        .init_resource::<FailedAssets>()
+        .add_systems(OnEnter(AppState::Loading), (reset_failed_assets, spawn_loading_screen))
+        .add_systems(OnExit(AppState::Loading), cleanup_loading_screen)
+        .add_systems(
+            Update,
+            (update_progress_bar, record_failed_assets).run_if(in_state(AppState::Loading)),
+        )
+        .add_systems(OnEnter(AppState::LoadingFailed), spawn_error_screen)
+        .add_systems(OnExit(AppState::LoadingFailed), cleanup_error_screen)
+        .add_systems(
+            Update,
+            back_button_interactions.run_if(in_state(AppState::LoadingFailed)),
+        );
     }
 }
 
+/// Fonts used by the menus and the HUD.
+#[derive(AssetCollection, Resource)]
+pub struct FontAssets {
+    #[asset(path = "fonts/Inter-Bold.ttf")]
+    pub inter_bold: Handle<Font>,
+}
+
+/// Asset paths that failed during the current loading state, shown on the error screen.
+#[derive(Resource, Default, Debug)]
+pub struct FailedAssets(pub Vec<String>);
+
 #[derive(Component)]
 struct LoadingScreenRoot;
 
+#[derive(Component)]
+struct ProgressBarFill;
+
+#[derive(Component)]
+struct ProgressText;
+
+#[derive(Component)]
+struct ErrorScreenRoot;
+
+#[derive(Component)]
+struct BackToMenuButton;
+
 fn spawn_loading_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
     let font = asset_server.load("fonts/Inter-Bold.ttf");
 
@@ -24,8 +67,10 @@ fn spawn_loading_screen(mut commands: Commands, asset_server: Res<AssetServer>)
                 style: Style {
                     width: Val::Percent(100.0),
                     height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
                     justify_content: JustifyContent::Center,
                     align_items: AlignItems::Center,
+                    row_gap: Val::Px(12.0),
                     ..default()
                 },
                 background_color: Color::BLACK.into(),
@@ -33,25 +78,173 @@ fn spawn_loading_screen(mut commands: Commands, asset_server: Res<AssetServer>)
             },
             LoadingScreenRoot,
         ))
+        .with_children(|root| {
+            root.spawn((
+                TextBundle::from_section(
+                    "Loading...",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 32.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                ProgressText,
+            ));
+
+            // Progress bar
+            root.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Px(400.0),
+                    height: Val::Px(12.0),
+                    border: UiRect::all(Val::Px(1.0)),
+                    ..default()
+                },
+                background_color: Color::srgba(1.0, 1.0, 1.0, 0.05).into(),
+                border_color: BorderColor(Color::srgba(1.0, 1.0, 1.0, 0.3)),
+                ..default()
+            })
+            .with_children(|bar| {
+                bar.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(0.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        background_color: Color::srgb(0.2, 0.4, 0.7).into(),
+                        ..default()
+                    },
+                    ProgressBarFill,
+                ));
+            });
+        });
+}
+
+fn update_progress_bar(
+    progress: Option<Res<ProgressCounter>>,
+    mut fill: Query<&mut Style, With<ProgressBarFill>>,
+    mut text: Query<&mut Text, With<ProgressText>>,
+) {
+    let Some(progress) = progress.map(|counter| counter.progress()) else {
+        return;
+    };
+    let ratio: f32 = progress.into();
+
+    for mut style in &mut fill {
+        style.width = Val::Percent(ratio * 100.0);
+    }
+    for mut text in &mut text {
+        text.sections[0].value = format!("Loading... {}/{}", progress.done, progress.total);
+    }
+}
+
+fn cleanup_loading_screen(mut commands: Commands, q: Query<Entity, With<LoadingScreenRoot>>) {
+    for e in &q {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+fn reset_failed_assets(mut failed: ResMut<FailedAssets>) {
+    failed.0.clear();
+}
+
+// bevy_asset_loader säger bara att något gick fel, så vi sparar själva sökvägarna
+fn record_failed_assets(
+    mut events: EventReader<UntypedAssetLoadFailedEvent>,
+    mut failed: ResMut<FailedAssets>,
+) {
+    for ev in events.read() {
+        error!("Failed to load {}: {}", ev.path, ev.error);
+        failed.0.push(ev.path.to_string());
+    }
+}
+
+fn spawn_error_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    failed: Res<FailedAssets>,
+) {
+    let font = asset_server.load("fonts/Inter-Bold.ttf");
+
+    let details = if failed.0.is_empty() {
+        "An unknown asset could not be loaded.".to_string()
+    } else {
+        format!("Missing or broken assets:\n{}", failed.0.join("\n"))
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                background_color: Color::BLACK.into(),
+                z_index: ZIndex::Global(20), // över navbaren
+                ..default()
+            },
+            ErrorScreenRoot,
+        ))
         .with_children(|root| {
             root.spawn(TextBundle::from_section(
-                "Loading...",
+                "Failed to load the game",
                 TextStyle {
                     font: font.clone(),
                     font_size: 32.0,
-                    color: Color::WHITE,
+                    color: Color::srgb(0.9, 0.3, 0.3),
+                },
+            ));
+            root.spawn(TextBundle::from_section(
+                details,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    color: Color::srgb(0.8, 0.8, 0.8),
                 },
             ));
+            root.spawn((
+                ButtonBundle {
+                    style: Style {
+                        padding: UiRect::axes(Val::Px(14.0), Val::Px(6.0)),
+                        margin: UiRect::top(Val::Px(12.0)),
+                        ..default()
+                    },
+                    background_color: Color::srgba(0.1, 0.1, 0.1, 0.8).into(),
+                    ..default()
+                },
+                BackToMenuButton,
+            ))
+            .with_children(|btn| {
+                btn.spawn(TextBundle::from_section(
+                    "BACK TO MENU",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 16.0,
+                        color: Color::srgb(0.9, 0.9, 0.9),
+                    },
+                ));
+            });
         });
 }
 
-fn cleanup_loading_screen(mut commands: Commands, q: Query<Entity, With<LoadingScreenRoot>>) {
-    for e in &q {
-        commands.entity(e).despawn_recursive();
+fn back_button_interactions(
+    q: Query<&Interaction, (Changed<Interaction>, With<BackToMenuButton>)>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for interaction in &q {
+        if *interaction == Interaction::Pressed {
+            next_state.set(AppState::MainMenu);
+        }
     }
 }
 
-// Senare kan vi vänta på att kartans assets laddas
-fn check_if_ready(mut next_state: ResMut<NextState<AppState>>) {
-    next_state.set(AppState::InGame);
+fn cleanup_error_screen(mut commands: Commands, q: Query<Entity, With<ErrorScreenRoot>>) {
+    for e in &q {
+        commands.entity(e).despawn_recursive();
+    }
 }
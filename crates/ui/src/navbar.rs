@@ -2,7 +2,7 @@
 //!
 //! # Overview
 //! The navigation bar provides buttons for navigating between different application states,
-//! such as Home, Play, Inventory, Options, and Exit. It visually highlights the active and hovered
+//! such as Home, Play, Servers, Inventory, Options, and Exit. It visually highlights the active and hovered
 //! buttons and handles user interactions to trigger state changes or exit the application.
 //!
 //! # Components
@@ -42,6 +42,7 @@ use shared::types::OptionsSubState;
 enum NavItem {
     Home,
     Play,
+    Servers,
     Inventory,
     Options,
     Exit,
@@ -52,6 +53,7 @@ impl NavItem {
         match self {
             NavItem::Home => "HOME",
             NavItem::Play => "PLAY",
+            NavItem::Servers => "SERVERS",
             NavItem::Inventory => "INVENTORY",
             NavItem::Options => "OPTIONS",
             NavItem::Exit => "EXIT",
@@ -62,6 +64,7 @@ impl NavItem {
         match self {
             NavItem::Home => Some(AppState::MainMenu),
             NavItem::Play => Some(AppState::PlayMenu),
+            NavItem::Servers => Some(AppState::ServerBrowser),
             NavItem::Inventory => Some(AppState::InventoryMenu),
             NavItem::Options => Some(AppState::OptionsMenu(OptionsSubState::Root)),
             NavItem::Exit => None,
@@ -100,6 +103,7 @@ fn spawn_navbar(mut commands: Commands, font: Res<UiFont>) {
     let items = [
         NavItem::Home,
         NavItem::Play,
+        NavItem::Servers,
         NavItem::Inventory,
         NavItem::Options,
         NavItem::Exit,
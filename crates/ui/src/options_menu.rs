@@ -1,8 +1,12 @@
 use bevy::prelude::*;
 use shared::config::GameConfig;
-use bevy::window::{Window, WindowMode, PresentMode};
 use shared::AppState;
-use shared::types::OptionsSubState;
+use shared::types::{options_screen, InGameMenu, OptionsSubState};
+use crate::crosshair::{CROSSHAIR_COLORS, CROSSHAIR_STYLES};
+use crate::hud::{HUD_COLORS, HUD_LAYOUTS};
+use crate::keybindings;
+use crate::settings;
+use crate::video::SHADOW_QUALITY;
 
 pub struct OptionsMenuPlugin;
 
@@ -25,12 +29,27 @@ impl Plugin for OptionsMenuPlugin {
                 (
                     subnav_button_interactions,
                     update_subnav_highlight,
-                    apply_window_settings, // nytt system
+                    back_button_interactions,
                 ),
             );
+
+        // samma sidor ovanpå spelet, från pausmenyn
+        for sub in SUB_PAGES {
+            app.add_systems(OnEnter(InGameMenu::Options(sub.clone())), spawn_options_menu)
+                .add_systems(OnExit(InGameMenu::Options(sub)), cleanup_options_menu);
+        }
     }
 }
 
+const SUB_PAGES: [OptionsSubState; 6] = [
+    OptionsSubState::Root,
+    OptionsSubState::KeyboardMouse,
+    OptionsSubState::GameSettings,
+    OptionsSubState::VideoSettings,
+    OptionsSubState::AudioSettings,
+    OptionsSubState::Credits,
+];
+
 #[derive(Component)]
 struct OptionsMenuRoot;
 
@@ -40,20 +59,24 @@ struct SubNavRoot;
 #[derive(Component)]
 struct SubNavButton(OptionsSubState);
 
+/// Back to the pause menu, only there when the options are open in game.
 #[derive(Component)]
-struct ApplyButton;
+struct OptionsBackButton;
 
 fn spawn_options_menu(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     state: Res<State<AppState>>,
+    menu: Option<Res<State<InGameMenu>>>,
+    config: Res<GameConfig>,
 ) {
     let font = asset_server.load("fonts/Inter-Bold.ttf");
 
-    let substate = match state.get() {
-        AppState::OptionsMenu(sub) => sub.clone(),
-        _ => return,
+    let Some(substate) = options_screen(state.get(), menu.as_deref().map(State::get)) else {
+        return;
     };
+    // i spelet syns världen bakom, så den mörkas ner
+    let in_game = menu.is_some();
 
     // Root container
     commands
@@ -67,7 +90,11 @@ fn spawn_options_menu(
                     margin: UiRect::top(Val::Px(48.0)), // lämna plats för huvud-navbar
                     ..default()
                 },
-                background_color: Color::NONE.into(),
+                background_color: if in_game {
+                    Color::srgba(0.0, 0.0, 0.0, 0.8).into()
+                } else {
+                    Color::NONE.into()
+                },
                 ..default()
             },
             OptionsMenuRoot,
@@ -100,6 +127,9 @@ fn spawn_options_menu(
                 SubNavRoot,
             ))
             .with_children(|bar| {
+                if in_game {
+                    spawn_back_button(bar, &font);
+                }
                 for (i, sub) in subs.iter().enumerate() {
                     spawn_subnav_button(bar, sub.clone(), &font, sub == &substate);
 
@@ -134,30 +164,76 @@ fn spawn_options_menu(
             })
             .with_children(|content| {
                 match substate {
-                    OptionsSubState::VideoSettings => spawn_video_settings(content, &font),
+                    OptionsSubState::VideoSettings => {
+                        spawn_video_settings(content, &font, &config);
+                        settings::spawn_settings_footer(content, &font);
+                    }
                     OptionsSubState::GameSettings => {
-                        spawn_dummy_section(content, &font, "GAME SETTINGS", &[
-                            ("Difficulty", "Normal"),
-                            ("HUD", "Enabled"),
-                            ("Crosshair", "Default"),
-                        ]);
+                        settings::spawn_section_title(content, &font, "GAME SETTINGS");
+                        settings::spawn_slider(content, &font, "Field of View", "fov_desired", 1.0, whole);
+                        settings::spawn_section_title(content, &font, "CROSSHAIR");
+                        settings::spawn_dropdown(
+                            content,
+                            &font,
+                            "Style",
+                            "cl_crosshairstyle",
+                            choice_labels(CROSSHAIR_STYLES),
+                        );
+                        settings::spawn_slider(content, &font, "Size", "cl_crosshairsize", 0.5, tenths);
+                        settings::spawn_slider(content, &font, "Gap", "cl_crosshairgap", 0.5, tenths);
+                        settings::spawn_slider(content, &font, "Thickness", "cl_crosshairthickness", 0.5, tenths);
+                        settings::spawn_toggle(content, &font, "Outline", "cl_crosshair_drawoutline");
+                        settings::spawn_toggle(content, &font, "Center Dot", "cl_crosshairdot");
+                        settings::spawn_dropdown(
+                            content,
+                            &font,
+                            "Color",
+                            "cl_crosshaircolor",
+                            choice_labels(CROSSHAIR_COLORS),
+                        );
+                        settings::spawn_slider(content, &font, "Opacity", "cl_crosshairalpha", 5.0, whole);
+                        settings::spawn_section_title(content, &font, "HUD");
+                        settings::spawn_slider(content, &font, "HUD Scale", "hud_scaling", 0.05, percent);
+                        settings::spawn_dropdown(
+                            content,
+                            &font,
+                            "HUD Color",
+                            "cl_hud_color",
+                            choice_labels(HUD_COLORS),
+                        );
+                        settings::spawn_dropdown(
+                            content,
+                            &font,
+                            "HUD Layout",
+                            "cl_hud_layout",
+                            choice_labels(HUD_LAYOUTS),
+                        );
+                        settings::spawn_slider(content, &font, "Kill Feed Lines", "cl_killfeed_lines", 1.0, whole);
+                        settings::spawn_settings_footer(content, &font);
                     }
                     OptionsSubState::KeyboardMouse => {
-                        spawn_dummy_section(content, &font, "KEYBOARD / MOUSE", &[
-                            ("Sensitivity", "1.2"),
-                            ("Invert Mouse", "Off"),
-                            ("Key Bindings", "[Open Menu]"),
-                        ]);
+                        settings::spawn_section_title(content, &font, "KEYBOARD / MOUSE");
+                        settings::spawn_slider(content, &font, "Sensitivity", "sensitivity", 0.05, hundredths);
+                        settings::spawn_slider(
+                            content,
+                            &font,
+                            "Zoom Sensitivity",
+                            "zoom_sensitivity_ratio",
+                            0.05,
+                            hundredths,
+                        );
+                        settings::spawn_toggle(content, &font, "Invert Mouse", "m_invert");
+                        settings::spawn_settings_footer(content, &font);
+                        keybindings::spawn_key_bindings(content, &font);
                     }
                     OptionsSubState::AudioSettings => {
-                        spawn_dummy_section(content, &font, "AUDIO SETTINGS", &[
-                            ("Master Volume", "80%"),
-                            ("Music Volume", "50%"),
-                            ("Voice Chat", "Push-To-Talk"),
-                        ]);
+                        settings::spawn_section_title(content, &font, "AUDIO SETTINGS");
+                        settings::spawn_slider(content, &font, "Master Volume", "volume", 0.01, percent);
+                        settings::spawn_slider(content, &font, "Music Volume", "snd_musicvolume", 0.01, percent);
+                        settings::spawn_settings_footer(content, &font);
                     }
                     OptionsSubState::Credits => {
-                        spawn_dummy_section(content, &font, "CREDITS", &[
+                        spawn_info_section(content, &font, "CREDITS", &[
                             ("Game Design", "Your Name"),
                             ("Programming", "Rust Devs"),
                             ("Engine", "Bevy"),
@@ -218,24 +294,68 @@ fn spawn_subnav_button(
         });
 }
 
+fn spawn_back_button(parent: &mut ChildBuilder, font: &Handle<Font>) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    padding: UiRect::axes(Val::Px(10.0), Val::Px(4.0)),
+                    margin: UiRect::right(Val::Px(24.0)),
+                    ..default()
+                },
+                background_color: Color::srgba(0.1, 0.1, 0.1, 0.8).into(),
+                ..default()
+            },
+            OptionsBackButton,
+        ))
+        .with_children(|btn| {
+            btn.spawn(TextBundle::from_section(
+                "< BACK",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 18.0,
+                    color: Color::srgb(0.80, 0.82, 0.86),
+                },
+            ));
+        });
+}
+
 fn subnav_button_interactions(
     mut q: Query<(&Interaction, &SubNavButton), (Changed<Interaction>, With<Button>)>,
+    menu: Option<Res<State<InGameMenu>>>,
     mut next_state: ResMut<NextState<AppState>>,
+    mut next_menu: ResMut<NextState<InGameMenu>>,
 ) {
     for (interaction, btn) in &mut q {
         if *interaction == Interaction::Pressed {
-            next_state.set(AppState::OptionsMenu(btn.0.clone()));
+            if menu.is_some() {
+                next_menu.set(InGameMenu::Options(btn.0.clone()));
+            } else {
+                next_state.set(AppState::OptionsMenu(btn.0.clone()));
+            }
+        }
+    }
+}
+
+fn back_button_interactions(
+    q: Query<&Interaction, (Changed<Interaction>, With<OptionsBackButton>)>,
+    mut next_menu: ResMut<NextState<InGameMenu>>,
+) {
+    for interaction in &q {
+        if *interaction == Interaction::Pressed {
+            next_menu.set(InGameMenu::Paused);
         }
     }
 }
 
 fn update_subnav_highlight(
     state: Res<State<AppState>>,
+    menu: Option<Res<State<InGameMenu>>>,
     mut q: Query<(&SubNavButton, &mut BorderColor, &mut BackgroundColor, &Children), With<Button>>,
     mut texts: Query<&mut Text>,
     interactions: Query<&Interaction, With<Button>>,
 ) {
-    let active = state.get().clone();
+    let active = options_screen(state.get(), menu.as_deref().map(State::get));
 
     for (btn, mut border, mut bg, children) in &mut q {
         if let Some(&child) = children.first() {
@@ -245,7 +365,7 @@ fn update_subnav_highlight(
                     .map(|i| matches!(*i, Interaction::Hovered))
                     .unwrap_or(false);
 
-                let is_active = matches!(active, AppState::OptionsMenu(ref sub) if *sub == btn.0);
+                let is_active = active.as_ref() == Some(&btn.0);
 
                 border.0 = if is_active {
                     Color::WHITE
@@ -268,72 +388,78 @@ fn update_subnav_highlight(
     }
 }
 
-fn spawn_video_settings(root: &mut ChildBuilder, font: &Handle<Font>) {
-    spawn_dummy_section(root, font, "VIDEO SETTINGS", &[
-        ("Brightness", "Slider [ ]"),
-        ("Resolution", "1920x1080"),
-        ("Display Mode", "Fullscreen"),
+/// Vanliga upplösningar, plus den nuvarande om den inte finns med
+const RESOLUTIONS: &[&str] = &["1280x720", "1600x900", "1920x1080", "2560x1440", "3840x2160"];
+
+fn spawn_video_settings(root: &mut ChildBuilder, font: &Handle<Font>, config: &GameConfig) {
+    let mut resolutions: Vec<String> = RESOLUTIONS.iter().map(|r| r.to_string()).collect();
+    if let Some(current) = config.get_str("cl_resolution") {
+        if !resolutions.iter().any(|r| r == current) {
+            resolutions.push(current.to_string());
+        }
+    }
+
+    settings::spawn_section_title(root, font, "VIDEO SETTINGS");
+    settings::spawn_dropdown(root, font, "Display Mode", "cl_fullscreen", vec![
+        ("Fullscreen".into(), "1".into()),
+        ("Windowed".into(), "0".into()),
     ]);
-    spawn_dummy_section(root, font, "ADVANCED VIDEO OPTIONS", &[
-        ("Shadow Quality", "Low"),
-        ("Texture Detail", "Medium"),
-        ("Anti-Aliasing", "None"),
+    settings::spawn_dropdown(
+        root,
+        font,
+        "Resolution",
+        "cl_resolution",
+        resolutions.into_iter().map(|r| (r.clone(), r)).collect(),
+    );
+    settings::spawn_toggle(root, font, "VSync", "cl_vsync");
+    settings::spawn_slider(root, font, "Max FPS", "fps_max", 10.0, fps_cap);
+    settings::spawn_slider(root, font, "Render Scale", "r_renderscale", 0.05, percent);
+    settings::spawn_dropdown(root, font, "Anti-Aliasing", "r_msaa", vec![
+        ("Off".into(), "1".into()),
+        ("2x MSAA".into(), "2".into()),
+        ("4x MSAA".into(), "4".into()),
+        ("8x MSAA".into(), "8".into()),
     ]);
+    settings::spawn_dropdown(root, font, "Shadows", "r_shadows", choice_labels(SHADOW_QUALITY));
+}
+
+fn fps_cap(v: f32) -> String {
+    if v < 1.0 { "Unlimited".into() } else { format!("{v:.0}") }
+}
+
+fn choice_labels(choices: &[&str]) -> Vec<(String, String)> {
+    choices
+        .iter()
+        .map(|c| {
+            let mut label = c.to_string();
+            label[..1].make_ascii_uppercase();
+            (label, c.to_string())
+        })
+        .collect()
+}
+
+fn percent(v: f32) -> String {
+    format!("{:.0}%", v * 100.0)
 }
 
-fn spawn_dummy_section(root: &mut ChildBuilder, font: &Handle<Font>, title: &str, rows: &[(&str, &str)]) {
-    root.spawn(TextBundle::from_section(
-        title,
-        TextStyle {
-            font: font.clone(),
-            font_size: 20.0,
-            color: Color::srgb(0.5, 0.7, 0.9),
-        },
-    ));
+fn whole(v: f32) -> String {
+    format!("{v:.0}")
+}
+
+fn tenths(v: f32) -> String {
+    format!("{v:.1}")
+}
+
+fn hundredths(v: f32) -> String {
+    format!("{v:.2}")
+}
+
+fn spawn_info_section(root: &mut ChildBuilder, font: &Handle<Font>, title: &str, rows: &[(&str, &str)]) {
+    settings::spawn_section_title(root, font, title);
 
     for (label, value) in rows {
         spawn_setting_row(root, font, label, value);
     }
-
-    if title.contains("VIDEO") {
-        root.spawn(NodeBundle {
-            style: Style {
-                display: Display::Flex,
-                justify_content: JustifyContent::SpaceBetween,
-                margin: UiRect::top(Val::Px(20.0)),
-                ..default()
-            },
-            ..default()
-        })
-        .with_children(|buttons| {
-            spawn_button(buttons, font, "BACK");
-            spawn_button(buttons, font, "RESTORE DEFAULTS");
-
-            // Apply-knapp markerad
-            buttons
-                .spawn((
-                    ButtonBundle {
-                        style: Style {
-                            padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
-                            ..default()
-                        },
-                        background_color: Color::srgba(0.1, 0.1, 0.1, 0.8).into(),
-                        ..default()
-                    },
-                    ApplyButton,
-                ))
-                .with_children(|btn| {
-                    btn.spawn(TextBundle::from_section(
-                        "APPLY",
-                        TextStyle {
-                            font: font.clone(),
-                            font_size: 16.0,
-                            color: Color::srgb(0.9, 0.9, 0.9),
-                        },
-                    ));
-                });
-        });
-    }
 }
 
 fn spawn_setting_row(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, value: &str) {
@@ -369,68 +495,6 @@ fn spawn_setting_row(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str
         });
 }
 
-fn spawn_button(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str) {
-    parent
-        .spawn(ButtonBundle {
-            style: Style {
-                padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
-                ..default()
-            },
-            background_color: Color::srgba(0.1, 0.1, 0.1, 0.8).into(),
-            ..default()
-        })
-        .with_children(|btn| {
-            btn.spawn(TextBundle::from_section(
-                label,
-                TextStyle {
-                    font: font.clone(),
-                    font_size: 16.0,
-                    color: Color::srgb(0.9, 0.9, 0.9),
-                },
-            ));
-        });
-}
-
-fn apply_window_settings(
-    mut windows: Query<&mut Window>,
-    config: ResMut<GameConfig>,
-    interaction_q: Query<&Interaction, With<ApplyButton>>,
-) {
-    for interaction in &interaction_q {
-        if *interaction == Interaction::Pressed {
-            if let Ok(mut window) = windows.get_single_mut() {
-                // Fullscreen
-                let fullscreen = config.values.get("cl_fullscreen").map(|s| s == "1").unwrap_or(false);
-                window.mode = if fullscreen {
-                    WindowMode::Fullscreen
-                } else {
-                    WindowMode::Windowed
-                };
-
-                // Resolution
-                if let Some(res) = config.values.get("cl_resolution") {
-                    if let Some((w,h)) = res.split_once('x') {
-                        if let (Ok(w), Ok(h)) = (w.parse::<f32>(), h.parse::<f32>()) {
-                            window.resolution.set(w,h);
-                        }
-                    }
-                }
-
-                // VSync
-                let vsync = config.values.get("cl_vsync").map(|s| s == "1").unwrap_or(true);
-                window.present_mode = if vsync {
-                    PresentMode::AutoVsync
-                } else {
-                    PresentMode::AutoNoVsync
-                };
-    
-            }
-
-            config.save();
-            info!("Applied & saved config.cfg");
-        }
-    }
-}
 fn cleanup_options_menu(mut commands: Commands, q: Query<Entity, With<OptionsMenuRoot>>) {
     for e in &q {
         commands.entity(e).despawn_recursive();
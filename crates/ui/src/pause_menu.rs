@@ -0,0 +1,189 @@
+//! Escape menu while in game.
+//!
+//! The menu is the `InGameMenu` sub-state, so opening it or the options on top of it
+//! doesn't leave `AppState::InGame` and the world stays. Local input is blocked while it's
+//! open, which also lets go of the cursor. A local game is paused; a networked one keeps
+//! running since the server doesn't wait for anybody.
+use bevy::prelude::*;
+use shared::actions::{ActionState, ActionsPlugin};
+use shared::config::GameConfig;
+use shared::types::{InGameMenu, OptionsSubState};
+use shared::AppState;
+
+pub struct PauseMenuPlugin;
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ActionsPlugin>() {
+            app.add_plugins(ActionsPlugin);
+        }
+        app.add_sub_state::<InGameMenu>()
+            .add_systems(OnEnter(InGameMenu::Paused), spawn_pause_menu)
+            .add_systems(OnExit(InGameMenu::Paused), cleanup_pause_menu)
+            .add_systems(OnExit(InGameMenu::Closed), suspend_game)
+            .add_systems(OnEnter(InGameMenu::Closed), resume_game)
+            .add_systems(OnExit(AppState::InGame), resume_game)
+            .add_systems(
+                Update,
+                (toggle_pause_menu, pause_button_interactions).run_if(in_state(AppState::InGame)),
+            );
+    }
+}
+
+#[derive(Component)]
+struct PauseMenuRoot;
+
+#[derive(Component, Clone, Copy)]
+enum PauseButton {
+    Resume,
+    Options,
+    Disconnect,
+    Quit,
+}
+
+impl PauseButton {
+    fn label(self) -> &'static str {
+        match self {
+            PauseButton::Resume => "RESUME",
+            PauseButton::Options => "OPTIONS",
+            PauseButton::Disconnect => "DISCONNECT",
+            PauseButton::Quit => "QUIT GAME",
+        }
+    }
+}
+
+fn toggle_pause_menu(
+    keys: Res<ButtonInput<KeyCode>>,
+    actions: Res<ActionState>,
+    menu: Res<State<InGameMenu>>,
+    mut next_menu: ResMut<NextState<InGameMenu>>,
+) {
+    // konsolen och ombindningen förbrukar Escape när de stänger, så ordningen spelar ingen
+    // roll: körs de först är trycket borta, körs vi först är de fortfarande blockerande.
+    // Chatten stänger via egui och släpper blockeringen först nästa frame.
+    let escape_taken = ["console", "rebind", "chat"].iter().any(|s| actions.is_blocked_by(s));
+    if !keys.just_pressed(KeyCode::Escape) || escape_taken {
+        return;
+    }
+    next_menu.set(match menu.get() {
+        InGameMenu::Closed => InGameMenu::Paused,
+        InGameMenu::Paused => InGameMenu::Closed,
+        InGameMenu::Options(_) => InGameMenu::Paused,
+    });
+}
+
+fn suspend_game(
+    config: Res<GameConfig>,
+    mut actions: ResMut<ActionState>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    actions.set_blocked("pause", true);
+    if !config.server_authoritative {
+        time.pause();
+    }
+}
+
+fn resume_game(mut actions: ResMut<ActionState>, mut time: ResMut<Time<Virtual>>) {
+    actions.set_blocked("pause", false);
+    time.unpause();
+}
+
+fn spawn_pause_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/Inter-Bold.ttf");
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
+                background_color: Color::srgba(0.0, 0.0, 0.0, 0.6).into(),
+                z_index: ZIndex::Global(20),
+                ..default()
+            },
+            PauseMenuRoot,
+        ))
+        .with_children(|root| {
+            root.spawn(
+                TextBundle::from_section(
+                    "PAUSED",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 36.0,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::bottom(Val::Px(16.0)),
+                    ..default()
+                }),
+            );
+
+            for button in [
+                PauseButton::Resume,
+                PauseButton::Options,
+                PauseButton::Disconnect,
+                PauseButton::Quit,
+            ] {
+                root.spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(240.0),
+                            padding: UiRect::axes(Val::Px(12.0), Val::Px(8.0)),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        background_color: Color::srgba(0.1, 0.1, 0.1, 0.8).into(),
+                        ..default()
+                    },
+                    button,
+                ))
+                .with_children(|btn| {
+                    btn.spawn(TextBundle::from_section(
+                        button.label(),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 20.0,
+                            color: Color::srgb(0.9, 0.9, 0.9),
+                        },
+                    ));
+                });
+            }
+        });
+}
+
+fn cleanup_pause_menu(mut commands: Commands, q: Query<Entity, With<PauseMenuRoot>>) {
+    for e in &q {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+fn pause_button_interactions(
+    mut q: Query<(&Interaction, &PauseButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut next_menu: ResMut<NextState<InGameMenu>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for (interaction, button, mut bg) in &mut q {
+        match *interaction {
+            Interaction::Pressed => match button {
+                PauseButton::Resume => next_menu.set(InGameMenu::Closed),
+                PauseButton::Options => next_menu.set(InGameMenu::Options(OptionsSubState::Root)),
+                // att lämna InGame river världen
+                PauseButton::Disconnect => next_state.set(AppState::MainMenu),
+                PauseButton::Quit => {
+                    exit.send(AppExit::Success);
+                }
+            },
+            Interaction::Hovered => bg.0 = Color::srgba(0.2, 0.2, 0.2, 0.9),
+            Interaction::None => bg.0 = Color::srgba(0.1, 0.1, 0.1, 0.8),
+        }
+    }
+}
@@ -1,4 +1,17 @@
+use std::time::Instant;
+
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
 use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use net::browser::{JoinServer, ServerBrowserPlugin};
+use net::matchmaking::{MatchmakingClient, MatchmakingPlugin, MatchmakingRequest, MatchmakingStatus, PartyMember};
+use shared::actions::ActionState;
+use shared::chat::{ChatChannel, ChatLog, ChatMessage, SendChat};
+use shared::lobby::{
+    GameMode, Lobby, LobbyPermissions, LobbyPlugin, LobbyRequest, MatchedServer, MAX_LOBBY_MEMBERS,
+};
+use shared::maps::MapId;
 use shared::AppState;
 
 // återanvändbara komponenter
@@ -9,12 +22,30 @@ pub struct PlayMenuPlugin;
 
 impl Plugin for PlayMenuPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(SelectedMap(MapId::Tutorial)) // default
-            .add_systems(OnEnter(AppState::PlayMenu), spawn_play_menu)
+        if !app.is_plugin_added::<LobbyPlugin>() {
+            app.add_plugins(LobbyPlugin);
+        }
+        if !app.is_plugin_added::<MatchmakingPlugin>() {
+            app.add_plugins(MatchmakingPlugin);
+        }
+        if !app.is_plugin_added::<ServerBrowserPlugin>() {
+            app.add_plugins(ServerBrowserPlugin);
+        }
+        app.add_systems(OnEnter(AppState::PlayMenu), spawn_play_menu)
             .add_systems(OnExit(AppState::PlayMenu), cleanup_play_menu)
             .add_systems(Update, (
                 map_button_interactions,
+                lobby_setting_interactions,
                 play_button_interactions,
+                update_lobby_widgets,
+                update_lobby_members,
+                start_match,
+                lobby_chat_typing,
+                update_lobby_chat,
+                ready_check_popup,
+                share_matched_game,
+                join_matched_game.after(share_matched_game),
+                report_matchmaking_errors,
             ).run_if(in_state(AppState::PlayMenu)));
     }
 }
@@ -25,17 +56,41 @@ struct PlayMenuRoot;
 #[derive(Component)]
 struct MapButton(MapId);
 
-#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
-pub enum MapId {
-    Tutorial,
-    BoxArena,
+/// Lobby settings shown in the left column. Only the leader can change them.
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum LobbySetting {
+    Mode,
+    Permissions,
+    Leader,
 }
 
-#[derive(Resource, Debug, Clone, Copy)]
-pub struct SelectedMap(pub MapId);
+#[derive(Component)]
+struct LobbySettingText(LobbySetting);
+
+/// Raden med medlemmar, byggs om när lobbyn ändras
+#[derive(Component)]
+struct LobbyMembersRoot;
+
+#[derive(Component)]
+struct PlayButtonText;
+
+/// Chatthistoriken, byggs om när loggen ändras
+#[derive(Component)]
+struct LobbyChatHistory;
 
-fn spawn_play_menu(mut commands: Commands, asset_server: Res<AssetServer>, selected: Res<SelectedMap>) {
+/// The line being typed into the lobby chat.
+#[derive(Component, Default)]
+struct LobbyChatInput(String);
+
+/// Lobby chat lines kept on screen.
+const LOBBY_CHAT_LINES: usize = 14;
+
+#[derive(Resource, Clone)]
+struct PlayMenuFont(Handle<Font>);
+
+fn spawn_play_menu(mut commands: Commands, asset_server: Res<AssetServer>, lobby: Res<Lobby>) {
     let font = asset_server.load("fonts/Inter-Bold.ttf");
+    commands.insert_resource(PlayMenuFont(font.clone()));
 
     // Root
     commands.spawn((
@@ -81,8 +136,9 @@ fn spawn_play_menu(mut commands: Commands, asset_server: Res<AssetServer>, selec
                 },
             ));
 
-            spawn_game_setting(left, &font, "Permissions", "Invited Friends Only");
-            spawn_game_setting(left, &font, "Lobby Leader", "Uber- (Global Elite)");
+            spawn_game_setting(left, &font, "Game Mode", LobbySetting::Mode);
+            spawn_game_setting(left, &font, "Permissions", LobbySetting::Permissions);
+            spawn_game_setting(left, &font, "Lobby Leader", LobbySetting::Leader);
 
             // Kartväljare
             left.spawn(TextBundle::from_section(
@@ -94,8 +150,8 @@ fn spawn_play_menu(mut commands: Commands, asset_server: Res<AssetServer>, selec
                 },
             ));
 
-            for map in [MapId::Tutorial, MapId::BoxArena] {
-                spawn_map_button(left, &font, map, map == selected.0);
+            for map in MapId::ALL {
+                spawn_map_button(left, &font, map, map == lobby.map);
             }
         });
 
@@ -112,22 +168,20 @@ fn spawn_play_menu(mut commands: Commands, asset_server: Res<AssetServer>, selec
             ..default()
         })
         .with_children(|right| {
-            // Lobby player list
-            right.spawn(NodeBundle {
-                style: Style {
-                    flex_direction: FlexDirection::Row,
-                    justify_content: JustifyContent::FlexStart,
-                    column_gap: Val::Px(12.0),
-                    padding: UiRect::all(Val::Px(8.0)),
+            // Lobby player list, fylls av update_lobby_members
+            right.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        justify_content: JustifyContent::FlexStart,
+                        column_gap: Val::Px(12.0),
+                        padding: UiRect::all(Val::Px(8.0)),
+                        ..default()
+                    },
                     ..default()
                 },
-                ..default()
-            })
-            .with_children(|players| {
-                for name in ["Uber", "DOXA", "CapuRoss", "Jeffty", "Professor Manly"] {
-                    spawn_lobby_player(players, &font, name);
-                }
-            });
+                LobbyMembersRoot,
+            ));
 
             // Chat box
             right.spawn(NodeBundle {
@@ -141,13 +195,34 @@ fn spawn_play_menu(mut commands: Commands, asset_server: Res<AssetServer>, selec
                 ..default()
             })
             .with_children(|chat| {
-                chat.spawn(TextBundle::from_section(
-                    "Connected to lobby...\nUber: Hello team!\nJeffty: Ready!",
-                    TextStyle {
-                        font: font.clone(),
-                        font_size: 14.0,
-                        color: Color::srgb(0.9, 0.9, 0.9),
+                chat.spawn((
+                    NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            justify_content: JustifyContent::FlexEnd,
+                            flex_grow: 1.0,
+                            padding: UiRect::all(Val::Px(6.0)),
+                            overflow: Overflow::clip(),
+                            ..default()
+                        },
+                        ..default()
                     },
+                    LobbyChatHistory,
+                ));
+                chat.spawn((
+                    TextBundle::from_section(
+                        "> ",
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 14.0,
+                            color: Color::srgb(0.7, 0.85, 1.0),
+                        },
+                    )
+                    .with_style(Style {
+                        padding: UiRect::all(Val::Px(6.0)),
+                        ..default()
+                    }),
+                    LobbyChatInput::default(),
                 ));
             });
 
@@ -172,10 +247,7 @@ fn spawn_play_menu(mut commands: Commands, asset_server: Res<AssetServer>, selec
 /// ==== Helpers ====
 
 fn spawn_map_button(parent: &mut ChildBuilder, font: &Handle<Font>, map: MapId, active: bool) {
-    let label = match map {
-        MapId::Tutorial => "Tutorial Map",
-        MapId::BoxArena => "Box Arena",
-    };
+    let label = map.label();
 
     parent
         .spawn((
@@ -207,28 +279,30 @@ fn spawn_map_button(parent: &mut ChildBuilder, font: &Handle<Font>, map: MapId,
 }
 
 fn map_button_interactions(
-    mut q: Query<(&Interaction, &MapButton, &mut BackgroundColor), (Changed<Interaction>, With<Button>)>,
-    mut selected: ResMut<SelectedMap>,
+    q: Query<(&Interaction, &MapButton), (Changed<Interaction>, With<Button>)>,
+    lobby: Res<Lobby>,
+    mut requests: EventWriter<LobbyRequest>,
 ) {
-    for (interaction, btn, mut bg) in &mut q {
-        if *interaction == Interaction::Pressed {
-            selected.0 = btn.0;
-            *bg = Color::srgb(0.2, 0.4, 0.7).into();
-            info!("Selected map: {:?}", selected.0);
+    for (interaction, btn) in &q {
+        if *interaction == Interaction::Pressed && lobby.local_is_leader() {
+            requests.send(LobbyRequest::SetMap(btn.0));
         }
     }
 }
 
-fn spawn_game_setting(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, value: &str) {
-    parent.spawn(NodeBundle {
-        style: Style {
-            flex_direction: FlexDirection::Column,
-            padding: UiRect::all(Val::Px(8.0)),
+fn spawn_game_setting(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, setting: LobbySetting) {
+    parent.spawn((
+        ButtonBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            background_color: Color::srgba(0.1, 0.1, 0.15, 0.6).into(),
             ..default()
         },
-        background_color: Color::srgba(0.1, 0.1, 0.15, 0.6).into(),
-        ..default()
-    })
+        setting,
+    ))
     .with_children(|row| {
         row.spawn(TextBundle::from_section(
             label,
@@ -238,18 +312,139 @@ fn spawn_game_setting(parent: &mut ChildBuilder, font: &Handle<Font>, label: &st
                 color: Color::srgb(0.8, 0.8, 0.9),
             },
         ));
-        row.spawn(TextBundle::from_section(
-            value,
-            TextStyle {
-                font: font.clone(),
-                font_size: 14.0,
-                color: Color::srgb(0.9, 0.9, 0.9),
-            },
+        row.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 14.0,
+                    color: Color::srgb(0.9, 0.9, 0.9),
+                },
+            ),
+            LobbySettingText(setting),
         ));
     });
 }
 
-fn spawn_lobby_player(parent: &mut ChildBuilder, font: &Handle<Font>, name: &str) {
+/// Klick på läge eller behörighet stegar till nästa val
+fn lobby_setting_interactions(
+    q: Query<(&Interaction, &LobbySetting), Changed<Interaction>>,
+    lobby: Res<Lobby>,
+    mut requests: EventWriter<LobbyRequest>,
+) {
+    if !lobby.local_is_leader() {
+        return;
+    }
+    for (interaction, setting) in &q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match setting {
+            LobbySetting::Mode => {
+                let next = GameMode::ALL.iter().position(|m| *m == lobby.mode).map_or(0, |i| i + 1);
+                requests.send(LobbyRequest::SetMode(GameMode::ALL[next % GameMode::ALL.len()]));
+            }
+            LobbySetting::Permissions => {
+                let all = LobbyPermissions::ALL;
+                let next = all.iter().position(|p| *p == lobby.permissions).map_or(0, |i| i + 1);
+                requests.send(LobbyRequest::SetPermissions(all[next % all.len()]));
+            }
+            LobbySetting::Leader => {}
+        }
+    }
+}
+
+fn update_lobby_widgets(
+    lobby: Res<Lobby>,
+    matchmaking: Res<MatchmakingClient>,
+    mut settings: Query<(&LobbySettingText, &mut Text), Without<PlayButtonText>>,
+    mut play_text: Query<&mut Text, With<PlayButtonText>>,
+    mut maps: Query<(&MapButton, &mut BackgroundColor, &Interaction)>,
+    spawned: Query<(), Added<PlayButtonText>>,
+) {
+    for (btn, mut bg, interaction) in &mut maps {
+        *bg = if btn.0 == lobby.map {
+            Color::srgb(0.2, 0.4, 0.7).into()
+        } else if *interaction == Interaction::Hovered && lobby.local_is_leader() {
+            Color::srgba(0.15, 0.15, 0.2, 0.9).into()
+        } else {
+            Color::srgba(0.1, 0.1, 0.1, 0.8).into()
+        };
+    }
+    // söktiden räknas upp varje frame
+    let searching = matches!(matchmaking.status, MatchmakingStatus::Searching { .. });
+    if !lobby.is_changed() && !matchmaking.is_changed() && !searching && spawned.is_empty() {
+        return;
+    }
+
+    for (setting, mut text) in &mut settings {
+        text.sections[0].value = match setting.0 {
+            LobbySetting::Mode => lobby.mode.label().to_string(),
+            LobbySetting::Permissions => lobby.permissions.label().to_string(),
+            LobbySetting::Leader => lobby.member(lobby.leader).map(|m| m.name.clone()).unwrap_or_default(),
+        };
+    }
+    for mut text in &mut play_text {
+        text.sections[0].value = play_button_label(&lobby, &matchmaking.status);
+    }
+}
+
+fn play_button_label(lobby: &Lobby, matchmaking: &MatchmakingStatus) -> String {
+    match matchmaking {
+        MatchmakingStatus::Idle => {}
+        MatchmakingStatus::Searching { since, .. } => {
+            let secs = since.elapsed().as_secs();
+            return format!("SEARCHING {}:{:02}", secs / 60, secs % 60);
+        }
+        MatchmakingStatus::MatchFound { .. } => return "MATCH FOUND".into(),
+        MatchmakingStatus::Ready { .. } => return "CONNECTING".into(),
+    }
+    let label = if lobby.local_is_leader() {
+        if lobby.all_ready() { "PLAY" } else { "WAITING FOR PLAYERS" }
+    } else if lobby.local_member().is_some_and(|m| m.ready) {
+        "NOT READY"
+    } else {
+        "READY"
+    };
+    label.to_string()
+}
+
+fn update_lobby_members(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    font: Res<PlayMenuFont>,
+    roots: Query<Entity, With<LobbyMembersRoot>>,
+    spawned: Query<(), Added<LobbyMembersRoot>>,
+) {
+    if !lobby.is_changed() && spawned.is_empty() {
+        return;
+    }
+    for root in &roots {
+        commands.entity(root).despawn_descendants().with_children(|players| {
+            for member in &lobby.members {
+                spawn_lobby_player(players, &font.0, &member.name, member.id == lobby.leader, member.ready);
+            }
+            // tomma platser
+            for _ in lobby.members.len()..MAX_LOBBY_MEMBERS {
+                spawn_empty_slot(players);
+            }
+        });
+    }
+}
+
+fn spawn_empty_slot(parent: &mut ChildBuilder) {
+    parent.spawn(NodeBundle {
+        style: Style {
+            width: Val::Px(60.0),
+            height: Val::Px(80.0),
+            ..default()
+        },
+        background_color: Color::srgba(0.12, 0.12, 0.18, 0.3).into(),
+        ..default()
+    });
+}
+
+fn spawn_lobby_player(parent: &mut ChildBuilder, font: &Handle<Font>, name: &str, leader: bool, ready: bool) {
     parent.spawn(NodeBundle {
         style: Style {
             flex_direction: FlexDirection::Column,
@@ -265,9 +460,16 @@ fn spawn_lobby_player(parent: &mut ChildBuilder, font: &Handle<Font>, name: &str
             style: Style {
                 width: Val::Px(48.0),
                 height: Val::Px(48.0),
+                border: UiRect::all(Val::Px(2.0)),
                 ..default()
             },
             background_color: Color::srgb(0.3, 0.3, 0.4).into(),
+            // grön ram när man är redo
+            border_color: if ready || leader {
+                Color::srgb(0.3, 0.8, 0.3).into()
+            } else {
+                Color::srgba(1.0, 1.0, 1.0, 0.1).into()
+            },
             ..default()
         });
         p.spawn(TextBundle::from_section(
@@ -278,6 +480,14 @@ fn spawn_lobby_player(parent: &mut ChildBuilder, font: &Handle<Font>, name: &str
                 color: Color::srgb(0.9, 0.9, 0.9),
             },
         ));
+        p.spawn(TextBundle::from_section(
+            if leader { "LEADER" } else if ready { "READY" } else { "NOT READY" },
+            TextStyle {
+                font: font.clone(),
+                font_size: 11.0,
+                color: Color::srgb(0.6, 0.6, 0.7),
+            },
+        ));
     });
 }
 
@@ -317,13 +527,16 @@ fn spawn_play_button(parent: &mut ChildBuilder, font: &Handle<Font>) {
         PlayButton,
     ))
     .with_children(|btn| {
-        btn.spawn(TextBundle::from_section(
-            "PLAY",
-            TextStyle {
-                font: font.clone(),
-                font_size: 18.0,
-                color: Color::WHITE,
-            },
+        btn.spawn((
+            TextBundle::from_section(
+                "PLAY",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 18.0,
+                    color: Color::WHITE,
+                },
+            ),
+            PlayButtonText,
         ));
     });
 }
@@ -333,16 +546,26 @@ struct PlayButton;
 
 fn play_button_interactions(
     mut q: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<PlayButton>)>,
-    mut next_state: ResMut<NextState<AppState>>,
-    selected: Res<SelectedMap>,
+    lobby: Res<Lobby>,
+    mut matchmaking: ResMut<MatchmakingClient>,
+    mut requests: EventWriter<LobbyRequest>,
+    mut search: EventWriter<MatchmakingRequest>,
 ) {
     for (interaction, mut bg) in &mut q {
         match *interaction {
             Interaction::Pressed => {
                 *bg = Color::srgb(0.1, 0.4, 0.1).into();
-                // Gå till Loading screen och ta med vald karta
-                next_state.set(AppState::Loading);
-                info!("Starting game with map {:?}", selected.0);
+                // ledaren startar, de andra växlar redo
+                if lobby.local_is_leader() && lobby.mode.matchmaking_team_size().is_some() {
+                    matchmaking_button(&lobby, &mut matchmaking, &mut search);
+                } else if lobby.local_is_leader() {
+                    if lobby.can_start(lobby.local_id()) {
+                        requests.send(LobbyRequest::Start);
+                    }
+                } else {
+                    let ready = lobby.local_member().is_some_and(|m| m.ready);
+                    requests.send(LobbyRequest::SetReady(!ready));
+                }
             }
             Interaction::Hovered => {
                 *bg = Color::srgb(0.3, 0.7, 0.3).into();
@@ -354,6 +577,215 @@ fn play_button_interactions(
     }
 }
 
+/// Rated modes are started by queueing the whole lobby as a party; pressing again cancels.
+fn matchmaking_button(
+    lobby: &Lobby,
+    matchmaking: &mut MatchmakingClient,
+    search: &mut EventWriter<MatchmakingRequest>,
+) {
+    match matchmaking.status {
+        MatchmakingStatus::Idle => {
+            let team_size = lobby.mode.matchmaking_team_size().unwrap_or(0);
+            if lobby.members.len() > team_size {
+                matchmaking.last_error =
+                    Some(format!("{} takes parties of up to {team_size} players", lobby.mode.label()));
+            } else if lobby.can_start(lobby.local_id()) {
+                let party = lobby
+                    .members
+                    .iter()
+                    .map(|m| PartyMember { id: m.id, name: m.name.clone() })
+                    .collect();
+                search.send(MatchmakingRequest::Search { party, mode: lobby.mode, maps: vec![lobby.map] });
+            }
+        }
+        MatchmakingStatus::Searching { .. } => {
+            search.send(MatchmakingRequest::Cancel);
+        }
+        MatchmakingStatus::MatchFound { .. } | MatchmakingStatus::Ready { .. } => {}
+    }
+}
+
+/// Accept/decline window while a found match waits for everybody.
+fn ready_check_popup(
+    mut contexts: EguiContexts,
+    matchmaking: Res<MatchmakingClient>,
+    mut requests: EventWriter<MatchmakingRequest>,
+) {
+    let MatchmakingStatus::MatchFound { mode, map, deadline, accepted_by_us, accepted, needed, .. } =
+        &matchmaking.status
+    else {
+        return;
+    };
+    let seconds_left = deadline.saturating_duration_since(Instant::now()).as_secs();
+
+    egui::Window::new("MATCH FOUND")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                ui.label(egui::RichText::new(format!("{} on {}", mode.label(), map.label())).size(18.0));
+                ui.label(format!("{seconds_left} s"));
+                if *needed > 0 {
+                    ui.label(format!("{accepted}/{needed} accepted"));
+                }
+                ui.add_space(8.0);
+                if *accepted_by_us {
+                    ui.label("Waiting for other players...");
+                    return;
+                }
+                ui.horizontal(|ui| {
+                    if ui.button(egui::RichText::new("ACCEPT").size(18.0)).clicked() {
+                        requests.send(MatchmakingRequest::Accept);
+                    }
+                    if ui.button(egui::RichText::new("DECLINE").size(18.0)).clicked() {
+                        requests.send(MatchmakingRequest::Decline);
+                    }
+                });
+            });
+        });
+}
+
+/// Hands the match the matchmaker put us in, and the party's tickets for it, to the lobby.
+/// Only the leader queues, so only the leader gets them.
+fn share_matched_game(mut matchmaking: ResMut<MatchmakingClient>, mut requests: EventWriter<LobbyRequest>) {
+    let MatchmakingStatus::Ready { server, map, tickets } = std::mem::take(&mut matchmaking.status) else {
+        return;
+    };
+    let Some(match_id) = tickets.first().map(|t| t.match_id) else {
+        return;
+    };
+    let tickets = tickets.iter().map(|t| (t.player_id, t.token)).collect();
+    requests.send(LobbyRequest::Matched(MatchedServer { match_id, server, map, tickets }));
+}
+
+/// Every member goes to the match the leader shared, with their own ticket.
+fn join_matched_game(lobby: Res<Lobby>, mut joined: Local<Option<u64>>, mut joins: EventWriter<JoinServer>) {
+    let Some(matched) = lobby.matched.as_ref().filter(|m| *joined != Some(m.match_id)) else {
+        return;
+    };
+    *joined = Some(matched.match_id);
+    let Some(ticket) = matched.ticket(lobby.local_id()) else {
+        warn!("match {} has no ticket for us", matched.match_id);
+        return;
+    };
+    info!("Matched on {}, {} on {}", matched.server, lobby.mode.label(), matched.map.label());
+    joins.send(JoinServer { addr: matched.server, map: matched.map, remember: false, ticket: Some(ticket) });
+}
+
+/// Varför en sökning tog slut visas i lobbychatten
+fn report_matchmaking_errors(
+    matchmaking: Res<MatchmakingClient>,
+    mut shown: Local<Option<String>>,
+    mut messages: EventWriter<ChatMessage>,
+) {
+    if matchmaking.last_error != *shown {
+        shown.clone_from(&matchmaking.last_error);
+        if let Some(error) = &matchmaking.last_error {
+            messages.send(ChatMessage::notice(error.clone()));
+        }
+    }
+}
+
+/// Allt man skriver i spelmenyn går till lobbychatten
+fn lobby_chat_typing(
+    mut keys: EventReader<KeyboardInput>,
+    actions: Res<ActionState>,
+    mut input: Query<(&mut LobbyChatInput, &mut Text)>,
+    mut send: EventWriter<SendChat>,
+) {
+    let Ok((mut line, mut text)) = input.get_single_mut() else {
+        return;
+    };
+    for key in keys.read() {
+        // konsolen har tangentbordet
+        if key.state != ButtonState::Pressed || actions.is_blocked_by("console") {
+            continue;
+        }
+        match &key.logical_key {
+            Key::Character(c) => line.0.push_str(c),
+            Key::Space => line.0.push(' '),
+            Key::Backspace => {
+                line.0.pop();
+            }
+            Key::Enter => {
+                let message = std::mem::take(&mut line.0);
+                if !message.trim().is_empty() {
+                    send.send(SendChat {
+                        channel: ChatChannel::Lobby,
+                        text: message,
+                    });
+                }
+            }
+            Key::Escape => line.0.clear(),
+            _ => {}
+        }
+    }
+    if line.is_changed() {
+        text.sections[0].value = format!("> {}", line.0);
+    }
+}
+
+fn update_lobby_chat(
+    mut commands: Commands,
+    log: Res<ChatLog>,
+    font: Res<PlayMenuFont>,
+    roots: Query<Entity, With<LobbyChatHistory>>,
+    spawned: Query<(), Added<LobbyChatHistory>>,
+) {
+    if !log.is_changed() && spawned.is_empty() {
+        return;
+    }
+    let mut lines: Vec<_> = log
+        .lines()
+        .rev()
+        .filter(|line| matches!(line.message.channel, ChatChannel::Lobby | ChatChannel::Server))
+        .take(LOBBY_CHAT_LINES)
+        .collect();
+    lines.reverse();
+
+    for root in &roots {
+        commands.entity(root).despawn_descendants().with_children(|history| {
+            if lines.is_empty() {
+                history.spawn(TextBundle::from_section(
+                    "Connected to lobby",
+                    TextStyle {
+                        font: font.0.clone(),
+                        font_size: 14.0,
+                        color: Color::srgb(0.6, 0.6, 0.7),
+                    },
+                ));
+            }
+            for line in &lines {
+                let message = &line.message;
+                let style = |color| TextStyle {
+                    font: font.0.clone(),
+                    font_size: 14.0,
+                    color,
+                };
+                let sections = match &message.sender {
+                    Some(sender) => vec![
+                        TextSection::new(format!("{sender}: "), style(Color::srgb(0.7, 0.85, 1.0))),
+                        TextSection::new(message.text.clone(), style(Color::srgb(0.9, 0.9, 0.9))),
+                    ],
+                    None => vec![TextSection::new(message.text.clone(), style(Color::srgb(0.95, 0.8, 0.3)))],
+                };
+                history.spawn(TextBundle::from_sections(sections));
+            }
+        });
+    }
+}
+
+/// Everyone in the lobby heads for the match once the leader has started it.
+fn start_match(mut lobby: ResMut<Lobby>, mut next_state: ResMut<NextState<AppState>>) {
+    if lobby.starting {
+        lobby.starting = false;
+        // Gå till Loading screen och ta med vald karta
+        next_state.set(AppState::Loading);
+        info!("Starting {} on {}", lobby.mode.label(), lobby.map.label());
+    }
+}
+
 fn cleanup_play_menu(mut commands: Commands, q: Query<Entity, With<PlayMenuRoot>>) {
     for e in &q {
         commands.entity(e).despawn_recursive();
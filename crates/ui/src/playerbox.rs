@@ -1,8 +1,29 @@
 use bevy::prelude::*;
+use net::matchmaking::{MatchmakingClient, MatchmakingPlugin};
+use net::profile::{ProfileClient, ProfilePlugin};
+use shared::lobby::Lobby;
+
+pub struct PlayerBoxPlugin;
+
+impl Plugin for PlayerBoxPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<MatchmakingPlugin>() {
+            app.add_plugins(MatchmakingPlugin);
+        }
+        if !app.is_plugin_added::<ProfilePlugin>() {
+            app.add_plugins(ProfilePlugin);
+        }
+        app.add_systems(Update, update_playerbox);
+    }
+}
 
 #[derive(Component)]
 pub struct PlayerBoxRoot;
 
+/// Namn och rank, fylls i av `update_playerbox`
+#[derive(Component)]
+struct PlayerBoxText;
+
 pub fn spawn_playerbox(
     parent: &mut ChildBuilder,
     font_bold: &Handle<Font>,
@@ -37,13 +58,37 @@ pub fn spawn_playerbox(
             });
 
             // Text info
-            row.spawn(TextBundle::from_section(
-                "PlayerName\nRank: Gold Nova 1",
-                TextStyle {
-                    font: font_bold.clone(),
-                    font_size: 14.0,
-                    color: Color::WHITE,
-                },
+            row.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font_bold.clone(),
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                PlayerBoxText,
             ));
         });
 }
+
+fn update_playerbox(
+    lobby: Res<Lobby>,
+    matchmaking: Res<MatchmakingClient>,
+    profiles: Res<ProfileClient>,
+    mut texts: Query<&mut Text, With<PlayerBoxText>>,
+    spawned: Query<(), Added<PlayerBoxText>>,
+) {
+    if !lobby.is_changed() && !matchmaking.is_changed() && !profiles.is_changed() && spawned.is_empty() {
+        return;
+    }
+    // profilens namn när tjänsten svarat, annars vårt eget
+    let name = match &profiles.profile {
+        Some(profile) if !profile.name.is_empty() => profile.name.as_str(),
+        _ => lobby.local_member().map_or("", |m| m.name.as_str()),
+    };
+    let rank = matchmaking.rank.map_or("-".to_string(), |summary| summary.label());
+    for mut text in &mut texts {
+        text.sections[0].value = format!("{name}\nRank: {rank}");
+    }
+}
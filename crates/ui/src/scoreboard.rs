@@ -0,0 +1,132 @@
+//! Scoreboard shown while the scoreboard key is held.
+//!
+//! It lists the `PlayerStats` the game server sends, see `net::stats`.
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use shared::actions::{Action, ActionState, ActionsPlugin};
+use shared::game_state::{GameStatePlugin, PlayerStats, RoundState, Team};
+use shared::lobby::{Lobby, LobbyPlugin};
+use shared::AppState;
+
+use crate::hud::team_color;
+
+pub struct ScoreboardPlugin;
+
+impl Plugin for ScoreboardPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ActionsPlugin>() {
+            app.add_plugins(ActionsPlugin);
+        }
+        if !app.is_plugin_added::<GameStatePlugin>() {
+            app.add_plugins(GameStatePlugin);
+        }
+        if !app.is_plugin_added::<LobbyPlugin>() {
+            app.add_plugins(LobbyPlugin);
+        }
+        app.add_systems(Update, scoreboard_ui.run_if(in_state(AppState::InGame)));
+    }
+}
+
+const DEAD: egui::Color32 = egui::Color32::from_gray(120);
+
+fn scoreboard_ui(
+    mut contexts: EguiContexts,
+    actions: Res<ActionState>,
+    round: Res<RoundState>,
+    lobby: Res<Lobby>,
+    players: Query<&PlayerStats>,
+) {
+    if !actions.pressed(Action::Scoreboard) {
+        return;
+    }
+    let local_id = lobby.local_id();
+    // pengar syns bara för det egna laget
+    let own_team = players.iter().find(|p| p.player_id == local_id).and_then(|p| p.team);
+    let mut players: Vec<&PlayerStats> = players.iter().collect();
+    // högst poäng först, sen flest kills och minst deaths
+    players.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(b.kills.cmp(&a.kills))
+            .then(a.deaths.cmp(&b.deaths))
+            .then(a.name.cmp(&b.name))
+    });
+
+    egui::Area::new(egui::Id::new("scoreboard"))
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::none()
+                .fill(egui::Color32::from_black_alpha(200))
+                .rounding(6.0)
+                .inner_margin(16.0)
+                .show(ui, |ui| {
+                    ui.set_min_width(640.0);
+                    for team in [Team::CounterTerrorist, Team::Terrorist] {
+                        let members: Vec<&PlayerStats> =
+                            players.iter().copied().filter(|p| p.team == Some(team)).collect();
+                        team_table(ui, team, round.score(team), &members, local_id, own_team == Some(team));
+                        ui.add_space(12.0);
+                    }
+
+                    let spectators: Vec<&str> = players
+                        .iter()
+                        .filter(|p| p.team.is_none())
+                        .map(|p| p.name.as_str())
+                        .collect();
+                    if !spectators.is_empty() {
+                        ui.label(egui::RichText::new(format!("Spectators: {}", spectators.join(", "))).color(DEAD));
+                    }
+                });
+        });
+}
+
+fn team_table(ui: &mut egui::Ui, team: Team, score: u32, members: &[&PlayerStats], local_id: u64, show_money: bool) {
+    let color = team_color(team);
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new(score.to_string()).size(28.0).color(color).strong());
+        ui.label(egui::RichText::new(team.label()).size(18.0).color(color));
+    });
+
+    egui::Grid::new(("scoreboard_team", team.label()))
+        .num_columns(8)
+        .min_col_width(48.0)
+        .spacing([12.0, 4.0])
+        .striped(true)
+        .show(ui, |ui| {
+            let header = |ui: &mut egui::Ui, text: &str| {
+                ui.label(egui::RichText::new(text).small().color(DEAD));
+            };
+            for column in ["NAME", "MONEY", "K", "D", "A", "MVP", "SCORE", "PING"] {
+                header(ui, column);
+            }
+            ui.end_row();
+
+            for player in members {
+                let row_color = if !player.alive {
+                    DEAD
+                } else if player.player_id == local_id {
+                    egui::Color32::WHITE
+                } else {
+                    egui::Color32::from_gray(210)
+                };
+                let cell = |ui: &mut egui::Ui, text: String| {
+                    ui.label(egui::RichText::new(text).color(row_color));
+                };
+                let name = if player.alive {
+                    player.name.clone()
+                } else {
+                    format!("{} (dead)", player.name)
+                };
+                let name = egui::RichText::new(name).color(row_color);
+                ui.label(if player.player_id == local_id { name.strong() } else { name });
+                cell(ui, if show_money { format!("${}", player.money) } else { String::new() });
+                cell(ui, player.kills.to_string());
+                cell(ui, player.deaths.to_string());
+                cell(ui, player.assists.to_string());
+                cell(ui, if player.mvps > 0 { format!("*{}", player.mvps) } else { String::new() });
+                cell(ui, player.score.to_string());
+                cell(ui, player.ping.to_string());
+                ui.end_row();
+            }
+        });
+}
@@ -0,0 +1,344 @@
+//! Server browser screen: internet, LAN, favourite and recent servers with ping, sorting and filters.
+use std::cmp::Ordering;
+use std::net::SocketAddr;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use net::browser::{
+    master_address, parse_address, JoinServer, ServerBookmarks, ServerBrowser, ServerBrowserPlugin, ServerEntry,
+};
+use net::query::VERSION;
+use shared::config::GameConfig;
+use shared::AppState;
+
+pub struct ServerBrowserUiPlugin;
+
+impl Plugin for ServerBrowserUiPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ServerBrowserPlugin>() {
+            app.add_plugins(ServerBrowserPlugin);
+        }
+        app.init_resource::<BrowserView>()
+            .add_systems(OnEnter(AppState::ServerBrowser), refresh_servers)
+            .add_systems(Update, server_browser_ui.run_if(in_state(AppState::ServerBrowser)));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BrowserTab {
+    Internet,
+    Lan,
+    Favorites,
+    History,
+}
+
+impl BrowserTab {
+    const ALL: [BrowserTab; 4] = [
+        BrowserTab::Internet,
+        BrowserTab::Lan,
+        BrowserTab::Favorites,
+        BrowserTab::History,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            BrowserTab::Internet => "INTERNET",
+            BrowserTab::Lan => "LAN",
+            BrowserTab::Favorites => "FAVORITES",
+            BrowserTab::History => "HISTORY",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    Name,
+    Map,
+    Players,
+    Ping,
+}
+
+/// Vad skärmen visar, sparas mellan besöken
+#[derive(Resource)]
+struct BrowserView {
+    tab: BrowserTab,
+    search: String,
+    hide_full: bool,
+    hide_empty: bool,
+    hide_password: bool,
+    max_ping: u32,
+    sort: SortColumn,
+    descending: bool,
+    selected: Option<SocketAddr>,
+    add_address: String,
+    /// Lösenordet till den valda servern, om den har ett
+    password: String,
+    error: Option<String>,
+}
+
+impl Default for BrowserView {
+    fn default() -> Self {
+        BrowserView {
+            tab: BrowserTab::Internet,
+            search: String::new(),
+            hide_full: false,
+            hide_empty: false,
+            hide_password: false,
+            max_ping: 0,
+            sort: SortColumn::Ping,
+            descending: false,
+            selected: None,
+            add_address: String::new(),
+            password: String::new(),
+            error: None,
+        }
+    }
+}
+
+impl BrowserView {
+    fn shows(&self, entry: &ServerEntry, bookmarks: &ServerBookmarks) -> bool {
+        let in_tab = match self.tab {
+            BrowserTab::Internet => entry.internet,
+            BrowserTab::Lan => entry.lan,
+            BrowserTab::Favorites => bookmarks.is_favorite(entry.addr),
+            BrowserTab::History => bookmarks.history.contains(&entry.addr),
+        };
+        if !in_tab {
+            return false;
+        }
+        // servrar som inte svarat visas bara i favoriter och historik
+        let Some(info) = &entry.info else {
+            return matches!(self.tab, BrowserTab::Favorites | BrowserTab::History);
+        };
+        let search = self.search.to_lowercase();
+        (search.is_empty()
+            || info.name.to_lowercase().contains(&search)
+            || info.map.label().to_lowercase().contains(&search))
+            && !(self.hide_full && info.is_full())
+            && !(self.hide_empty && info.players == 0)
+            && !(self.hide_password && info.password)
+            && (self.max_ping == 0 || entry.ping.is_some_and(|p| p <= self.max_ping))
+    }
+
+    fn compare(&self, a: &ServerEntry, b: &ServerEntry) -> Ordering {
+        // servrar som inte svarat hamnar alltid sist
+        let (ai, bi) = match (&a.info, &b.info) {
+            (Some(ai), Some(bi)) => (ai, bi),
+            (ai, bi) => return bi.is_some().cmp(&ai.is_some()),
+        };
+        let order = match self.sort {
+            SortColumn::Name => ai.name.to_lowercase().cmp(&bi.name.to_lowercase()),
+            SortColumn::Map => ai.map.label().cmp(bi.map.label()),
+            SortColumn::Players => ai.players.cmp(&bi.players),
+            SortColumn::Ping => a.ping.unwrap_or(u32::MAX).cmp(&b.ping.unwrap_or(u32::MAX)),
+        };
+        if self.descending {
+            order.reverse()
+        } else {
+            order
+        }
+    }
+
+    fn sort_by(&mut self, column: SortColumn) {
+        if self.sort == column {
+            self.descending = !self.descending;
+        } else {
+            self.sort = column;
+            // flest spelare först är det vanliga
+            self.descending = column == SortColumn::Players;
+        }
+    }
+}
+
+fn refresh_servers(mut browser: ResMut<ServerBrowser>, bookmarks: Res<ServerBookmarks>, config: Res<GameConfig>) {
+    browser.refresh(&bookmarks, master_address(&config));
+}
+
+const DIM: egui::Color32 = egui::Color32::from_gray(120);
+
+fn server_browser_ui(
+    mut contexts: EguiContexts,
+    mut config: ResMut<GameConfig>,
+    mut browser: ResMut<ServerBrowser>,
+    mut bookmarks: ResMut<ServerBookmarks>,
+    mut view: ResMut<BrowserView>,
+    mut joins: EventWriter<JoinServer>,
+) {
+    let ctx = contexts.ctx_mut();
+    let screen = ctx.screen_rect();
+
+    egui::Area::new(egui::Id::new("server_browser"))
+        .fixed_pos([screen.left() + 16.0, screen.top() + 64.0])
+        .show(ctx, |ui| {
+            egui::Frame::none()
+                .fill(egui::Color32::from_rgba_unmultiplied(13, 13, 18, 230))
+                .rounding(4.0)
+                .inner_margin(12.0)
+                .show(ui, |ui| {
+                    ui.set_width(screen.width() - 56.0);
+                    ui.set_min_height(screen.height() - 110.0);
+                    browser_contents(ui, &mut config, &mut browser, &mut bookmarks, &mut view, &mut joins);
+                });
+        });
+}
+
+fn browser_contents(
+    ui: &mut egui::Ui,
+    config: &mut ResMut<GameConfig>,
+    browser: &mut ServerBrowser,
+    bookmarks: &mut ServerBookmarks,
+    view: &mut BrowserView,
+    joins: &mut EventWriter<JoinServer>,
+) {
+    ui.horizontal(|ui| {
+        for tab in BrowserTab::ALL {
+            if ui.selectable_label(view.tab == tab, tab.label()).clicked() {
+                view.tab = tab;
+            }
+        }
+        ui.separator();
+        let refreshing = browser.is_refreshing();
+        if ui.add_enabled(!refreshing, egui::Button::new("REFRESH")).clicked() {
+            browser.refresh(bookmarks, master_address(config));
+        }
+        if refreshing {
+            ui.spinner();
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Search");
+        ui.add(egui::TextEdit::singleline(&mut view.search).desired_width(180.0));
+        ui.checkbox(&mut view.hide_full, "Hide full");
+        ui.checkbox(&mut view.hide_empty, "Hide empty");
+        ui.checkbox(&mut view.hide_password, "Hide password protected");
+        ui.label("Max ping");
+        ui.add(
+            egui::DragValue::new(&mut view.max_ping)
+                .range(0..=999)
+                .custom_formatter(|v, _| if v == 0.0 { "any".into() } else { format!("{v}") }),
+        );
+    });
+    ui.separator();
+
+    let mut rows: Vec<&ServerEntry> = browser.entries().iter().filter(|e| view.shows(e, bookmarks)).collect();
+    rows.sort_by(|a, b| view.compare(a, b));
+
+    let mut toggle_favorite = None;
+    let mut connect = None;
+    egui::ScrollArea::vertical()
+        .max_height(ui.available_height() - 48.0)
+        .auto_shrink([false, false])
+        .show(ui, |ui| {
+            egui::Grid::new("server_list")
+                .num_columns(7)
+                .min_col_width(40.0)
+                .spacing([16.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("");
+                    let arrow = if view.descending { " v" } else { " ^" };
+                    for (column, label) in [
+                        (SortColumn::Name, "NAME"),
+                        (SortColumn::Map, "MAP"),
+                        (SortColumn::Players, "PLAYERS"),
+                        (SortColumn::Ping, "PING"),
+                    ] {
+                        let text = if view.sort == column { format!("{label}{arrow}") } else { label.to_string() };
+                        let header = egui::Label::new(egui::RichText::new(text).small().color(DIM));
+                        if ui.add(header.sense(egui::Sense::click())).clicked() {
+                            view.sort_by(column);
+                        }
+                        if column == SortColumn::Name {
+                            ui.label(egui::RichText::new("MODE").small().color(DIM));
+                        }
+                    }
+                    ui.label(egui::RichText::new("TICK").small().color(DIM));
+                    ui.end_row();
+
+                    for entry in &rows {
+                        let favorite = bookmarks.is_favorite(entry.addr);
+                        if ui.selectable_label(favorite, if favorite { "★" } else { "☆" }).clicked() {
+                            toggle_favorite = Some(entry.addr);
+                        }
+                        let selected = view.selected == Some(entry.addr);
+                        let Some(info) = &entry.info else {
+                            let status = if entry.timed_out { "not responding" } else { "..." };
+                            if ui.selectable_label(selected, format!("{} ({status})", entry.addr)).clicked() {
+                                view.selected = Some(entry.addr);
+                            }
+                            ui.end_row();
+                            continue;
+                        };
+
+                        let lock = if info.password { " [locked]" } else { "" };
+                        let name = ui.selectable_label(selected, format!("{}{lock}", info.name));
+                        if name.clicked() {
+                            view.selected = Some(entry.addr);
+                        }
+                        if name.double_clicked() {
+                            connect = Some(entry.addr);
+                        }
+                        ui.label(info.mode.label());
+                        ui.label(info.map.label());
+                        let players = egui::RichText::new(format!("{}/{}", info.players, info.max_players));
+                        ui.label(if info.is_full() { players.color(DIM) } else { players });
+                        ui.label(entry.ping.map_or("-".to_string(), |p| p.to_string()));
+                        ui.label(info.tick_rate.to_string());
+                        ui.end_row();
+                    }
+                });
+            if rows.is_empty() {
+                ui.label(egui::RichText::new("No servers found").color(DIM));
+            }
+        });
+
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.label("Address");
+        ui.add(egui::TextEdit::singleline(&mut view.add_address).hint_text("host:port").desired_width(180.0));
+        if ui.button("ADD FAVORITE").clicked() {
+            match parse_address(&view.add_address) {
+                Some(addr) => {
+                    if !bookmarks.is_favorite(addr) {
+                        bookmarks.toggle_favorite(addr);
+                        bookmarks.save();
+                    }
+                    browser.query(addr);
+                    view.add_address.clear();
+                    view.tab = BrowserTab::Favorites;
+                    view.error = None;
+                }
+                None => view.error = Some(format!("can't resolve {}", view.add_address.trim())),
+            }
+        }
+        if let Some(error) = &view.error {
+            ui.label(egui::RichText::new(error).color(egui::Color32::from_rgb(240, 90, 90)));
+        }
+
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            let selected = view.selected.and_then(|addr| browser.entry(addr)).and_then(|e| e.info.as_ref());
+            let joinable = selected.is_some_and(|info| info.version == VERSION && !info.is_full());
+            if ui.add_enabled(joinable, egui::Button::new("CONNECT")).clicked() {
+                connect = view.selected;
+            }
+            if selected.is_some_and(|info| info.password) {
+                let field = egui::TextEdit::singleline(&mut view.password).password(true).hint_text("password");
+                ui.add(field.desired_width(140.0));
+            }
+        });
+    });
+
+    if let Some(addr) = toggle_favorite {
+        bookmarks.toggle_favorite(addr);
+        bookmarks.save();
+    }
+    if let Some((addr, info)) = connect.and_then(|addr| Some((addr, browser.entry(addr)?.info.as_ref()?))) {
+        // servern kollar lösenordet, vi skickar bara med det
+        let password = if info.password { view.password.as_str() } else { "" };
+        if let Err(err) = config.set("password", password) {
+            warn!("can't set the password: {err}");
+        }
+        joins.send(JoinServer { addr, map: info.map, remember: true, ticket: None });
+    }
+}
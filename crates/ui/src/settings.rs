@@ -0,0 +1,617 @@
+//! Option widgets bound to cvars: toggles, sliders and dropdowns.
+//!
+//! Widgets never touch `GameConfig` directly. Edits collect in [`PendingSettings`] until
+//! APPLY sets and saves them, and REVERT throws them away. A widget whose cvar isn't
+//! registered (the plugin owning it isn't loaded) shows `-` and ignores input.
+use bevy::prelude::*;
+use bevy::ui::RelativeCursorPosition;
+use shared::config::GameConfig;
+use shared::cvars::{CvarKind, CvarValue};
+use shared::types::{options_screen, InGameMenu};
+use shared::AppState;
+use std::collections::BTreeMap;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingSettings>()
+            .add_systems(
+                Update,
+                (
+                    toggle_interactions,
+                    slider_interactions,
+                    dropdown_interactions,
+                    dropdown_option_interactions,
+                    footer_interactions,
+                    discard_outside_options,
+                    update_setting_widgets,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Ändringar som inte är applicerade än, cvar -> råvärde
+#[derive(Resource, Default)]
+pub struct PendingSettings {
+    values: BTreeMap<String, String>,
+    status: String,
+}
+
+impl PendingSettings {
+    /// The value a widget should show: the pending edit, or the current cvar value.
+    pub fn value(&self, config: &GameConfig, cvar: &str) -> Option<String> {
+        self.values
+            .get(cvar)
+            .cloned()
+            .or_else(|| config.get(cvar).map(CvarValue::to_string))
+    }
+
+    pub fn set(&mut self, config: &GameConfig, cvar: &str, raw: String) {
+        let parsed = config.def(cvar).and_then(|def| def.kind.parse(cvar, &raw).ok());
+        if parsed.is_some() && parsed.as_ref() == config.get(cvar) {
+            self.values.remove(cvar);
+        } else {
+            self.values.insert(cvar.to_string(), raw);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Sets every pending value and saves the config. Values the cvar rejects are
+    /// reported in the status line and dropped.
+    pub fn apply(&mut self, config: &mut GameConfig) {
+        let errors: Vec<String> = std::mem::take(&mut self.values)
+            .iter()
+            .filter_map(|(cvar, raw)| config.set(cvar, raw).err().map(|e| e.to_string()))
+            .collect();
+        config.save();
+        self.status = if errors.is_empty() {
+            "Settings applied".into()
+        } else {
+            errors.join(", ")
+        };
+    }
+
+    pub fn revert(&mut self) {
+        self.values.clear();
+        self.status.clear();
+    }
+}
+
+/// How a value is shown next to its widget.
+#[derive(Clone)]
+pub enum ValueFormat {
+    OnOff,
+    Number(fn(f32) -> String),
+    /// (label, raw value) pairs, the label of the matching value is shown
+    Choice(Vec<(String, String)>),
+}
+
+impl ValueFormat {
+    fn display(&self, raw: &str) -> String {
+        match self {
+            ValueFormat::OnOff => if raw == "1" { "On" } else { "Off" }.into(),
+            ValueFormat::Number(format) => raw.parse().map(format).unwrap_or_else(|_| raw.into()),
+            ValueFormat::Choice(choices) => choices
+                .iter()
+                .find(|(_, value)| value.eq_ignore_ascii_case(raw))
+                .map(|(label, _)| label.clone())
+                .unwrap_or_else(|| raw.into()),
+        }
+    }
+}
+
+#[derive(Component)]
+struct SettingToggle {
+    cvar: &'static str,
+}
+
+#[derive(Component)]
+struct SettingSlider {
+    cvar: &'static str,
+    step: f32,
+}
+
+#[derive(Component)]
+struct SliderFill {
+    cvar: &'static str,
+}
+
+#[derive(Component)]
+struct SettingDropdown {
+    cvar: &'static str,
+}
+
+#[derive(Component)]
+struct DropdownList {
+    cvar: &'static str,
+}
+
+#[derive(Component)]
+struct DropdownOption {
+    cvar: &'static str,
+    raw: String,
+}
+
+#[derive(Component)]
+struct SettingValueText {
+    cvar: &'static str,
+    format: ValueFormat,
+}
+
+#[derive(Component)]
+enum FooterButton {
+    Revert,
+    Defaults,
+    Apply,
+}
+
+#[derive(Component)]
+struct SettingsStatusText;
+
+const WIDGET_BG: Color = Color::srgba(0.1, 0.1, 0.1, 0.8);
+const WIDGET_ON: Color = Color::srgb(0.25, 0.55, 0.35);
+const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+
+fn text_style(font: &Handle<Font>, size: f32) -> TextStyle {
+    TextStyle {
+        font: font.clone(),
+        font_size: size,
+        color: TEXT_COLOR,
+    }
+}
+
+/// Label to the left, widget to the right, same look as the other option rows.
+fn spawn_row(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, widget: impl FnOnce(&mut ChildBuilder)) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                display: Display::Flex,
+                justify_content: JustifyContent::SpaceBetween,
+                align_items: AlignItems::Center,
+                width: Val::Percent(100.0),
+                padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                ..default()
+            },
+            background_color: Color::srgba(0.05, 0.05, 0.05, 0.6).into(),
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(TextBundle::from_section(label, text_style(font, 16.0)));
+            widget(row);
+        });
+}
+
+pub fn spawn_section_title(parent: &mut ChildBuilder, font: &Handle<Font>, title: &str) {
+    parent.spawn(TextBundle::from_section(
+        title,
+        TextStyle {
+            font: font.clone(),
+            font_size: 20.0,
+            color: Color::srgb(0.5, 0.7, 0.9),
+        },
+    ));
+}
+
+pub fn spawn_toggle(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, cvar: &'static str) {
+    spawn_row(parent, font, label, |row| {
+        row.spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(80.0),
+                    padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: WIDGET_BG.into(),
+                ..default()
+            },
+            SettingToggle { cvar },
+        ))
+        .with_children(|btn| {
+            btn.spawn((
+                TextBundle::from_section("", text_style(font, 16.0)),
+                SettingValueText {
+                    cvar,
+                    format: ValueFormat::OnOff,
+                },
+            ));
+        });
+    });
+}
+
+/// A slider over the cvar's registered range, snapped to `step`.
+pub fn spawn_slider(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    label: &str,
+    cvar: &'static str,
+    step: f32,
+    format: fn(f32) -> String,
+) {
+    spawn_row(parent, font, label, |row| {
+        row.spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(12.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|widget| {
+            // högre klickyta än själva linjen
+            widget
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Px(220.0),
+                            height: Val::Px(20.0),
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    Interaction::default(),
+                    RelativeCursorPosition::default(),
+                    SettingSlider { cvar, step },
+                ))
+                .with_children(|hit| {
+                    hit.spawn(NodeBundle {
+                        style: Style {
+                            width: Val::Percent(100.0),
+                            height: Val::Px(6.0),
+                            ..default()
+                        },
+                        background_color: WIDGET_BG.into(),
+                        ..default()
+                    })
+                    .with_children(|track| {
+                        track.spawn((
+                            NodeBundle {
+                                style: Style {
+                                    width: Val::Percent(0.0),
+                                    height: Val::Percent(100.0),
+                                    ..default()
+                                },
+                                background_color: Color::srgb(0.5, 0.7, 0.9).into(),
+                                ..default()
+                            },
+                            SliderFill { cvar },
+                        ));
+                    });
+                });
+            widget.spawn((
+                TextBundle {
+                    style: Style {
+                        width: Val::Px(60.0),
+                        ..default()
+                    },
+                    ..TextBundle::from_section("", text_style(font, 16.0))
+                },
+                SettingValueText {
+                    cvar,
+                    format: ValueFormat::Number(format),
+                },
+            ));
+        });
+    });
+}
+
+/// A dropdown listing `choices` as (label, raw value) pairs.
+pub fn spawn_dropdown(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    label: &str,
+    cvar: &'static str,
+    choices: Vec<(String, String)>,
+) {
+    spawn_row(parent, font, label, |row| {
+        row.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                width: Val::Px(180.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|container| {
+            container
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                            justify_content: JustifyContent::SpaceBetween,
+                            ..default()
+                        },
+                        background_color: WIDGET_BG.into(),
+                        ..default()
+                    },
+                    SettingDropdown { cvar },
+                ))
+                .with_children(|btn| {
+                    btn.spawn((
+                        TextBundle::from_section("", text_style(font, 16.0)),
+                        SettingValueText {
+                            cvar,
+                            format: ValueFormat::Choice(choices.clone()),
+                        },
+                    ));
+                    btn.spawn(TextBundle::from_section("v", text_style(font, 14.0)));
+                });
+
+            // listan ligger ovanpå raderna under och visas bara när den är öppen
+            container
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            display: Display::None,
+                            position_type: PositionType::Absolute,
+                            top: Val::Percent(100.0),
+                            width: Val::Percent(100.0),
+                            flex_direction: FlexDirection::Column,
+                            ..default()
+                        },
+                        background_color: Color::srgb(0.08, 0.09, 0.11).into(),
+                        z_index: ZIndex::Global(10),
+                        ..default()
+                    },
+                    DropdownList { cvar },
+                ))
+                .with_children(|list| {
+                    for (label, raw) in choices {
+                        list.spawn((
+                            ButtonBundle {
+                                style: Style {
+                                    padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                                    ..default()
+                                },
+                                background_color: Color::NONE.into(),
+                                ..default()
+                            },
+                            DropdownOption { cvar, raw },
+                        ))
+                        .with_children(|option| {
+                            option.spawn(TextBundle::from_section(label, text_style(font, 16.0)));
+                        });
+                    }
+                });
+        });
+    });
+}
+
+/// REVERT, RESTORE DEFAULTS and APPLY, plus a line for errors and confirmations.
+pub fn spawn_settings_footer(parent: &mut ChildBuilder, font: &Handle<Font>) {
+    parent.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: font.clone(),
+                font_size: 14.0,
+                color: Color::srgb(0.95, 0.75, 0.3),
+            },
+        ),
+        SettingsStatusText,
+    ));
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                display: Display::Flex,
+                justify_content: JustifyContent::SpaceBetween,
+                margin: UiRect::top(Val::Px(12.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|buttons| {
+            for (label, button) in [
+                ("REVERT", FooterButton::Revert),
+                ("RESTORE DEFAULTS", FooterButton::Defaults),
+                ("APPLY", FooterButton::Apply),
+            ] {
+                buttons
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
+                                ..default()
+                            },
+                            background_color: WIDGET_BG.into(),
+                            ..default()
+                        },
+                        button,
+                    ))
+                    .with_children(|btn| {
+                        btn.spawn(TextBundle::from_section(label, text_style(font, 16.0)));
+                    });
+            }
+        });
+}
+
+fn toggle_interactions(
+    q: Query<(&Interaction, &SettingToggle), Changed<Interaction>>,
+    config: Res<GameConfig>,
+    mut pending: ResMut<PendingSettings>,
+) {
+    for (interaction, toggle) in &q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let Some(value) = pending.value(&config, toggle.cvar) {
+            let flipped = if value == "1" { "0" } else { "1" };
+            pending.set(&config, toggle.cvar, flipped.into());
+        }
+    }
+}
+
+/// Steps `value` to the nearest multiple of `step` from `min` and formats it for the cvar.
+fn slider_raw(kind: &CvarKind, position: f32, step: f32) -> Option<String> {
+    let (min, max) = match kind {
+        CvarKind::Float { min, max } => (*min, *max),
+        CvarKind::Int { min, max } => (*min as f32, *max as f32),
+        _ => return None,
+    };
+    let value = min + position.clamp(0.0, 1.0) * (max - min);
+    let value = (min + ((value - min) / step).round() * step).clamp(min, max);
+    Some(match kind {
+        CvarKind::Int { .. } => format!("{}", value.round() as i32),
+        _ => {
+            // lika många decimaler som steget, så att 0.3 inte blir 0.30000001
+            let decimals = (-step.log10()).ceil().max(0.0) as usize;
+            let text = format!("{value:.decimals$}");
+            if text.contains('.') {
+                text.trim_end_matches('0').trim_end_matches('.').to_string()
+            } else {
+                text
+            }
+        }
+    })
+}
+
+fn slider_interactions(
+    q: Query<(&Interaction, &RelativeCursorPosition, &SettingSlider)>,
+    config: Res<GameConfig>,
+    mut pending: ResMut<PendingSettings>,
+) {
+    for (interaction, cursor, slider) in &q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let (Some(position), Some(def)) = (cursor.normalized, config.def(slider.cvar)) else {
+            continue;
+        };
+        if let Some(raw) = slider_raw(&def.kind, position.x, slider.step) {
+            if pending.value(&config, slider.cvar).as_deref() != Some(raw.as_str()) {
+                pending.set(&config, slider.cvar, raw);
+            }
+        }
+    }
+}
+
+fn dropdown_interactions(
+    q: Query<(&Interaction, &SettingDropdown), Changed<Interaction>>,
+    mut lists: Query<(&DropdownList, &mut Style)>,
+) {
+    for (interaction, dropdown) in &q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        for (list, mut style) in &mut lists {
+            // bara en lista öppen åt gången
+            style.display = if list.cvar == dropdown.cvar && style.display == Display::None {
+                Display::Flex
+            } else {
+                Display::None
+            };
+        }
+    }
+}
+
+fn dropdown_option_interactions(
+    mut q: Query<(&Interaction, &DropdownOption, &mut BackgroundColor), Changed<Interaction>>,
+    mut lists: Query<&mut Style, With<DropdownList>>,
+    config: Res<GameConfig>,
+    mut pending: ResMut<PendingSettings>,
+) {
+    for (interaction, option, mut bg) in &mut q {
+        match interaction {
+            Interaction::Pressed => {
+                pending.set(&config, option.cvar, option.raw.clone());
+                for mut style in &mut lists {
+                    style.display = Display::None;
+                }
+            }
+            Interaction::Hovered => bg.0 = Color::srgba(1.0, 1.0, 1.0, 0.08),
+            Interaction::None => bg.0 = Color::NONE,
+        }
+    }
+}
+
+fn footer_interactions(
+    q: Query<(&Interaction, &FooterButton), Changed<Interaction>>,
+    widgets: Query<&SettingValueText>,
+    mut config: ResMut<GameConfig>,
+    mut pending: ResMut<PendingSettings>,
+) {
+    for (interaction, button) in &q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            FooterButton::Revert => pending.revert(),
+            FooterButton::Apply => pending.apply(&mut config),
+            FooterButton::Defaults => {
+                // bara inställningarna som syns på den här fliken
+                for widget in &widgets {
+                    if let Some(def) = config.def(widget.cvar) {
+                        let default = def.default.to_string();
+                        pending.set(&config, widget.cvar, default);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Osparade ändringar försvinner när man lämnar options-menyn
+fn discard_outside_options(
+    state: Res<State<AppState>>,
+    menu: Option<Res<State<InGameMenu>>>,
+    mut pending: ResMut<PendingSettings>,
+) {
+    let open = options_screen(state.get(), menu.as_deref().map(State::get)).is_some();
+    if !open && (!pending.is_empty() || !pending.status.is_empty()) {
+        pending.revert();
+    }
+}
+
+fn update_setting_widgets(
+    config: Res<GameConfig>,
+    pending: Res<PendingSettings>,
+    spawned: Query<(), Added<SettingValueText>>,
+    mut texts: Query<(&SettingValueText, &mut Text), Without<SettingsStatusText>>,
+    mut fills: Query<(&SliderFill, &mut Style)>,
+    mut toggles: Query<(&SettingToggle, &mut BackgroundColor)>,
+    mut status: Query<&mut Text, With<SettingsStatusText>>,
+) {
+    if !config.is_changed() && !pending.is_changed() && spawned.is_empty() {
+        return;
+    }
+    for (widget, mut text) in &mut texts {
+        let value = pending.value(&config, widget.cvar);
+        let mut shown = value.map(|raw| widget.format.display(&raw)).unwrap_or_else(|| "-".into());
+        if pending.values.contains_key(widget.cvar) {
+            shown.push('*');
+        }
+        text.sections[0].value = shown;
+    }
+    for (fill, mut style) in &mut fills {
+        let range = config.def(fill.cvar).and_then(|def| match def.kind {
+            CvarKind::Float { min, max } => Some((min, max)),
+            CvarKind::Int { min, max } => Some((min as f32, max as f32)),
+            _ => None,
+        });
+        let value = pending.value(&config, fill.cvar).and_then(|raw| raw.parse::<f32>().ok());
+        let percent = match (range, value) {
+            (Some((min, max)), Some(value)) if max > min => (value - min) / (max - min) * 100.0,
+            _ => 0.0,
+        };
+        style.width = Val::Percent(percent.clamp(0.0, 100.0));
+    }
+    for (toggle, mut bg) in &mut toggles {
+        let on = pending.value(&config, toggle.cvar).as_deref() == Some("1");
+        bg.0 = if on { WIDGET_ON } else { WIDGET_BG };
+    }
+    for mut text in &mut status {
+        text.sections[0].value = if pending.is_empty() && pending.status.is_empty() {
+            String::new()
+        } else if pending.is_empty() {
+            pending.status.clone()
+        } else {
+            "Unsaved changes, press APPLY to keep them".into()
+        };
+    }
+}
@@ -0,0 +1,253 @@
+//! The unboxing screen that spins when a case is opened, and the notice for match drops.
+//!
+//! What comes out of a case is rolled by the profile service before the reel starts, the
+//! reel only lands on it. The skins it passes on the way are picked with the case's
+//! published odds, so it looks like what opening the case is like.
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use net::auth::to_hex;
+use net::profile::{InventoryItem, ProfileClient, ProfilePlugin};
+use shared::items::{item_def, ItemDef, ItemKind, Rarity};
+use shared::loot::{case_odds, RollKind, RollRecord};
+use shared::AppState;
+
+/// Entries on the reel; the item we got is at `WINNER_INDEX`.
+const REEL_LENGTH: usize = 40;
+const WINNER_INDEX: usize = 35;
+const SPIN_TIME: Duration = Duration::from_secs(5);
+/// How long we wait for the service to open the case.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(5);
+const ENTRY_WIDTH: f32 = 110.0;
+const ENTRY_GAP: f32 = 6.0;
+const REEL_SIZE: egui::Vec2 = egui::vec2(600.0, 110.0);
+
+#[derive(Resource, Default)]
+pub enum Unboxing {
+    #[default]
+    Idle,
+    /// The case is sent off to be opened
+    Waiting { since: Instant },
+    Spinning {
+        reel: Vec<&'static ItemDef>,
+        /// Var på vinnaren reelen stannar, i bråkdelar av en ruta
+        stop: f32,
+        started: Instant,
+        item: InventoryItem,
+        roll: RollRecord,
+    },
+    Revealed { item: InventoryItem, roll: RollRecord },
+}
+
+impl Unboxing {
+    /// Shows the screen while a case is being opened.
+    pub fn start(&mut self) {
+        *self = Unboxing::Waiting { since: Instant::now() };
+    }
+
+    pub fn is_idle(&self) -> bool {
+        matches!(self, Unboxing::Idle)
+    }
+}
+
+pub struct UnboxingPlugin;
+
+impl Plugin for UnboxingPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ProfilePlugin>() {
+            app.add_plugins(ProfilePlugin);
+        }
+        app.init_resource::<Unboxing>().add_systems(
+            Update,
+            (
+                (start_spinning, unboxing_window).chain(),
+                drop_notice.run_if(not(in_state(AppState::InGame))),
+            ),
+        );
+    }
+}
+
+/// Ett tal i 0..1 till reelens utfyllnad, bara för syns skull
+fn filler_unit(roll: &RollRecord, index: usize) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    (roll.nonce, &roll.commitment, index).hash(&mut hasher);
+    hasher.finish() as f64 / u64::MAX as f64
+}
+
+/// A skin from the case, picked with its odds.
+fn filler(
+    contents: &[&'static str],
+    odds: &[(Rarity, f64)],
+    roll: &RollRecord,
+    index: usize,
+) -> Option<&'static ItemDef> {
+    let mut target = filler_unit(roll, index * 2);
+    let rarity = odds
+        .iter()
+        .find(|(_, chance)| {
+            target -= chance;
+            target < 0.0
+        })
+        .or(odds.last())?
+        .0;
+    let tier: Vec<&'static ItemDef> =
+        contents.iter().filter_map(|id| item_def(id)).filter(|d| d.rarity() == Some(rarity)).collect();
+    let pick = (filler_unit(roll, index * 2 + 1) * tier.len() as f64) as usize;
+    tier.get(pick.min(tier.len().saturating_sub(1))).copied()
+}
+
+fn build_reel(item: &InventoryItem, roll: &RollRecord) -> Vec<&'static ItemDef> {
+    let Some(winner) = item.def() else {
+        return Vec::new();
+    };
+    let Some(case) = (match &roll.kind {
+        RollKind::Case(case) => item_def(case),
+        RollKind::Drop => None,
+    }) else {
+        return vec![winner];
+    };
+    let ItemKind::Case { contents } = case.kind else {
+        return vec![winner];
+    };
+    let odds = case_odds(case);
+    (0..REEL_LENGTH)
+        .map(|i| if i == WINNER_INDEX { Some(winner) } else { filler(contents, &odds, roll, i) })
+        .map(|def| def.unwrap_or(winner))
+        .collect()
+}
+
+fn start_spinning(mut unboxing: ResMut<Unboxing>, mut profiles: ResMut<ProfileClient>) {
+    if let Unboxing::Waiting { since } = *unboxing {
+        if since.elapsed() >= OPEN_TIMEOUT {
+            *unboxing = Unboxing::Idle;
+        }
+    }
+    // rör bara klienten när något kommit, annars byggs menyerna om varje frame
+    if profiles.unboxed.is_empty() || matches!(*unboxing, Unboxing::Spinning { .. } | Unboxing::Revealed { .. }) {
+        return;
+    }
+    let (item, roll) = profiles.unboxed.remove(0);
+    let reel = build_reel(&item, &roll);
+    // stannar inte mitt på rutan, då syns det att allt är bestämt
+    let stop = (filler_unit(&roll, REEL_LENGTH * 2) as f32 - 0.5) * 0.8;
+    *unboxing = Unboxing::Spinning { reel, stop, started: Instant::now(), item, roll };
+}
+
+pub fn color32(color: Color) -> egui::Color32 {
+    let [r, g, b, _] = color.to_srgba().to_u8_array();
+    egui::Color32::from_rgb(r, g, b)
+}
+
+fn rarity_color(def: &ItemDef) -> egui::Color32 {
+    def.rarity().map_or(egui::Color32::from_gray(160), |r| color32(r.color()))
+}
+
+fn paint_reel(ui: &mut egui::Ui, reel: &[&'static ItemDef], offset: f32) {
+    let (response, painter) = ui.allocate_painter(REEL_SIZE, egui::Sense::hover());
+    let rect = response.rect;
+    let painter = painter.with_clip_rect(rect);
+    painter.rect_filled(rect, 4.0, egui::Color32::from_gray(20));
+    let stride = ENTRY_WIDTH + ENTRY_GAP;
+    for (i, def) in reel.iter().enumerate() {
+        let left = rect.center().x - offset + i as f32 * stride - ENTRY_WIDTH / 2.0;
+        if left > rect.right() || left + ENTRY_WIDTH < rect.left() {
+            continue;
+        }
+        let entry = egui::Rect::from_min_size(egui::pos2(left, rect.top() + 8.0), egui::vec2(ENTRY_WIDTH, 94.0));
+        painter.rect_filled(entry, 3.0, egui::Color32::from_gray(45));
+        let bar = egui::Rect::from_min_max(egui::pos2(entry.left(), entry.bottom() - 6.0), entry.max);
+        painter.rect_filled(bar, 0.0, rarity_color(def));
+        painter.text(
+            entry.left_top() + egui::vec2(6.0, 6.0),
+            egui::Align2::LEFT_TOP,
+            def.name.replace(" | ", "\n"),
+            egui::FontId::proportional(13.0),
+            egui::Color32::from_gray(220),
+        );
+    }
+    // markören i mitten
+    let x = rect.center().x;
+    painter.line_segment(
+        [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+        egui::Stroke::new(2.0, egui::Color32::from_rgb(240, 200, 80)),
+    );
+}
+
+fn unboxing_window(mut contexts: EguiContexts, mut unboxing: ResMut<Unboxing>) {
+    if unboxing.is_idle() {
+        return;
+    }
+    let mut close = false;
+    let mut revealed = None;
+    egui::Window::new("Unboxing")
+        .id(egui::Id::new("unboxing"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| match &*unboxing {
+            Unboxing::Idle => {}
+            Unboxing::Waiting { .. } => {
+                ui.label("Opening case...");
+            }
+            Unboxing::Spinning { reel, stop, started, item, roll } => {
+                let t = (started.elapsed().as_secs_f32() / SPIN_TIME.as_secs_f32()).min(1.0);
+                // saktar in mot slutet
+                let eased = 1.0 - (1.0 - t).powi(3);
+                let target = (WINNER_INDEX as f32 + stop) * (ENTRY_WIDTH + ENTRY_GAP);
+                paint_reel(ui, reel, eased * target);
+                ui.ctx().request_repaint();
+                if t >= 1.0 {
+                    revealed = Some((item.clone(), roll.clone()));
+                }
+            }
+            Unboxing::Revealed { item, roll } => {
+                let def = item.def();
+                ui.heading(item.display_name());
+                if let Some(rarity) = def.and_then(ItemDef::rarity) {
+                    ui.colored_label(color32(rarity.color()), rarity.label());
+                }
+                if let Some(skin) = item.skin() {
+                    ui.label(format!("{} ({:.6})", skin.exterior().label(), skin.wear));
+                    ui.label(format!("Pattern {}", skin.pattern));
+                }
+                ui.separator();
+                ui.label(format!("Roll #{} with client seed \"{}\"", roll.nonce, roll.client_seed));
+                ui.label(format!("Server seed hash {}", to_hex(&roll.commitment)));
+                ui.small("Set a new client seed to reveal the server seed, then check the roll with `rolls`.");
+                if ui.button("CLOSE").clicked() {
+                    close = true;
+                }
+            }
+        });
+    if let Some((item, roll)) = revealed {
+        *unboxing = Unboxing::Revealed { item, roll };
+    } else if close {
+        *unboxing = Unboxing::Idle;
+    }
+}
+
+/// Tells us about match drops once we're out of the game.
+fn drop_notice(mut contexts: EguiContexts, mut profiles: ResMut<ProfileClient>) {
+    if profiles.drops.is_empty() {
+        return;
+    }
+    let mut seen = false;
+    egui::Window::new("New items")
+        .id(egui::Id::new("drop_notice"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("You got something for playing:");
+            for item in &profiles.drops {
+                let color = item.def().map_or(egui::Color32::from_gray(220), rarity_color);
+                ui.colored_label(color, item.display_name());
+            }
+            seen = ui.button("OK").clicked();
+        });
+    if seen {
+        profiles.drops.clear();
+    }
+}
@@ -0,0 +1,344 @@
+//! Video settings applied while the game runs.
+//!
+//! Everything reacts to `CvarChanged`, so a change from the options menu, the console or
+//! an `exec` takes effect the same way. Changing window mode or resolution asks the player
+//! to keep the new settings and reverts them if nobody answers.
+use bevy::pbr::{DirectionalLightShadowMap, PointLightShadowMap};
+use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+use bevy::window::{PrimaryWindow, WindowRef, WindowResized};
+use bevy_egui::{egui, EguiContexts};
+use bevy_framepace::{FramepacePlugin, FramepaceSettings, Limiter};
+use shared::config::GameConfig;
+use shared::cvars::{CvarChanged, CvarDef, CvarFlags, RegisterCvarExt};
+use shared::startup::apply_window_config;
+
+const MSAA_SAMPLES: &[&str] = &["1", "2", "4", "8"];
+pub const SHADOW_QUALITY: &[&str] = &["off", "low", "medium", "high"];
+
+/// Hur länge man har på sig att bekräfta nya skärminställningar
+const CONFIRM_SECONDS: f32 = 15.0;
+
+pub struct VideoPlugin;
+
+impl Plugin for VideoPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FramepacePlugin>() {
+            app.add_plugins(FramepacePlugin);
+        }
+        app.register_cvar(
+            CvarDef::int("fps_max", 0)
+                .int_range(0, 1000)
+                .flags(CvarFlags::ARCHIVE)
+                .description("Frame rate cap, 0 for no cap"),
+        )
+        .register_cvar(
+            CvarDef::float("r_renderscale", 1.0)
+                .range(0.25, 2.0)
+                .flags(CvarFlags::ARCHIVE)
+                .description("3D resolution relative to the window"),
+        )
+        .register_cvar(
+            CvarDef::enumeration("r_msaa", MSAA_SAMPLES, "4")
+                .flags(CvarFlags::ARCHIVE)
+                .description("MSAA samples"),
+        )
+        .register_cvar(
+            CvarDef::enumeration("r_shadows", SHADOW_QUALITY, "medium")
+                .flags(CvarFlags::ARCHIVE)
+                .description("Shadow map quality"),
+        )
+        .init_resource::<DisplayConfirm>()
+        .init_resource::<RenderScaleTarget>()
+        .add_systems(Startup, (apply_render_settings, remember_display_settings))
+        .add_systems(
+            Update,
+            (
+                apply_window_changes,
+                apply_render_changes,
+                apply_shadow_toggle,
+                apply_render_scale,
+                confirm_display_settings,
+            ),
+        );
+    }
+}
+
+/// Fönsterläge och upplösning som de var innan en ändring, tills spelaren bekräftar dem
+#[derive(Resource, Default)]
+struct DisplayConfirm {
+    applied: (String, String),
+    previous: Option<(String, String)>,
+    timer: Timer,
+    reverting: bool,
+}
+
+fn display_settings(config: &GameConfig) -> (String, String) {
+    let get = |name| config.get(name).map(|v| v.to_string()).unwrap_or_default();
+    (get("cl_fullscreen"), get("cl_resolution"))
+}
+
+fn remember_display_settings(config: Res<GameConfig>, mut confirm: ResMut<DisplayConfirm>) {
+    confirm.applied = display_settings(&config);
+}
+
+fn apply_window_changes(
+    mut changes: EventReader<CvarChanged>,
+    config: Res<GameConfig>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut confirm: ResMut<DisplayConfirm>,
+) {
+    let mut window_changed = false;
+    for change in changes.read() {
+        window_changed |= matches!(change.name.as_str(), "cl_fullscreen" | "cl_resolution" | "cl_vsync");
+    }
+    if !window_changed {
+        return;
+    }
+    if let Ok(mut window) = windows.get_single_mut() {
+        apply_window_config(&mut window, &config);
+    }
+
+    let current = display_settings(&config);
+    if current == confirm.applied {
+        return;
+    }
+    let previous = std::mem::replace(&mut confirm.applied, current.clone());
+    if confirm.reverting {
+        confirm.reverting = false;
+    } else if confirm.previous.as_ref() == Some(&current) {
+        // ändrat tillbaka för hand under nedräkningen
+        confirm.previous = None;
+    } else if confirm.previous.is_none() {
+        confirm.previous = Some(previous);
+        confirm.timer = Timer::from_seconds(CONFIRM_SECONDS, TimerMode::Once);
+    }
+}
+
+fn confirm_display_settings(
+    mut contexts: EguiContexts,
+    time: Res<Time>,
+    mut confirm: ResMut<DisplayConfirm>,
+    mut config: ResMut<GameConfig>,
+) {
+    let Some((fullscreen, resolution)) = confirm.previous.clone() else {
+        return;
+    };
+    confirm.timer.tick(time.delta());
+    let remaining = confirm.timer.remaining_secs().ceil();
+
+    let mut keep = false;
+    let mut revert = confirm.timer.finished();
+    egui::Window::new("Display settings")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("Keep these display settings?");
+            ui.label(format!("Reverting in {remaining:.0} seconds"));
+            ui.horizontal(|ui| {
+                keep = ui.button("Keep").clicked();
+                revert |= ui.button("Revert").clicked();
+            });
+        });
+
+    if keep {
+        confirm.previous = None;
+    } else if revert {
+        confirm.previous = None;
+        confirm.reverting = true;
+        for (name, value) in [("cl_fullscreen", &fullscreen), ("cl_resolution", &resolution)] {
+            if let Err(err) = config.set(name, value) {
+                warn!("couldn't revert {name}: {err}");
+            }
+        }
+        config.save();
+    }
+}
+
+fn shadow_map_size(quality: &str) -> usize {
+    match quality {
+        "low" => 1024,
+        "high" => 4096,
+        _ => 2048,
+    }
+}
+
+fn apply_render_settings(
+    config: Res<GameConfig>,
+    mut framepace: ResMut<FramepaceSettings>,
+    mut msaa: ResMut<Msaa>,
+    mut directional: ResMut<DirectionalLightShadowMap>,
+    mut point: ResMut<PointLightShadowMap>,
+) {
+    framepace.limiter = match config.get_int("fps_max").unwrap_or(0) {
+        // utan tak låter vi vsync (eller ingenting) styra
+        0 if config.get_bool("cl_vsync").unwrap_or(true) => Limiter::Auto,
+        0 => Limiter::Off,
+        fps => Limiter::from_framerate(fps as f64),
+    };
+
+    *msaa = match config.get_str("r_msaa").unwrap_or("4") {
+        "1" => Msaa::Off,
+        "2" => Msaa::Sample2,
+        "8" => Msaa::Sample8,
+        _ => Msaa::Sample4,
+    };
+
+    let size = shadow_map_size(config.get_str("r_shadows").unwrap_or("medium"));
+    if directional.size != size {
+        directional.size = size;
+    }
+    if point.size != size {
+        point.size = size;
+    }
+}
+
+fn apply_render_changes(
+    mut changes: EventReader<CvarChanged>,
+    config: Res<GameConfig>,
+    framepace: ResMut<FramepaceSettings>,
+    msaa: ResMut<Msaa>,
+    directional: ResMut<DirectionalLightShadowMap>,
+    point: ResMut<PointLightShadowMap>,
+) {
+    let mut changed = false;
+    for change in changes.read() {
+        changed |= matches!(change.name.as_str(), "fps_max" | "cl_vsync" | "r_msaa" | "r_shadows");
+    }
+    if changed {
+        apply_render_settings(config, framepace, msaa, directional, point);
+    }
+}
+
+/// Set on lights whose shadows were turned off by `r_shadows off`, so turning shadows back
+/// on doesn't enable them on lights that never had any.
+#[derive(Component)]
+struct ShadowsDisabled;
+
+type LightShadows = (
+    Entity,
+    Option<&'static mut DirectionalLight>,
+    Option<&'static mut PointLight>,
+    Option<&'static mut SpotLight>,
+    Has<ShadowsDisabled>,
+);
+type AnyLight = Or<(With<DirectionalLight>, With<PointLight>, With<SpotLight>)>;
+
+fn apply_shadow_toggle(
+    mut commands: Commands,
+    config: Res<GameConfig>,
+    mut lights: Query<LightShadows, AnyLight>,
+) {
+    let enabled = config.get_str("r_shadows") != Some("off");
+    for (entity, directional, point, spot, disabled) in &mut lights {
+        let shadows = directional
+            .map(|l| l.map_unchanged(|l| &mut l.shadows_enabled))
+            .or_else(|| point.map(|l| l.map_unchanged(|l| &mut l.shadows_enabled)))
+            .or_else(|| spot.map(|l| l.map_unchanged(|l| &mut l.shadows_enabled)));
+        let Some(mut shadows) = shadows else {
+            continue;
+        };
+        if !enabled && *shadows {
+            *shadows = false;
+            commands.entity(entity).insert(ShadowsDisabled);
+        } else if enabled && disabled {
+            *shadows = true;
+            commands.entity(entity).remove::<ShadowsDisabled>();
+        }
+    }
+}
+
+/// Bilden 3D-kameran renderar till när `r_renderscale` inte är 1
+#[derive(Resource, Default)]
+struct RenderScaleTarget(Option<Handle<Image>>);
+
+/// Full-window UI image showing the scaled 3D view behind the rest of the UI.
+#[derive(Component)]
+struct RenderScaleView;
+
+#[allow(clippy::too_many_arguments)]
+fn apply_render_scale(
+    mut commands: Commands,
+    mut changes: EventReader<CvarChanged>,
+    mut resized: EventReader<WindowResized>,
+    added: Query<(), Added<Camera3d>>,
+    config: Res<GameConfig>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<&mut Camera, With<Camera3d>>,
+    mut images: ResMut<Assets<Image>>,
+    mut target: ResMut<RenderScaleTarget>,
+    views: Query<Entity, With<RenderScaleView>>,
+) {
+    let scale_changed = changes.read().any(|c| c.name == "r_renderscale");
+    let window_resized = resized.read().count() > 0;
+    if !scale_changed && !window_resized && added.is_empty() {
+        return;
+    }
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let scale = config.get_float("r_renderscale").unwrap_or(1.0);
+
+    if (scale - 1.0).abs() < 0.01 {
+        for mut camera in &mut cameras {
+            camera.target = RenderTarget::Window(WindowRef::Primary);
+        }
+        for view in &views {
+            commands.entity(view).despawn_recursive();
+        }
+        target.0 = None;
+        return;
+    }
+
+    let size = Extent3d {
+        width: ((window.physical_width() as f32 * scale) as u32).max(1),
+        height: ((window.physical_height() as f32 * scale) as u32).max(1),
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("render_scale_target"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+
+    let handle = images.add(image);
+    if let Some(old) = target.0.replace(handle.clone()) {
+        images.remove(&old);
+    }
+    for mut camera in &mut cameras {
+        camera.target = RenderTarget::Image(handle.clone());
+    }
+    for view in &views {
+        commands.entity(view).despawn_recursive();
+    }
+    commands.spawn((
+        ImageBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            image: UiImage::new(handle),
+            // under all annan UI
+            z_index: ZIndex::Global(-1000),
+            ..default()
+        },
+        RenderScaleView,
+    ));
+}
@@ -1,22 +1,306 @@
-use std::fs;
-use std::path::Path;
+//! Developer tasks for the workspace, run with `cargo xtask <command>`.
+//!
+//! # Commands
+//! - `build-assets [--force]`: processes `assets_raw/` into `assets/`. Only files whose
+//!   content hash changed since the last build are processed again. A manifest with every
+//!   built asset and its hash is written to `assets/manifest.txt`.
+//! - `clean`: removes `assets/` including the manifest.
+//! - `check-assets`: verifies that `assets/` matches the manifest and that the manifest is
+//!   up to date with `assets_raw/`. Exits with a non-zero code if anything is wrong.
+//!
+//! # External tools
+//! Textures are compressed with `toktx` (KTX-Software), glTF files are optimized with
+//! `gltfpack` and maps are compiled with `mapc`. The binaries are looked up on `PATH` and can
+//! be overridden with the `TOKTX`, `GLTFPACK` and `MAPC` environment variables.
+mod manifest;
+mod pipeline;
 
-fn main() {
-    let src = Path::new("assets_raw"); // utgår från tools/xtask/
-    let dst = Path::new("assets");
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-    // rensa gamla assets
+use manifest::Manifest;
+
+const RAW_DIR: &str = "assets_raw";
+const OUT_DIR: &str = "assets";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let root = workspace_root();
+
+    let result = match args.first().map(String::as_str) {
+        Some("build-assets") => {
+            let force = args.iter().skip(1).any(|a| a == "--force");
+            build_assets(&root, force)
+        }
+        Some("clean") => clean(&root),
+        Some("check-assets") => check_assets(&root),
+        Some(other) => Err(format!("unknown command `{other}`\n\n{USAGE}")),
+        None => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USAGE: &str = "usage: cargo xtask <command>
+
+commands:
+    build-assets [--force]   process assets_raw/ into assets/ (incremental)
+    clean                    remove assets/
+    check-assets             verify assets/ against the manifest";
+
+/// xtask ligger i tools/xtask, så roten är två nivåer upp
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .ancestors()
+        .nth(2)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+fn build_assets(root: &Path, force: bool) -> Result<(), String> {
+    let src = root.join(RAW_DIR);
+    let dst = root.join(OUT_DIR);
+    if !src.is_dir() {
+        return Err(format!("{} does not exist", src.display()));
+    }
+
+    let BuildCounts { built, skipped, failed } = build(&src, &dst, &pipeline::Tools::detect(), force)?;
+    println!("Assets: {built} built, {skipped} up to date, {failed} failed");
+
+    if failed > 0 {
+        return Err(format!("{failed} asset(s) failed to build"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BuildCounts {
+    built: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+/// Builds `src` into `dst` and writes the manifest. `force` rebuilds everything, but the old
+/// manifest is still read so outputs of removed sources, and old outputs of sources that now
+/// build to another file, are cleaned up.
+fn build(src: &Path, dst: &Path, tools: &pipeline::Tools, force: bool) -> Result<BuildCounts, String> {
+    let previous = Manifest::load(&dst.join(manifest::FILE_NAME)).unwrap_or_default();
+    let mut next = Manifest::default();
+    let mut counts = BuildCounts::default();
+
+    for source in pipeline::raw_files(src)? {
+        let source_hash = manifest::hash_file(&src.join(&source))?;
+        let processor = pipeline::Processor::for_path(&source, tools);
+
+        // oförändrad källa och output finns kvar -> hoppa över
+        if let Some(entry) = previous.get(&source).filter(|_| !force) {
+            let out_path = dst.join(&entry.output);
+            if entry.source_hash == source_hash
+                && entry.processor == processor.name()
+                && manifest::hash_file(&out_path).ok().as_deref() == Some(entry.output_hash.as_str())
+            {
+                next.insert(entry.clone());
+                counts.skipped += 1;
+                continue;
+            }
+        }
+
+        match processor.run(src, dst, &source) {
+            Ok(output) => {
+                let output_hash = manifest::hash_file(&dst.join(&output))?;
+                println!("  built {} -> {} ({})", source, output, processor.name());
+                next.insert(manifest::Entry {
+                    source,
+                    source_hash,
+                    output,
+                    output_hash,
+                    processor: processor.name().to_string(),
+                });
+                counts.built += 1;
+            }
+            Err(err) => {
+                eprintln!("  failed {source}: {err}");
+                // den gamla outputen får stå kvar tills källan byggs igen
+                if let Some(entry) = previous.get(&source) {
+                    next.insert(entry.clone());
+                }
+                counts.failed += 1;
+            }
+        }
+    }
+
+    // ta bort outputs som ingen källa längre bygger: källan har försvunnit eller fått en ny output
+    for stale in previous.entries() {
+        if next.entries().all(|e| e.output != stale.output) {
+            let _ = std::fs::remove_file(dst.join(&stale.output));
+            println!("  removed {}", stale.output);
+        }
+    }
+
+    next.save(&dst.join(manifest::FILE_NAME))?;
+    Ok(counts)
+}
+
+fn clean(root: &Path) -> Result<(), String> {
+    let dst = root.join(OUT_DIR);
     if dst.exists() {
-        fs::remove_dir_all(dst).unwrap();
+        std::fs::remove_dir_all(&dst).map_err(|e| format!("removing {}: {e}", dst.display()))?;
     }
+    println!("Removed {}", dst.display());
+    Ok(())
+}
+
+fn check_assets(root: &Path) -> Result<(), String> {
+    let src = root.join(RAW_DIR);
+    let dst = root.join(OUT_DIR);
+    let manifest = Manifest::load(&dst.join(manifest::FILE_NAME))
+        .map_err(|e| format!("{e} (run `cargo xtask build-assets` first)"))?;
+    let mut problems = Vec::new();
+
+    for entry in manifest.entries() {
+        match manifest::hash_file(&dst.join(&entry.output)) {
+            Ok(hash) if hash == entry.output_hash => {}
+            Ok(_) => problems.push(format!("{} does not match its manifest hash", entry.output)),
+            Err(_) => problems.push(format!("{} is missing", entry.output)),
+        }
+    }
+
+    for source in pipeline::raw_files(&src)? {
+        match manifest.get(&source) {
+            Some(entry) if entry.source_hash == manifest::hash_file(&src.join(&source))? => {}
+            Some(_) => problems.push(format!("{source} changed since the last build")),
+            None => problems.push(format!("{source} has not been built")),
+        }
+    }
+
+    for output in pipeline::raw_files(&dst)? {
+        if output != manifest::FILE_NAME && manifest.entries().all(|e| e.output != output) {
+            problems.push(format!("{output} is not listed in the manifest"));
+        }
+    }
+
+    if problems.is_empty() {
+        println!("All {} assets are up to date", manifest.entries().count());
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("  {problem}");
+    }
+    Err(format!("{} asset problem(s) found", problems.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
 
-    // kopiera om alla assets
-    fs_extra::dir::copy(
-        src,
-        dst,
-        &fs_extra::dir::CopyOptions::new().copy_inside(true),
-    )
-    .unwrap();
+    /// En tom mapp under temp för ett test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtask-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(RAW_DIR)).unwrap();
+        dir
+    }
+
+    fn counts(built: usize, skipped: usize, failed: usize) -> BuildCounts {
+        BuildCounts { built, skipped, failed }
+    }
+
+    #[test]
+    fn unchanged_sources_are_skipped() {
+        let dir = test_dir("skip");
+        let (src, dst) = (dir.join(RAW_DIR), dir.join(OUT_DIR));
+        let tools = pipeline::Tools::default();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("b.txt"), "b").unwrap();
+
+        assert_eq!(build(&src, &dst, &tools, false).unwrap(), counts(2, 0, 0));
+        assert_eq!(build(&src, &dst, &tools, false).unwrap(), counts(0, 2, 0));
+        fs::write(src.join("b.txt"), "changed").unwrap();
+        assert_eq!(build(&src, &dst, &tools, false).unwrap(), counts(1, 1, 0));
+        // en ändrad output byggs också om
+        fs::write(dst.join("a.txt"), "tampered").unwrap();
+        assert_eq!(build(&src, &dst, &tools, false).unwrap(), counts(1, 1, 0));
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "a");
+        assert_eq!(build(&src, &dst, &tools, true).unwrap(), counts(2, 0, 0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removed_sources_are_cleaned_up_even_when_forced() {
+        let dir = test_dir("stale");
+        let (src, dst) = (dir.join(RAW_DIR), dir.join(OUT_DIR));
+        let tools = pipeline::Tools::default();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("b.txt"), "b").unwrap();
+        build(&src, &dst, &tools, false).unwrap();
 
-    println!("Assets copied from assets_raw → assets/");
+        fs::remove_file(src.join("b.txt")).unwrap();
+        assert_eq!(build(&src, &dst, &tools, true).unwrap(), counts(1, 0, 0));
+        assert!(dst.join("a.txt").exists());
+        assert!(!dst.join("b.txt").exists());
+        let manifest = Manifest::load(&dst.join(manifest::FILE_NAME)).unwrap();
+        assert_eq!(manifest.entries().map(|e| e.source.as_str()).collect::<Vec<_>>(), ["a.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn the_old_output_is_removed_when_a_source_builds_to_another_file() {
+        let dir = test_dir("renamed");
+        let (src, dst) = (dir.join(RAW_DIR), dir.join(OUT_DIR));
+        fs::write(src.join("wall.png"), "png").unwrap();
+        // som om toktx fanns förra gången och gjorde en .ktx2 av bilden
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(dst.join("wall.ktx2"), "ktx").unwrap();
+        let mut previous = Manifest::default();
+        previous.insert(manifest::Entry {
+            source: "wall.png".into(),
+            source_hash: manifest::hash_file(&src.join("wall.png")).unwrap(),
+            output: "wall.ktx2".into(),
+            output_hash: manifest::hash_file(&dst.join("wall.ktx2")).unwrap(),
+            processor: "toktx".into(),
+        });
+        previous.save(&dst.join(manifest::FILE_NAME)).unwrap();
+
+        assert_eq!(build(&src, &dst, &pipeline::Tools::default(), false).unwrap(), counts(1, 0, 0));
+        assert!(dst.join("wall.png").exists());
+        assert!(!dst.join("wall.ktx2").exists());
+        assert!(check_assets(&dir).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_sources_keep_their_previous_output() {
+        let dir = test_dir("failed");
+        let (src, dst) = (dir.join(RAW_DIR), dir.join(OUT_DIR));
+        fs::write(src.join("level.map"), "map").unwrap();
+        // en tidigare lyckad build; nu finns inget mapc, så bygget misslyckas
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(dst.join("level.cmap"), "compiled").unwrap();
+        let entry = manifest::Entry {
+            source: "level.map".into(),
+            source_hash: "old".into(),
+            output: "level.cmap".into(),
+            output_hash: manifest::hash_file(&dst.join("level.cmap")).unwrap(),
+            processor: "mapc".into(),
+        };
+        let mut previous = Manifest::default();
+        previous.insert(entry.clone());
+        previous.save(&dst.join(manifest::FILE_NAME)).unwrap();
+
+        for force in [false, true] {
+            assert_eq!(build(&src, &dst, &pipeline::Tools::default(), force).unwrap(), counts(0, 0, 1));
+            assert_eq!(fs::read_to_string(dst.join("level.cmap")).unwrap(), "compiled");
+            let manifest = Manifest::load(&dst.join(manifest::FILE_NAME)).unwrap();
+            assert_eq!(manifest.get("level.map"), Some(&entry));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Manifestet ligger i output-mappen, bredvid de byggda filerna
+pub const FILE_NAME: &str = "manifest.txt";
+
+const HEADER: &str = "# source\tsource_hash\toutput\toutput_hash\tprocessor";
+
+/// One built asset. Paths are relative to `assets_raw/` and `assets/` and always use `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub source: String,
+    pub source_hash: String,
+    pub output: String,
+    pub output_hash: String,
+    pub processor: String,
+}
+
+/// Every built asset keyed by its source path, saved sorted so diffs stay readable.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+        let mut manifest = Self::default();
+
+        for (i, line) in data.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [source, source_hash, output, output_hash, processor] = fields[..] else {
+                return Err(format!("{}:{}: expected 5 fields", path.display(), i + 1));
+            };
+            manifest.insert(Entry {
+                source: source.to_string(),
+                source_hash: source_hash.to_string(),
+                output: output.to_string(),
+                output_hash: output_hash.to_string(),
+                processor: processor.to_string(),
+            });
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = String::from(HEADER);
+        out.push('\n');
+        for e in self.entries.values() {
+            out.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                e.source, e.source_hash, e.output, e.output_hash, e.processor
+            ));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("creating {}: {e}", dir.display()))?;
+        }
+        fs::write(path, out).map_err(|e| format!("writing {}: {e}", path.display()))
+    }
+
+    pub fn get(&self, source: &str) -> Option<&Entry> {
+        self.entries.get(source)
+    }
+
+    pub fn insert(&mut self, entry: Entry) {
+        self.entries.insert(entry.source.clone(), entry);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+}
+
+pub fn hash_file(path: &Path) -> Result<String, String> {
+    let data = fs::read(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+    Ok(blake3::hash(&data).to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("xtask-manifest-{}.txt", std::process::id()));
+        let mut manifest = Manifest::default();
+        for source in ["textures/wall.png", "maps/de_dust.map"] {
+            manifest.insert(Entry {
+                source: source.into(),
+                source_hash: blake3::hash(source.as_bytes()).to_hex().to_string(),
+                output: format!("{source}.out"),
+                output_hash: "00ff".into(),
+                processor: "copy".into(),
+            });
+        }
+        manifest.save(&path).unwrap();
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded.entries().collect::<Vec<_>>(), manifest.entries().collect::<Vec<_>>());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_lines_are_reported_with_their_number() {
+        let path = std::env::temp_dir().join(format!("xtask-bad-manifest-{}.txt", std::process::id()));
+        fs::write(&path, format!("{HEADER}\na\tb\n")).unwrap();
+        let err = Manifest::load(&path).unwrap_err();
+        assert!(err.ends_with(":2: expected 5 fields"), "{err}");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hash_follows_content() {
+        let path = std::env::temp_dir().join(format!("xtask-hash-{}.txt", std::process::id()));
+        fs::write(&path, "abc").unwrap();
+        assert_eq!(hash_file(&path).unwrap(), blake3::hash(b"abc").to_hex().to_string());
+        fs::write(&path, "abd").unwrap();
+        assert_ne!(hash_file(&path).unwrap(), blake3::hash(b"abc").to_hex().to_string());
+        fs::remove_file(&path).unwrap();
+        assert!(hash_file(&path).is_err());
+    }
+}
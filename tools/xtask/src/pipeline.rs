@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use walkdir::WalkDir;
+
+const TEXTURE_EXTS: &[&str] = &["png", "jpg", "jpeg", "tga"];
+const GLTF_EXTS: &[&str] = &["gltf", "glb"];
+/// Råa kartor (`.map`) kompileras av mapc till `.cmap`
+const MAP_EXT: &str = "map";
+const COMPILED_MAP_EXT: &str = "cmap";
+
+/// External tools found on this machine. `None` means the tool is not installed.
+#[derive(Default)]
+pub struct Tools {
+    toktx: Option<PathBuf>,
+    gltfpack: Option<PathBuf>,
+    mapc: Option<PathBuf>,
+}
+
+impl Tools {
+    pub fn detect() -> Self {
+        let tools = Self {
+            toktx: find_tool("TOKTX", "toktx"),
+            gltfpack: find_tool("GLTFPACK", "gltfpack"),
+            mapc: find_tool("MAPC", "mapc"),
+        };
+        if tools.toktx.is_none() {
+            println!("warning: toktx not found, textures are copied uncompressed");
+        }
+        if tools.gltfpack.is_none() {
+            println!("warning: gltfpack not found, glTF files are copied unoptimized");
+        }
+        tools
+    }
+}
+
+fn find_tool(env_var: &str, name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os(env_var)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(name));
+    Command::new(&path)
+        .arg("--version")
+        .output()
+        .ok()
+        .map(|_| path)
+}
+
+/// How a single raw file is turned into a built asset.
+pub enum Processor<'a> {
+    Ktx2(&'a Path),
+    Gltfpack(&'a Path),
+    Mapc(Option<&'a Path>),
+    Copy,
+}
+
+impl<'a> Processor<'a> {
+    pub fn for_path(source: &str, tools: &'a Tools) -> Self {
+        let ext = extension(source);
+        if TEXTURE_EXTS.contains(&ext.as_str()) {
+            if let Some(toktx) = &tools.toktx {
+                return Processor::Ktx2(toktx);
+            }
+        } else if GLTF_EXTS.contains(&ext.as_str()) {
+            if let Some(gltfpack) = &tools.gltfpack {
+                return Processor::Gltfpack(gltfpack);
+            }
+        } else if ext == MAP_EXT {
+            // kartor kan inte laddas okompilerade, så här finns ingen fallback
+            return Processor::Mapc(tools.mapc.as_deref());
+        }
+        Processor::Copy
+    }
+
+    /// Stored in the manifest so a file is rebuilt when the way it is processed changes.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Processor::Ktx2(_) => "ktx2",
+            Processor::Gltfpack(_) => "gltfpack",
+            Processor::Mapc(_) => "mapc",
+            Processor::Copy => "copy",
+        }
+    }
+
+    /// Processes `source` (relative to `src`) into `dst` and returns the output path
+    /// relative to `dst`.
+    pub fn run(&self, src: &Path, dst: &Path, source: &str) -> Result<String, String> {
+        let output = match self {
+            Processor::Ktx2(_) => with_extension(source, "ktx2"),
+            Processor::Mapc(_) => with_extension(source, COMPILED_MAP_EXT),
+            Processor::Gltfpack(_) | Processor::Copy => source.to_string(),
+        };
+        let in_path = src.join(source);
+        let out_path = dst.join(&output);
+        if let Some(dir) = out_path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("creating {}: {e}", dir.display()))?;
+        }
+
+        match self {
+            Processor::Ktx2(toktx) => run_tool(
+                Command::new(toktx)
+                    .args(["--t2", "--genmipmap", "--encode", "uastc", "--zcmp", "19"])
+                    .arg(&out_path)
+                    .arg(&in_path),
+            )?,
+            Processor::Gltfpack(gltfpack) => run_tool(
+                // -noq: Bevy kan inte läsa KHR_mesh_quantization
+                Command::new(gltfpack)
+                    .arg("-i")
+                    .arg(&in_path)
+                    .arg("-o")
+                    .arg(&out_path)
+                    .arg("-noq"),
+            )?,
+            Processor::Mapc(Some(mapc)) => run_tool(Command::new(mapc).arg(&in_path).arg(&out_path))?,
+            Processor::Mapc(None) => return Err("mapc not found (set MAPC or add it to PATH)".into()),
+            Processor::Copy => {
+                fs::copy(&in_path, &out_path).map_err(|e| format!("copying {}: {e}", in_path.display()))?;
+            }
+        }
+        Ok(output)
+    }
+}
+
+fn run_tool(cmd: &mut Command) -> Result<(), String> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let out = cmd.output().map_err(|e| format!("running {program}: {e}"))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{program} exited with {}: {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))
+    }
+}
+
+/// All files under `dir` as sorted, `/`-separated paths relative to `dir`.
+/// Hidden files (editor swap files, `.DS_Store` etc.) are skipped.
+pub fn raw_files(dir: &Path) -> Result<Vec<String>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.map_err(|e| format!("walking {}: {e}", dir.display()))?;
+        if !entry.file_type().is_file() || entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        let parts: Vec<_> = rel.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+        files.push(parts.join("/"));
+    }
+    Ok(files)
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn with_extension(path: &str, ext: &str) -> String {
+    Path::new(path).with_extension(ext).to_string_lossy().into_owned()
+}